@@ -0,0 +1,179 @@
+//! Minimal client connection
+//!
+//! Enough of the client side of the protocol for bots and tests: connect,
+//! handshake, log in, then exchange transactions.
+//!
+//! ```rust,no_run
+//! # async fn run() -> rhxcore::Result<()> {
+//! use rhxcore::client::Client;
+//! use rhxcore::protocol::{Field, FieldId, Transaction, TransactionType};
+//!
+//! let mut client = Client::connect("127.0.0.1:5500").await?;
+//! client.handshake().await?;
+//! client.login("bot", "secret").await?;
+//! client.agree("Bot").await?;
+//!
+//! let mut chat = Transaction::new(TransactionType::SendChat);
+//! chat.add_field(Field::string(FieldId::Data, "Hello"));
+//! client.send(chat).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::codec::TransactionCodec;
+use crate::error::{ProtocolError, Result};
+use crate::password::xor_password;
+use crate::protocol::{
+    Field, FieldId, Handshake, HandshakeReply, Transaction, TransactionType, PROTOCOL_MAGIC,
+};
+use bytes::BytesMut;
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio_util::codec::Framed;
+
+/// A client connection to a server
+pub struct Client<S = TcpStream> {
+    framed: Framed<S, TransactionCodec>,
+    next_id: u32,
+}
+
+impl Client<TcpStream> {
+    /// Open a TCP connection (call [`handshake`](Self::handshake) next)
+    pub async fn connect(addr: impl ToSocketAddrs) -> Result<Self> {
+        Ok(Self::new(TcpStream::connect(addr).await?))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Client<S> {
+    /// Wrap an already connected stream
+    pub fn new(stream: S) -> Self {
+        Self {
+            framed: Framed::new(stream, TransactionCodec::new()),
+            next_id: 1,
+        }
+    }
+
+    /// Exchange handshakes with the server
+    pub async fn handshake(&mut self) -> Result<()> {
+        let stream = self.framed.get_mut();
+
+        let mut buf = BytesMut::with_capacity(Handshake::SIZE);
+        Handshake::new().to_bytes(&mut buf);
+        stream.write_all(&buf).await?;
+
+        let mut reply = [0u8; HandshakeReply::SIZE];
+        stream.read_exact(&mut reply).await?;
+        let reply = HandshakeReply::from_bytes(&reply)?;
+        if reply.protocol_id != PROTOCOL_MAGIC || !reply.is_success() {
+            return Err(ProtocolError::InvalidHandshake);
+        }
+
+        Ok(())
+    }
+
+    /// Log in, returning the server's reply (an empty login is a guest login)
+    pub async fn login(&mut self, login: &str, password: &str) -> Result<Transaction> {
+        let mut transaction = Transaction::new(TransactionType::Login);
+        transaction.add_field(Field::binary(FieldId::UserLogin, xor_password(login.as_bytes())));
+        transaction.add_field(Field::binary(FieldId::UserPassword, xor_password(password.as_bytes())));
+        self.request(transaction).await
+    }
+
+    /// Accept the agreement with the nickname others will see
+    pub async fn agree(&mut self, nickname: &str) -> Result<Transaction> {
+        let mut transaction = Transaction::new(TransactionType::Agreed);
+        transaction.add_field(Field::string(FieldId::UserName, nickname));
+        self.request(transaction).await
+    }
+
+    /// Send a transaction, returning its ID
+    ///
+    /// Transactions with an ID of 0 are given the next free one.
+    pub async fn send(&mut self, mut transaction: Transaction) -> Result<u32> {
+        if transaction.id == 0 {
+            transaction.id = self.next_id;
+            self.next_id = self.next_id.wrapping_add(1).max(1);
+        }
+        let id = transaction.id;
+        self.framed.send(transaction).await?;
+        Ok(id)
+    }
+
+    /// Receive the next transaction from the server
+    pub async fn recv(&mut self) -> Result<Transaction> {
+        self.framed.next().await.unwrap_or(Err(ProtocolError::ConnectionClosed))
+    }
+
+    /// Send a transaction and wait for its reply
+    ///
+    /// Anything else the server sends in the meantime is dropped. A reply
+    /// carrying an error code is returned as [`ProtocolError::ErrorReply`].
+    pub async fn request(&mut self, transaction: Transaction) -> Result<Transaction> {
+        let id = self.send(transaction).await?;
+        loop {
+            let reply = self.recv().await?;
+            if !reply.is_reply || reply.id != id {
+                continue;
+            }
+            if reply.error_code != 0 {
+                return Err(ProtocolError::ErrorReply { code: reply.error_code });
+            }
+            return Ok(reply);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ErrorCode;
+
+    /// Play the server side of a handshake and one login
+    async fn serve_login(stream: tokio::io::DuplexStream, error_code: u32) -> Handshake {
+        let mut framed = Framed::new(stream, TransactionCodec::new());
+
+        let mut handshake = [0u8; Handshake::SIZE];
+        framed.get_mut().read_exact(&mut handshake).await.unwrap();
+        let mut reply = BytesMut::new();
+        HandshakeReply::new().to_bytes(&mut reply);
+        framed.get_mut().write_all(&reply).await.unwrap();
+
+        let login = framed.next().await.unwrap().unwrap();
+        assert_eq!(login.transaction_type, TransactionType::Login);
+        let login_field = login.get_field(FieldId::UserLogin).unwrap().as_binary().unwrap();
+        assert_eq!(xor_password(login_field), b"bot");
+
+        // Unrelated traffic before the reply is skipped
+        framed.send(Transaction::new(TransactionType::ChatMessage)).await.unwrap();
+        let mut reply = Transaction::new_reply(TransactionType::Login, login.id);
+        reply.error_code = error_code;
+        framed.send(reply).await.unwrap();
+
+        Handshake::from_bytes(&handshake).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_handshake_and_login() {
+        let (client_side, server_side) = tokio::io::duplex(1024);
+        let server = tokio::spawn(serve_login(server_side, 0));
+
+        let mut client = Client::new(client_side);
+        client.handshake().await.unwrap();
+        let reply = client.login("bot", "secret").await.unwrap();
+        assert_eq!(reply.transaction_type, TransactionType::Login);
+        assert!(server.await.unwrap().is_valid());
+    }
+
+    #[tokio::test]
+    async fn test_error_reply() {
+        let (client_side, server_side) = tokio::io::duplex(1024);
+        let code = ErrorCode::PermissionDenied.to_u32();
+        tokio::spawn(serve_login(server_side, code));
+
+        let mut client = Client::new(client_side);
+        client.handshake().await.unwrap();
+        let error = client.login("bot", "wrong").await.unwrap_err();
+        assert!(matches!(error, ProtocolError::ErrorReply { code: c } if c == code));
+    }
+}
@@ -1,7 +1,7 @@
 //! Date parameter encoding/decoding
 
 use bytes::{Buf, BufMut};
-use chrono::{DateTime, Datelike, Timelike, Utc};
+use chrono::{DateTime, Datelike, Duration, TimeZone, Timelike, Utc};
 
 /// Hotline date parameter (8 bytes)
 #[derive(Debug, Clone, Copy)]
@@ -49,6 +49,14 @@ impl DateParam {
         })
     }
 
+    /// Convert to a DateTime, or `None` if the year is out of range
+    pub fn to_datetime(&self) -> Option<DateTime<Utc>> {
+        let new_year = Utc.with_ymd_and_hms(self.year as i32, 1, 1, 0, 0, 0).single()?;
+        new_year.checked_add_signed(
+            Duration::seconds(self.seconds as i64) + Duration::milliseconds(self.milliseconds as i64),
+        )
+    }
+
     /// Encode to bytes
     pub fn to_bytes(&self, buf: &mut impl BufMut) {
         buf.put_u16(self.year);
@@ -75,7 +83,7 @@ const MONTH_SECS: [u32; 12] = [
 
 /// Convert month to seconds since January 1st
 fn month_to_seconds(month: u8, is_leap: bool) -> u32 {
-    if month < 1 || month > 12 {
+    if !(1..=12).contains(&month) {
         return 0;
     }
 
@@ -91,7 +99,7 @@ fn month_to_seconds(month: u8, is_leap: bool) -> u32 {
 
 /// Check if a year is a leap year
 fn is_leap_year(year: u16) -> bool {
-    (year % 4 == 0 && year % 100 != 0) || (year % 400 == 0)
+    (year.is_multiple_of(4) && !year.is_multiple_of(100)) || year.is_multiple_of(400)
 }
 
 /// Encode a DateTime to date parameter bytes
@@ -104,11 +112,9 @@ pub fn encode_date(dt: &DateTime<Utc>) -> Vec<u8> {
 
 /// Decode date parameter bytes to a DateTime
 pub fn decode_date(buf: &[u8]) -> Result<DateTime<Utc>, std::io::Error> {
-    let _param = DateParam::from_bytes(buf)?;
-
-    // TODO: Convert back to DateTime (complex, deferred for now)
-    // For MVP, just return current time
-    Ok(Utc::now())
+    DateParam::from_bytes(buf)?.to_datetime().ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, "Date parameter out of range")
+    })
 }
 
 #[cfg(test)]
@@ -137,4 +143,16 @@ mod tests {
         // March 1st (leap) = 60 days
         assert_eq!(month_to_seconds(3, true), 60 * 86400);
     }
+
+    #[test]
+    fn test_date_round_trip() {
+        for text in ["2024-02-29T23:59:59.250Z", "1999-01-01T00:00:00Z", "2023-12-31T12:34:56.789Z"] {
+            let dt: DateTime<Utc> = text.parse().unwrap();
+            let encoded = encode_date(&dt);
+            assert_eq!(encoded.len(), DateParam::SIZE);
+            assert_eq!(decode_date(&encoded).unwrap(), dt);
+        }
+
+        assert!(decode_date(&[0x07, 0xE8]).is_err());
+    }
 }
@@ -1,7 +1,8 @@
 //! Field codec for encoding and decoding fields
 
 use crate::error::{ProtocolError, Result};
-use crate::protocol::field::{Field, FieldData, FieldHeader, FieldId};
+use crate::protocol::field::{integer_len, Field, FieldData, FieldHeader, FieldId};
+use crate::protocol::MAX_FIELD_SIZE;
 use bytes::{Buf, BufMut, BytesMut};
 
 /// Decode fields from a buffer
@@ -47,7 +48,8 @@ pub fn decode_fields(buf: &mut BytesMut) -> Result<Vec<Field>> {
             | FieldId::UserFlags
             | FieldId::Version
             | FieldId::ReferenceNumber
-            | FieldId::WaitingCount => {
+            | FieldId::WaitingCount
+            | FieldId::FolderItemCount => {
                 // Integer fields (2 or 4 bytes)
                 if header.size == 2 {
                     FieldData::Integer(field_data.get_i16() as i32)
@@ -60,7 +62,7 @@ pub fn decode_fields(buf: &mut BytesMut) -> Result<Vec<Field>> {
 
             FieldId::UserAccess => {
                 // UserAccess is always 8 bytes and needs special bit-reversal handling
-                // Store as Binary so it can be decoded with AccessPrivileges::from_wire_format()
+                // Store as Binary so it can be decoded with Field::access_privileges()
                 if header.size == 8 {
                     FieldData::Binary(field_data.to_vec())
                 } else {
@@ -69,12 +71,14 @@ pub fn decode_fields(buf: &mut BytesMut) -> Result<Vec<Field>> {
                 }
             }
 
-            FieldId::UserName
+            FieldId::ErrorText
+            | FieldId::UserName
             | FieldId::ServerName
             | FieldId::ChatSubject
             | FieldId::FileName
             | FieldId::FileComment => {
-                // String fields (try to decode as UTF-8)
+                // String fields (try to decode as UTF-8; anything else, such as
+                // MacRoman, stays binary and `Field::as_text` reports it)
                 match String::from_utf8(field_data.to_vec()) {
                     Ok(s) => FieldData::String(s),
                     Err(_) => FieldData::Binary(field_data.to_vec()),
@@ -94,7 +98,17 @@ pub fn decode_fields(buf: &mut BytesMut) -> Result<Vec<Field>> {
 }
 
 /// Encode fields into a buffer
+///
+/// Fails with [`ProtocolError::FieldTooLarge`] before writing anything if a
+/// field's data doesn't fit the 2-byte size in its header.
 pub fn encode_fields(fields: &[Field], buf: &mut BytesMut) -> Result<()> {
+    if let Some(field) = fields.iter().find(|f| f.encoded_len() > MAX_FIELD_SIZE) {
+        return Err(ProtocolError::FieldTooLarge {
+            field: field.id,
+            size: field.encoded_len(),
+        });
+    }
+
     // Write field count
     buf.put_u16(fields.len() as u16);
 
@@ -104,8 +118,8 @@ pub fn encode_fields(fields: &[Field], buf: &mut BytesMut) -> Result<()> {
 
         match &field.data {
             FieldData::Integer(v) => {
-                // Use appropriate size based on value
-                if *v >= i16::MIN as i32 && *v <= i16::MAX as i32 {
+                // Use appropriate size based on field and value
+                if integer_len(field.id, *v) == 2 {
                     field_buf.put_i16(*v as i16);
                 } else {
                     field_buf.put_i32(*v);
@@ -132,3 +146,67 @@ pub fn encode_fields(fields: &[Field], buf: &mut BytesMut) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::SERVER_VERSION;
+
+    #[test]
+    fn test_small_reference_number_encodes_as_4_bytes() {
+        let fields = vec![
+            Field::integer(FieldId::ReferenceNumber, 7),
+            Field::integer(FieldId::UserId, 7),
+        ];
+        let mut buf = BytesMut::new();
+        encode_fields(&fields, &mut buf).unwrap();
+
+        // count, then id/size/data for each field
+        assert_eq!(&buf[..], &[0, 2, 0, 107, 0, 4, 0, 0, 0, 7, 0, 103, 0, 2, 0, 7]);
+        assert_eq!(fields[0].encoded_len(), 4);
+
+        let decoded = decode_fields(&mut buf).unwrap();
+        assert_eq!(decoded[0].as_integer(), Some(7));
+
+        // References past i32::MAX survive the round trip as their bit pattern
+        let reference = 0xFFFF_FFF0u32;
+        let field = Field::integer(FieldId::ReferenceNumber, reference as i32);
+        let mut buf = BytesMut::new();
+        encode_fields(&[field], &mut buf).unwrap();
+        let decoded = decode_fields(&mut buf).unwrap();
+        assert_eq!(decoded[0].as_integer().map(|v| v as u32), Some(reference));
+    }
+
+    #[test]
+    fn test_version_encodes_as_2_bytes() {
+        let field = Field::integer(FieldId::Version, SERVER_VERSION as i32);
+        let mut buf = BytesMut::new();
+        encode_fields(&[field], &mut buf).unwrap();
+
+        // 197 = 0x00C5
+        assert_eq!(&buf[..], &[0, 1, 0, 160, 0, 2, 0, 0xC5]);
+
+        let decoded = decode_fields(&mut buf).unwrap();
+        assert_eq!(decoded[0].as_integer(), Some(SERVER_VERSION as i32));
+    }
+
+    #[test]
+    fn test_oversized_field_is_an_error() {
+        let fields = vec![
+            Field::integer(FieldId::UserId, 1),
+            Field::binary(FieldId::Data, vec![b'x'; MAX_FIELD_SIZE + 1]),
+        ];
+        let mut buf = BytesMut::new();
+        let error = encode_fields(&fields, &mut buf).unwrap_err();
+        assert!(matches!(
+            error,
+            ProtocolError::FieldTooLarge { field: FieldId::Data, size } if size == 65536
+        ));
+        assert!(buf.is_empty());
+
+        // The largest size the header can hold still encodes
+        let field = Field::binary(FieldId::Data, vec![b'x'; MAX_FIELD_SIZE]);
+        encode_fields(&[field], &mut buf).unwrap();
+        assert_eq!(buf.len(), 2 + 4 + MAX_FIELD_SIZE);
+    }
+}
@@ -23,6 +23,11 @@ impl TransactionCodec {
     pub fn with_max_size(max_size: usize) -> Self {
         Self { max_size }
     }
+
+    /// Maximum transaction data size accepted or produced by this codec
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
 }
 
 impl Default for TransactionCodec {
@@ -31,6 +36,10 @@ impl Default for TransactionCodec {
     }
 }
 
+/// Decoding consumes a transaction's full frame (header plus the declared
+/// data size) before parsing it. When a [recoverable](ProtocolError::is_recoverable)
+/// error is returned, the bad frame is already gone and the next call decodes
+/// the following transaction.
 impl Decoder for TransactionCodec {
     type Item = Transaction;
     type Error = ProtocolError;
@@ -44,6 +53,14 @@ impl Decoder for TransactionCodec {
         // Parse header without consuming bytes
         let header = TransactionHeader::from_bytes(&src[..TransactionHeader::SIZE])?;
 
+        // Check max size before waiting for (and buffering) the data
+        if header.data_size as usize > self.max_size {
+            return Err(ProtocolError::TransactionTooLarge {
+                size: header.data_size as usize,
+                max: self.max_size,
+            });
+        }
+
         // Check if we have the full transaction
         let total_needed = TransactionHeader::SIZE + header.data_size as usize;
         if src.len() < total_needed {
@@ -52,16 +69,12 @@ impl Decoder for TransactionCodec {
             return Ok(None);
         }
 
-        // Check max size
-        if header.data_size as usize > self.max_size {
-            return Err(ProtocolError::TransactionTooLarge {
-                size: header.data_size as usize,
-                max: self.max_size,
-            });
-        }
+        // Take the whole frame up front, so an error below leaves the buffer
+        // at the start of the next transaction
+        let mut frame = src.split_to(total_needed);
+        frame.advance(TransactionHeader::SIZE);
 
-        // Now consume the bytes
-        src.advance(TransactionHeader::SIZE);
+        header.check_single_part()?;
 
         // Parse transaction type
         let transaction_type = TransactionType::from_u16(header.transaction_type).ok_or(
@@ -70,7 +83,7 @@ impl Decoder for TransactionCodec {
 
         // Parse fields
         let fields = if header.data_size > 0 {
-            super::field_codec::decode_fields(&mut src.split_to(header.data_size as usize))?
+            super::field_codec::decode_fields(&mut frame)?
         } else {
             Vec::new()
         };
@@ -81,7 +94,8 @@ impl Decoder for TransactionCodec {
             transaction_type,
             id: header.id,
             error_code: header.error_code,
-            total_size: header.total_size,
+            // A total size of 0 means the same as repeating the data size
+            total_size: header.data_size,
             data_size: header.data_size,
             fields,
         }))
@@ -92,6 +106,10 @@ impl Encoder<Transaction> for TransactionCodec {
     type Error = ProtocolError;
 
     fn encode(&mut self, item: Transaction, dst: &mut BytesMut) -> Result<()> {
+        // Refuse to produce a frame the peer would reject
+        item.validate()?;
+        item.check_size(self.max_size)?;
+
         // Encode fields first to know the size
         let mut fields_buf = BytesMut::new();
         super::field_codec::encode_fields(&item.fields, &mut fields_buf)?;
@@ -119,3 +137,181 @@ impl Encoder<Transaction> for TransactionCodec {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{Field, FieldId};
+
+    /// Build a GetUserNameList reply with `count` UserNameWithInfo entries
+    fn user_list_reply(count: usize) -> Transaction {
+        let mut reply = Transaction::new_reply(TransactionType::GetUserNameList, 1);
+        for i in 0..count {
+            let name = format!("User with a fairly long nickname {}", i);
+            let mut info = Vec::new();
+            info.extend_from_slice(&(i as u16).to_be_bytes());
+            info.extend_from_slice(&0u16.to_be_bytes());
+            info.extend_from_slice(&0u16.to_be_bytes());
+            info.extend_from_slice(&(name.len() as u16).to_be_bytes());
+            info.extend_from_slice(name.as_bytes());
+            reply.add_field(Field::binary(FieldId::UserNameWithInfo, info));
+        }
+        reply
+    }
+
+    #[test]
+    fn test_resynchronizes_after_malformed_frame() {
+        let mut codec = TransactionCodec::new();
+        let mut src = BytesMut::new();
+
+        // A frame with an unknown transaction type and some data
+        let header = TransactionHeader {
+            flags: 0,
+            is_reply: 0,
+            transaction_type: 9999,
+            id: 1,
+            error_code: 0,
+            total_size: 4,
+            data_size: 4,
+        };
+        header.to_bytes(&mut src);
+        src.extend_from_slice(&[0xDE, 0xAD, 0xBE, 0xEF]);
+
+        let mut valid = Transaction::new(TransactionType::GetUserNameList);
+        valid.id = 2;
+        codec.encode(valid, &mut src).unwrap();
+
+        let error = codec.decode(&mut src).unwrap_err();
+        assert!(matches!(error, ProtocolError::InvalidTransactionType(9999)));
+        assert!(error.is_recoverable());
+
+        let decoded = codec.decode(&mut src).unwrap().unwrap();
+        assert_eq!(decoded.transaction_type, TransactionType::GetUserNameList);
+        assert_eq!(decoded.id, 2);
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn test_reply_to_zero_id_request_encodes() {
+        let mut src = BytesMut::new();
+        let mut request = Transaction::new(TransactionType::GetUserNameList);
+        request.id = 0;
+        TransactionCodec::new().encode(request, &mut src).unwrap();
+        let request = TransactionCodec::new().decode(&mut src).unwrap().unwrap();
+
+        // Clients may number a request 0, and its reply has to echo that
+        let reply = Transaction::new_reply(request.transaction_type, request.id);
+        let mut dst = BytesMut::new();
+        TransactionCodec::new().encode(reply, &mut dst).unwrap();
+        let decoded = TransactionCodec::new().decode(&mut dst).unwrap().unwrap();
+        assert!(decoded.is_reply);
+        assert_eq!(decoded.id, 0);
+    }
+
+    /// A GetUserNameList frame with one empty field and the given total size
+    fn frame_with_total_size(total_size: u32) -> BytesMut {
+        let data = [0x00, 0x01, 0x00, 0x65, 0x00, 0x00];
+        let header = TransactionHeader {
+            flags: 0,
+            is_reply: 0,
+            transaction_type: TransactionType::GetUserNameList.to_u16(),
+            id: 7,
+            error_code: 0,
+            total_size,
+            data_size: data.len() as u32,
+        };
+        let mut src = BytesMut::new();
+        header.to_bytes(&mut src);
+        src.extend_from_slice(&data);
+        src
+    }
+
+    #[test]
+    fn test_consistent_total_size_is_single_part() {
+        for total_size in [0, 6] {
+            let mut src = frame_with_total_size(total_size);
+            let decoded = TransactionCodec::new().decode(&mut src).unwrap().unwrap();
+            assert_eq!(decoded.id, 7);
+            assert_eq!(decoded.total_size, 6);
+            assert_eq!(decoded.data_size, 6);
+            assert_eq!(decoded.fields.len(), 1);
+            assert!(src.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_inconsistent_total_size_rejected() {
+        for total_size in [3, 100] {
+            let mut src = frame_with_total_size(total_size);
+            let mut valid = Transaction::new(TransactionType::GetUserNameList);
+            valid.id = 8;
+            let mut codec = TransactionCodec::new();
+            codec.encode(valid, &mut src).unwrap();
+
+            let error = codec.decode(&mut src).unwrap_err();
+            assert!(matches!(error, ProtocolError::MalformedTransaction(_)));
+            assert!(error.is_recoverable());
+
+            // The bad frame is skipped without losing the next one
+            let decoded = codec.decode(&mut src).unwrap().unwrap();
+            assert_eq!(decoded.id, 8);
+        }
+    }
+
+    #[test]
+    fn test_encoded_data_size_matches_encoder() {
+        let reply = user_list_reply(10);
+        let mut dst = BytesMut::new();
+        TransactionCodec::new().encode(reply.clone(), &mut dst).unwrap();
+
+        assert_eq!(
+            dst.len(),
+            TransactionHeader::SIZE + reply.encoded_data_size()
+        );
+    }
+
+    #[test]
+    fn test_oversized_user_list_rejected() {
+        let reply = user_list_reply(1000);
+        assert!(reply.encoded_data_size() > crate::protocol::MAX_TRANSACTION_SIZE);
+
+        assert!(matches!(
+            reply.check_size(crate::protocol::MAX_TRANSACTION_SIZE),
+            Err(ProtocolError::TransactionTooLarge { .. })
+        ));
+
+        // Nothing is written for an over-budget transaction
+        let mut dst = BytesMut::new();
+        let result = TransactionCodec::new().encode(reply, &mut dst);
+        assert!(matches!(
+            result,
+            Err(ProtocolError::TransactionTooLarge { max, .. })
+                if max == crate::protocol::MAX_TRANSACTION_SIZE
+        ));
+        assert!(dst.is_empty());
+    }
+
+    #[test]
+    fn test_oversized_header_rejected_before_data_arrives() {
+        let header = TransactionHeader {
+            flags: 0,
+            is_reply: 0,
+            transaction_type: TransactionType::SendChat.to_u16(),
+            id: 1,
+            error_code: 0,
+            total_size: crate::protocol::MAX_TRANSACTION_SIZE as u32 + 1,
+            data_size: crate::protocol::MAX_TRANSACTION_SIZE as u32 + 1,
+        };
+        let mut src = BytesMut::new();
+        header.to_bytes(&mut src);
+
+        // Only the header has arrived, but it already announces too much data
+        let result = TransactionCodec::new().decode(&mut src);
+        assert!(matches!(
+            result,
+            Err(ProtocolError::TransactionTooLarge { size, max })
+                if size == crate::protocol::MAX_TRANSACTION_SIZE + 1
+                    && max == crate::protocol::MAX_TRANSACTION_SIZE
+        ));
+    }
+}
@@ -1,5 +1,6 @@
 //! Error types
 
+use crate::protocol::FieldId;
 use thiserror::Error;
 
 /// Protocol errors
@@ -20,14 +21,53 @@ pub enum ProtocolError {
     #[error("Transaction too large: {size} bytes (max: {max})")]
     TransactionTooLarge { size: usize, max: usize },
 
+    #[error("Field {field:?} too large: {size} bytes (max: {max})", max = crate::protocol::MAX_FIELD_SIZE)]
+    FieldTooLarge { field: FieldId, size: usize },
+
     #[error("Invalid field data")]
     InvalidFieldData,
 
+    #[error("Malformed transaction: {0}")]
+    MalformedTransaction(&'static str),
+
+    #[error("Field data truncated: needed {needed} bytes, {available} available")]
+    TruncatedField { needed: usize, available: usize },
+
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 
     #[error("UTF-8 error: {0}")]
     Utf8(#[from] std::string::FromUtf8Error),
+
+    #[error("Invalid UTF-8 in field {field:?}")]
+    InvalidUtf8 { field: FieldId },
+
+    #[error("Connection closed")]
+    ConnectionClosed,
+
+    #[error("Server replied with error code {code}")]
+    ErrorReply { code: u32 },
+}
+
+impl ProtocolError {
+    /// Whether the connection can carry on after this error
+    ///
+    /// Decoding errors confined to a single transaction are recoverable: the
+    /// codec has already discarded the bad frame, so the next one can be read.
+    /// Anything that leaves the stream itself in doubt is fatal.
+    pub fn is_recoverable(&self) -> bool {
+        matches!(
+            self,
+            ProtocolError::InvalidTransactionType(_)
+                | ProtocolError::InvalidFieldId(_)
+                | ProtocolError::InvalidFieldData
+                | ProtocolError::MalformedTransaction(_)
+                | ProtocolError::TruncatedField { .. }
+                | ProtocolError::Utf8(_)
+                | ProtocolError::InvalidUtf8 { .. }
+                | ProtocolError::ErrorReply { .. }
+        )
+    }
 }
 
 /// Result type for protocol operations
@@ -9,6 +9,7 @@
 //! - Encoding/decoding (codec implementation for transactions and fields)
 //! - Type-safe protocol structures
 //! - Legacy password handling
+//! - A minimal async client connection for bots and tests
 //!
 //! ## Example
 //!
@@ -26,8 +27,10 @@ pub mod codec;
 pub mod types;
 pub mod password;
 pub mod error;
+pub mod client;
 
 // Re-export commonly used types
+pub use client::Client;
 pub use error::{ProtocolError, Result};
 pub use protocol::{Transaction, TransactionType, Field, FieldId};
 pub use types::access::AccessPrivileges;
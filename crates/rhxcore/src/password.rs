@@ -1,4 +1,27 @@
-//! Password handling utilities (legacy XOR obfuscation)
+//! Password handling utilities
+//!
+//! Passwords are stored either with the legacy XOR obfuscation or as Argon2
+//! hashes in PHC string format. [`verify_password`] accepts both, so servers
+//! can move to Argon2 by rehashing each password on its next login.
+
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use serde::{Deserialize, Serialize};
+
+/// Prefix shared by every stored Argon2 hash
+const ARGON2_PREFIX: &[u8] = b"$argon2";
+
+/// How newly stored passwords are protected
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PasswordScheme {
+    /// Legacy XOR obfuscation, readable by anyone with the database
+    #[default]
+    Legacy,
+    /// Argon2id with a random salt
+    Argon2,
+}
 
 /// Transform password using legacy XOR obfuscation (bitwise NOT)
 ///
@@ -32,11 +55,45 @@ pub fn unscramble_password(data: &[u8]) -> Vec<u8> {
     xor_password(data)
 }
 
-/// Verify password against stored scrambled version
-#[inline]
-pub fn verify_password(stored_scrambled: &[u8], provided: &[u8]) -> bool {
+/// Hash a plaintext password for storage with `scheme`
+pub fn hash_password(
+    password: &[u8],
+    scheme: PasswordScheme,
+) -> Result<Vec<u8>, argon2::password_hash::Error> {
+    match scheme {
+        PasswordScheme::Legacy => Ok(xor_password(password)),
+        PasswordScheme::Argon2 => {
+            let salt = SaltString::generate(&mut OsRng);
+            let hash = Argon2::default().hash_password(password, &salt)?;
+            Ok(hash.to_string().into_bytes())
+        }
+    }
+}
+
+/// Verify password against its stored form (Argon2 hash or scrambled)
+pub fn verify_password(stored: &[u8], provided: &[u8]) -> bool {
+    if is_argon2(stored) {
+        let Ok(stored) = std::str::from_utf8(stored) else {
+            return false;
+        };
+        return match PasswordHash::new(stored) {
+            Ok(hash) => Argon2::default().verify_password(provided, &hash).is_ok(),
+            Err(_) => false,
+        };
+    }
+
     let provided_scrambled = xor_password(provided);
-    stored_scrambled == provided_scrambled.as_slice()
+    stored == provided_scrambled.as_slice()
+}
+
+/// Whether a stored password still uses the legacy scheme and should be
+/// rehashed once the plaintext is known
+pub fn needs_rehash(stored: &[u8]) -> bool {
+    !is_argon2(stored)
+}
+
+fn is_argon2(stored: &[u8]) -> bool {
+    stored.starts_with(ARGON2_PREFIX)
 }
 
 #[cfg(test)]
@@ -63,4 +120,15 @@ mod tests {
         assert!(verify_password(&scrambled, password));
         assert!(!verify_password(&scrambled, b"wrongpassword"));
     }
+
+    #[test]
+    fn test_argon2_hash_verifies() {
+        let hash = hash_password(b"mypassword", PasswordScheme::Argon2).unwrap();
+
+        assert!(hash.starts_with(ARGON2_PREFIX));
+        assert!(verify_password(&hash, b"mypassword"));
+        assert!(!verify_password(&hash, b"wrongpassword"));
+        assert!(!needs_rehash(&hash));
+        assert!(needs_rehash(&xor_password(b"mypassword")));
+    }
 }
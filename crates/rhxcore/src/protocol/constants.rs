@@ -3,11 +3,14 @@
 /// Protocol magic bytes: 'TRTP'
 pub const PROTOCOL_MAGIC: [u8; 4] = [b'T', b'R', b'T', b'P'];
 
-/// Protocol version
+/// TRTP protocol version exchanged in the handshake
 pub const PROTOCOL_VERSION: u16 = 1;
 
-/// Server version we report (Hotline 1.9.x compatible)
-/// Version 197 = Hotline 1.9.2
+/// Server version we report in the `Version` field (160) of login replies
+///
+/// Version 197 = Hotline 1.9.2. This is the application version clients use
+/// to decide which features to offer, not the handshake's
+/// [`PROTOCOL_VERSION`]; it is always sent as 2 bytes.
 pub const SERVER_VERSION: u16 = 197;
 
 /// HTXF protocol magic bytes: 'HTXF'
@@ -20,10 +23,14 @@ pub const DEFAULT_PORT: u16 = 5500;
 pub const DEFAULT_TRACKER_PORT: u16 = 5498;
 
 /// Maximum transaction data size (32 KB)
+///
+/// The default limit of [`TransactionCodec`](crate::codec::TransactionCodec)
+/// in both directions: larger frames are refused as soon as their header is
+/// read, and larger transactions aren't encoded.
 pub const MAX_TRANSACTION_SIZE: usize = 32768;
 
-/// Maximum field data size
-pub const MAX_FIELD_SIZE: usize = 32768;
+/// Maximum field data size (the field header's size is a u16)
+pub const MAX_FIELD_SIZE: usize = u16::MAX as usize;
 
 /// Maximum chat message size (8 KB per spec)
 pub const MAX_CHAT_SIZE: usize = 8192;
@@ -39,3 +46,27 @@ pub const MAX_PASSWORD_SIZE: usize = 31;
 
 /// Maximum file path size
 pub const MAX_PATH_SIZE: usize = 2048;
+
+// Clients send their extended login (and expect the server name and banner
+// back) from version 151 on, and read the version as 2 bytes; chat, fields
+// and transactions have to fit inside each other and their size fields
+const _: () = {
+    assert!(SERVER_VERSION >= 151 && SERVER_VERSION <= i16::MAX as u16);
+    assert!(MAX_CHAT_SIZE <= MAX_TRANSACTION_SIZE);
+    assert!(MAX_FIELD_SIZE == u16::MAX as usize);
+    assert!(MAX_TRANSACTION_SIZE <= u32::MAX as usize);
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Handshake;
+
+    #[test]
+    fn test_handshake_matches_advertised_version() {
+        assert_eq!(&PROTOCOL_MAGIC, b"TRTP");
+        assert_eq!(PROTOCOL_VERSION, 1);
+        assert_eq!(Handshake::new().version, PROTOCOL_VERSION);
+        assert_eq!(Handshake::new().protocol_id, PROTOCOL_MAGIC);
+    }
+}
@@ -1,12 +1,18 @@
 //! Field types and structures
 
+use crate::codec::date::{decode_date, encode_date};
+use crate::error::{ProtocolError, Result};
+use crate::password::xor_password;
+use crate::types::AccessPrivileges;
 use bytes::{Buf, BufMut};
+use chrono::{DateTime, Utc};
 
 /// Field identifier
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[repr(u16)]
 pub enum FieldId {
     // Data
+    ErrorText = 100,
     Data = 101,
     UserName = 102,
     UserId = 103,
@@ -45,6 +51,9 @@ pub enum FieldId {
     QuotingMsg = 214,
     AutomaticResponse = 215,
 
+    // Folder transfers
+    FolderItemCount = 220,
+
     // Server info
     ServerAgreement = 151,
     ServerBanner = 152,
@@ -90,6 +99,7 @@ impl FieldId {
     /// Convert from u16
     pub const fn from_u16(value: u16) -> Option<Self> {
         match value {
+            100 => Some(Self::ErrorText),
             101 => Some(Self::Data),
             102 => Some(Self::UserName),
             103 => Some(Self::UserId),
@@ -130,6 +140,7 @@ impl FieldId {
             213 => Some(Self::FileType),
             214 => Some(Self::QuotingMsg),
             215 => Some(Self::AutomaticResponse),
+            220 => Some(Self::FolderItemCount),
             300 => Some(Self::UserNameWithInfo),
             320 => Some(Self::NewsArticleId),
             321 => Some(Self::NewsArticleDataFlavor),
@@ -159,6 +170,34 @@ impl FieldId {
     pub const fn to_u16(self) -> u16 {
         self as u16
     }
+
+    /// Fixed encoded width of integer data for this field, if it has one
+    ///
+    /// Integers are otherwise sent as 2 bytes when they fit and 4 bytes when
+    /// they don't. Fields a peer compares byte for byte, like the reference
+    /// number echoed on transfer connections, always use their full width.
+    /// `Version` is always 2 bytes, since clients read it as a 16-bit value.
+    pub const fn integer_width(self) -> Option<usize> {
+        match self {
+            Self::ReferenceNumber => Some(4),
+            Self::Version => Some(2),
+            _ => None,
+        }
+    }
+
+    /// Whether a transaction may carry this field more than once
+    ///
+    /// List replies repeat one field per entry: `FileNameWithInfo` for file
+    /// lists, `UserNameWithInfo` for user lists, `NewsCategoryListData` for
+    /// news categories, and `Data` for the message board. Every other field
+    /// appears at most once; if a peer repeats one anyway, the first
+    /// occurrence is the one that counts (see `Transaction::get_field`).
+    pub const fn may_repeat(self) -> bool {
+        matches!(
+            self,
+            Self::Data | Self::FileNameWithInfo | Self::UserNameWithInfo | Self::NewsCategoryListData
+        )
+    }
 }
 
 impl From<FieldId> for u16 {
@@ -211,6 +250,16 @@ impl Field {
         }
     }
 
+    /// Create a date field (e.g. FileCreateDate) as an 8-byte date parameter
+    pub fn date(id: FieldId, value: &DateTime<Utc>) -> Self {
+        Self::binary(id, encode_date(value))
+    }
+
+    /// Create a UserAccess field, in the protocol's bit-reversed wire format
+    pub fn from_access(access: AccessPrivileges) -> Self {
+        Self::binary(FieldId::UserAccess, access.to_wire_format())
+    }
+
     /// Get as integer
     pub fn as_integer(&self) -> Option<i32> {
         match &self.data {
@@ -234,6 +283,56 @@ impl Field {
             _ => None,
         }
     }
+
+    /// Get as access privileges, if the data is 8 bytes of wire-format access
+    pub fn access_privileges(&self) -> Option<AccessPrivileges> {
+        let bytes: [u8; 8] = self.as_binary()?.try_into().ok()?;
+        Some(AccessPrivileges::from_wire_format(bytes))
+    }
+
+    /// Get as a date, if the data is a valid date parameter
+    pub fn as_date(&self) -> Option<DateTime<Utc>> {
+        decode_date(self.as_binary()?).ok()
+    }
+
+    /// Get as text, whether it was decoded as a string or kept as binary
+    ///
+    /// String fields that aren't valid UTF-8 (e.g. MacRoman from classic
+    /// clients) are kept as binary by the decoder, so they fail here with
+    /// [`ProtocolError::InvalidUtf8`] naming the field.
+    pub fn as_text(&self) -> Result<&str> {
+        match &self.data {
+            FieldData::String(s) => Ok(s),
+            FieldData::Binary(b) => {
+                std::str::from_utf8(b).map_err(|_| ProtocolError::InvalidUtf8 { field: self.id })
+            }
+            FieldData::Integer(_) => Err(ProtocolError::InvalidFieldData),
+        }
+    }
+
+    /// Get scrambled binary data (logins, passwords) as unscrambled text
+    pub fn as_scrambled_text(&self) -> Result<String> {
+        let data = self.as_binary().ok_or(ProtocolError::InvalidFieldData)?;
+        String::from_utf8(xor_password(data)).map_err(|_| ProtocolError::InvalidUtf8 { field: self.id })
+    }
+
+    /// Size of the field data once encoded (excluding the field header)
+    pub fn encoded_len(&self) -> usize {
+        match &self.data {
+            FieldData::Integer(v) => integer_len(self.id, *v),
+            FieldData::String(s) => s.len(),
+            FieldData::Binary(b) => b.len(),
+        }
+    }
+}
+
+/// Encoded width of an integer value for field `id`
+pub(crate) fn integer_len(id: FieldId, value: i32) -> usize {
+    match id.integer_width() {
+        Some(width) => width,
+        None if value >= i16::MIN as i32 && value <= i16::MAX as i32 => 2,
+        None => 4,
+    }
 }
 
 /// Field header (4 bytes: 2 for ID, 2 for size)
@@ -247,7 +346,7 @@ impl FieldHeader {
     pub const SIZE: usize = 4;
 
     /// Parse from bytes
-    pub fn from_bytes(mut buf: &[u8]) -> Result<Self, std::io::Error> {
+    pub fn from_bytes(mut buf: &[u8]) -> std::result::Result<Self, std::io::Error> {
         if buf.len() < Self::SIZE {
             return Err(std::io::Error::new(
                 std::io::ErrorKind::UnexpectedEof,
@@ -267,3 +366,74 @@ impl FieldHeader {
         buf.put_u16(self.size);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::field_codec::{decode_fields, encode_fields};
+
+    #[test]
+    fn test_scrambled_login_with_invalid_utf8() {
+        // 0xA5 is a MacRoman bullet, not valid UTF-8 on its own
+        let login = Field::binary(FieldId::UserLogin, xor_password(b"caf\xA5"));
+        assert!(matches!(
+            login.as_scrambled_text(),
+            Err(ProtocolError::InvalidUtf8 { field: FieldId::UserLogin })
+        ));
+
+        let login = Field::binary(FieldId::UserLogin, xor_password("café".as_bytes()));
+        assert_eq!(login.as_scrambled_text().unwrap(), "café");
+    }
+
+    #[test]
+    fn test_access_round_trip() {
+        for access in [
+            AccessPrivileges::empty(),
+            AccessPrivileges::guest(),
+            AccessPrivileges::user(),
+            AccessPrivileges::admin(),
+            AccessPrivileges::DELETE_FILES | AccessPrivileges::MOVE_FOLDERS,
+        ] {
+            let field = Field::from_access(access);
+            assert_eq!(field.id, FieldId::UserAccess);
+            assert_eq!(field.as_binary().map(|b| b.len()), Some(8));
+            assert_eq!(field.access_privileges(), Some(access));
+        }
+
+        // Bit 0 is the high bit of the first byte on the wire
+        let field = Field::from_access(AccessPrivileges::DELETE_FILES);
+        assert_eq!(field.as_binary().unwrap()[0], 0x80);
+
+        let short = Field::binary(FieldId::UserAccess, vec![0x80; 4]);
+        assert_eq!(short.access_privileges(), None);
+    }
+
+    #[test]
+    fn test_date_round_trip() {
+        let created: DateTime<Utc> = "2001-09-09T01:46:40.500Z".parse().unwrap();
+        let field = Field::date(FieldId::FileCreateDate, &created);
+        assert_eq!(field.as_binary().map(|b| b.len()), Some(8));
+        assert_eq!(field.as_date(), Some(created));
+
+        // Through the wire encoding too
+        let mut buf = bytes::BytesMut::new();
+        encode_fields(&[field], &mut buf).unwrap();
+        let decoded = decode_fields(&mut buf).unwrap();
+        assert_eq!(decoded[0].id, FieldId::FileCreateDate);
+        assert_eq!(decoded[0].as_date(), Some(created));
+
+        assert_eq!(Field::string(FieldId::FileModifyDate, "yesterday").as_date(), None);
+    }
+
+    #[test]
+    fn test_as_text() {
+        let name = Field::string(FieldId::UserName, "Alice");
+        assert_eq!(name.as_text().unwrap(), "Alice");
+
+        let name = Field::binary(FieldId::UserName, vec![b'A', 0xFF]);
+        let error = name.as_text().unwrap_err();
+        assert!(matches!(error, ProtocolError::InvalidUtf8 { field: FieldId::UserName }));
+        assert_eq!(error.to_string(), "Invalid UTF-8 in field UserName");
+        assert!(error.is_recoverable());
+    }
+}
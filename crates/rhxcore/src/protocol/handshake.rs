@@ -1,6 +1,6 @@
 //! Handshake structures
 
-use super::constants::PROTOCOL_MAGIC;
+use super::constants::{PROTOCOL_MAGIC, PROTOCOL_VERSION};
 use bytes::{Buf, BufMut};
 
 /// Client handshake (12 bytes)
@@ -12,7 +12,7 @@ pub struct Handshake {
     /// Sub-protocol ID (user defined)
     pub sub_protocol_id: u32,
 
-    /// Version (currently 1)
+    /// Version ([`PROTOCOL_VERSION`])
     pub version: u16,
 
     /// Sub-version (user defined)
@@ -27,7 +27,7 @@ impl Handshake {
         Self {
             protocol_id: PROTOCOL_MAGIC,
             sub_protocol_id: 0,
-            version: 1,
+            version: PROTOCOL_VERSION,
             sub_version: 2,
         }
     }
@@ -64,6 +64,14 @@ impl Handshake {
     pub fn is_valid(&self) -> bool {
         self.protocol_id == PROTOCOL_MAGIC
     }
+
+    /// Whether this is the protocol version we speak
+    ///
+    /// This is the TRTP version, not the application version clients and
+    /// servers exchange at login (see [`SERVER_VERSION`](super::SERVER_VERSION)).
+    pub fn is_supported_version(&self) -> bool {
+        self.version == PROTOCOL_VERSION
+    }
 }
 
 impl Default for Handshake {
@@ -136,3 +144,26 @@ impl Default for HandshakeReply {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::BytesMut;
+
+    #[test]
+    fn test_our_handshake_is_accepted() {
+        let mut buf = BytesMut::new();
+        Handshake::new().to_bytes(&mut buf);
+        assert_eq!(buf.len(), Handshake::SIZE);
+        assert_eq!(&buf[..4], b"TRTP");
+
+        let parsed = Handshake::from_bytes(&buf).unwrap();
+        assert!(parsed.is_valid());
+        assert!(parsed.is_supported_version());
+        assert_eq!(parsed.version, PROTOCOL_VERSION);
+
+        let mut other = parsed.clone();
+        other.version = PROTOCOL_VERSION + 1;
+        assert!(!other.is_supported_version());
+    }
+}
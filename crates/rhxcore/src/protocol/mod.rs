@@ -3,11 +3,13 @@
 pub mod constants;
 pub mod field;
 pub mod handshake;
+pub mod privileges;
 pub mod transaction;
 pub mod types;
 
 pub use constants::*;
 pub use field::{Field, FieldData, FieldId};
 pub use handshake::{Handshake, HandshakeReply};
+pub use privileges::required_privilege;
 pub use transaction::{Transaction, TransactionHeader};
 pub use types::{ErrorCode, TransactionType};
@@ -0,0 +1,115 @@
+//! Privileges required by each transaction type
+
+use super::types::TransactionType;
+use crate::types::AccessPrivileges;
+
+/// Privilege a client needs to send a transaction type
+///
+/// This is the single source of truth for transaction authorization: servers
+/// check it before dispatching, and clients can use it to hide actions the
+/// user's access doesn't allow.
+///
+/// Returns `None` for transactions any connected client may send (login,
+/// agreement, keep-alives, listing users and files, ...) and for
+/// server-to-client notifications.
+pub fn required_privilege(transaction_type: TransactionType) -> Option<AccessPrivileges> {
+    use TransactionType::*;
+
+    let privilege = match transaction_type {
+        // Chat and messaging
+        SendChat => AccessPrivileges::SEND_CHAT,
+        SendInstantMsg => AccessPrivileges::SEND_PRIVATE_MESSAGES,
+        InviteNewChat => AccessPrivileges::CREATE_PRIVATE_CHAT,
+        DisconnectUser => AccessPrivileges::DISCONNECT_USERS,
+        UserBroadcast => AccessPrivileges::BROADCAST,
+
+        // Files
+        DownloadFile => AccessPrivileges::DOWNLOAD_FILES,
+        UploadFile => AccessPrivileges::UPLOAD_FILES,
+        DeleteFile => AccessPrivileges::DELETE_FILES,
+        NewFolder => AccessPrivileges::CREATE_FOLDERS,
+        MoveFile => AccessPrivileges::MOVE_FILES,
+        MakeFileAlias => AccessPrivileges::MAKE_ALIASES,
+        DownloadFolder => AccessPrivileges::DOWNLOAD_FOLDERS,
+        UploadFolder => AccessPrivileges::UPLOAD_FOLDERS,
+
+        // Users
+        GetClientInfoText => AccessPrivileges::GET_USER_INFO,
+        NewUser => AccessPrivileges::CREATE_USERS,
+        DeleteUser => AccessPrivileges::DELETE_USERS,
+        GetUser => AccessPrivileges::OPEN_USER,
+        SetUser => AccessPrivileges::MODIFY_USERS,
+
+        // News
+        GetMessages | GetNewsCategoryNameList | GetNewsArticleNameList | GetNewsArticleData => {
+            AccessPrivileges::READ_NEWS
+        }
+        OldPostNews | PostNewsArticle => AccessPrivileges::POST_NEWS,
+        DeleteNewsArticle => AccessPrivileges::DELETE_NEWS,
+        NewNewsFolder => AccessPrivileges::CREATE_NEWS_BUNDLE,
+        NewNewsCategory => AccessPrivileges::CREATE_NEWS_CATEGORY,
+
+        _ => return None,
+    };
+
+    Some(privilege)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_account_management_privileges() {
+        assert_eq!(
+            required_privilege(TransactionType::NewUser),
+            Some(AccessPrivileges::CREATE_USERS)
+        );
+        assert_eq!(
+            required_privilege(TransactionType::DeleteUser),
+            Some(AccessPrivileges::DELETE_USERS)
+        );
+        assert_eq!(
+            required_privilege(TransactionType::GetUser),
+            Some(AccessPrivileges::OPEN_USER)
+        );
+        assert_eq!(
+            required_privilege(TransactionType::SetUser),
+            Some(AccessPrivileges::MODIFY_USERS)
+        );
+    }
+
+    #[test]
+    fn test_file_and_chat_privileges() {
+        assert_eq!(
+            required_privilege(TransactionType::DownloadFile),
+            Some(AccessPrivileges::DOWNLOAD_FILES)
+        );
+        assert_eq!(
+            required_privilege(TransactionType::UploadFile),
+            Some(AccessPrivileges::UPLOAD_FILES)
+        );
+        assert_eq!(
+            required_privilege(TransactionType::SendChat),
+            Some(AccessPrivileges::SEND_CHAT)
+        );
+        assert_eq!(
+            required_privilege(TransactionType::DisconnectUser),
+            Some(AccessPrivileges::DISCONNECT_USERS)
+        );
+    }
+
+    #[test]
+    fn test_unrestricted_transactions() {
+        for transaction_type in [
+            TransactionType::Login,
+            TransactionType::Agreed,
+            TransactionType::Error,
+            TransactionType::KeepConnectionAlive,
+            TransactionType::GetUserNameList,
+            TransactionType::GetFileNameList,
+        ] {
+            assert_eq!(required_privilege(transaction_type), None);
+        }
+    }
+}
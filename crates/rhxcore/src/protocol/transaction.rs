@@ -1,7 +1,8 @@
 //! Transaction types and structures
 
-use super::field::Field;
+use super::field::{Field, FieldHeader, FieldId};
 use super::types::TransactionType;
+use crate::error::ProtocolError;
 use bytes::{Buf, BufMut};
 
 /// A Hotline protocol transaction
@@ -81,14 +82,73 @@ impl Transaction {
     }
 
     /// Get a field by ID
+    ///
+    /// Returns the first occurrence; use [`get_all`](Self::get_all) for
+    /// fields that may repeat (see [`FieldId::may_repeat`]).
     pub fn get_field(&self, id: super::field::FieldId) -> Option<&Field> {
         self.fields.iter().find(|f| f.id == id)
     }
 
+    /// Get every occurrence of a field, in transaction order
+    pub fn get_all(&self, id: FieldId) -> impl Iterator<Item = &Field> {
+        self.fields.iter().filter(move |f| f.id == id)
+    }
+
     /// Check if transaction has a specific field
     pub fn has_field(&self, id: super::field::FieldId) -> bool {
         self.get_field(id).is_some()
     }
+
+    /// Size of the encoded field data (field count, field headers and payloads)
+    pub fn encoded_data_size(&self) -> usize {
+        2 + self
+            .fields
+            .iter()
+            .map(|f| FieldHeader::SIZE + f.encoded_len())
+            .sum::<usize>()
+    }
+
+    /// Check that the transaction is internally consistent
+    ///
+    /// Catches transactions built incorrectly before they go on the wire:
+    /// - error replies may only carry an error message (fields 100 and 101)
+    /// - the field count must fit in the u16 count prefix
+    /// - the encoded data must fit in the u32 size header fields
+    pub fn validate(&self) -> crate::error::Result<()> {
+        if self.is_reply
+            && self.error_code != 0
+            && self
+                .fields
+                .iter()
+                .any(|f| !matches!(f.id, FieldId::ErrorText | FieldId::Data))
+        {
+            return Err(ProtocolError::MalformedTransaction(
+                "error reply carries fields other than an error message",
+            ));
+        }
+
+        if self.fields.len() > u16::MAX as usize {
+            return Err(ProtocolError::MalformedTransaction("more than 65535 fields"));
+        }
+
+        if self.encoded_data_size() > u32::MAX as usize {
+            return Err(ProtocolError::MalformedTransaction("data size overflows header"));
+        }
+
+        Ok(())
+    }
+
+    /// Check that the encoded field data fits within `max_size` bytes
+    pub fn check_size(&self, max_size: usize) -> crate::error::Result<()> {
+        let size = self.encoded_data_size();
+        if size > max_size {
+            return Err(ProtocolError::TransactionTooLarge {
+                size,
+                max: max_size,
+            });
+        }
+        Ok(())
+    }
 }
 
 /// Transaction header structure (20 bytes)
@@ -127,6 +187,22 @@ impl TransactionHeader {
         })
     }
 
+    /// Check that the size fields describe a single-part transaction
+    ///
+    /// The total size should repeat the data size, but some clients leave it
+    /// at 0; either way the frame holds the whole transaction. A larger total
+    /// announces a multipart transaction, which isn't reassembled, and a
+    /// smaller one is inconsistent.
+    pub fn check_single_part(&self) -> Result<(), ProtocolError> {
+        if self.total_size == 0 || self.total_size == self.data_size {
+            Ok(())
+        } else if self.total_size < self.data_size {
+            Err(ProtocolError::MalformedTransaction("total size is smaller than data size"))
+        } else {
+            Err(ProtocolError::MalformedTransaction("multipart transactions are not supported"))
+        }
+    }
+
     /// Encode the transaction header to bytes
     pub fn to_bytes(&self, buf: &mut impl BufMut) {
         buf.put_u8(self.flags);
@@ -138,3 +214,69 @@ impl TransactionHeader {
         buf.put_u32(self.data_size);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::field_codec::decode_fields;
+    use bytes::BytesMut;
+
+    #[test]
+    fn test_valid_transaction_passes() {
+        let mut reply = Transaction::new_reply(TransactionType::Login, 7);
+        reply.add_field(Field::integer(FieldId::Version, 190));
+        assert!(reply.validate().is_ok());
+
+        // Server-initiated requests legitimately use ID 0
+        let notification = Transaction::new(TransactionType::ChatMessage);
+        assert!(notification.validate().is_ok());
+
+        let mut error = Transaction::new_reply(TransactionType::Login, 7);
+        error.error_code = 2;
+        error.add_field(Field::string(FieldId::Data, "Guests are not allowed"));
+        assert!(error.validate().is_ok());
+    }
+
+    #[test]
+    fn test_error_reply_with_user_fields_rejected() {
+        let mut error = Transaction::new_reply(TransactionType::GetUser, 3);
+        error.error_code = 3;
+        error.add_field(Field::string(FieldId::UserName, "Alice"));
+        assert!(error.validate().is_err());
+    }
+
+    #[test]
+    fn test_repeated_fields_all_kept() {
+        let mut buf = BytesMut::new();
+        buf.put_u16(3);
+        for (id, data) in [(101u16, &b"first"[..]), (103, &[0, 5][..]), (101, &b"second"[..])] {
+            buf.put_u16(id);
+            buf.put_u16(data.len() as u16);
+            buf.put_slice(data);
+        }
+
+        let mut transaction = Transaction::new_reply(TransactionType::GetMessages, 1);
+        transaction.fields = decode_fields(&mut buf).unwrap();
+
+        let data: Vec<_> = transaction
+            .get_all(FieldId::Data)
+            .map(|f| f.as_binary().unwrap())
+            .collect();
+        assert_eq!(data, [&b"first"[..], &b"second"[..]]);
+        let first = transaction.get_field(FieldId::Data).and_then(|f| f.as_binary());
+        assert_eq!(first, Some(&b"first"[..]));
+        assert_eq!(transaction.get_all(FieldId::UserId).count(), 1);
+        assert!(FieldId::Data.may_repeat());
+        assert!(!FieldId::UserId.may_repeat());
+    }
+
+    #[test]
+    fn test_too_many_fields_rejected() {
+        let mut transaction = Transaction::new_reply(TransactionType::GetUserNameList, 1);
+        transaction.fields = vec![Field::integer(FieldId::UserId, 1); u16::MAX as usize + 1];
+        assert!(matches!(
+            transaction.validate(),
+            Err(ProtocolError::MalformedTransaction(_))
+        ));
+    }
+}
@@ -152,6 +152,33 @@ impl TransactionType {
     pub const fn to_u16(self) -> u16 {
         self as u16
     }
+
+    /// Whether the transaction changes files, accounts or news stored on
+    /// the server
+    ///
+    /// Chat, messages and changes to the sender's own session (nickname,
+    /// icon, chat subject) don't count as writes.
+    pub const fn is_write(self) -> bool {
+        matches!(
+            self,
+            Self::OldPostNews
+                | Self::UploadFile
+                | Self::DeleteFile
+                | Self::NewFolder
+                | Self::SetFileInfo
+                | Self::MoveFile
+                | Self::MakeFileAlias
+                | Self::UploadFolder
+                | Self::NewUser
+                | Self::DeleteUser
+                | Self::SetUser
+                | Self::DeleteNewsItem
+                | Self::NewNewsFolder
+                | Self::NewNewsCategory
+                | Self::PostNewsArticle
+                | Self::DeleteNewsArticle
+        )
+    }
 }
 
 impl From<TransactionType> for u16 {
@@ -16,4 +16,74 @@ impl ChatRoom {
             users: Vec::new(),
         }
     }
+
+    /// Add a user, returning false if they were already a member
+    pub fn add_member(&mut self, user_id: u16) -> bool {
+        if self.is_member(user_id) {
+            return false;
+        }
+        self.users.push(user_id);
+        true
+    }
+
+    /// Remove a user, returning false if they weren't a member
+    pub fn remove_member(&mut self, user_id: u16) -> bool {
+        let before = self.users.len();
+        self.users.retain(|&id| id != user_id);
+        self.users.len() != before
+    }
+
+    /// Members in the order they joined
+    pub fn members(&self) -> &[u16] {
+        &self.users
+    }
+
+    /// Whether `user_id` is in the room
+    pub fn is_member(&self, user_id: u16) -> bool {
+        self.users.contains(&user_id)
+    }
+
+    /// Set the subject (an empty subject clears it)
+    pub fn set_subject(&mut self, subject: impl Into<String>) {
+        let subject = subject.into();
+        self.subject = if subject.is_empty() { None } else { Some(subject) };
+    }
+
+    /// Whether the last member has left, so the room can be closed
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_and_remove_members() {
+        let mut room = ChatRoom::new(1);
+        assert!(room.is_empty());
+
+        assert!(room.add_member(3));
+        assert!(room.add_member(5));
+        assert!(!room.add_member(3));
+        assert_eq!(room.members(), &[3, 5]);
+
+        assert!(room.remove_member(3));
+        assert!(!room.remove_member(3));
+        assert_eq!(room.members(), &[5]);
+        assert!(!room.is_empty());
+
+        assert!(room.remove_member(5));
+        assert!(room.is_empty());
+    }
+
+    #[test]
+    fn test_subject() {
+        let mut room = ChatRoom::new(1);
+        room.set_subject("Plans");
+        assert_eq!(room.subject.as_deref(), Some("Plans"));
+        room.set_subject("");
+        assert_eq!(room.subject, None);
+    }
 }
@@ -1,13 +1,26 @@
 //! File types
 
+use super::field_slice;
+use crate::codec::DateParam;
+use crate::error::{ProtocolError, Result};
+use crate::protocol::FieldId;
+use chrono::{DateTime, Utc};
 use std::path::PathBuf;
 
+/// Type code used for folders in FileNameWithInfo
+pub const FOLDER_TYPE_CODE: [u8; 4] = *b"fldr";
+
 /// File entry information
-#[derive(Debug, Clone)]
+///
+/// This is the canonical representation of a file or folder. The server's
+/// database rows convert into it, and it converts to and from the wire
+/// `FileNameWithInfo` (field 200) format.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct FileEntry {
     pub name: String,
     pub path: String,
     pub physical_path: PathBuf,
+    /// File size in bytes, or the number of contained items for folders
     pub size: i64,
     pub type_code: Option<[u8; 4]>,
     pub creator_code: Option<[u8; 4]>,
@@ -28,4 +41,515 @@ impl FileEntry {
             is_folder: false,
         }
     }
+
+    /// Encode as FileNameWithInfo field data
+    ///
+    /// FileNameWithInfo format (binary):
+    /// - type: [u8; 4] (`fldr` for folders)
+    /// - creator: [u8; 4]
+    /// - size: u32 (bytes, or item count for folders)
+    /// - reserved: u32
+    /// - name_script: u16
+    /// - name_len: u16
+    /// - name: [u8] (variable length)
+    pub fn to_name_with_info(&self) -> Vec<u8> {
+        let type_code = if self.is_folder {
+            FOLDER_TYPE_CODE
+        } else {
+            self.type_code.unwrap_or([0; 4])
+        };
+        let creator_code = self.creator_code.unwrap_or([0; 4]);
+        let size = self.size.clamp(0, u32::MAX as i64) as u32;
+        let name = self.name.as_bytes();
+
+        let mut data = Vec::with_capacity(20 + name.len());
+        data.extend_from_slice(&type_code);
+        data.extend_from_slice(&creator_code);
+        data.extend_from_slice(&size.to_be_bytes());
+        data.extend_from_slice(&0u32.to_be_bytes());
+        data.extend_from_slice(&0u16.to_be_bytes());
+        data.extend_from_slice(&(name.len() as u16).to_be_bytes());
+        data.extend_from_slice(name);
+        data
+    }
+
+    /// Decode FileNameWithInfo field data
+    ///
+    /// The wire format carries no location, so `parent_path` is used to
+    /// rebuild the entry's virtual path.
+    pub fn from_name_with_info(data: &[u8], parent_path: &str) -> Result<Self> {
+        field_slice(data, 0, 20)?;
+
+        let type_code: [u8; 4] = data[0..4].try_into().unwrap();
+        let creator_code: [u8; 4] = data[4..8].try_into().unwrap();
+        let size = u32::from_be_bytes(data[8..12].try_into().unwrap());
+        let name_len = u16::from_be_bytes(data[18..20].try_into().unwrap()) as usize;
+
+        let name = String::from_utf8(field_slice(data, 20, name_len)?.to_vec())
+            .map_err(|_| ProtocolError::InvalidUtf8 { field: FieldId::FileNameWithInfo })?;
+
+        let is_folder = type_code == FOLDER_TYPE_CODE;
+        let code = |c: [u8; 4]| if c == [0; 4] { None } else { Some(c) };
+
+        let parent = parent_path.trim_end_matches('/');
+        let path = format!("{}/{}", parent, name);
+
+        Ok(Self {
+            name,
+            path,
+            physical_path: PathBuf::new(),
+            size: size as i64,
+            type_code: if is_folder { None } else { code(type_code) },
+            creator_code: code(creator_code),
+            comment: None,
+            is_folder,
+        })
+    }
+}
+
+/// Fork type of a file's data fork in resume data
+pub const DATA_FORK: [u8; 4] = *b"DATA";
+
+/// Fork type of a file's resource fork in resume data
+pub const RESOURCE_FORK: [u8; 4] = *b"MACR";
+
+/// How much of each fork a client already has
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ForkResume {
+    pub fork_type: [u8; 4],
+    pub offset: u32,
+}
+
+/// Resume state for a partial transfer (`FileResumeData`, field 203)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResumeData {
+    pub forks: Vec<ForkResume>,
+}
+
+impl ResumeData {
+    /// Resume a transfer with `offset` bytes of the data fork already present
+    pub fn data_fork(offset: u32) -> Self {
+        Self {
+            forks: vec![ForkResume { fork_type: DATA_FORK, offset }],
+        }
+    }
+
+    /// Offset into the given fork (0 if the fork isn't listed)
+    pub fn offset(&self, fork_type: [u8; 4]) -> u32 {
+        self.forks
+            .iter()
+            .find(|fork| fork.fork_type == fork_type)
+            .map_or(0, |fork| fork.offset)
+    }
+
+    /// Encode as FileResumeData field data
+    ///
+    /// FileResumeData format (binary):
+    /// - format: [u8; 4] (`RFLT`)
+    /// - version: u16 (1)
+    /// - reserved: [u8; 34]
+    /// - fork_count: u16
+    /// - for each fork:
+    ///   - fork_type: [u8; 4] (`DATA` or `MACR`)
+    ///   - offset: u32
+    ///   - reserved: [u8; 8]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(42 + self.forks.len() * 16);
+        data.extend_from_slice(b"RFLT");
+        data.extend_from_slice(&1u16.to_be_bytes());
+        data.extend_from_slice(&[0; 34]);
+        data.extend_from_slice(&(self.forks.len() as u16).to_be_bytes());
+
+        for fork in &self.forks {
+            data.extend_from_slice(&fork.fork_type);
+            data.extend_from_slice(&fork.offset.to_be_bytes());
+            data.extend_from_slice(&[0; 8]);
+        }
+
+        data
+    }
+
+    /// Decode FileResumeData field data
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let header = field_slice(data, 0, 42)?;
+        if &header[0..4] != b"RFLT" {
+            return Err(ProtocolError::InvalidFieldData);
+        }
+
+        let count = u16::from_be_bytes([header[40], header[41]]) as usize;
+        let mut forks = Vec::with_capacity(count.min(4));
+
+        for index in 0..count {
+            let fork = field_slice(data, 42 + index * 16, 16)?;
+            forks.push(ForkResume {
+                fork_type: fork[0..4].try_into().unwrap(),
+                offset: u32::from_be_bytes(fork[4..8].try_into().unwrap()),
+            });
+        }
+
+        Ok(Self { forks })
+    }
+}
+
+/// Fork type of the information fork of a flattened file object
+pub const INFO_FORK: [u8; 4] = *b"INFO";
+
+/// Header of a flattened file object (`FILP`), the form a file takes on a
+/// transfer connection
+///
+/// The header is followed by `fork_count` forks, each a [`ForkHeader`] and
+/// its data: the [`FlatFileInfo`] fork first, then the data fork and,
+/// from Mac clients, a resource fork.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlatFileHeader {
+    pub fork_count: u16,
+}
+
+impl FlatFileHeader {
+    pub const SIZE: usize = 24;
+
+    /// Encode the header
+    ///
+    /// Format (binary):
+    /// - format: [u8; 4] (`FILP`)
+    /// - version: u16 (1)
+    /// - reserved: [u8; 16]
+    /// - fork_count: u16
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut data = [0; Self::SIZE];
+        data[0..4].copy_from_slice(b"FILP");
+        data[4..6].copy_from_slice(&1u16.to_be_bytes());
+        data[22..24].copy_from_slice(&self.fork_count.to_be_bytes());
+        data
+    }
+
+    /// Decode the header
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let header = field_slice(data, 0, Self::SIZE)?;
+        if &header[0..4] != b"FILP" {
+            return Err(ProtocolError::InvalidFieldData);
+        }
+
+        Ok(Self {
+            fork_count: u16::from_be_bytes([header[22], header[23]]),
+        })
+    }
+}
+
+/// Header of one fork of a flattened file object
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ForkHeader {
+    pub fork_type: [u8; 4],
+    pub data_size: u32,
+}
+
+impl ForkHeader {
+    pub const SIZE: usize = 16;
+
+    /// Encode the header
+    ///
+    /// Format (binary):
+    /// - fork_type: [u8; 4] (`INFO`, `DATA` or `MACR`)
+    /// - compression: u32 (0, none)
+    /// - reserved: u32
+    /// - data_size: u32
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut data = [0; Self::SIZE];
+        data[0..4].copy_from_slice(&self.fork_type);
+        data[12..16].copy_from_slice(&self.data_size.to_be_bytes());
+        data
+    }
+
+    /// Decode the header
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let header = field_slice(data, 0, Self::SIZE)?;
+        Ok(Self {
+            fork_type: header[0..4].try_into().unwrap(),
+            data_size: u32::from_be_bytes(header[12..16].try_into().unwrap()),
+        })
+    }
+}
+
+/// Information fork of a flattened file object
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlatFileInfo {
+    pub type_code: [u8; 4],
+    pub creator_code: [u8; 4],
+    pub created: DateTime<Utc>,
+    pub modified: DateTime<Utc>,
+    pub name: String,
+    pub comment: String,
+}
+
+impl FlatFileInfo {
+    /// Size of everything before the name
+    const FIXED_SIZE: usize = 72;
+
+    /// Encode the fork data
+    ///
+    /// Format (binary):
+    /// - platform: [u8; 4] (`AMAC`)
+    /// - type: [u8; 4]
+    /// - creator: [u8; 4]
+    /// - flags: u32
+    /// - platform_flags: u32
+    /// - reserved: [u8; 32]
+    /// - create_date: date (8 bytes)
+    /// - modify_date: date (8 bytes)
+    /// - name_script: u16
+    /// - name_len: u16
+    /// - name: [u8] (variable length, cut to 255 bytes)
+    /// - comment_len: u16
+    /// - comment: [u8] (variable length, cut to 255 bytes)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let name = &self.name.as_bytes()[..self.name.len().min(u8::MAX as usize)];
+        let comment = &self.comment.as_bytes()[..self.comment.len().min(u8::MAX as usize)];
+
+        let mut data = Vec::with_capacity(Self::FIXED_SIZE + name.len() + 2 + comment.len());
+        data.extend_from_slice(b"AMAC");
+        data.extend_from_slice(&self.type_code);
+        data.extend_from_slice(&self.creator_code);
+        data.extend_from_slice(&[0; 40]);
+        DateParam::from_datetime(&self.created).to_bytes(&mut data);
+        DateParam::from_datetime(&self.modified).to_bytes(&mut data);
+        data.extend_from_slice(&0u16.to_be_bytes());
+        data.extend_from_slice(&(name.len() as u16).to_be_bytes());
+        data.extend_from_slice(name);
+        data.extend_from_slice(&(comment.len() as u16).to_be_bytes());
+        data.extend_from_slice(comment);
+        data
+    }
+
+    /// Decode the fork data
+    ///
+    /// Dates out of range decode as the Unix epoch, and a missing comment as
+    /// an empty one.
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let fixed = field_slice(data, 0, Self::FIXED_SIZE)?;
+        let date = |start: usize| {
+            DateParam::from_bytes(&fixed[start..start + DateParam::SIZE])
+                .ok()
+                .and_then(|date| date.to_datetime())
+                .unwrap_or_default()
+        };
+        let text = |bytes: &[u8]| {
+            String::from_utf8(bytes.to_vec()).map_err(|_| ProtocolError::InvalidUtf8 { field: FieldId::FileName })
+        };
+
+        let name_len = u16::from_be_bytes([fixed[70], fixed[71]]) as usize;
+        let name = text(field_slice(data, Self::FIXED_SIZE, name_len)?)?;
+
+        let comment_start = Self::FIXED_SIZE + name_len;
+        let comment = match data.get(comment_start..comment_start + 2) {
+            Some(len) => {
+                let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+                text(field_slice(data, comment_start + 2, len)?)?
+            }
+            None => String::new(),
+        };
+
+        Ok(Self {
+            type_code: fixed[4..8].try_into().unwrap(),
+            creator_code: fixed[8..12].try_into().unwrap(),
+            created: date(52),
+            modified: date(60),
+            name,
+            comment,
+        })
+    }
+}
+
+/// Decode FilePath field data into its path components
+///
+/// FilePath format (binary):
+/// - count: u16
+/// - for each component:
+///   - reserved: u16
+///   - name_len: u8
+///   - name: [u8] (variable length)
+///
+/// Components that would escape the file root (`.`, `..`, or names
+/// containing a path separator) are rejected.
+pub fn decode_file_path(data: &[u8]) -> Result<Vec<String>> {
+    if data.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let count = field_slice(data, 0, 2)?;
+    let count = u16::from_be_bytes([count[0], count[1]]) as usize;
+    let mut components = Vec::with_capacity(count);
+    let mut pos = 2;
+
+    for _ in 0..count {
+        let len = field_slice(data, pos, 3)?[2] as usize;
+        pos += 3;
+
+        let name_bytes = field_slice(data, pos, len)?;
+        pos += len;
+
+        let name = String::from_utf8(name_bytes.to_vec())
+            .map_err(|_| ProtocolError::InvalidUtf8 { field: FieldId::FilePath })?;
+        if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\', ':']) {
+            return Err(ProtocolError::InvalidFieldData);
+        }
+
+        components.push(name);
+    }
+
+    Ok(components)
+}
+
+/// Encode path components as FilePath field data
+pub fn encode_file_path<S: AsRef<str>>(components: &[S]) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(&(components.len() as u16).to_be_bytes());
+
+    for component in components {
+        let name = component.as_ref().as_bytes();
+        let len = name.len().min(u8::MAX as usize);
+        data.extend_from_slice(&0u16.to_be_bytes());
+        data.push(len as u8);
+        data.extend_from_slice(&name[..len]);
+    }
+
+    data
+}
+
+/// Join path components into a virtual path rooted at `/`
+pub fn virtual_path<S: AsRef<str>>(components: &[S]) -> String {
+    let mut path = String::new();
+    for component in components {
+        path.push('/');
+        path.push_str(component.as_ref());
+    }
+
+    if path.is_empty() {
+        path.push('/');
+    }
+
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_name_with_info_roundtrip() {
+        let mut entry = FileEntry::new("notes.txt".to_string(), "/docs/notes.txt".to_string());
+        entry.size = 1024;
+        entry.type_code = Some(*b"TEXT");
+        entry.creator_code = Some(*b"ttxt");
+
+        let wire = entry.to_name_with_info();
+        assert_eq!(&wire[0..4], b"TEXT");
+        assert_eq!(wire.len(), 20 + "notes.txt".len());
+
+        let decoded = FileEntry::from_name_with_info(&wire, "/docs").unwrap();
+        assert_eq!(decoded, entry);
+    }
+
+    #[test]
+    fn test_folder_name_with_info() {
+        let mut entry = FileEntry::new("Uploads".to_string(), "/Uploads".to_string());
+        entry.is_folder = true;
+        entry.size = 3;
+
+        let wire = entry.to_name_with_info();
+        assert_eq!(&wire[0..4], &FOLDER_TYPE_CODE);
+
+        let decoded = FileEntry::from_name_with_info(&wire, "/").unwrap();
+        assert!(decoded.is_folder);
+        assert_eq!(decoded.size, 3);
+        assert_eq!(decoded.path, "/Uploads");
+    }
+
+    #[test]
+    fn test_truncated_name_with_info() {
+        let wire = FileEntry::new("a.txt".to_string(), "/a.txt".to_string()).to_name_with_info();
+        assert!(FileEntry::from_name_with_info(&wire[..wire.len() - 1], "/").is_err());
+    }
+
+    #[test]
+    fn test_resume_data_roundtrip() {
+        let resume = ResumeData {
+            forks: vec![
+                ForkResume { fork_type: DATA_FORK, offset: 4096 },
+                ForkResume { fork_type: RESOURCE_FORK, offset: 12 },
+            ],
+        };
+
+        let wire = resume.to_bytes();
+        assert_eq!(wire.len(), 42 + 2 * 16);
+
+        let decoded = ResumeData::from_bytes(&wire).unwrap();
+        assert_eq!(decoded, resume);
+        assert_eq!(decoded.offset(DATA_FORK), 4096);
+        assert_eq!(ResumeData::data_fork(7).offset(RESOURCE_FORK), 0);
+    }
+
+    #[test]
+    fn test_truncated_resume_data() {
+        let wire = ResumeData::data_fork(100).to_bytes();
+        assert!(matches!(
+            ResumeData::from_bytes(&wire[..wire.len() - 1]),
+            Err(ProtocolError::TruncatedField { .. })
+        ));
+    }
+
+    #[test]
+    fn test_flat_file_roundtrip() {
+        let header = FlatFileHeader { fork_count: 2 };
+        let wire = header.to_bytes();
+        assert_eq!(&wire[0..6], b"FILP\0\x01");
+        assert_eq!(FlatFileHeader::from_bytes(&wire).unwrap(), header);
+        assert!(FlatFileHeader::from_bytes(&ForkHeader { fork_type: INFO_FORK, data_size: 0 }.to_bytes()).is_err());
+
+        let fork = ForkHeader { fork_type: DATA_FORK, data_size: 70_000 };
+        assert_eq!(ForkHeader::from_bytes(&fork.to_bytes()).unwrap(), fork);
+
+        let modified = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let info = FlatFileInfo {
+            type_code: *b"TEXT",
+            creator_code: *b"ttxt",
+            created: DateTime::from_timestamp(1_600_000_000, 0).unwrap(),
+            modified,
+            name: "notes.txt".to_string(),
+            comment: "Meeting notes".to_string(),
+        };
+        let wire = info.to_bytes();
+        assert_eq!(wire.len(), 72 + 9 + 2 + 13);
+        assert_eq!(FlatFileInfo::from_bytes(&wire).unwrap(), info);
+
+        // Some clients leave the comment out altogether
+        let short = FlatFileInfo::from_bytes(&wire[..72 + 9]).unwrap();
+        assert_eq!(short.comment, "");
+        assert_eq!(short.modified, modified);
+        assert!(FlatFileInfo::from_bytes(&wire[..72 + 8]).is_err());
+    }
+
+    #[test]
+    fn test_file_path_roundtrip() {
+        let data = encode_file_path(&["Uploads", "Pictures"]);
+        let components = decode_file_path(&data).unwrap();
+        assert_eq!(components, vec!["Uploads", "Pictures"]);
+        assert_eq!(virtual_path(&components), "/Uploads/Pictures");
+        assert_eq!(virtual_path::<&str>(&[]), "/");
+    }
+
+    #[test]
+    fn test_file_path_oversized_name_len() {
+        let mut data = encode_file_path(&["Uploads"]);
+        data[4] = 200;
+        assert!(matches!(
+            decode_file_path(&data),
+            Err(ProtocolError::TruncatedField { needed: 205, available: 12 })
+        ));
+    }
+
+    #[test]
+    fn test_file_path_rejects_parent_components() {
+        let data = encode_file_path(&["Uploads", ".."]);
+        assert!(decode_file_path(&data).is_err());
+    }
 }
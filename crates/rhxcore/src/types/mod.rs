@@ -7,5 +7,22 @@ pub mod user;
 
 pub use access::AccessPrivileges;
 pub use chat::ChatRoom;
-pub use file::FileEntry;
+pub use file::{FileEntry, FlatFileHeader, FlatFileInfo, ForkHeader, ResumeData};
 pub use user::{User, UserFlags, UserOptions};
+
+use crate::error::{ProtocolError, Result};
+
+/// Borrow `len` bytes at `start` from compound field data
+///
+/// Embedded lengths come from the client, so they are checked against the
+/// bytes actually present instead of being trusted.
+pub(crate) fn field_slice(data: &[u8], start: usize, len: usize) -> Result<&[u8]> {
+    let end = start.checked_add(len).filter(|&end| end <= data.len());
+    match end {
+        Some(end) => Ok(&data[start..end]),
+        None => Err(ProtocolError::TruncatedField {
+            needed: start.saturating_add(len),
+            available: data.len(),
+        }),
+    }
+}
@@ -1,5 +1,9 @@
 //! User types
 
+use super::field_slice;
+use crate::error::{ProtocolError, Result};
+use crate::protocol::FieldId;
+
 /// User information
 #[derive(Debug, Clone)]
 pub struct User {
@@ -18,6 +22,46 @@ impl User {
             name,
         }
     }
+
+    /// Encode as UserNameWithInfo field data
+    ///
+    /// UserNameWithInfo format (binary):
+    /// - user_id: u16
+    /// - icon_id: u16
+    /// - flags: u16
+    /// - name_len: u16
+    /// - name: [u8] (variable length, cut to 65535 bytes)
+    pub fn to_name_with_info(&self) -> Vec<u8> {
+        let name = self.name.as_bytes();
+        let len = name.len().min(u16::MAX as usize);
+
+        let mut data = Vec::with_capacity(8 + len);
+        data.extend_from_slice(&self.id.to_be_bytes());
+        data.extend_from_slice(&self.icon_id.to_be_bytes());
+        data.extend_from_slice(&self.flags.to_be_bytes());
+        data.extend_from_slice(&(len as u16).to_be_bytes());
+        data.extend_from_slice(&name[..len]);
+        data
+    }
+
+    /// Decode UserNameWithInfo field data
+    pub fn from_name_with_info(data: &[u8]) -> Result<Self> {
+        let header = field_slice(data, 0, 8)?;
+        let id = u16::from_be_bytes([header[0], header[1]]);
+        let icon_id = i16::from_be_bytes([header[2], header[3]]);
+        let flags = u16::from_be_bytes([header[4], header[5]]);
+        let name_len = u16::from_be_bytes([header[6], header[7]]) as usize;
+
+        let name = String::from_utf8(field_slice(data, 8, name_len)?.to_vec())
+            .map_err(|_| ProtocolError::InvalidUtf8 { field: FieldId::UserNameWithInfo })?;
+
+        Ok(Self {
+            id,
+            icon_id,
+            flags,
+            name,
+        })
+    }
 }
 
 bitflags::bitflags! {
@@ -67,7 +111,8 @@ impl UserOptions {
     /// Convert UserOptions to UserFlags for broadcasting
     ///
     /// Maps the user's preferences to the corresponding flags that
-    /// other users see in the user list.
+    /// other users see in the user list. `AUTOMATIC_RESPONSE` has no
+    /// user list flag, so it is not mapped.
     pub fn to_user_flags(&self) -> u16 {
         let mut flags = 0u16;
 
@@ -81,3 +126,47 @@ impl UserOptions {
         flags
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ProtocolError;
+
+    #[test]
+    fn test_user_name_with_info_roundtrip() {
+        let mut user = User::new(7, "Alice".to_string());
+        user.icon_id = 128;
+        user.flags = UserFlags::AWAY.bits();
+
+        let wire = user.to_name_with_info();
+        assert_eq!(wire.len(), 8 + 5);
+
+        let decoded = User::from_name_with_info(&wire).unwrap();
+        assert_eq!(decoded.id, 7);
+        assert_eq!(decoded.icon_id, 128);
+        assert_eq!(decoded.flags, UserFlags::AWAY.bits());
+        assert_eq!(decoded.name, "Alice");
+    }
+
+    #[test]
+    fn test_user_name_with_info_oversized_name_len() {
+        let mut wire = User::new(7, "Alice".to_string()).to_name_with_info();
+        wire[6..8].copy_from_slice(&u16::MAX.to_be_bytes());
+
+        match User::from_name_with_info(&wire) {
+            Err(ProtocolError::TruncatedField { needed, available }) => {
+                assert_eq!(needed, 8 + u16::MAX as usize);
+                assert_eq!(available, 13);
+            }
+            other => panic!("expected TruncatedField, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_user_name_with_info_short_header() {
+        assert!(matches!(
+            User::from_name_with_info(&[0, 7, 0]),
+            Err(ProtocolError::TruncatedField { needed: 8, available: 3 })
+        ));
+    }
+}
@@ -0,0 +1,255 @@
+//! Admin HTTP API
+//!
+//! Optional token-authenticated JSON API for managing a running server:
+//!
+//! - `GET /sessions` - connected users
+//! - `POST /kick` - disconnect a user (`{"target": "<user_id|nickname>"}`)
+//! - `GET /accounts` - server accounts
+//! - `POST /accounts` - create an account (`{"login", "password", "access_level"}`)
+//! - `POST /broadcast` - send a server message (`{"message": "..."}`)
+//! - `GET /info` - server summary (name, address, account and file counts, features)
+//! - `GET /metrics` - handler latency histograms in the Prometheus text format
+//!
+//! Every request must carry `Authorization: Bearer <api_token>`.
+
+use crate::console::{execute_command_as, Command, CommandOutput, UserFilter, UserSort};
+use crate::info::collect_server_info;
+use crate::state::ServerState;
+use anyhow::{bail, Context, Result};
+use axum::extract::{Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+/// Who the audit trail records for changes made through this API
+const AUDIT_ACTOR: &str = "admin-http";
+
+#[derive(Debug, Deserialize)]
+struct KickRequest {
+    target: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateAccountRequest {
+    login: String,
+    password: String,
+    access_level: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct BroadcastRequest {
+    message: String,
+}
+
+/// Build the admin API router
+pub fn router(state: Arc<ServerState>) -> Router {
+    Router::new()
+        .route("/sessions", get(list_sessions))
+        .route("/kick", post(kick))
+        .route("/accounts", get(list_accounts).post(create_account))
+        .route("/broadcast", post(broadcast))
+        .route("/info", get(info))
+        .route("/metrics", get(metrics))
+        .layer(middleware::from_fn_with_state(state.clone(), require_token))
+        .with_state(state)
+}
+
+/// Bind the admin API listener and serve it in a background task
+pub async fn spawn(state: Arc<ServerState>) -> Result<JoinHandle<()>> {
+    let config = state.config().admin_http.clone();
+
+    if config.api_token.is_empty() {
+        bail!("admin_http.api_token must be set when the admin HTTP API is enabled");
+    }
+
+    let addr = format!("{}:{}", config.address, config.port);
+    let listener = TcpListener::bind(&addr)
+        .await
+        .context(format!("Failed to bind admin HTTP API to {}", addr))?;
+
+    tracing::info!("Admin HTTP API listening on {}", addr);
+
+    let app = router(state);
+    Ok(tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            tracing::error!("Admin HTTP API error: {}", e);
+        }
+    }))
+}
+
+/// Reject requests without the configured bearer token
+async fn require_token(
+    State(state): State<Arc<ServerState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let config = state.config();
+    let expected = &config.admin_http.api_token;
+
+    let provided = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    let authorized = matches!(
+        provided,
+        Some(token) if !expected.is_empty() && token_matches(token, expected)
+    );
+
+    if !authorized {
+        tracing::warn!("Rejected admin HTTP request to {}", request.uri().path());
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(json!({ "error": "Missing or invalid API token" })),
+        )
+            .into_response();
+    }
+
+    next.run(request).await
+}
+
+/// Compare tokens without short-circuiting on the first mismatch
+fn token_matches(provided: &str, expected: &str) -> bool {
+    let (a, b) = (provided.as_bytes(), expected.as_bytes());
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Convert a command result to a JSON response
+fn command_response(result: Result<CommandOutput>) -> Response {
+    match result {
+        Ok(CommandOutput::Users(users)) => Json(users).into_response(),
+        Ok(CommandOutput::Accounts(accounts)) => Json(accounts).into_response(),
+        Ok(CommandOutput::Message(message)) => Json(json!({ "message": message })).into_response(),
+        Ok(CommandOutput::Empty) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+async fn list_sessions(State(state): State<Arc<ServerState>>) -> Response {
+    let cmd = Command::UserList {
+        sort: UserSort::default(),
+        filter: UserFilter::default(),
+    };
+    command_response(execute_command_as(cmd, state, AUDIT_ACTOR).await)
+}
+
+async fn kick(State(state): State<Arc<ServerState>>, Json(body): Json<KickRequest>) -> Response {
+    command_response(execute_command_as(Command::UserKick { target: body.target }, state, AUDIT_ACTOR).await)
+}
+
+async fn list_accounts(State(state): State<Arc<ServerState>>) -> Response {
+    command_response(execute_command_as(Command::AccountList, state, AUDIT_ACTOR).await)
+}
+
+async fn create_account(
+    State(state): State<Arc<ServerState>>,
+    Json(body): Json<CreateAccountRequest>,
+) -> Response {
+    let cmd = Command::AccountCreate {
+        login: body.login,
+        password: body.password,
+        access_level: body.access_level,
+    };
+    command_response(execute_command_as(cmd, state, AUDIT_ACTOR).await)
+}
+
+async fn broadcast(
+    State(state): State<Arc<ServerState>>,
+    Json(body): Json<BroadcastRequest>,
+) -> Response {
+    command_response(execute_command_as(Command::Broadcast { message: body.message }, state, AUDIT_ACTOR).await)
+}
+
+async fn info(State(state): State<Arc<ServerState>>) -> Response {
+    match collect_server_info(&state.config(), &state.database).await {
+        Ok(info) => Json(info).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+async fn metrics(State(state): State<Arc<ServerState>>) -> Response {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.handler_metrics.render(),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::Session;
+    use crate::test_util::test_state;
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    async fn admin_state() -> Arc<ServerState> {
+        test_state(|config| {
+            config.admin_http.enabled = true;
+            config.admin_http.api_token = "s3cret".to_string();
+        })
+        .await
+    }
+
+    fn sessions_request(authorization: Option<&str>) -> Request {
+        let mut builder = axum::http::Request::builder().uri("/sessions");
+        if let Some(value) = authorization {
+            builder = builder.header(header::AUTHORIZATION, value);
+        }
+        builder.body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_sessions_with_valid_token() {
+        let state = admin_state().await;
+
+        let mut session = Session::new(3, "127.0.0.1:5500".parse().unwrap());
+        session.authenticate_guest("Alice".to_string(), 0);
+        state.register_session(session);
+
+        let response = router(state)
+            .oneshot(sessions_request(Some("Bearer s3cret")))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let sessions: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(sessions[0]["user_id"], 3);
+        assert_eq!(sessions[0]["nickname"], "Alice");
+    }
+
+    #[tokio::test]
+    async fn test_sessions_without_valid_token() {
+        let state = admin_state().await;
+
+        let missing = router(state.clone())
+            .oneshot(sessions_request(None))
+            .await
+            .unwrap();
+        assert_eq!(missing.status(), StatusCode::UNAUTHORIZED);
+
+        let wrong = router(state)
+            .oneshot(sessions_request(Some("Bearer nope")))
+            .await
+            .unwrap();
+        assert_eq!(wrong.status(), StatusCode::UNAUTHORIZED);
+    }
+}
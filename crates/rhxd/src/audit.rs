@@ -0,0 +1,167 @@
+//! Audit trail
+//!
+//! Administrative actions (account changes, disconnects) and, optionally,
+//! chat metadata go through an [`AuditSink`] so operators who must keep a
+//! record can. The server uses [`DatabaseAudit`] when `security.audit_log`
+//! is set and [`NoAudit`] otherwise; other sinks can be swapped in with
+//! [`ServerState::with_audit_sink`].
+//!
+//! Chat is recorded as who spoke and how much, never what was said.
+
+use crate::db::store::StoreFuture;
+use crate::db::Database;
+use crate::state::ServerState;
+use chrono::Utc;
+use rhxcore::types::AccessPrivileges;
+
+/// A change to an account
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountChange {
+    Created { access: AccessPrivileges },
+    AccessChanged { access: AccessPrivileges },
+    PasswordChanged,
+    Deleted,
+}
+
+impl AccountChange {
+    /// Action name as stored in the audit log
+    pub fn action(&self) -> &'static str {
+        match self {
+            Self::Created { .. } => "account_created",
+            Self::AccessChanged { .. } => "access_changed",
+            Self::PasswordChanged => "password_changed",
+            Self::Deleted => "account_deleted",
+        }
+    }
+    
+    fn detail(&self) -> String {
+        match self {
+            Self::Created { access } | Self::AccessChanged { access } => format!("access=0x{:016X}", access.bits()),
+            Self::PasswordChanged | Self::Deleted => String::new(),
+        }
+    }
+}
+
+/// Destination for audit records
+///
+/// `actor` names who acted: `"console"`, `"admin-http"`, or a connected user
+/// as given by [`user_actor`].
+pub trait AuditSink: Send + Sync {
+    /// An account was created, changed or deleted
+    fn account_changed<'a>(&'a self, actor: &'a str, login: &'a str, change: AccountChange) -> StoreFuture<'a, ()>;
+    
+    /// A user was disconnected by someone else
+    fn disconnected<'a>(&'a self, actor: &'a str, user_id: u16, nickname: &'a str) -> StoreFuture<'a, ()>;
+    
+    /// A user sent `length` bytes of public chat (ignored unless overridden)
+    fn chat<'a>(&'a self, _user_id: u16, _nickname: &'a str, _length: usize) -> StoreFuture<'a, ()> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Sink that records nothing
+pub struct NoAudit;
+
+impl AuditSink for NoAudit {
+    fn account_changed<'a>(&'a self, _actor: &'a str, _login: &'a str, _change: AccountChange) -> StoreFuture<'a, ()> {
+        Box::pin(async { Ok(()) })
+    }
+    
+    fn disconnected<'a>(&'a self, _actor: &'a str, _user_id: u16, _nickname: &'a str) -> StoreFuture<'a, ()> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Sink writing to the `audit_log` table
+pub struct DatabaseAudit {
+    db: Database,
+    /// Record chat metadata (`security.audit_chat`)
+    chat: bool,
+}
+
+impl DatabaseAudit {
+    pub fn new(db: Database, chat: bool) -> Self {
+        Self { db, chat }
+    }
+    
+    async fn insert(&self, actor: &str, action: &str, target: &str, detail: &str) -> anyhow::Result<()> {
+        sqlx::query("INSERT INTO audit_log (at, actor, action, target, detail) VALUES (?, ?, ?, ?, ?)")
+            .bind(Utc::now().timestamp())
+            .bind(actor)
+            .bind(action)
+            .bind(target)
+            .bind(detail)
+            .execute(self.db.pool())
+            .await?;
+        Ok(())
+    }
+}
+
+impl AuditSink for DatabaseAudit {
+    fn account_changed<'a>(&'a self, actor: &'a str, login: &'a str, change: AccountChange) -> StoreFuture<'a, ()> {
+        Box::pin(async move { self.insert(actor, change.action(), login, &change.detail()).await })
+    }
+    
+    fn disconnected<'a>(&'a self, actor: &'a str, user_id: u16, nickname: &'a str) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            self.insert(actor, "disconnected", nickname, &format!("user_id={}", user_id)).await
+        })
+    }
+    
+    fn chat<'a>(&'a self, user_id: u16, nickname: &'a str, length: usize) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            if !self.chat {
+                return Ok(());
+            }
+            let actor = format!("user {} ({})", user_id, nickname);
+            self.insert(&actor, "chat", "public", &format!("length={}", length)).await
+        })
+    }
+}
+
+/// Actor name for a connected user, e.g. `user 5 (Alice)`
+pub fn user_actor(state: &ServerState, user_id: u16) -> String {
+    match state.get_session(user_id) {
+        Some(session) => format!("user {} ({})", user_id, session.nickname),
+        None => format!("user {}", user_id),
+    }
+}
+
+/// Wait for an audit write, logging a failure rather than failing the
+/// action being audited
+pub async fn record(write: StoreFuture<'_, ()>) {
+    if let Err(e) = write.await {
+        tracing::error!("Failed to write audit record: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_db_path;
+    
+    #[tokio::test]
+    async fn test_database_audit_rows() {
+        let db_path = test_db_path("audit_rows");
+        let db = Database::new(&db_path).await.unwrap();
+        db.init_schema().await.unwrap();
+        
+        let audit = DatabaseAudit::new(db.clone(), false);
+        let change = AccountChange::AccessChanged { access: AccessPrivileges::DELETE_FILES };
+        audit.account_changed("console", "bob", change).await.unwrap();
+        audit.disconnected("user 1 (Admin)", 7, "Spammer").await.unwrap();
+        
+        // Chat metadata is off
+        audit.chat(7, "Spammer", 12).await.unwrap();
+        
+        let rows: Vec<(String, String, String, String)> =
+            sqlx::query_as("SELECT actor, action, target, detail FROM audit_log ORDER BY id")
+                .fetch_all(db.pool())
+                .await
+                .unwrap();
+        assert_eq!(rows, vec![
+            ("console".into(), "access_changed".into(), "bob".into(), "access=0x0000000000000001".into()),
+            ("user 1 (Admin)".into(), "disconnected".into(), "Spammer".into(), "user_id=7".into()),
+        ]);
+    }
+}
@@ -0,0 +1,117 @@
+//! Chat log
+//!
+//! When `chat.log_path` is set, public chat (and private room chat when
+//! `chat.log_private` is also set) is appended to the log file, one line per
+//! message:
+//!
+//! ```text
+//! <RFC 3339 time>\t<public|room N>\t<user ID>\t<nickname>\t<message>
+//! ```
+//!
+//! Line breaks and tabs in messages are replaced with spaces so every message
+//! stays on its own line.
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Append-only chat log file
+pub struct ChatLog {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl ChatLog {
+    /// Open (or create) the log file in append mode
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open chat log {}", path.display()))?;
+        
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+        })
+    }
+    
+    /// Append one chat message (`chat_id` is `None` for public chat)
+    pub fn record(&self, chat_id: Option<u32>, user_id: u16, nickname: &str, message: &[u8]) {
+        let channel = match chat_id {
+            Some(chat_id) => format!("room {}", chat_id),
+            None => "public".to_string(),
+        };
+        let line = format!(
+            "{}\t{}\t{}\t{}\t{}\n",
+            Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            channel,
+            user_id,
+            single_line(nickname),
+            single_line(&String::from_utf8_lossy(message)),
+        );
+        
+        let mut file = match self.file.lock() {
+            Ok(file) => file,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            tracing::warn!("Failed to write chat log: {}", e);
+        }
+    }
+    
+    /// Make sure everything logged so far is on disk
+    pub fn sync(&self) {
+        let file = match self.file.lock() {
+            Ok(file) => file,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        
+        if let Err(e) = file.sync_data() {
+            tracing::warn!("Failed to sync chat log: {}", e);
+        }
+    }
+    
+    /// The last `count` logged lines, oldest first
+    pub fn tail(&self, count: usize) -> Result<Vec<String>> {
+        let contents = std::fs::read_to_string(&self.path)
+            .with_context(|| format!("Failed to read chat log {}", self.path.display()))?;
+        let lines: Vec<&str> = contents.lines().collect();
+        
+        Ok(lines[lines.len().saturating_sub(count)..]
+            .iter()
+            .map(|line| line.to_string())
+            .collect())
+    }
+}
+
+fn single_line(text: &str) -> String {
+    text.replace(['\r', '\n', '\t'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempPath;
+    
+    #[test]
+    fn test_tail_returns_latest_lines() {
+        let path = TempPath::new("chat_log", "log");
+        let log = ChatLog::open(&path).unwrap();
+        
+        log.record(None, 1, "Alice", b"first");
+        log.record(Some(3), 2, "Bob", b"two\rlines");
+        log.record(None, 1, "Alice", b"third");
+        
+        let tail = log.tail(2).unwrap();
+        assert_eq!(tail.len(), 2);
+        assert!(tail[0].ends_with("\troom 3\t2\tBob\ttwo lines"), "{}", tail[0]);
+        assert!(tail[1].ends_with("\tpublic\t1\tAlice\tthird"), "{}", tail[1]);
+        assert_eq!(log.tail(10).unwrap().len(), 3);
+    }
+}
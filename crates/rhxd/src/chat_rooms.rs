@@ -0,0 +1,182 @@
+//! Private chat rooms
+//!
+//! Tracks which users are in which private chat, and who has been invited
+//! to join. Room IDs start at 1; chat ID 0 (or no chat ID at all) is the
+//! public chat, which has no entry here. A room disappears, along with its
+//! outstanding invitations, once its last member leaves.
+
+use dashmap::{DashMap, DashSet};
+use rhxcore::types::ChatRoom;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Members of each private chat room
+#[derive(Debug)]
+pub struct ChatRooms {
+    rooms: DashMap<u32, ChatRoom>,
+    /// Outstanding invitations as (chat ID, user ID)
+    invitations: DashSet<(u32, u16)>,
+    next_id: AtomicU32,
+}
+
+impl Default for ChatRooms {
+    fn default() -> Self {
+        Self {
+            rooms: DashMap::new(),
+            invitations: DashSet::new(),
+            next_id: AtomicU32::new(1),
+        }
+    }
+}
+
+impl ChatRooms {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Open a room with `owner` as its only member, returning its ID
+    pub fn create(&self, owner: u16) -> u32 {
+        let mut chat_id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        while chat_id == 0 || self.rooms.contains_key(&chat_id) {
+            chat_id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        }
+        let mut room = ChatRoom::new(chat_id);
+        room.add_member(owner);
+        self.rooms.insert(chat_id, room);
+        chat_id
+    }
+    
+    /// Let a user join an existing room (false if there is no such room)
+    pub fn invite(&self, chat_id: u32, user_id: u16) -> bool {
+        if !self.rooms.contains_key(&chat_id) {
+            return false;
+        }
+        self.invitations.insert((chat_id, user_id));
+        true
+    }
+    
+    /// Add an invited user to a room, using up the invitation
+    ///
+    /// False if there is no such room, or the user wasn't invited and isn't
+    /// already a member.
+    pub fn join(&self, chat_id: u32, user_id: u16) -> bool {
+        let Some(mut room) = self.rooms.get_mut(&chat_id) else {
+            return false;
+        };
+        if self.invitations.remove(&(chat_id, user_id)).is_none() && !room.is_member(user_id) {
+            return false;
+        }
+        room.add_member(user_id);
+        true
+    }
+    
+    /// Remove a user from a room, closing it if it is left empty
+    ///
+    /// Returns whether the user was a member.
+    pub fn leave(&self, chat_id: u32, user_id: u16) -> bool {
+        let left = self.rooms
+            .get_mut(&chat_id)
+            .is_some_and(|mut room| room.remove_member(user_id));
+        if self.rooms.remove_if(&chat_id, |_, room| room.is_empty()).is_some() {
+            self.invitations.retain(|&(id, _)| id != chat_id);
+        }
+        left
+    }
+    
+    /// Remove a disconnecting user from every room, and drop their
+    /// invitations
+    ///
+    /// Returns the rooms they left that still have members, so those can
+    /// be told.
+    pub fn leave_all(&self, user_id: u16) -> Vec<u32> {
+        let mut left = Vec::new();
+        self.rooms.retain(|&chat_id, room| {
+            if room.remove_member(user_id) && !room.is_empty() {
+                left.push(chat_id);
+            }
+            !room.is_empty()
+        });
+        self.invitations.retain(|&(chat_id, id)| id != user_id && self.rooms.contains_key(&chat_id));
+        left
+    }
+    
+    /// Whether `user_id` is in room `chat_id`
+    pub fn is_member(&self, chat_id: u32, user_id: u16) -> bool {
+        self.rooms.get(&chat_id).is_some_and(|room| room.is_member(user_id))
+    }
+    
+    /// Members of room `chat_id` (empty if there is no such room)
+    pub fn members(&self, chat_id: u32) -> Vec<u16> {
+        self.rooms.get(&chat_id).map(|room| room.members().to_vec()).unwrap_or_default()
+    }
+    
+    /// Set a room's subject (false if there is no such room)
+    pub fn set_subject(&self, chat_id: u32, subject: &str) -> bool {
+        match self.rooms.get_mut(&chat_id) {
+            Some(mut room) => {
+                room.set_subject(subject);
+                true
+            }
+            None => false,
+        }
+    }
+    
+    /// A room's subject, if it exists and has one
+    pub fn subject(&self, chat_id: u32) -> Option<String> {
+        self.rooms.get(&chat_id).and_then(|room| room.subject.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_membership() {
+        let rooms = ChatRooms::new();
+        let chat_id = rooms.create(1);
+        assert_ne!(chat_id, 0);
+        assert!(rooms.invite(chat_id, 2));
+        assert!(!rooms.invite(chat_id + 1, 2));
+        assert!(rooms.join(chat_id, 2));
+        assert!(!rooms.join(chat_id + 1, 2));
+        assert!(rooms.is_member(chat_id, 1) && rooms.is_member(chat_id, 2));
+        assert!(!rooms.is_member(chat_id, 3));
+        assert_eq!(rooms.members(chat_id), vec![1, 2]);
+        
+        assert!(rooms.set_subject(chat_id, "Plans"));
+        assert_eq!(rooms.subject(chat_id).as_deref(), Some("Plans"));
+        
+        assert!(rooms.leave(chat_id, 1));
+        assert!(!rooms.leave(chat_id, 1));
+        assert!(!rooms.is_member(chat_id, 1));
+        
+        // The last member leaving closes the room
+        assert!(rooms.invite(chat_id, 3));
+        assert!(rooms.leave_all(2).is_empty());
+        assert!(!rooms.join(chat_id, 3));
+        assert!(rooms.members(chat_id).is_empty());
+        assert!(!rooms.set_subject(chat_id, "Gone"));
+    }
+    
+    #[test]
+    fn test_join_needs_invitation() {
+        let rooms = ChatRooms::new();
+        let chat_id = rooms.create(1);
+        
+        assert!(!rooms.join(chat_id, 2));
+        
+        // An invitation is good for one join
+        assert!(rooms.invite(chat_id, 2));
+        assert!(rooms.join(chat_id, 2));
+        assert!(rooms.leave(chat_id, 2));
+        assert!(!rooms.join(chat_id, 2));
+        
+        // Disconnecting drops invitations too
+        rooms.invite(chat_id, 3);
+        rooms.invite(chat_id, 4);
+        rooms.join(chat_id, 4);
+        assert_eq!(rooms.leave_all(3), Vec::<u32>::new());
+        assert!(!rooms.join(chat_id, 3));
+        assert_eq!(rooms.leave_all(4), vec![chat_id]);
+    }
+}
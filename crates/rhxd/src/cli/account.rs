@@ -1,7 +1,10 @@
 //! Account management commands
 
+use crate::cli::open_database;
+use crate::db::bulk::{apply_access_changes, parse_privileges, plan_access_change};
 use anyhow::Result;
 use clap::Subcommand;
+use rhxcore::types::AccessPrivileges;
 
 #[derive(Subcommand)]
 pub enum AccountCommands {
@@ -24,10 +27,68 @@ pub enum AccountCommands {
     Show { login: String },
     /// Change account password
     SetPassword { login: String, new_password: String },
+    /// Grant privileges to accounts
+    Grant {
+        /// Comma-separated privilege names, e.g. UPLOAD_FILES,SEND_CHAT
+        privileges: String,
+        logins: Vec<String>,
+        /// Report what would change without writing to the database
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Revoke privileges from accounts
+    Revoke {
+        /// Comma-separated privilege names, e.g. UPLOAD_FILES,SEND_CHAT
+        privileges: String,
+        logins: Vec<String>,
+        /// Report what would change without writing to the database
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+pub async fn run(config_path: &str, command: AccountCommands) -> Result<()> {
+    match command {
+        AccountCommands::Grant { privileges, logins, dry_run } => {
+            let grant = parse_privileges(&privileges)?;
+            change_access(config_path, &logins, grant, AccessPrivileges::empty(), dry_run).await
+        }
+        AccountCommands::Revoke { privileges, logins, dry_run } => {
+            let revoke = parse_privileges(&privileges)?;
+            change_access(config_path, &logins, AccessPrivileges::empty(), revoke, dry_run).await
+        }
+        _ => {
+            // TODO: Implement account management
+            println!("Account management not yet implemented");
+            Ok(())
+        }
+    }
 }
 
-pub async fn run(_config_path: &str, _command: AccountCommands) -> Result<()> {
-    // TODO: Implement account management
-    println!("Account management not yet implemented");
+/// Apply a bulk grant/revoke, or preview it with `dry_run`
+async fn change_access(
+    config_path: &str,
+    logins: &[String],
+    grant: AccessPrivileges,
+    revoke: AccessPrivileges,
+    dry_run: bool,
+) -> Result<()> {
+    let db = open_database(config_path).await?;
+    let changes = plan_access_change(db.pool(), logins, grant, revoke).await?;
+    
+    for change in &changes {
+        println!("{}", change);
+    }
+    
+    let count = changes.iter().filter(|c| c.is_change()).count();
+    
+    if dry_run {
+        println!("\nDry run: {} of {} accounts would change", count, changes.len());
+        return Ok(());
+    }
+    
+    apply_access_changes(db.pool(), &changes).await?;
+    println!("\nUpdated {} of {} accounts", count, changes.len());
+    
     Ok(())
 }
@@ -1,6 +1,9 @@
 //! Database management commands
 
-use anyhow::Result;
+use crate::cli::open_database;
+use crate::config::Config;
+use crate::db::bulk::{apply_import, check_account_limit, plan_import, AccountImport, ImportAction};
+use anyhow::{Context, Result};
 use clap::Subcommand;
 
 #[derive(Subcommand)]
@@ -13,10 +16,66 @@ pub enum DbCommands {
     Backup { output: String },
     /// Vacuum database (compact)
     Vacuum,
+    /// Import accounts from a JSON file
+    ImportAccounts {
+        file: String,
+        /// Replace existing accounts with the same login
+        #[arg(long)]
+        overwrite: bool,
+        /// Report what would change without writing to the database
+        #[arg(long)]
+        dry_run: bool,
+    },
 }
 
-pub async fn run(_config_path: &str, _command: DbCommands) -> Result<()> {
-    // TODO: Implement database management
-    println!("Database management not yet implemented");
+pub async fn run(config_path: &str, command: DbCommands) -> Result<()> {
+    match command {
+        DbCommands::Migrate => {
+            // Opening the database applies any pending migrations
+            let db = open_database(config_path).await?;
+            println!("Database is at schema version {}", db.schema_version().await?);
+            db.close().await;
+            Ok(())
+        }
+        DbCommands::ImportAccounts { file, overwrite, dry_run } => {
+            import_accounts(config_path, &file, overwrite, dry_run).await
+        }
+        _ => {
+            // TODO: Implement database management
+            println!("Database management not yet implemented");
+            Ok(())
+        }
+    }
+}
+
+/// Import accounts, or preview the import with `dry_run`
+async fn import_accounts(config_path: &str, file: &str, overwrite: bool, dry_run: bool) -> Result<()> {
+    let content = std::fs::read_to_string(file)
+        .with_context(|| format!("Failed to read {}", file))?;
+    let entries: Vec<AccountImport> = serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse {}", file))?;
+    
+    let security = Config::load(config_path)?.security;
+    let db = open_database(config_path).await?;
+    let plan = plan_import(db.pool(), entries, overwrite).await?;
+    
+    for item in &plan {
+        println!("{}", item);
+    }
+    
+    let changes = plan
+        .iter()
+        .filter(|item| !matches!(item.action, ImportAction::Skip { .. }))
+        .count();
+    
+    if dry_run {
+        check_account_limit(db.pool(), &plan, security.max_accounts).await?;
+        println!("\nDry run: {} of {} accounts would change", changes, plan.len());
+        return Ok(());
+    }
+    
+    apply_import(db.pool(), &plan, security.password_scheme, security.max_accounts).await?;
+    println!("\nImported {} of {} accounts", changes, plan.len());
+    
     Ok(())
 }
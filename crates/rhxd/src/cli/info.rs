@@ -1,25 +1,41 @@
 //! Server info command
 
+use crate::db::Database;
+use crate::info::collect_server_info;
 use crate::Config;
-use anyhow::Result;
+use anyhow::{Context, Result};
 
 pub async fn run(config_path: &str) -> Result<()> {
     let config = Config::load(config_path)?;
     
+    // Read-only, so this is safe to run next to a live server
+    let db = Database::open_read_only(&config.database.path)
+        .await
+        .with_context(|| format!("Failed to open database {}", config.database.path.display()))?;
+    let info = collect_server_info(&config, &db).await?;
+    db.close().await;
+    
+    let enabled = |on: bool| if on { "enabled" } else { "disabled" };
+    
     println!("Server Information");
     println!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
-    println!("Name:        {}", config.server.name);
-    println!("Description: {}", config.server.description);
-    println!("Address:     {}:{}", config.server.address, config.server.port);
-    println!("Max clients: {}", config.server.max_connections);
+    println!("Name:        {}", info.name);
+    println!("Description: {}", info.description);
+    println!("Address:     {}", info.bind_address);
+    println!("Max clients: {}", info.max_connections);
     println!();
-    println!("Files root:  {}", config.files.root_path.display());
-    println!("Database:    {}", config.database.path.display());
+    println!("Files root:  {}", info.files_root.display());
+    println!("Database:    {} (schema {})", info.database_path.display(), info.schema_version);
+    println!("Accounts:    {}", info.account_count);
+    println!("Files:       {}", info.file_count);
     println!();
     println!("Features:");
-    println!("  News:          {}", if config.features.enable_news { "enabled" } else { "disabled" });
-    println!("  Private chat:  {}", if config.features.enable_private_chat { "enabled" } else { "disabled" });
-    println!("  File transfers: {}", if config.features.enable_file_transfers { "enabled" } else { "disabled" });
+    println!("  News:           {}", enabled(info.features.news));
+    println!("  Private chat:   {}", enabled(info.features.private_chat));
+    println!("  File transfers: {}", enabled(info.features.file_transfers));
+    println!("  Uploads:        {}", enabled(info.features.uploads));
+    println!("  Downloads:      {}", enabled(info.features.downloads));
+    println!("  Auto-away:      {}", enabled(info.features.auto_away));
     
     Ok(())
 }
@@ -1,17 +1,33 @@
 //! Server initialization command
 
+use crate::db::accounts::{hash_stored_password, list_accounts};
+use crate::db::schema::SCHEMA_VERSION;
+use crate::db::Database;
 use crate::Config;
 use anyhow::{Context, Result};
+use rhxcore::types::AccessPrivileges;
+use std::fmt;
 use std::io::{self, Write};
 use std::path::Path;
 
-pub async fn run(config_path: &str, non_interactive: bool) -> Result<()> {
+pub async fn run(config_path: &str, non_interactive: bool, check: bool) -> Result<()> {
+    if check {
+        let report = check_setup(config_path).await;
+        print!("{}", report);
+        
+        let failed = report.failures();
+        if failed > 0 {
+            anyhow::bail!("{} of {} checks failed", failed, report.checks.len());
+        }
+        return Ok(());
+    }
+    
     println!("Initializing rhxd server...\n");
     
     // Check if config already exists
     if Path::new(config_path).exists() {
         println!("Error: Configuration file already exists: {}", config_path);
-        println!("Remove it first or use a different path.");
+        println!("Remove it first, use a different path, or verify it with --check.");
         return Ok(());
     }
     
@@ -19,10 +35,7 @@ pub async fn run(config_path: &str, non_interactive: bool) -> Result<()> {
     let config = Config::default();
     
     // Create necessary directories
-    std::fs::create_dir_all(&config.files.root_path)?;
-    if let Some(parent) = config.logging.file.parent() {
-        std::fs::create_dir_all(parent)?;
-    }
+    prepare_directories(&config)?;
     
     // Save config
     config.save(config_path)?;
@@ -66,14 +79,14 @@ pub async fn run(config_path: &str, non_interactive: bool) -> Result<()> {
     };
     
     // Create admin account
-    let scrambled_password = rhxcore::password::xor_password(admin_password.as_bytes());
+    let admin_password_hash = hash_stored_password(admin_password.as_bytes(), config.security.password_scheme).await?;
     let admin_access = rhxcore::types::AccessPrivileges::admin().bits() as i64;
     
     sqlx::query(
         "INSERT INTO accounts (login, password, name, icon_id, access_privileges) VALUES (?, ?, ?, ?, ?)"
     )
     .bind(&admin_login)
-    .bind(scrambled_password)
+    .bind(admin_password_hash)
     .bind("Administrator")
     .bind(0)
     .bind(admin_access)
@@ -84,7 +97,7 @@ pub async fn run(config_path: &str, non_interactive: bool) -> Result<()> {
     println!("✓ Admin account created: {}", admin_login);
     
     // Create guest account
-    let guest_password = rhxcore::password::xor_password(b"");
+    let guest_password = hash_stored_password(b"", config.security.password_scheme).await?;
     let guest_access = rhxcore::types::AccessPrivileges::guest().bits() as i64;
     
     sqlx::query(
@@ -121,6 +134,159 @@ pub async fn run(config_path: &str, non_interactive: bool) -> Result<()> {
     Ok(())
 }
 
+/// Create the file root and log directory, and make sure the server will be
+/// able to write to them
+fn prepare_directories(config: &Config) -> Result<()> {
+    let mut dirs = vec![config.files.root_path.as_path()];
+    if let Some(parent) = config.logging.file.parent().filter(|p| !p.as_os_str().is_empty()) {
+        dirs.push(parent);
+    }
+    
+    for dir in dirs {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Cannot create directory {}", dir.display()))?;
+        check_writable(dir)?;
+    }
+    
+    Ok(())
+}
+
+/// Create and remove a probe file in `dir`
+///
+/// Actually writing is the only check that sees everything the OS enforces:
+/// the effective user, ACLs and read-only mounts.
+fn check_writable(dir: &Path) -> Result<()> {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or_default();
+    let probe = dir.join(format!(".rhxd_write_test_{}_{}", std::process::id(), nanos));
+    
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)
+        .with_context(|| format!("Cannot write to {}; check its permissions", dir.display()))?;
+    std::fs::remove_file(&probe)
+        .with_context(|| format!("Cannot remove {} after checking it is writable", probe.display()))?;
+    Ok(())
+}
+
+/// Outcome of one `init --check` step
+#[derive(Debug)]
+pub struct Check {
+    pub name: &'static str,
+    pub passed: bool,
+    pub detail: String,
+}
+
+/// Results of verifying an existing setup with `init --check`
+#[derive(Debug, Default)]
+pub struct CheckReport {
+    pub checks: Vec<Check>,
+}
+
+impl CheckReport {
+    fn record(&mut self, name: &'static str, result: std::result::Result<String, String>) -> bool {
+        let passed = result.is_ok();
+        let detail = result.unwrap_or_else(|e| e);
+        self.checks.push(Check { name, passed, detail });
+        passed
+    }
+    
+    /// Number of failed checks
+    pub fn failures(&self) -> usize {
+        self.checks.iter().filter(|check| !check.passed).count()
+    }
+}
+
+impl fmt::Display for CheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            let mark = if check.passed { "✓" } else { "✗" };
+            writeln!(f, "{} {:<14} {}", mark, check.name, check.detail)?;
+        }
+        Ok(())
+    }
+}
+
+/// Verify an existing setup: the config loads, the file root and database
+/// exist, the schema is current, and there is an admin account
+///
+/// Steps that depend on an earlier failed one are skipped. Nothing is
+/// created or changed.
+pub async fn check_setup(config_path: &str) -> CheckReport {
+    let mut report = CheckReport::default();
+    
+    let config = Config::load(config_path).map_err(|e| format!("{}: {}", config_path, e));
+    let config = match config {
+        Ok(config) => {
+            report.record("Config", Ok(format!("{} loaded", config_path)));
+            config
+        }
+        Err(e) => {
+            report.record("Config", Err(e));
+            return report;
+        }
+    };
+    
+    let root = &config.files.root_path;
+    let root_result = if !root.is_dir() {
+        Err(format!("{} is not a directory", root.display()))
+    } else if let Err(e) = check_writable(root) {
+        Err(format!("{:#}", e))
+    } else {
+        Ok(root.display().to_string())
+    };
+    report.record("Files root", root_result);
+    
+    let db_path = &config.database.path;
+    let db_result = if db_path.is_file() {
+        Ok(db_path.display().to_string())
+    } else {
+        Err(format!("{} does not exist", db_path.display()))
+    };
+    if !report.record("Database", db_result) {
+        return report;
+    }
+    
+    let db = match Database::open_read_only(db_path).await {
+        Ok(db) => db,
+        Err(e) => {
+            report.record("Schema", Err(format!("cannot open database: {}", e)));
+            return report;
+        }
+    };
+    
+    report.record("Schema", match db.schema_version().await {
+        Ok(version) if version == SCHEMA_VERSION => Ok(format!("version {}", version)),
+        Ok(version) => Err(format!(
+            "version {}, expected {} (run `rhxd db migrate`)",
+            version, SCHEMA_VERSION
+        )),
+        Err(e) => Err(format!("cannot read schema version: {}", e)),
+    });
+    
+    report.record("Admin account", match list_accounts(db.pool()).await {
+        Ok(accounts) => {
+            let admins: Vec<_> = accounts
+                .iter()
+                .filter(|account| account.access_privileges().contains(AccessPrivileges::admin()))
+                .map(|account| account.login.as_str())
+                .collect();
+            if admins.is_empty() {
+                Err("no account has full admin access".to_string())
+            } else {
+                Ok(admins.join(", "))
+            }
+        }
+        Err(e) => Err(format!("cannot list accounts: {}", e)),
+    });
+    
+    db.close().await;
+    report
+}
+
 /// Prompt for text input
 fn prompt_input(prompt: &str) -> Result<String> {
     print!("{}", prompt);
@@ -149,3 +315,93 @@ fn prompt_password(prompt: &str) -> Result<String> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::accounts::create_account;
+    use crate::test_util::{test_db_path, TempPath};
+    
+    /// A config file and initialized database, optionally with an admin
+    async fn setup(name: &str, with_admin: bool) -> (TempPath, TempPath) {
+        let config_path = TempPath::new(&format!("init_check_{}", name), "json");
+        let db_path = test_db_path(&format!("init_check_{}", name));
+        
+        let mut config = Config::default();
+        config.database.path = db_path.to_path_buf();
+        config.files.root_path = std::env::temp_dir();
+        config.save(&config_path).unwrap();
+        
+        let db = Database::new(&db_path).await.unwrap();
+        db.init_schema().await.unwrap();
+        create_account(db.pool(), "guest", b"", "Guest", AccessPrivileges::guest()).await.unwrap();
+        if with_admin {
+            create_account(db.pool(), "admin", b"pw", "Admin", AccessPrivileges::admin())
+                .await
+                .unwrap();
+        }
+        db.close().await;
+        
+        (config_path, db_path)
+    }
+    
+    #[tokio::test]
+    async fn test_check_initialized_setup() {
+        let (config_path, _db_path) = setup("ok", true).await;
+        
+        let report = check_setup(&config_path.to_string_lossy()).await;
+        assert_eq!(report.failures(), 0, "{}", report);
+        assert_eq!(report.checks.len(), 5);
+    }
+    
+    #[test]
+    fn test_writable_check_leaves_nothing_behind() {
+        let root = TempPath::new("init_writable", "dir");
+        std::fs::create_dir(&root).unwrap();
+        
+        check_writable(&root).unwrap();
+        let empty = std::fs::read_dir(&root).unwrap().next().is_none();
+        let _ = std::fs::remove_dir_all(&root);
+        assert!(empty, "check left files behind");
+    }
+    
+    #[cfg(unix)]
+    #[test]
+    fn test_read_only_root_reported() {
+        use std::os::unix::fs::PermissionsExt;
+        
+        let root = TempPath::new("init_read_only", "dir");
+        std::fs::create_dir(&root).unwrap();
+        std::fs::set_permissions(&root, std::fs::Permissions::from_mode(0o555)).unwrap();
+        
+        // Root ignores directory permissions, so there's nothing to test
+        if std::fs::write(root.join("probe"), b"").is_ok() {
+            std::fs::set_permissions(&root, std::fs::Permissions::from_mode(0o755)).unwrap();
+            let _ = std::fs::remove_dir_all(&root);
+            return;
+        }
+        
+        let mut config = Config::default();
+        config.files.root_path = root.to_path_buf();
+        config.logging.file = root.join("logs").join("rhxd.log");
+        let result = prepare_directories(&config);
+        
+        // Restore so the directory can be cleaned up
+        std::fs::set_permissions(&root, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let _ = std::fs::remove_dir_all(&root);
+        
+        let error = format!("{:#}", result.unwrap_err());
+        assert!(error.contains("Cannot write to"), "{}", error);
+        assert!(error.contains("ermission denied"), "{}", error);
+    }
+    
+    #[tokio::test]
+    async fn test_check_flags_missing_admin() {
+        let (config_path, _db_path) = setup("no_admin", false).await;
+        
+        let report = check_setup(&config_path.to_string_lossy()).await;
+        assert_eq!(report.failures(), 1, "{}", report);
+        let admin = report.checks.iter().find(|check| check.name == "Admin account").unwrap();
+        assert!(!admin.passed);
+    }
+}
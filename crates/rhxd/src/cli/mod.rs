@@ -1,7 +1,19 @@
 //! CLI command handlers
 
+use crate::db::Database;
+use crate::config::Config;
+use anyhow::Result;
+
 pub mod init;
 pub mod serve;
 pub mod account;
 pub mod db;
 pub mod info;
+
+/// Open the server database named in the configuration file
+pub async fn open_database(config_path: &str) -> Result<Database> {
+    let config = Config::load(config_path)?;
+    let db = Database::open(&config.database).await?;
+    db.init_schema().await?;
+    Ok(db)
+}
@@ -1,26 +1,37 @@
 //! Server serve command
 
-use crate::console;
+use crate::console::{self, ConsoleExit};
 use crate::{Config, Server};
-use anyhow::Result;
+use anyhow::{bail, Result};
+use std::io::IsTerminal;
+use std::path::Path;
+use tokio::sync::Notify;
 
-pub async fn run(config_path: &str) -> Result<()> {
+pub async fn run(config_path: &str, use_defaults: bool, no_console: bool) -> Result<()> {
     // Load configuration
-    let config = Config::load(config_path)?;
+    let config = load_config(config_path, use_defaults)?;
     
     tracing::info!("Starting rhxd server");
     tracing::info!("Server name: {}", config.server.name);
     tracing::info!("Listening on: {}:{}", config.server.address, config.server.port);
     
+    let exit_on_eof = config.console.exit_on_eof;
+    
     // Create server
     let server = Server::new(config).await?;
     
+    // Without a console, shutdown is driven by signals alone
+    if !should_run_console(no_console, std::io::stdin().is_terminal(), exit_on_eof) {
+        tracing::info!("Interactive console disabled; send SIGINT (Ctrl+C) or SIGTERM to stop");
+        return server.run().await;
+    }
+    
     // Get state and shutdown handle for console
     let state = server.state();
     let shutdown = server.shutdown_handle();
     
     // Spawn server in background task
-    let server_handle = tokio::spawn(async move {
+    let mut server_handle = tokio::spawn(async move {
         if let Err(e) = server.run().await {
             tracing::error!("Server error: {}", e);
         }
@@ -28,22 +39,158 @@ pub async fn run(config_path: &str) -> Result<()> {
     
     // Run console in main task
     let console_handle = tokio::spawn(async move {
-        if let Err(e) = console::run_console(state).await {
-            tracing::error!("Console error: {}", e);
-        }
-        // When console exits, trigger server shutdown
-        shutdown.notify_waiters();
+        let exit = console::run_console(state).await;
+        finish_console(exit, exit_on_eof, &shutdown);
     });
     
-    // Wait for both tasks
+    // Wait for both tasks; a console exit still lets the server finish
+    // shutting down, so the database is closed before the process exits
     tokio::select! {
-        _ = server_handle => {
+        _ = &mut server_handle => {
             tracing::info!("Server task completed");
         }
         _ = console_handle => {
             tracing::info!("Console task completed");
+            let _ = server_handle.await;
         }
     }
     
     Ok(())
 }
+
+/// Whether to run the interactive console alongside the server
+///
+/// With `console.exit_on_eof` on, the console treats EOF on stdin as a
+/// shutdown request, so it is skipped when stdin isn't a terminal (systemd,
+/// containers, redirected input). With it off, piped input is read as a
+/// script of commands.
+fn should_run_console(no_console: bool, stdin_is_terminal: bool, exit_on_eof: bool) -> bool {
+    !no_console && (stdin_is_terminal || !exit_on_eof)
+}
+
+/// Shut the server down once the console is done, unless its input just
+/// ran out and `console.exit_on_eof` is off
+fn finish_console(exit: Result<ConsoleExit>, exit_on_eof: bool, shutdown: &Notify) {
+    match exit {
+        Ok(ConsoleExit::Eof) if !exit_on_eof => {
+            tracing::info!("Console input ended; send SIGINT (Ctrl+C) or SIGTERM to stop");
+            return;
+        }
+        Ok(_) => {}
+        Err(e) => tracing::error!("Console error: {}", e),
+    }
+    shutdown.notify_waiters();
+}
+
+/// Load the server configuration, explaining what to do if it is missing
+fn load_config(config_path: &str, use_defaults: bool) -> Result<Config> {
+    if Path::new(config_path).exists() {
+        return Config::load(config_path);
+    }
+    
+    if use_defaults {
+        tracing::warn!(
+            "Configuration file not found: {}; using default configuration",
+            config_path
+        );
+        return Ok(Config::default());
+    }
+    
+    bail!(
+        "Configuration file not found: {}\n\
+         Run `rhxd init` first, specify a config file with --config, \
+         or pass --use-defaults to run with the default configuration",
+        config_path
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{test_config, TempPath};
+    use std::time::Duration;
+    use tokio::io::{AsyncWriteExt, BufReader};
+    
+    #[test]
+    fn test_missing_config_gives_guidance() {
+        let path = TempPath::new("serve_missing_config", "json");
+        
+        let err = load_config(&path.to_string_lossy(), false).unwrap_err();
+        let message = err.to_string();
+        
+        assert!(message.contains("Configuration file not found"));
+        assert!(message.contains("rhxd init"));
+        assert!(message.contains("--config"));
+        assert!(err.downcast_ref::<std::io::Error>().is_none());
+    }
+    
+    #[test]
+    fn test_missing_config_with_use_defaults() {
+        let path = TempPath::new("serve_missing_config_defaults", "json");
+        
+        let config = load_config(&path.to_string_lossy(), true).unwrap();
+        assert_eq!(config.server.port, Config::default().server.port);
+    }
+    
+    #[test]
+    fn test_unknown_disabled_transaction_rejected() {
+        let path = TempPath::new("serve_unknown_disabled", "json");
+        let mut config = Config::default();
+        config.features.disabled_transactions = vec!["DeleteUser".into(), "DeleteUsers".into()];
+        config.save(&path).unwrap();
+        
+        let err = load_config(&path.to_string_lossy(), false).unwrap_err();
+        assert!(err.to_string().contains("\"DeleteUsers\""));
+        
+        config.features.disabled_transactions = vec![" DeleteUser ".into(), "UploadFile".into()];
+        config.save(&path).unwrap();
+        load_config(&path.to_string_lossy(), false).unwrap();
+    }
+    
+    #[test]
+    fn test_console_disabled_without_terminal() {
+        // With the console disabled, stdin is never read, so closing it
+        // can't trigger a shutdown
+        assert!(!should_run_console(true, true, true));
+        assert!(!should_run_console(false, false, true));
+        assert!(!should_run_console(true, false, true));
+        assert!(should_run_console(false, true, true));
+        
+        // Piped scripts are read when EOF doesn't stop the server
+        assert!(should_run_console(false, false, false));
+        assert!(!should_run_console(true, false, false));
+    }
+    
+    #[tokio::test]
+    async fn test_server_keeps_running_after_piped_script() {
+        let mut config = test_config();
+        config.server.address = "127.0.0.1".to_string();
+        config.server.port = 0;
+        config.console.exit_on_eof = false;
+        
+        let server = Server::new(config).await.unwrap();
+        let state = server.state();
+        let shutdown = server.shutdown_handle();
+        let server_handle = tokio::spawn(server.run());
+        
+        // Feed a script through a pipe and close it
+        let (mut writer, reader) = tokio::io::duplex(1024);
+        writer.write_all(b"broadcast Maintenance at noon\nuser list\n").await.unwrap();
+        drop(writer);
+        
+        let exit = console::run_commands(state.clone(), BufReader::new(reader)).await;
+        assert_eq!(exit.as_ref().ok(), Some(&ConsoleExit::Eof));
+        finish_console(exit, false, &shutdown);
+        
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!server_handle.is_finished());
+        state.database.health_check().await.unwrap();
+        
+        shutdown.notify_waiters();
+        tokio::time::timeout(Duration::from_secs(5), server_handle)
+            .await
+            .expect("Server did not shut down")
+            .unwrap()
+            .unwrap();
+    }
+}
@@ -1,6 +1,12 @@
 //! Configuration management
 
+use crate::db::bulk::parse_access;
+use anyhow::Context;
+use rhxcore::password::PasswordScheme;
+use rhxcore::protocol::TransactionType;
+use rhxcore::types::AccessPrivileges;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -11,6 +17,18 @@ pub struct Config {
     pub logging: LoggingConfig,
     pub security: SecurityConfig,
     pub features: FeaturesConfig,
+    #[serde(default)]
+    pub chat: ChatConfig,
+    #[serde(default)]
+    pub banner: BannerConfig,
+    #[serde(default)]
+    pub admin_http: AdminHttpConfig,
+    #[serde(default)]
+    pub console: ConsoleConfig,
+    #[serde(default)]
+    pub tracker: TrackerConfig,
+    #[serde(default)]
+    pub debug: DebugConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,6 +38,45 @@ pub struct ServerConfig {
     pub address: String,
     pub port: u16,
     pub max_connections: usize,
+    /// Connection slots kept free for clients still handshaking or logging in
+    ///
+    /// Authenticated users may fill at most `max_connections` minus this many
+    /// slots, so new clients can always connect far enough to be told the
+    /// server is full.
+    #[serde(default)]
+    pub reserved_handshake_slots: usize,
+    /// Maximum number of pending connections queued by the listener
+    #[serde(default = "default_listen_backlog")]
+    pub listen_backlog: u32,
+    /// Disable Nagle's algorithm on client connections
+    #[serde(default = "default_tcp_nodelay")]
+    pub tcp_nodelay: bool,
+    /// Idle time before TCP keepalive probes are sent (disabled when unset)
+    #[serde(default)]
+    pub tcp_keepalive_secs: Option<u64>,
+    /// Reason sent to connected clients when the server shuts down
+    #[serde(default = "default_shutdown_message")]
+    pub shutdown_message: String,
+    /// Append the logged in and maximum user counts to the advertised server
+    /// name, e.g. "My Server (3/100)"
+    #[serde(default)]
+    pub name_shows_user_count: bool,
+    /// Refuse every transaction that changes files, accounts or news, even
+    /// for administrators; browsing, downloads and chat still work
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+fn default_listen_backlog() -> u32 {
+    1024
+}
+
+fn default_tcp_nodelay() -> bool {
+    true
+}
+
+fn default_shutdown_message() -> String {
+    "The server is shutting down".to_string()
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -28,11 +85,126 @@ pub struct FilesConfig {
     pub max_download_size: u64,
     pub enable_uploads: bool,
     pub enable_downloads: bool,
+    /// Maximum number of folders a path may nest below the file root
+    #[serde(default = "default_max_path_depth")]
+    pub max_path_depth: usize,
+    /// Maximum length of a single file or folder name, in bytes
+    #[serde(default = "default_max_file_name_length")]
+    pub max_file_name_length: usize,
+    /// Maximum number of transfers running at once; the rest wait in a queue
+    /// (0 for no limit)
+    #[serde(default = "default_max_concurrent_transfers")]
+    pub max_concurrent_transfers: usize,
+    /// Maximum number of transfers one user may run at once (0 for no limit)
+    #[serde(default = "default_max_transfers_per_user")]
+    pub max_transfers_per_user: usize,
+    /// How long a client gets to connect for an accepted transfer, and how
+    /// long a running transfer may stall, in seconds
+    #[serde(default = "default_transfer_timeout_seconds")]
+    pub transfer_timeout_seconds: u64,
+    /// Extensions uploads must have, e.g. `["txt", "sit"]` (any when empty)
+    #[serde(default)]
+    pub upload_allowed_extensions: Vec<String>,
+    /// Extensions uploads may not have, e.g. `["exe", "scr"]`
+    #[serde(default)]
+    pub upload_blocked_extensions: Vec<String>,
+    /// Let users with `UPLOAD_ANYWHERE` upload any extension
+    #[serde(default)]
+    pub upload_anywhere_ignores_extensions: bool,
+}
+
+impl FilesConfig {
+    /// Whether the extension lists permit uploading a file called `name`
+    ///
+    /// Extensions are compared case-insensitively, with or without a leading
+    /// dot in the configured entries. A name without an extension only passes
+    /// when there is no allowlist.
+    pub fn upload_extension_allowed(&self, name: &str) -> bool {
+        let extension = match name.rsplit_once('.') {
+            Some((stem, extension)) if !stem.is_empty() => extension,
+            _ => "",
+        };
+        let listed = |list: &[String]| {
+            list.iter()
+                .any(|entry| entry.trim().trim_start_matches('.').eq_ignore_ascii_case(extension))
+        };
+
+        if !extension.is_empty() && listed(&self.upload_blocked_extensions) {
+            return false;
+        }
+
+        self.upload_allowed_extensions.is_empty()
+            || (!extension.is_empty() && listed(&self.upload_allowed_extensions))
+    }
+}
+
+fn default_max_path_depth() -> usize {
+    32
+}
+
+fn default_max_file_name_length() -> usize {
+    255
+}
+
+fn default_max_concurrent_transfers() -> usize {
+    10
+}
+
+fn default_max_transfers_per_user() -> usize {
+    2
+}
+
+fn default_transfer_timeout_seconds() -> u64 {
+    60
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DatabaseConfig {
     pub path: PathBuf,
+    /// Connections in the pool
+    #[serde(default = "default_db_max_connections")]
+    pub max_connections: u32,
+    /// How long a request waits for a free connection before failing with
+    /// an error reply
+    #[serde(default = "default_db_acquire_timeout_ms")]
+    pub acquire_timeout_ms: u64,
+    /// How long a write waits on another connection's lock before failing
+    /// with "database is locked"
+    #[serde(default = "default_db_busy_timeout_ms")]
+    pub busy_timeout_ms: u64,
+    /// How often to checkpoint and truncate the write-ahead log (0 disables
+    /// checkpointing beyond SQLite's own)
+    #[serde(default = "default_db_checkpoint_interval_seconds")]
+    pub checkpoint_interval_seconds: u64,
+}
+
+impl DatabaseConfig {
+    /// Default settings for the database at `path`
+    pub fn with_path(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            max_connections: default_db_max_connections(),
+            acquire_timeout_ms: default_db_acquire_timeout_ms(),
+            busy_timeout_ms: default_db_busy_timeout_ms(),
+            checkpoint_interval_seconds: default_db_checkpoint_interval_seconds(),
+        }
+    }
+}
+
+fn default_db_max_connections() -> u32 {
+    32
+}
+
+fn default_db_acquire_timeout_ms() -> u64 {
+    5000
+}
+
+fn default_db_busy_timeout_ms() -> u64 {
+    5000
+}
+
+fn default_db_checkpoint_interval_seconds() -> u64 {
+    600
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -46,6 +218,63 @@ pub struct SecurityConfig {
     pub require_login: bool,
     pub allow_guest: bool,
     pub ban_list_path: PathBuf,
+    /// Privileges required per transaction type, overriding the built-in table
+    /// (e.g. `"DownloadFile": ["DOWNLOAD_FILES"]`; an empty list allows everyone)
+    #[serde(default)]
+    pub transaction_privileges: BTreeMap<String, Vec<String>>,
+    /// Reason sent to clients attempting a guest login when guests are disabled
+    #[serde(default = "default_guest_denied_message")]
+    pub guest_denied_message: String,
+    /// How passwords are stored: `legacy` or `argon2` (legacy passwords are
+    /// rehashed on their next successful login)
+    #[serde(default)]
+    pub password_scheme: PasswordScheme,
+    /// Failed logins for one login from one IP before further attempts are
+    /// refused (0, the default, disables lockout)
+    #[serde(default)]
+    pub max_failed_logins: u32,
+    /// How long a lockout lasts
+    #[serde(default = "default_lockout_seconds")]
+    pub lockout_seconds: u64,
+    /// Accounts the server will hold before refusing to create more
+    /// (0 for no limit)
+    #[serde(default)]
+    pub max_accounts: u64,
+    /// Record account changes and disconnects in the database `audit_log`
+    #[serde(default)]
+    pub audit_log: bool,
+    /// Also record who sent public chat and how long it was (never the text)
+    #[serde(default)]
+    pub audit_chat: bool,
+    /// Access for guest logins: a preset name (`guest`, `user`, ...) or raw
+    /// bits such as `0x...` (the `guest` preset when unset)
+    #[serde(default)]
+    pub guest_access: Option<String>,
+}
+
+impl SecurityConfig {
+    /// Access privileges guests receive
+    ///
+    /// An unparseable `guest_access` falls back to the `guest` preset;
+    /// [`Config::load`] refuses such a value up front.
+    pub fn guest_access(&self) -> AccessPrivileges {
+        match self.guest_access.as_deref().map(parse_access) {
+            Some(Ok(access)) => access,
+            Some(Err(e)) => {
+                tracing::warn!("{}; using the guest preset", e);
+                AccessPrivileges::guest()
+            }
+            None => AccessPrivileges::guest(),
+        }
+    }
+}
+
+fn default_lockout_seconds() -> u64 {
+    300
+}
+
+fn default_guest_denied_message() -> String {
+    "Guest access is disabled on this server".to_string()
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -53,13 +282,269 @@ pub struct FeaturesConfig {
     pub enable_news: bool,
     pub enable_private_chat: bool,
     pub enable_file_transfers: bool,
+    /// Idle time after which users are marked away (disabled when unset)
+    #[serde(default)]
+    pub auto_away_seconds: Option<u64>,
+    /// Idle time after which users are disconnected (disabled when unset)
+    #[serde(default)]
+    pub idle_timeout_seconds: Option<u64>,
+    /// Idle time at which users are warned of the coming disconnect; must be
+    /// below `idle_timeout_seconds` (no warning when unset)
+    #[serde(default)]
+    pub idle_warning_seconds: Option<u64>,
+    /// Window over which user list joins/changes/leaves are coalesced into
+    /// one notification (sent individually when unset)
+    #[serde(default)]
+    pub user_list_batch_ms: Option<u64>,
+    /// Nicknames and logins only users with `ANY_NAME` may take
+    /// (compared case-insensitively, e.g. `["Admin", "Server"]`)
+    #[serde(default)]
+    pub reserved_nicknames: Vec<String>,
+    /// Messages the broadcast channel holds for subscribers that fall behind
+    /// (defaults to 8 per allowed connection, at least 256)
+    ///
+    /// Every slot is kept in memory for as long as the slowest subscriber
+    /// hasn't read it, so a larger buffer costs memory. A subscriber that
+    /// falls more than this many messages behind skips the oldest ones and
+    /// misses those user list updates. Read at startup only.
+    #[serde(default)]
+    pub broadcast_buffer: Option<usize>,
+    /// What to do with a connection that falls further behind than
+    /// `broadcast_buffer`
+    #[serde(default)]
+    pub broadcast_lag_policy: LagPolicy,
+    /// Transaction types refused to everyone, whatever their privileges
+    /// (e.g. `["NewUser", "DeleteUser", "UploadFile"]`); [`Config::load`]
+    /// refuses names that aren't transaction types
+    #[serde(default)]
+    pub disabled_transactions: Vec<String>,
+    /// Reply to KeepConnectionAlive with the server time, for latency
+    /// monitoring (off by default, as some clients don't expect a reply)
+    #[serde(default)]
+    pub keepalive_reply: bool,
+    /// Order of the user list sent in reply to GetUserNameList
+    #[serde(default)]
+    pub user_list_order: UserListOrder,
+    /// Skip the agreement for clients that log in with a version below 151,
+    /// which predate it; they're let in as though they had agreed
+    #[serde(default)]
+    pub skip_agreement_for_old_clients: bool,
+    /// Handshake sub-protocol IDs (4 characters, e.g. `["BOTS"]`) whose
+    /// clients are let in without the agreement
+    #[serde(default)]
+    pub skip_agreement_sub_protocols: Vec<String>,
+}
+
+/// Order of users in user list replies (ties broken by user ID)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UserListOrder {
+    /// By user ID, i.e. roughly by when they connected
+    #[default]
+    Id,
+    /// By nickname, ignoring case
+    Name,
+}
+
+/// How a connection recovers from missing broadcasts it fell behind on
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LagPolicy {
+    /// Skip what was missed and resend the user list and chat subject
+    #[default]
+    Resync,
+    /// Disconnect the client, telling it why
+    Disconnect,
+}
+
+impl FeaturesConfig {
+    /// Whether `name` is on the reserved list
+    pub fn is_reserved_name(&self, name: &str) -> bool {
+        let name = name.trim();
+        self.reserved_nicknames
+            .iter()
+            .any(|reserved| reserved.trim().eq_ignore_ascii_case(name))
+    }
+
+    /// Whether `transaction_type` is on the disabled list
+    pub fn is_transaction_disabled(&self, transaction_type: TransactionType) -> bool {
+        let name = format!("{:?}", transaction_type);
+        self.disabled_transactions.iter().any(|disabled| disabled.trim() == name)
+    }
+
+    /// The switched-off feature a transaction type belongs to, if any, named
+    /// for the error sent back
+    ///
+    /// `enable_file_transfers` gates file and folder transfers and
+    /// `enable_news` the news transactions.
+    pub fn disabled_feature(&self, transaction_type: TransactionType) -> Option<&'static str> {
+        use TransactionType::*;
+
+        match transaction_type {
+            DownloadFile | UploadFile | DownloadFolder | UploadFolder | DownloadBanner
+                if !self.enable_file_transfers =>
+            {
+                Some("File transfers")
+            }
+            OldPostNews | GetNewsCategoryNameList | GetNewsArticleNameList | DeleteNewsItem
+            | NewNewsFolder | NewNewsCategory | GetNewsArticleData | PostNewsArticle
+            | DeleteNewsArticle
+                if !self.enable_news =>
+            {
+                Some("News")
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether a client logging in with this version and handshake
+    /// sub-protocol goes without the agreement
+    pub fn skips_agreement(&self, client_version: Option<u16>, sub_protocol_id: u32) -> bool {
+        let old_client = matches!(client_version, Some(version) if version < 151);
+        let sub_protocol = sub_protocol_id.to_be_bytes();
+        (self.skip_agreement_for_old_clients && old_client)
+            || self
+                .skip_agreement_sub_protocols
+                .iter()
+                .any(|id| id.as_bytes() == sub_protocol)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdminHttpConfig {
+    pub enabled: bool,
+    pub address: String,
+    pub port: u16,
+    /// Token clients must send as `Authorization: Bearer <token>`
+    pub api_token: String,
+}
+
+impl Default for AdminHttpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: "127.0.0.1".to_string(),
+            port: 5580,
+            api_token: String::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConsoleConfig {
+    /// Also accept console commands on this Unix domain socket (disabled
+    /// when unset; Unix only)
+    #[serde(default)]
+    pub socket_path: Option<PathBuf>,
+    /// Shut the server down when console input ends; turn off to pipe in a
+    /// script of commands and leave the server running until signalled
+    #[serde(default = "default_exit_on_eof")]
+    pub exit_on_eof: bool,
+}
+
+fn default_exit_on_eof() -> bool {
+    true
+}
+
+impl Default for ConsoleConfig {
+    fn default() -> Self {
+        Self {
+            socket_path: None,
+            exit_on_eof: default_exit_on_eof(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrackerConfig {
+    /// Trackers to announce to, as `host:port` (tracker port is usually 5499)
+    pub trackers: Vec<String>,
+    /// Seconds between announcements
+    pub interval_seconds: u64,
+    /// Whether guests count towards the announced user count
+    pub count_guests: bool,
+}
+
+impl Default for TrackerConfig {
+    fn default() -> Self {
+        Self {
+            trackers: Vec::new(),
+            interval_seconds: 300,
+            count_guests: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChatConfig {
+    /// Prefix that marks a chat line from a privileged user as a server command
+    /// (empty disables chat commands)
+    pub command_prefix: String,
+    /// Append chat to this file (disabled when unset); read back with the
+    /// `chat-tail` command
+    pub log_path: Option<PathBuf>,
+    /// Also log private room chat (public chat only by default)
+    pub log_private: bool,
+}
+
+impl Default for ChatConfig {
+    fn default() -> Self {
+        Self {
+            command_prefix: "/".to_string(),
+            log_path: None,
+            log_private: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BannerConfig {
+    /// Send a ServerBanner (122) to clients once they accept the agreement
+    pub enabled: bool,
+    /// JPEG, GIF, PNG or BMP image shown as the banner
+    pub image_path: Option<PathBuf>,
+    /// Text shown as the banner when there is no image
+    pub text: Option<String>,
+    /// Link the banner opens; with no image or text the page is shown instead
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DebugConfig {
+    /// Append every transaction to this file as JSONL (disabled when unset)
+    #[serde(default)]
+    pub capture_path: Option<PathBuf>,
 }
 
 impl Config {
+    /// Size of the broadcast channel buffer, from `features.broadcast_buffer`
+    /// or scaled to `server.max_connections`
+    pub fn broadcast_buffer(&self) -> usize {
+        self.features
+            .broadcast_buffer
+            .unwrap_or(self.server.max_connections.saturating_mul(8).max(256))
+            .max(1)
+    }
+
     /// Load configuration from a file
     pub fn load(path: impl AsRef<std::path::Path>) -> anyhow::Result<Self> {
         let content = std::fs::read_to_string(path)?;
-        let config = serde_json::from_str(&content)?;
+        let config: Self = serde_json::from_str(&content)?;
+        if let Some(guest_access) = &config.security.guest_access {
+            parse_access(guest_access).context("Invalid security.guest_access")?;
+        }
+        for name in &config.features.disabled_transactions {
+            if !is_transaction_name(name.trim()) {
+                anyhow::bail!(
+                    "Invalid features.disabled_transactions: unknown transaction type {:?}",
+                    name
+                );
+            }
+        }
         Ok(config)
     }
 
@@ -69,9 +554,19 @@ impl Config {
         std::fs::write(path, content)?;
         Ok(())
     }
+}
 
+/// Whether `name` names a transaction type, as written in
+/// `features.disabled_transactions`
+fn is_transaction_name(name: &str) -> bool {
+    (100..=500)
+        .filter_map(TransactionType::from_u16)
+        .any(|transaction_type| format!("{:?}", transaction_type) == name)
+}
+
+impl Default for Config {
     /// Create default configuration
-    pub fn default() -> Self {
+    fn default() -> Self {
         Self {
             server: ServerConfig {
                 name: "My Hotline Server".to_string(),
@@ -79,16 +574,29 @@ impl Config {
                 address: "0.0.0.0".to_string(),
                 port: 5500,
                 max_connections: 100,
+                reserved_handshake_slots: 0,
+                listen_backlog: default_listen_backlog(),
+                tcp_nodelay: default_tcp_nodelay(),
+                tcp_keepalive_secs: None,
+                shutdown_message: default_shutdown_message(),
+                name_shows_user_count: false,
+                read_only: false,
             },
             files: FilesConfig {
                 root_path: PathBuf::from("./files"),
                 max_download_size: 104857600, // 100 MB
                 enable_uploads: true,
                 enable_downloads: true,
+                max_path_depth: default_max_path_depth(),
+                max_file_name_length: default_max_file_name_length(),
+                max_concurrent_transfers: default_max_concurrent_transfers(),
+                max_transfers_per_user: default_max_transfers_per_user(),
+                transfer_timeout_seconds: default_transfer_timeout_seconds(),
+                upload_allowed_extensions: Vec::new(),
+                upload_blocked_extensions: Vec::new(),
+                upload_anywhere_ignores_extensions: false,
             },
-            database: DatabaseConfig {
-                path: PathBuf::from("./rhxd.db"),
-            },
+            database: DatabaseConfig::with_path("./rhxd.db"),
             logging: LoggingConfig {
                 level: "info".to_string(),
                 file: PathBuf::from("./logs/rhxd.log"),
@@ -97,12 +605,39 @@ impl Config {
                 require_login: true,
                 allow_guest: false,
                 ban_list_path: PathBuf::from("./banlist.txt"),
+                transaction_privileges: BTreeMap::new(),
+                guest_denied_message: default_guest_denied_message(),
+                password_scheme: PasswordScheme::default(),
+                max_failed_logins: 0,
+                lockout_seconds: default_lockout_seconds(),
+                max_accounts: 0,
+                audit_log: false,
+                audit_chat: false,
+                guest_access: None,
             },
             features: FeaturesConfig {
                 enable_news: false,
                 enable_private_chat: true,
                 enable_file_transfers: false,
+                auto_away_seconds: None,
+                idle_timeout_seconds: None,
+                idle_warning_seconds: None,
+                user_list_batch_ms: None,
+                reserved_nicknames: Vec::new(),
+                broadcast_buffer: None,
+                broadcast_lag_policy: LagPolicy::default(),
+                disabled_transactions: Vec::new(),
+                keepalive_reply: false,
+                user_list_order: UserListOrder::default(),
+                skip_agreement_for_old_clients: false,
+                skip_agreement_sub_protocols: Vec::new(),
             },
+            chat: ChatConfig::default(),
+            banner: BannerConfig::default(),
+            admin_http: AdminHttpConfig::default(),
+            console: ConsoleConfig::default(),
+            tracker: TrackerConfig::default(),
+            debug: DebugConfig::default(),
         }
     }
 }
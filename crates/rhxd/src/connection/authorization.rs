@@ -0,0 +1,160 @@
+//! Transaction authorization
+//!
+//! Checks that a transaction fits the session's place in the login sequence,
+//! that it isn't disabled server-wide, by a feature switch or by read-only
+//! mode, and the
+//! session's access privileges
+//! against the privilege each transaction type requires, before the
+//! transaction is dispatched, so disallowed requests are refused in one place.
+
+use crate::config::SecurityConfig;
+use crate::connection::session::AuthState;
+use crate::connection::transaction_helpers::create_error_reply;
+use crate::state::ServerState;
+use anyhow::Result;
+use rhxcore::protocol::{required_privilege, ErrorCode, Field, FieldId, Transaction, TransactionType};
+use rhxcore::types::AccessPrivileges;
+use std::sync::Arc;
+
+/// Privilege required for a transaction type under the given configuration
+///
+/// Entries in `security.transaction_privileges` replace the requirement from
+/// [`required_privilege`]. An override naming an unknown privilege denies the
+/// transaction rather than silently allowing it.
+pub fn configured_privilege(
+    config: &SecurityConfig,
+    transaction_type: TransactionType,
+) -> Option<AccessPrivileges> {
+    let name = format!("{:?}", transaction_type);
+
+    let Some(names) = config.transaction_privileges.get(&name) else {
+        return required_privilege(transaction_type);
+    };
+
+    let mut privilege = AccessPrivileges::empty();
+    for flag in names {
+        match AccessPrivileges::from_name(flag) {
+            Some(p) => privilege |= p,
+            None => {
+                tracing::warn!(
+                    "Unknown privilege {:?} configured for {}; denying it to everyone",
+                    flag,
+                    name
+                );
+                return Some(AccessPrivileges::all());
+            }
+        }
+    }
+
+    if privilege.is_empty() {
+        None
+    } else {
+        Some(privilege)
+    }
+}
+
+/// Whether a transaction type may be sent in a session state
+///
+/// Clients must log in, then agree, before anything else:
+/// - Before the handshake completes nothing is accepted
+/// - Before login only Login is accepted
+/// - After login, only Agreed until the agreement is accepted
+/// - After that, anything but another Login
+///
+/// Keep-alives are accepted from the handshake on, and client error
+/// reports at any time.
+pub fn allowed_in_state(auth_state: AuthState, transaction_type: TransactionType) -> bool {
+    use TransactionType::*;
+
+    match (auth_state, transaction_type) {
+        (_, Error) => true,
+        (AuthState::Handshake, _) => false,
+        (_, KeepConnectionAlive) => true,
+        (AuthState::LoginPending, Login) => true,
+        (AuthState::LoginPending, _) => false,
+        (AuthState::Authenticated, Agreed) => true,
+        (AuthState::Authenticated, _) => false,
+        (AuthState::Agreed, Login) => false,
+        (AuthState::Agreed, _) => true,
+    }
+}
+
+/// Refuse a transaction that is out of order, disabled by
+/// `features.disabled_transactions` or a feature switch such as
+/// `features.enable_file_transfers`, a write while `server.read_only` is set,
+/// or one that the sender's access doesn't permit
+///
+/// Returns the `PermissionDenied` reply to send, or `None` if the transaction
+/// may be dispatched.
+pub async fn authorize(
+    transaction: &Transaction,
+    user_id: u16,
+    state: &Arc<ServerState>,
+) -> Result<Option<Transaction>> {
+    let auth_state = state
+        .get_session(user_id)
+        .map_or(AuthState::Handshake, |session| session.auth_state);
+    if !allowed_in_state(auth_state, transaction.transaction_type) {
+        tracing::warn!(
+            "User {} sent {:?} out of order ({:?})",
+            user_id,
+            transaction.transaction_type,
+            auth_state
+        );
+        return Ok(Some(create_error_reply(transaction, ErrorCode::PermissionDenied)));
+    }
+
+    let config = state.config();
+    if config.features.is_transaction_disabled(transaction.transaction_type) {
+        tracing::warn!(
+            "User {} sent {:?}, which is disabled on this server",
+            user_id,
+            transaction.transaction_type
+        );
+        return Ok(Some(create_error_reply(transaction, ErrorCode::PermissionDenied)));
+    }
+
+    if let Some(feature) = config.features.disabled_feature(transaction.transaction_type) {
+        tracing::warn!(
+            "User {} sent {:?}, but {} are disabled on this server",
+            user_id,
+            transaction.transaction_type,
+            feature.to_lowercase()
+        );
+        let mut reply = create_error_reply(transaction, ErrorCode::PermissionDenied);
+        reply.add_field(Field::string(
+            FieldId::Data,
+            format!("{} are disabled on this server", feature),
+        ));
+        return Ok(Some(reply));
+    }
+
+    if config.server.read_only && transaction.transaction_type.is_write() {
+        tracing::warn!(
+            "User {} sent {:?}, but the server is read-only",
+            user_id,
+            transaction.transaction_type
+        );
+        let mut reply = create_error_reply(transaction, ErrorCode::PermissionDenied);
+        reply.add_field(Field::string(FieldId::Data, "This server is read-only"));
+        return Ok(Some(reply));
+    }
+
+    let Some(required) = configured_privilege(&config.security, transaction.transaction_type)
+    else {
+        return Ok(None);
+    };
+
+    let access = state.user_access(user_id);
+    if access.contains(required) {
+        return Ok(None);
+    }
+
+    tracing::warn!(
+        "User {} is not permitted to send {:?}",
+        user_id,
+        transaction.transaction_type
+    );
+
+    Ok(Some(create_error_reply(transaction, ErrorCode::PermissionDenied)))
+}
@@ -0,0 +1,170 @@
+//! Transaction capture for protocol debugging
+//!
+//! When `debug.capture_path` is set, every transaction that passes through a
+//! connection's codec is appended to the capture file as a single JSON line.
+//! When it is unset, [`CaptureCodec`] is a plain pass-through to
+//! [`TransactionCodec`].
+//!
+//! Recoverable decode errors are yielded as items rather than stream errors,
+//! since a framed stream ends after its first error.
+
+use anyhow::{Context, Result};
+use bytes::BytesMut;
+use chrono::Utc;
+use rhxcore::codec::TransactionCodec;
+use rhxcore::protocol::{FieldData, Transaction};
+use rhxcore::ProtocolError;
+use serde_json::{json, Value};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Direction of a captured transaction, relative to the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureDirection {
+    /// Decoded from the client
+    Inbound,
+    /// Encoded for the client
+    Outbound,
+}
+
+impl CaptureDirection {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Inbound => "in",
+            Self::Outbound => "out",
+        }
+    }
+}
+
+/// Append-only JSONL capture file shared by all connections
+pub struct TransactionCapture {
+    file: Mutex<File>,
+}
+
+impl TransactionCapture {
+    /// Open (or create) the capture file in append mode
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open capture file {}", path.display()))?;
+
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Append a single transaction to the capture file
+    pub fn record(&self, direction: CaptureDirection, user_id: u16, transaction: &Transaction) {
+        let mut line = capture_entry(direction, user_id, transaction).to_string();
+        line.push('\n');
+
+        let mut file = match self.file.lock() {
+            Ok(file) => file,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            tracing::warn!("Failed to write transaction capture: {}", e);
+        }
+    }
+}
+
+/// Build the JSON representation of a captured transaction
+fn capture_entry(direction: CaptureDirection, user_id: u16, transaction: &Transaction) -> Value {
+    let fields: Vec<Value> = transaction
+        .fields
+        .iter()
+        .map(|field| {
+            let data = match &field.data {
+                FieldData::Integer(v) => json!({ "integer": v }),
+                FieldData::String(s) => json!({ "string": s }),
+                FieldData::Binary(b) => json!({ "binary": hex::encode(b) }),
+            };
+
+            json!({
+                "id": field.id.to_u16(),
+                "name": format!("{:?}", field.id),
+                "data": data,
+            })
+        })
+        .collect();
+
+    json!({
+        "timestamp": Utc::now().to_rfc3339(),
+        "direction": direction.as_str(),
+        "user_id": user_id,
+        "type": format!("{:?}", transaction.transaction_type),
+        "type_id": transaction.transaction_type.to_u16(),
+        "id": transaction.id,
+        "is_reply": transaction.is_reply,
+        "error_code": transaction.error_code,
+        "fields": fields,
+    })
+}
+
+/// Transaction codec that taps decoded and encoded transactions into a capture
+pub struct CaptureCodec {
+    inner: TransactionCodec,
+    user_id: u16,
+    capture: Option<Arc<TransactionCapture>>,
+}
+
+impl CaptureCodec {
+    /// Wrap a transaction codec for the given connection
+    pub fn new(
+        inner: TransactionCodec,
+        user_id: u16,
+        capture: Option<Arc<TransactionCapture>>,
+    ) -> Self {
+        Self {
+            inner,
+            user_id,
+            capture,
+        }
+    }
+
+    /// Maximum transaction data size of the wrapped codec
+    pub fn max_size(&self) -> usize {
+        self.inner.max_size()
+    }
+}
+
+/// A decoded transaction, or the recoverable error that replaced it
+pub type Decoded = std::result::Result<Transaction, ProtocolError>;
+
+impl Decoder for CaptureCodec {
+    type Item = Decoded;
+    type Error = ProtocolError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> rhxcore::Result<Option<Self::Item>> {
+        let item = match self.inner.decode(src) {
+            Ok(item) => item,
+            Err(e) if e.is_recoverable() => return Ok(Some(Err(e))),
+            Err(e) => return Err(e),
+        };
+
+        if let (Some(capture), Some(transaction)) = (&self.capture, &item) {
+            capture.record(CaptureDirection::Inbound, self.user_id, transaction);
+        }
+
+        Ok(item.map(Ok))
+    }
+}
+
+impl Encoder<Transaction> for CaptureCodec {
+    type Error = ProtocolError;
+
+    fn encode(&mut self, item: Transaction, dst: &mut BytesMut) -> rhxcore::Result<()> {
+        if let Some(capture) = &self.capture {
+            capture.record(CaptureDirection::Outbound, self.user_id, &item);
+        }
+
+        self.inner.encode(item, dst)
+    }
+}
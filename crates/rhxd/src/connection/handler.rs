@@ -1,18 +1,31 @@
 //! Connection handler for individual clients
 
-use crate::connection::transaction_helpers::create_server_transaction;
+use crate::config::LagPolicy;
+use crate::connection::authorization;
+use crate::connection::capture::CaptureCodec;
+use crate::connection::transaction_helpers::{
+    create_error_reply, create_server_transaction, create_server_transactions,
+};
 use crate::connection::Session;
+use crate::db::is_pool_timeout;
 use crate::handlers;
-use crate::state::{BroadcastMessage, ServerState};
+use crate::state::{Broadcast, BroadcastMessage, IdleAction, ServerState, UserListDelta};
 use anyhow::{Context, Result};
 use bytes::BytesMut;
 use rhxcore::codec::TransactionCodec;
-use rhxcore::protocol::{Handshake, HandshakeReply, Transaction, TransactionType};
+use rhxcore::protocol::{ErrorCode, Handshake, HandshakeReply, Transaction, TransactionType};
+use rhxcore::types::User;
+use rhxcore::ProtocolError;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tokio_util::codec::Framed;
 
+/// How often a connection checks itself against the idle timeout
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
 /// Handle an incoming client connection
 pub async fn handle_connection(
     mut stream: TcpStream,
@@ -31,12 +44,14 @@ pub async fn handle_connection(
     
     // Perform handshake
     match perform_handshake(&mut stream, user_id).await {
-        Ok(_) => {
+        Ok(handshake) => {
             // Update session state to LoginPending
-            if let Some(mut session) = state.get_session_mut(user_id) {
+            state.update_session(user_id, |session| {
                 session.complete_handshake();
-                tracing::info!("User {} completed handshake", user_id);
-            }
+                session.sub_protocol_id = handshake.sub_protocol_id;
+                session.touch();
+            });
+            tracing::info!("User {} completed handshake", user_id);
         }
         Err(e) => {
             tracing::warn!("Handshake failed for user {}: {}", user_id, e);
@@ -47,11 +62,15 @@ pub async fn handle_connection(
     }
     
     // Create framed codec for transaction handling
-    let mut framed = Framed::new(stream, TransactionCodec::new());
+    let codec = CaptureCodec::new(TransactionCodec::new(), user_id, state.capture.clone());
+    let mut framed = Framed::new(stream, codec);
     
     // Subscribe to broadcast messages
     let mut broadcast_rx = state.broadcast_tx.subscribe();
     
+    let mut idle_check = tokio::time::interval(IDLE_CHECK_INTERVAL);
+    idle_check.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    
     // Main transaction loop
     use futures::StreamExt;
     use futures::SinkExt;
@@ -61,11 +80,9 @@ pub async fn handle_connection(
             // Read transaction from client
             result = framed.next() => {
                 match result {
-                    Some(Ok(transaction)) => {
-                        // Update session activity
-                        if let Some(mut session) = state.get_session_mut(user_id) {
-                            session.touch();
-                        }
+                    Some(Ok(Ok(transaction))) => {
+                        // Any inbound transaction, keep-alives included, is activity
+                        state.mark_active(user_id);
                         
                         tracing::debug!(
                             "User {} transaction: type={:?}, id={}, fields={}",
@@ -83,6 +100,20 @@ pub async fn handle_connection(
                         
                         match reply {
                             Ok(Some(reply_transaction)) => {
+                                // Never put an over-budget frame on the wire; fail the request instead
+                                let reply_transaction = match reply_transaction.check_size(framed.codec().max_size()) {
+                                    Ok(()) => reply_transaction,
+                                    Err(e) => {
+                                        tracing::error!(
+                                            "Reply to user {} for {:?} rejected: {}",
+                                            user_id,
+                                            transaction_type,
+                                            e
+                                        );
+                                        create_error_reply(&reply_transaction, ErrorCode::UnknownError)
+                                    }
+                                };
+                                
                                 // Check if this was a successful login
                                 let was_successful_login = transaction_type == TransactionType::Login 
                                     && reply_transaction.error_code == 0;
@@ -98,7 +129,14 @@ pub async fn handle_connection(
                                 }
                                 
                                 // After successful login, send ShowAgreement transaction
-                                if was_successful_login {
+                                // unless this client goes without
+                                let skips_agreement = was_successful_login
+                                    && state.get_session(user_id).is_some_and(|session| {
+                                        state.config().features.skips_agreement(session.client_version, session.sub_protocol_id)
+                                    });
+                                if skips_agreement {
+                                    handlers::agreed::skip_agreement(&state, user_id);
+                                } else if was_successful_login {
                                     tracing::debug!("Sending ShowAgreement to user {}", user_id);
                                     
                                     let show_agreement = create_server_transaction(
@@ -116,19 +154,17 @@ pub async fn handle_connection(
                                 if was_successful_agreed {
                                     // Get user's access privileges
                                     let access_privileges = {
-                                        if let Some(session) = state.get_session(user_id) {
-                                            if let Some(account_id) = session.account_id {
-                                                // Authenticated user - fetch from database
-                                                match crate::db::accounts::get_account_by_id(state.database.pool(), account_id).await {
-                                                    Ok(Some(account)) => account.access_privileges(),
-                                                    _ => rhxcore::types::AccessPrivileges::guest(),
-                                                }
-                                            } else {
-                                                // Guest user
-                                                rhxcore::types::AccessPrivileges::guest()
+                                        let guest_access = state.config().security.guest_access();
+                                        let account_id = state.get_session(user_id).and_then(|session| session.account_id);
+                                        if let Some(account_id) = account_id {
+                                            // Authenticated user - fetch from database
+                                            match state.accounts.get_account_by_id(account_id).await {
+                                                Ok(Some(account)) => account.access_privileges(),
+                                                _ => guest_access,
                                             }
                                         } else {
-                                            rhxcore::types::AccessPrivileges::guest()
+                                            // Guest user
+                                            guest_access
                                         }
                                     };
                                     
@@ -140,16 +176,30 @@ pub async fn handle_connection(
                                     
                                     let user_access_txn = create_server_transaction(
                                         TransactionType::UserAccess,
-                                        vec![rhxcore::protocol::Field::binary(
-                                            rhxcore::protocol::FieldId::UserAccess,
-                                            access_privileges.to_wire_format().to_vec()
-                                        )],
+                                        vec![rhxcore::protocol::Field::from_access(access_privileges)],
                                     );
                                     
                                     if let Err(e) = framed.send(user_access_txn).await {
                                         tracing::error!("Failed to send UserAccess to user {}: {}", user_id, e);
                                         break;
                                     }
+                                    
+                                    // Show newcomers the current public chat subject
+                                    let subject = state.chat_subject();
+                                    if !subject.is_empty()
+                                        && let Err(e) = framed.send(handlers::chat::notify_chat_subject(&subject)).await
+                                    {
+                                        tracing::error!("Failed to send chat subject to user {}: {}", user_id, e);
+                                        break;
+                                    }
+                                    
+                                    // Then the welcome banner, if one is configured
+                                    if let Some(banner) = handlers::banner::server_banner(&state.config().banner).await
+                                        && let Err(e) = framed.send(banner).await
+                                    {
+                                        tracing::error!("Failed to send banner to user {}: {}", user_id, e);
+                                        break;
+                                    }
                                 }
                             }
                             Ok(None) => {
@@ -161,6 +211,10 @@ pub async fn handle_connection(
                             }
                         }
                     }
+                    Some(Ok(Err(e))) => {
+                        // The codec already skipped the bad frame
+                        tracing::warn!("Skipping malformed transaction from user {}: {}", user_id, e);
+                    }
                     Some(Err(e)) => {
                         tracing::warn!("Error reading transaction from user {}: {}", user_id, e);
                         break;
@@ -172,15 +226,53 @@ pub async fn handle_connection(
                 }
             }
             
-            // TODO: Handle timeouts/keepalive
+            // Warn, then disconnect, idle clients (no-op unless features.idle_timeout_seconds is set)
+            _ = idle_check.tick() => {
+                match state.check_idle(user_id, SystemTime::now()) {
+                    Some(IdleAction::Warn { remaining }) => {
+                        let warning = create_server_transaction(
+                            TransactionType::ServerMessage,
+                            vec![rhxcore::protocol::Field::string(
+                                rhxcore::protocol::FieldId::Data,
+                                format!("You will be disconnected for inactivity in {} seconds", remaining)
+                            )],
+                        );
+                        
+                        if let Err(e) = framed.send(warning).await {
+                            tracing::error!("Failed to send idle warning to user {}: {}", user_id, e);
+                            break;
+                        }
+                    }
+                    Some(IdleAction::Disconnect) => {
+                        tracing::info!("User {} disconnected for inactivity", user_id);
+                        
+                        let notice = create_server_transaction(
+                            TransactionType::DisconnectMsg,
+                            vec![rhxcore::protocol::Field::string(
+                                rhxcore::protocol::FieldId::Data,
+                                "Disconnected for inactivity"
+                            )],
+                        );
+                        
+                        if let Err(e) = framed.send(notice).await {
+                            tracing::warn!("Failed to send idle notice to user {}: {}", user_id, e);
+                        }
+                        break;
+                    }
+                    None => {}
+                }
+            }
             
-            // Handle broadcast messages
+            // Handle broadcast messages (outgoing, so never counted as activity)
             msg = broadcast_rx.recv() => {
                 match msg {
-                    Ok(broadcast) => {
+                    Ok(Broadcast { exclude: Some(excluded), .. }) if excluded == user_id => {}
+                    Ok(Broadcast { message: broadcast, .. }) => {
                         // Convert broadcast to transaction if needed
                         let transaction = match broadcast {
-                            BroadcastMessage::ChatMessage { sender_id, message, is_emote } => {
+                            BroadcastMessage::ChatMessage { chat_id: Some(chat_id), .. }
+                                if !state.chat_rooms.is_member(chat_id, user_id) => None,
+                            BroadcastMessage::ChatMessage { sender_id, chat_id, message, is_emote } => {
                                 // Get sender nickname
                                 let sender_nickname = state.get_session(sender_id)
                                     .map(|s| s.nickname.clone())
@@ -199,43 +291,48 @@ pub async fn handle_connection(
                                 };
                                 let formatted_data = formatted_message.into_bytes();
                                 
-                                Some(create_server_transaction(
-                                    TransactionType::ChatMessage,
-                                    vec![
-                                        rhxcore::protocol::Field::binary(rhxcore::protocol::FieldId::Data, formatted_data),
-                                        rhxcore::protocol::Field::integer(rhxcore::protocol::FieldId::UserId, sender_id as i32),
-                                        rhxcore::protocol::Field::string(rhxcore::protocol::FieldId::UserName, sender_nickname),
-                                    ],
-                                ))
+                                let mut fields = vec![
+                                    rhxcore::protocol::Field::binary(rhxcore::protocol::FieldId::Data, formatted_data),
+                                    rhxcore::protocol::Field::integer(rhxcore::protocol::FieldId::UserId, sender_id as i32),
+                                    rhxcore::protocol::Field::string(rhxcore::protocol::FieldId::UserName, sender_nickname),
+                                ];
+                                if let Some(chat_id) = chat_id {
+                                    fields.push(rhxcore::protocol::Field::integer(rhxcore::protocol::FieldId::ChatId, chat_id as i32));
+                                }
+                                
+                                Some(create_server_transaction(TransactionType::ChatMessage, fields))
                             }
                             BroadcastMessage::UserJoined { user_id: joined_user_id, nickname } => {
-                                // Don't send the notification to the user who just joined
-                                if joined_user_id == user_id {
-                                    None
-                                } else {
-                                    // Get user info from session
-                                    let (icon_id, flags) = state.get_session(joined_user_id)
-                                        .map(|s| (s.icon_id, s.flags))
-                                        .unwrap_or((0, 0));
-                                    
-                                    // Build UserNameWithInfo field (Field 300)
-                                    // Format: user_id (2 bytes) + icon_id (2 bytes) + flags (2 bytes) + name_len (2 bytes) + name
-                                    let mut user_info = Vec::new();
-                                    user_info.extend_from_slice(&joined_user_id.to_be_bytes());
-                                    user_info.extend_from_slice(&icon_id.to_be_bytes());
-                                    user_info.extend_from_slice(&flags.to_be_bytes());
-                                    user_info.extend_from_slice(&(nickname.len() as u16).to_be_bytes());
-                                    user_info.extend_from_slice(nickname.as_bytes());
-                                    
-                                    Some(                                        create_server_transaction(
-                                            TransactionType::NotifyChangeUser,
-                                            vec![rhxcore::protocol::Field::binary(
-                                                rhxcore::protocol::FieldId::UserNameWithInfo,
-                                                user_info
-                                            )],
-                                        )
-                                    )
+                                // Get user info from session
+                                let mut user = User::new(joined_user_id, nickname);
+                                if let Some(session) = state.get_session(joined_user_id) {
+                                    user.icon_id = session.icon_id as i16;
+                                    user.flags = session.flags;
+                                }
+                                
+                                Some(notify_change_user(&user))
+                            }
+                            BroadcastMessage::UserChanged { user_id: changed_user_id } => {
+                                state.get_session(changed_user_id)
+                                    .filter(|s| s.is_authenticated())
+                                    .map(|s| notify_change_user(&s.to_user()))
+                            }
+                            BroadcastMessage::UserListDelta(delta) => {
+                                let mut batches = user_list_delta(&state, user_id, &delta, framed.codec().max_size());
+                                let last = batches.pop();
+                                
+                                let mut send_failed = false;
+                                for tx in batches {
+                                    if let Err(e) = framed.send(tx).await {
+                                        tracing::error!("Failed to send broadcast to user {}: {}", user_id, e);
+                                        send_failed = true;
+                                        break;
+                                    }
+                                }
+                                if send_failed {
+                                    break;
                                 }
+                                last
                             }
                             BroadcastMessage::UserLeft { user_id: left_user_id } => {
                                 Some(create_server_transaction(
@@ -246,10 +343,74 @@ pub async fn handle_connection(
                                     )],
                                 ))
                             }
-                            BroadcastMessage::ServerShutdown => {
+                            BroadcastMessage::ServerShutdown { reason } => {
+                                // Send the reason before closing so the client can show it
+                                let notice = create_server_transaction(
+                                    TransactionType::DisconnectMsg,
+                                    vec![rhxcore::protocol::Field::string(
+                                        rhxcore::protocol::FieldId::Data,
+                                        reason
+                                    )],
+                                );
+                                
+                                if let Err(e) = framed.send(notice).await {
+                                    tracing::warn!("Failed to send shutdown notice to user {}: {}", user_id, e);
+                                }
+                                
                                 tracing::info!("User {} notified of server shutdown", user_id);
                                 break;
                             }
+                            BroadcastMessage::ChatSubject { subject } => {
+                                Some(handlers::chat::notify_chat_subject(&subject))
+                            }
+                            BroadcastMessage::AdminAlert { text, recipients } => {
+                                recipients.contains(&user_id).then(|| create_server_transaction(
+                                    TransactionType::ServerMessage,
+                                    vec![rhxcore::protocol::Field::string(
+                                        rhxcore::protocol::FieldId::Data,
+                                        text
+                                    )],
+                                ))
+                            }
+                            BroadcastMessage::AccessChanged { account_id, access } => {
+                                let own_account = state.get_session(user_id)
+                                    .is_some_and(|session| session.account_id == Some(account_id));
+                                
+                                own_account.then(|| create_server_transaction(
+                                    TransactionType::UserAccess,
+                                    vec![rhxcore::protocol::Field::from_access(access)],
+                                ))
+                            }
+                            BroadcastMessage::PrivateMessage { user_id: recipient, .. } if recipient != user_id => None,
+                            BroadcastMessage::PrivateMessage { sender_id, message, quoting, .. } => {
+                                state.get_session(sender_id)
+                                    .map(|sender| handlers::message::private_message(
+                                        sender_id,
+                                        &sender.nickname,
+                                        message,
+                                        quoting,
+                                    ))
+                            }
+                            BroadcastMessage::ChatInvite { user_id: invitee, .. } if invitee != user_id => None,
+                            BroadcastMessage::ChatInvite { chat_id, inviter_id, .. } => {
+                                state.get_session(inviter_id)
+                                    .map(|inviter| handlers::chat::chat_invite(
+                                        TransactionType::InviteToChat,
+                                        chat_id,
+                                        inviter_id,
+                                        &inviter.nickname,
+                                    ))
+                            }
+                            BroadcastMessage::ChatUserJoined { chat_id, user_id: member }
+                            | BroadcastMessage::ChatUserLeft { chat_id, user_id: member }
+                                if member == user_id || !state.chat_rooms.is_member(chat_id, user_id) => None,
+                            BroadcastMessage::ChatUserJoined { chat_id, user_id: member } => {
+                                state.get_session(member)
+                                    .map(|session| handlers::chat::notify_chat_change_user(chat_id, &session.to_user()))
+                            }
+                            BroadcastMessage::ChatUserLeft { chat_id, user_id: member } => {
+                                Some(handlers::chat::notify_chat_delete_user(chat_id, member))
+                            }
                             BroadcastMessage::ServerMessage { message } => {
                                 Some(create_server_transaction(
                                     TransactionType::ServerMessage,
@@ -262,15 +423,40 @@ pub async fn handle_connection(
                         };
                         
                         // Send transaction if we created one
-                        if let Some(tx) = transaction {
+                        if let Some(tx) = transaction
+                            && let Err(e) = framed.send(tx).await
+                        {
+                            tracing::error!("Failed to send broadcast to user {}: {}", user_id, e);
+                            break;
+                        }
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                        let Some(resync) = lag_recovery(&state, user_id, skipped, framed.codec().max_size()) else {
+                            let notice = create_server_transaction(
+                                TransactionType::DisconnectMsg,
+                                vec![rhxcore::protocol::Field::string(
+                                    rhxcore::protocol::FieldId::Data,
+                                    "Disconnected for falling too far behind"
+                                )],
+                            );
+                            
+                            if let Err(e) = framed.send(notice).await {
+                                tracing::warn!("Failed to send lag notice to user {}: {}", user_id, e);
+                            }
+                            break;
+                        };
+                        
+                        let mut send_failed = false;
+                        for tx in resync {
                             if let Err(e) = framed.send(tx).await {
-                                tracing::error!("Failed to send broadcast to user {}: {}", user_id, e);
+                                tracing::error!("Failed to resync user {}: {}", user_id, e);
+                                send_failed = true;
                                 break;
                             }
                         }
-                    }
-                    Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
-                        tracing::warn!("User {} lagged behind, skipped {} broadcasts", user_id, skipped);
+                        if send_failed {
+                            break;
+                        }
                     }
                     Err(tokio::sync::broadcast::error::RecvError::Closed) => {
                         tracing::info!("Broadcast channel closed for user {}", user_id);
@@ -298,8 +484,76 @@ pub async fn handle_connection(
     Ok(())
 }
 
+/// Build a NotifyChangeUser (301) transaction announcing a user's details
+fn notify_change_user(user: &User) -> Transaction {
+    create_server_transaction(
+        TransactionType::NotifyChangeUser,
+        vec![rhxcore::protocol::Field::binary(
+            rhxcore::protocol::FieldId::UserNameWithInfo,
+            user.to_name_with_info()
+        )],
+    )
+}
+
+/// NotifyDeleteUser (302) and NotifyChangeUser (301) transactions sending
+/// `user_id` a batch of user list changes, each within `max_size`
+///
+/// Leaves come first, so a reused user ID ends up present. Like UserJoined,
+/// a join isn't announced to the user who joined.
+fn user_list_delta(state: &ServerState, user_id: u16, delta: &UserListDelta, max_size: usize) -> Vec<Transaction> {
+    let left = delta.left.iter()
+        .map(|&id| rhxcore::protocol::Field::integer(rhxcore::protocol::FieldId::UserId, id as i32))
+        .collect();
+    
+    let changed = delta.changed.iter()
+        .filter(|&&(id, joined)| !(joined && id == user_id))
+        .filter_map(|&(id, _)| state.get_session(id).filter(|s| s.is_authenticated()).map(|s| s.to_user()))
+        .map(|user| rhxcore::protocol::Field::binary(
+            rhxcore::protocol::FieldId::UserNameWithInfo,
+            user.to_name_with_info()
+        ))
+        .collect();
+    
+    let mut transactions = create_server_transactions(TransactionType::NotifyDeleteUser, left, max_size);
+    transactions.extend(create_server_transactions(TransactionType::NotifyChangeUser, changed, max_size));
+    transactions
+}
+
+/// Catch up a connection whose broadcast subscription skipped messages
+///
+/// Returns the transactions to resend, or `None` if `features.broadcast_lag_policy`
+/// says to disconnect it. The skipped messages are gone, so a resync resends
+/// the state they would have updated: every logged-in user, in as many
+/// transactions as fit within `max_size`, and the chat subject. Users who left while it lagged stay in the client's list until it
+/// asks for the list again.
+fn lag_recovery(state: &ServerState, user_id: u16, skipped: u64, max_size: usize) -> Option<Vec<Transaction>> {
+    if state.config().features.broadcast_lag_policy == LagPolicy::Disconnect {
+        tracing::warn!("User {} lagged behind by {} broadcasts, disconnecting", user_id, skipped);
+        return None;
+    }
+    
+    tracing::warn!("User {} lagged behind by {} broadcasts, resyncing", user_id, skipped);
+    
+    let users: Vec<_> = state.user_list()
+        .iter()
+        .map(|user| rhxcore::protocol::Field::binary(
+            rhxcore::protocol::FieldId::UserNameWithInfo,
+            user.to_name_with_info()
+        ))
+        .collect();
+    
+    let mut resync = create_server_transactions(TransactionType::NotifyChangeUser, users, max_size);
+    
+    let subject = state.chat_subject();
+    if !subject.is_empty() {
+        resync.push(handlers::chat::notify_chat_subject(&subject));
+    }
+    
+    Some(resync)
+}
+
 /// Perform the TRTP handshake with a client
-async fn perform_handshake(stream: &mut TcpStream, user_id: u16) -> Result<()> {
+async fn perform_handshake(stream: &mut TcpStream, user_id: u16) -> Result<Handshake> {
     // Read handshake from client (12 bytes)
     let mut buf = [0u8; Handshake::SIZE];
     stream
@@ -337,8 +591,8 @@ async fn perform_handshake(stream: &mut TcpStream, user_id: u16) -> Result<()> {
         return Err(anyhow::anyhow!("Invalid protocol magic"));
     }
     
-    // Validate protocol version (we support version 1)
-    if handshake.version != rhxcore::protocol::PROTOCOL_VERSION {
+    // Validate protocol version
+    if !handshake.is_supported_version() {
         tracing::warn!(
             "User {} sent unsupported protocol version: {} (expected {})",
             user_id,
@@ -372,16 +626,105 @@ async fn perform_handshake(stream: &mut TcpStream, user_id: u16) -> Result<()> {
     
     tracing::debug!("User {} handshake successful", user_id);
     
-    Ok(())
+    Ok(handshake)
 }
 
-/// Dispatch transaction to appropriate handler
+/// Handle a transaction, turning undecodable text fields and database pool
+/// exhaustion into error replies
+///
+/// Transactions from a connection are handled one at a time, so a client
+/// reusing an ID can't have two requests in flight under it. Replies always
+/// carry the request's ID and type (see [`correlate_reply`]), even when the
+/// client numbered the request 0.
 async fn handle_transaction(
     transaction: Transaction,
     user_id: u16,
     state: Arc<ServerState>,
 ) -> Result<Option<Transaction>> {
+    let invalid_parameter = create_error_reply(&transaction, ErrorCode::InvalidParameter);
+    let unknown_error = create_error_reply(&transaction, ErrorCode::UnknownError);
+    let transaction_type = transaction.transaction_type;
+    let request_id = transaction.id;
+    
+    if request_id == 0 && !transaction.is_reply {
+        tracing::debug!(
+            "User {} sent {:?} with ID 0; its reply is numbered 0 too",
+            user_id,
+            transaction_type
+        );
+    }
+    
+    let started = Instant::now();
+    let result = dispatch_transaction(transaction, user_id, state.clone()).await;
+    state.handler_metrics.observe(transaction_type, started.elapsed());
+    
+    let result = result.map(|reply| reply.map(|reply| correlate_reply(reply, request_id, transaction_type, user_id)));
+    
+    match result {
+        Err(e) if is_pool_timeout(&e) => {
+            let timeouts = state.db_pool_timeouts.fetch_add(1, Ordering::Relaxed) + 1;
+            tracing::warn!(
+                "No database connection for user {}'s {:?} within {} ms, failing it ({} so far)",
+                user_id,
+                transaction_type,
+                state.config().database.acquire_timeout_ms,
+                timeouts
+            );
+            Ok(Some(unknown_error))
+        }
+        Err(e) => match e.downcast_ref::<ProtocolError>() {
+            Some(ProtocolError::InvalidUtf8 { field }) => {
+                tracing::warn!("User {} sent invalid UTF-8 in field {:?}", user_id, field);
+                Ok(Some(invalid_parameter))
+            }
+            _ => Err(e),
+        },
+        result => result,
+    }
+}
+
+/// Make sure a reply answers the request it was produced for
+///
+/// Server-initiated transactions a handler returns instead (a rejected chat
+/// invite, chat command output) are left alone; they use ID 0.
+fn correlate_reply(
+    mut reply: Transaction,
+    request_id: u32,
+    request_type: TransactionType,
+    user_id: u16,
+) -> Transaction {
+    if reply.is_reply && (reply.id != request_id || reply.transaction_type != request_type) {
+        tracing::error!(
+            "Reply to user {}'s {:?} (ID {}) was built as {:?} (ID {}); correcting it",
+            user_id,
+            request_type,
+            request_id,
+            reply.transaction_type,
+            reply.id
+        );
+        reply.id = request_id;
+        reply.transaction_type = request_type;
+    }
+    reply
+}
+
+/// Dispatch transaction to appropriate handler
+async fn dispatch_transaction(
+    transaction: Transaction,
+    user_id: u16,
+    state: Arc<ServerState>,
+) -> Result<Option<Transaction>> {
+    // Refuse transactions the sender's access doesn't permit
+    if let Some(denied) = authorization::authorize(&transaction, user_id, &state).await? {
+        return Ok(Some(denied));
+    }
+    
     match transaction.transaction_type {
+        TransactionType::Error => {
+            let result = handlers::error::handle_error(transaction, user_id).await?;
+            Ok(result)
+        }
+        
         TransactionType::Login => {
             let reply = handlers::login::handle_login(transaction, user_id, state).await?;
             Ok(Some(reply))
@@ -397,11 +740,66 @@ async fn handle_transaction(
             Ok(result)
         }
         
+        TransactionType::SendInstantMsg => {
+            let result = handlers::message::handle_send_instant_msg(transaction, user_id, state).await?;
+            Ok(result)
+        }
+        
+        TransactionType::InviteNewChat => {
+            let result = handlers::chat::handle_invite_new_chat(transaction, user_id, state).await?;
+            Ok(result)
+        }
+        
+        TransactionType::InviteToChat => {
+            let result = handlers::chat::handle_invite_to_chat(transaction, user_id, state).await?;
+            Ok(result)
+        }
+        
+        TransactionType::JoinChat => {
+            let result = handlers::chat::handle_join_chat(transaction, user_id, state).await?;
+            Ok(result)
+        }
+        
+        TransactionType::LeaveChat => {
+            let result = handlers::chat::handle_leave_chat(transaction, user_id, state).await?;
+            Ok(result)
+        }
+        
+        TransactionType::SetChatSubject => {
+            let result = handlers::chat::handle_set_chat_subject(transaction, user_id, state).await?;
+            Ok(result)
+        }
+        
         TransactionType::GetUserNameList => {
             let result = handlers::user_list::handle_get_user_name_list(transaction, user_id, state).await?;
             Ok(result)
         }
         
+        TransactionType::GetFileNameList => {
+            let result = handlers::file_list::handle_get_file_name_list(transaction, user_id, state).await?;
+            Ok(result)
+        }
+        
+        TransactionType::DownloadFile => {
+            let result = handlers::download::handle_download_file(transaction, user_id, state).await?;
+            Ok(result)
+        }
+        
+        TransactionType::UploadFile => {
+            let result = handlers::upload::handle_upload_file(transaction, user_id, state).await?;
+            Ok(result)
+        }
+        
+        TransactionType::DownloadFolder => {
+            let result = handlers::download::handle_download_folder(transaction, user_id, state).await?;
+            Ok(result)
+        }
+        
+        TransactionType::UploadFolder => {
+            let result = handlers::upload::handle_upload_folder(transaction, user_id, state).await?;
+            Ok(result)
+        }
+        
         // Account management
         TransactionType::NewUser => {
             let reply = handlers::account::handle_new_user(transaction, user_id, state).await?;
@@ -423,11 +821,26 @@ async fn handle_transaction(
             Ok(Some(reply))
         }
         
+        TransactionType::KeepConnectionAlive => {
+            let result = handlers::keepalive::handle_keep_connection_alive(transaction, state).await?;
+            Ok(result)
+        }
+        
         TransactionType::GetClientInfoText => {
             let reply = handlers::user_info::handle_get_client_info_text(transaction, user_id, state).await?;
             Ok(reply)
         }
         
+        TransactionType::SetClientUserInfo => {
+            let result = handlers::user_info::handle_set_client_user_info(transaction, user_id, state).await?;
+            Ok(result)
+        }
+        
+        TransactionType::UserAccess => {
+            let result = handlers::user_info::handle_user_access(transaction, user_id, state).await?;
+            Ok(result)
+        }
+        
         _ => {
             tracing::warn!(
                 "User {} sent unhandled transaction type: {:?}",
@@ -438,3 +851,495 @@ async fn handle_transaction(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{test_config, test_db_path, test_state};
+    use rhxcore::password::xor_password;
+    use rhxcore::protocol::{Field, FieldId, MAX_TRANSACTION_SIZE};
+    use rhxcore::types::AccessPrivileges;
+    use std::sync::Mutex;
+    use tracing_subscriber::fmt::MakeWriter;
+    
+    /// In-memory log sink for asserting on handler output
+    #[derive(Clone, Default)]
+    struct LogBuffer(Arc<Mutex<Vec<u8>>>);
+    
+    impl LogBuffer {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+    
+    impl std::io::Write for LogBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    
+    impl<'a> MakeWriter<'a> for LogBuffer {
+        type Writer = LogBuffer;
+        
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+    
+    /// Route log output for the current thread into a buffer
+    fn capture_logs() -> (LogBuffer, tracing::subscriber::DefaultGuard) {
+        let logs = LogBuffer::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(logs.clone())
+            .with_max_level(tracing::Level::DEBUG)
+            .with_ansi(false)
+            .finish();
+        let guard = tracing::subscriber::set_default(subscriber);
+        (logs, guard)
+    }
+    
+    #[tokio::test]
+    async fn test_pool_exhaustion_fails_request() {
+        // A file database, since an in-memory one ignores the pool settings
+        let db_path = test_db_path("handler_pool_exhaustion");
+        let mut config = test_config();
+        config.database.path = db_path.to_path_buf();
+        config.database.max_connections = 1;
+        config.database.acquire_timeout_ms = 50;
+        let state = Arc::new(ServerState::new(config).await.unwrap());
+        
+        let account_id = state.accounts
+            .create_account("alice", b"pw", "Alice", AccessPrivileges::admin())
+            .await
+            .unwrap();
+        let mut session = Session::new(5, "127.0.0.1:5500".parse().unwrap());
+        session.authenticate_user(account_id, AccessPrivileges::admin(), "Alice".to_string(), 0);
+        session.agree();
+        state.register_session(session);
+        
+        // Hold the only connection, so looking up the account has to wait
+        let _held = state.database.pool().acquire().await.unwrap();
+        
+        let mut transaction = Transaction::new(TransactionType::GetUser);
+        transaction.id = 7;
+        transaction.add_field(Field::binary(FieldId::UserLogin, xor_password(b"alice")));
+        let reply = tokio::time::timeout(Duration::from_secs(5), handle_transaction(transaction, 5, state.clone()))
+            .await
+            .expect("Handler hung waiting for a connection")
+            .unwrap()
+            .expect("Expected an error reply");
+        
+        assert_eq!(reply.id, 7);
+        assert_eq!(reply.error_code, ErrorCode::UnknownError.to_u32());
+        assert_eq!(state.db_pool_timeouts.load(Ordering::Relaxed), 1);
+    }
+    
+    #[tokio::test]
+    async fn test_authorization_uses_cached_access() {
+        let db_path = test_db_path("handler_cached_access");
+        let mut config = test_config();
+        config.database.path = db_path.to_path_buf();
+        config.database.max_connections = 1;
+        config.database.acquire_timeout_ms = 50;
+        let state = Arc::new(ServerState::new(config).await.unwrap());
+        
+        let account_id = state.accounts
+            .create_account("alice", b"pw", "Alice", AccessPrivileges::user())
+            .await
+            .unwrap();
+        let mut session = Session::new(5, "127.0.0.1:5500".parse().unwrap());
+        session.authenticate_user(account_id, AccessPrivileges::user(), "Alice".to_string(), 0);
+        session.agree();
+        state.register_session(session);
+        
+        let chat = |id| {
+            let mut transaction = Transaction::new(TransactionType::SendChat);
+            transaction.id = id;
+            transaction.add_field(Field::binary(FieldId::Data, b"hello".to_vec()));
+            transaction
+        };
+        
+        // Checking Alice may chat doesn't need the database
+        let _held = state.database.pool().acquire().await.unwrap();
+        assert!(handle_transaction(chat(8), 5, state.clone()).await.unwrap().is_none());
+        
+        // Changes made through the server apply at once
+        state.notify_access_changed(account_id, AccessPrivileges::READ_CHAT);
+        let reply = handle_transaction(chat(9), 5, state.clone()).await.unwrap().unwrap();
+        assert_eq!(reply.error_code, ErrorCode::PermissionDenied.to_u32());
+        
+        state.notify_account_deleted(account_id);
+        assert_eq!(state.user_access(5), state.config().security.guest_access());
+    }
+    
+    #[tokio::test]
+    async fn test_login_latency_is_recorded() {
+        let state = test_state(|config| {
+            config.security.allow_guest = true;
+        })
+        .await;
+        
+        for _ in 0..3 {
+            let user_id = state.allocate_user_id();
+            let mut session = Session::new(user_id, "127.0.0.1:5500".parse().unwrap());
+            session.complete_handshake();
+            state.register_session(session);
+            let reply = handle_transaction(Transaction::new(TransactionType::Login), user_id, state.clone())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(reply.error_code, 0);
+        }
+        
+        let (count, sum) = state.handler_metrics.summary(TransactionType::Login);
+        assert_eq!(count, 3);
+        assert!(sum > Duration::ZERO);
+        assert!(state.handler_metrics.render().contains("rhxd_handler_seconds_count{type=\"Login\"} 3"));
+    }
+    
+    #[tokio::test]
+    async fn test_download_refused_with_transfers_disabled() {
+        let state = test_state(|_| {}).await;
+        assert!(!state.config().features.enable_file_transfers);
+        
+        let mut session = Session::new(3, "127.0.0.1:5500".parse().unwrap());
+        session.authenticate_guest("Guest".to_string(), 0);
+        session.agree();
+        state.register_session(session);
+        
+        let mut request = Transaction::new(TransactionType::DownloadFile);
+        request.id = 9;
+        request.add_field(Field::string(FieldId::FileName, "readme.txt"));
+        let reply = handle_transaction(request, 3, state.clone()).await.unwrap().unwrap();
+        
+        assert_eq!(reply.id, 9);
+        assert_eq!(reply.error_code, ErrorCode::PermissionDenied.to_u32());
+        let text = reply.get_field(FieldId::Data).and_then(|f| f.as_string());
+        assert_eq!(text, Some("File transfers are disabled on this server"));
+        assert!(state.downloads.is_empty());
+    }
+    
+    #[test]
+    fn test_replies_take_the_request_id() {
+        let mut reply = Transaction::new(TransactionType::GetUserNameList);
+        reply.is_reply = true;
+        let reply = correlate_reply(reply, 42, TransactionType::GetUserNameList, 1);
+        assert_eq!(reply.id, 42);
+        
+        // Server-initiated transactions keep ID 0
+        let notice = create_server_transaction(TransactionType::ServerMessage, vec![]);
+        let notice = correlate_reply(notice, 42, TransactionType::SendChat, 1);
+        assert_eq!(notice.id, 0);
+        assert_eq!(notice.transaction_type, TransactionType::ServerMessage);
+    }
+    
+    #[tokio::test]
+    async fn test_client_error_is_logged_without_reply() {
+        let (logs, _guard) = capture_logs();
+        let state = test_state(|_| {}).await;
+        
+        let mut transaction = Transaction::new(TransactionType::Error);
+        transaction.error_code = ErrorCode::InvalidParameter.to_u32();
+        transaction.add_field(Field::string(FieldId::ErrorText, "Unexpected field"));
+        
+        let reply = handle_transaction(transaction, 1, state).await.unwrap();
+        assert!(reply.is_none());
+        
+        let output = logs.contents();
+        assert!(output.contains("User 1 reported error 5: Unexpected field"));
+        assert!(!output.contains("unhandled"));
+    }
+    
+    #[tokio::test]
+    async fn test_guest_new_user_rejected_before_handler() {
+        let (logs, _guard) = capture_logs();
+        let state = test_state(|_| {}).await;
+        
+        let mut session = Session::new(4, "127.0.0.1:5500".parse().unwrap());
+        session.authenticate_guest("Guest".to_string(), 0);
+        session.agree();
+        state.register_session(session);
+        
+        let mut transaction = Transaction::new(TransactionType::NewUser);
+        transaction.id = 9;
+        transaction.add_field(Field::string(FieldId::UserName, "Intruder"));
+        
+        let reply = handle_transaction(transaction, 4, state)
+            .await
+            .unwrap()
+            .expect("Expected a permission error reply");
+        
+        assert!(reply.is_reply);
+        assert_eq!(reply.id, 9);
+        assert_eq!(reply.error_code, ErrorCode::PermissionDenied.to_u32());
+        
+        let output = logs.contents();
+        assert!(output.contains("User 4 is not permitted to send NewUser"));
+        assert!(!output.contains("attempting to create new account"));
+    }
+    
+    #[tokio::test]
+    async fn test_privilege_override_applies_to_account_handlers() {
+        let state = test_state(|config| {
+            config.security.transaction_privileges
+                .insert("DeleteUser".to_string(), vec!["DISCONNECT_USERS".to_string()]);
+        })
+        .await;
+        
+        let moderator_id = state.accounts
+            .create_account("mod", b"pw", "Moderator", AccessPrivileges::user() | AccessPrivileges::DISCONNECT_USERS)
+            .await
+            .unwrap();
+        state.accounts
+            .create_account("spammer", b"pw", "Spammer", AccessPrivileges::user())
+            .await
+            .unwrap();
+        let mut session = Session::new(6, "127.0.0.1:5500".parse().unwrap());
+        session.authenticate_user(moderator_id, AccessPrivileges::user() | AccessPrivileges::DISCONNECT_USERS, "Moderator".to_string(), 0);
+        session.agree();
+        state.register_session(session);
+        
+        // DELETE_USERS isn't needed once the override replaces it
+        let mut transaction = Transaction::new(TransactionType::DeleteUser);
+        transaction.id = 14;
+        transaction.add_field(Field::binary(FieldId::UserLogin, xor_password(b"spammer")));
+        
+        let reply = handle_transaction(transaction, 6, state.clone()).await.unwrap().unwrap();
+        assert_eq!(reply.id, 14);
+        assert_eq!(reply.error_code, 0);
+        assert!(state.accounts.get_account_by_login("spammer").await.unwrap().is_none());
+    }
+    
+    #[tokio::test]
+    async fn test_chat_before_login_rejected() {
+        let state = test_state(|_| {}).await;
+        
+        let mut session = Session::new(5, "127.0.0.1:5500".parse().unwrap());
+        session.complete_handshake();
+        state.register_session(session);
+        
+        let chat = || {
+            let mut transaction = Transaction::new(TransactionType::SendChat);
+            transaction.id = 11;
+            transaction.add_field(Field::binary(FieldId::Data, b"hello".to_vec()));
+            transaction
+        };
+        
+        let reply = handle_transaction(chat(), 5, state.clone()).await.unwrap().unwrap();
+        assert_eq!(reply.id, 11);
+        assert_eq!(reply.error_code, ErrorCode::PermissionDenied.to_u32());
+        
+        // Logging in isn't enough; the agreement has to be accepted first
+        state.update_session(5, |session| session.authenticate_guest("Guest".to_string(), 0)).unwrap();
+        let reply = handle_transaction(chat(), 5, state.clone()).await.unwrap().unwrap();
+        assert_eq!(reply.error_code, ErrorCode::PermissionDenied.to_u32());
+        
+        state.update_session(5, |session| session.agree()).unwrap();
+        let reply = handle_transaction(chat(), 5, state).await.unwrap();
+        assert!(reply.is_none());
+    }
+    
+    #[tokio::test]
+    async fn test_keepalive_reply_is_opt_in() {
+        let state = test_state(|_| {}).await;
+        
+        let mut session = Session::new(5, "127.0.0.1:5500".parse().unwrap());
+        session.complete_handshake();
+        state.register_session(session);
+        
+        let keepalive = || {
+            let mut transaction = Transaction::new(TransactionType::KeepConnectionAlive);
+            transaction.id = 14;
+            transaction
+        };
+        
+        let reply = handle_transaction(keepalive(), 5, state.clone()).await.unwrap();
+        assert!(reply.is_none());
+        
+        let mut config = (*state.config()).clone();
+        config.features.keepalive_reply = true;
+        state.reload_config(config);
+        
+        let reply = handle_transaction(keepalive(), 5, state).await.unwrap().unwrap();
+        assert!(reply.is_reply);
+        assert_eq!(reply.id, 14);
+        assert_eq!(reply.error_code, 0);
+        
+        let timestamp = reply.get_field(FieldId::Data).and_then(|f| f.as_date()).unwrap();
+        let skew = chrono::Utc::now() - timestamp;
+        assert!(skew.num_seconds().abs() < 60);
+    }
+    
+    #[tokio::test]
+    async fn test_disabled_transaction_rejected_for_admin() {
+        let state = test_state(|config| {
+            config.features.disabled_transactions = vec!["NewUser".to_string()];
+        })
+        .await;
+        
+        let account_id = state.accounts
+            .create_account("root", b"pw", "Root", AccessPrivileges::admin())
+            .await
+            .unwrap();
+        let mut session = Session::new(6, "127.0.0.1:5500".parse().unwrap());
+        session.authenticate_user(account_id, AccessPrivileges::admin(), "Root".to_string(), 0);
+        session.agree();
+        state.register_session(session);
+        
+        let mut transaction = Transaction::new(TransactionType::NewUser);
+        transaction.id = 12;
+        transaction.add_field(Field::string(FieldId::UserLogin, "newbie"));
+        transaction.add_field(Field::string(FieldId::UserName, "Newbie"));
+        
+        let reply = handle_transaction(transaction, 6, state.clone()).await.unwrap().unwrap();
+        assert_eq!(reply.id, 12);
+        assert_eq!(reply.error_code, ErrorCode::PermissionDenied.to_u32());
+        assert!(state.accounts.get_account_by_login("newbie").await.unwrap().is_none());
+    }
+    
+    #[tokio::test]
+    async fn test_read_only_refuses_writes_from_admin() {
+        let state = test_state(|config| {
+            config.files.root_path = std::env::temp_dir();
+            config.features.enable_file_transfers = true;
+            config.server.read_only = true;
+        })
+        .await;
+        
+        let account_id = state.accounts
+            .create_account("root", b"pw", "Root", AccessPrivileges::all())
+            .await
+            .unwrap();
+        let mut session = Session::new(6, "127.0.0.1:5500".parse().unwrap());
+        session.authenticate_user(account_id, AccessPrivileges::all(), "Root".to_string(), 0);
+        session.agree();
+        state.register_session(session);
+        
+        let mut upload = Transaction::new(TransactionType::UploadFile);
+        upload.id = 1;
+        upload.add_field(Field::string(FieldId::FileName, "read_only_upload.txt"));
+        let reply = handle_transaction(upload, 6, state.clone()).await.unwrap().unwrap();
+        assert_eq!(reply.error_code, ErrorCode::PermissionDenied.to_u32());
+        let text = reply.get_field(FieldId::Data).and_then(|f| f.as_string());
+        assert_eq!(text, Some("This server is read-only"));
+        assert!(state.uploads.is_empty());
+        
+        let mut new_user = Transaction::new(TransactionType::NewUser);
+        new_user.id = 2;
+        new_user.add_field(Field::string(FieldId::UserLogin, "newbie"));
+        new_user.add_field(Field::string(FieldId::UserName, "Newbie"));
+        let reply = handle_transaction(new_user, 6, state.clone()).await.unwrap().unwrap();
+        assert_eq!(reply.error_code, ErrorCode::PermissionDenied.to_u32());
+        assert!(state.accounts.get_account_by_login("newbie").await.unwrap().is_none());
+        
+        let mut list = Transaction::new(TransactionType::GetFileNameList);
+        list.id = 3;
+        let reply = handle_transaction(list, 6, state.clone()).await.unwrap().unwrap();
+        assert_eq!(reply.error_code, 0);
+        
+        let mut tap = state.subscribe_raw();
+        let mut chat = Transaction::new(TransactionType::SendChat);
+        chat.id = 4;
+        chat.add_field(Field::binary(FieldId::Data, b"still here".to_vec()));
+        assert!(handle_transaction(chat, 6, state.clone()).await.unwrap().is_none());
+        assert!(tap
+            .drain()
+            .iter()
+            .any(|sent| matches!(sent.message, BroadcastMessage::ChatMessage { .. })));
+    }
+    
+    /// Register a logged-in session and make a subscriber miss broadcasts
+    async fn lagged_state(policy: LagPolicy) -> (Arc<ServerState>, u64) {
+        let state = test_state(|config| {
+            config.features.broadcast_buffer = Some(2);
+            config.features.broadcast_lag_policy = policy;
+        })
+        .await;
+        
+        for id in [1, 2] {
+            let mut session = Session::new(id, "127.0.0.1:5500".parse().unwrap());
+            session.authenticate_guest(format!("Guest {}", id), 0);
+            state.register_session(session);
+        }
+        state.set_chat_subject("Lobby".to_string());
+        
+        let mut rx = state.broadcast_tx.subscribe();
+        for n in 0..5 {
+            state.broadcast(BroadcastMessage::ServerMessage { message: format!("Message {}", n) });
+        }
+        
+        let skipped = match rx.recv().await {
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => skipped,
+            other => panic!("Expected the subscriber to lag, got {:?}", other.map(|_| ())),
+        };
+        (state, skipped)
+    }
+    
+    #[tokio::test]
+    async fn test_lag_resync_resends_users_and_subject() {
+        let (state, skipped) = lagged_state(LagPolicy::Resync).await;
+        assert_eq!(skipped, 3);
+        
+        let resync = lag_recovery(&state, 1, skipped, MAX_TRANSACTION_SIZE).expect("Resync policy disconnected");
+        assert_eq!(resync.len(), 2);
+        assert_eq!(resync[0].transaction_type, TransactionType::NotifyChangeUser);
+        assert_eq!(resync[0].get_all(FieldId::UserNameWithInfo).count(), 2);
+        assert_eq!(resync[1].transaction_type, TransactionType::NotifyChatSubject);
+    }
+    
+    #[tokio::test]
+    async fn test_lag_resync_splits_large_user_lists() {
+        let (state, skipped) = lagged_state(LagPolicy::Resync).await;
+        for id in 3..1000 {
+            let mut session = Session::new(id, "127.0.0.1:5500".parse().unwrap());
+            session.authenticate_guest(format!("Guest with a long nickname {}", id), 0);
+            state.register_session(session);
+        }
+        
+        let resync = lag_recovery(&state, 1, skipped, MAX_TRANSACTION_SIZE).expect("Resync policy disconnected");
+        let (users, subject) = resync.split_at(resync.len() - 1);
+        assert!(users.len() > 1);
+        assert!(users.iter().all(|tx| tx.check_size(MAX_TRANSACTION_SIZE).is_ok()));
+        let sent: usize = users.iter().map(|tx| tx.get_all(FieldId::UserNameWithInfo).count()).sum();
+        assert_eq!(sent, 999);
+        assert_eq!(subject[0].transaction_type, TransactionType::NotifyChatSubject);
+    }
+    
+    #[tokio::test]
+    async fn test_user_list_delta_split_by_size() {
+        let state = test_state(|_| {}).await;
+        let mut delta = UserListDelta::default();
+        for id in 1..1000 {
+            let mut session = Session::new(id, "127.0.0.1:5500".parse().unwrap());
+            session.authenticate_guest(format!("Guest with a long nickname {}", id), 0);
+            state.register_session(session);
+            delta.changed.push((id, true));
+        }
+        delta.left = (1000..20000).collect();
+        
+        let batches = user_list_delta(&state, 1, &delta, MAX_TRANSACTION_SIZE);
+        assert!(batches.iter().all(|tx| tx.check_size(MAX_TRANSACTION_SIZE).is_ok()));
+        
+        let (deleted, changed): (Vec<_>, Vec<_>) = batches
+            .iter()
+            .partition(|tx| tx.transaction_type == TransactionType::NotifyDeleteUser);
+        assert!(deleted.len() > 1 && changed.len() > 1);
+        assert!(batches[..deleted.len()].iter().all(|tx| tx.transaction_type == TransactionType::NotifyDeleteUser));
+        assert_eq!(deleted.iter().map(|tx| tx.get_all(FieldId::UserId).count()).sum::<usize>(), 19000);
+        
+        // User 1's own join isn't announced to them
+        let announced: usize = changed.iter().map(|tx| tx.get_all(FieldId::UserNameWithInfo).count()).sum();
+        assert_eq!(announced, 998);
+    }
+    
+    #[tokio::test]
+    async fn test_lag_disconnect_policy() {
+        let (state, skipped) = lagged_state(LagPolicy::Disconnect).await;
+        assert!(lag_recovery(&state, 1, skipped, MAX_TRANSACTION_SIZE).is_none());
+    }
+}
@@ -1,5 +1,7 @@
 //! Connection handling
 
+pub mod authorization;
+pub mod capture;
 pub mod handler;
 pub mod session;
 pub mod transaction_helpers;
@@ -1,18 +1,21 @@
 //! Session management
 
-use rhxcore::types::UserOptions;
+use rhxcore::types::{AccessPrivileges, User, UserOptions};
+use serde::Serialize;
 use std::net::SocketAddr;
 use std::time::SystemTime;
 
 /// Authentication state for a session
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
 pub enum AuthState {
     /// Connection established, waiting for handshake
     Handshake,
     /// Handshake completed, waiting for login
     LoginPending,
-    /// Authenticated (either logged in or guest)
+    /// Authenticated (either logged in or guest), waiting for Agreed
     Authenticated,
+    /// Agreement accepted; the client may use the server
+    Agreed,
 }
 
 /// Represents a connected client session
@@ -24,6 +27,11 @@ pub struct Session {
     /// Database account ID (None for guests)
     pub account_id: Option<i64>,
 
+    /// Access of the account, read at login and kept current by
+    /// `ServerState::notify_access_changed` (None for guests, and accounts
+    /// deleted while connected, who get `security.guest_access`)
+    pub access: Option<AccessPrivileges>,
+
     /// Display nickname
     pub nickname: String,
 
@@ -36,17 +44,35 @@ pub struct Session {
     /// User options (refuse private messages, refuse private chat, automatic response)
     pub options: UserOptions,
 
+    /// Reply sent automatically to private messages while
+    /// `UserOptions::AUTOMATIC_RESPONSE` is set
+    pub auto_response: Option<String>,
+
     /// Client IP address
     pub address: SocketAddr,
 
     /// Connection timestamp
     pub connected_at: SystemTime,
 
-    /// Last activity timestamp
+    /// Last time the client sent us anything (handshake or transaction,
+    /// keep-alives included); data we send never counts, so this measures
+    /// genuine inactivity for the idle/away time
     pub last_activity: SystemTime,
 
     /// Authentication state
     pub auth_state: AuthState,
+
+    /// Whether the away flag was set by idle detection rather than the user
+    pub auto_away: bool,
+
+    /// Whether the inactivity warning was sent since the last activity
+    pub idle_warned: bool,
+
+    /// Sub-protocol ID from the handshake (0 for most clients)
+    pub sub_protocol_id: u32,
+
+    /// Version the client sent with Login (field 160), if any
+    pub client_version: Option<u16>,
 }
 
 impl Session {
@@ -56,14 +82,20 @@ impl Session {
         Self {
             user_id,
             account_id: None,
+            access: None,
             nickname: format!("Guest {}", user_id),
             icon_id: 0,
             flags: 0,
             options: UserOptions::default(),
+            auto_response: None,
             address,
             connected_at: now,
             last_activity: now,
             auth_state: AuthState::Handshake,
+            auto_away: false,
+            idle_warned: false,
+            sub_protocol_id: 0,
+            client_version: None,
         }
     }
 
@@ -75,8 +107,9 @@ impl Session {
     }
 
     /// Authenticate as a logged-in user
-    pub fn authenticate_user(&mut self, account_id: i64, nickname: String, icon_id: u16) {
+    pub fn authenticate_user(&mut self, account_id: i64, access: AccessPrivileges, nickname: String, icon_id: u16) {
         self.account_id = Some(account_id);
+        self.access = Some(access);
         self.nickname = nickname;
         self.icon_id = icon_id;
         self.auth_state = AuthState::Authenticated;
@@ -87,14 +120,50 @@ impl Session {
         self.auth_state = AuthState::LoginPending;
     }
 
-    /// Update last activity timestamp
+    /// Mark the agreement as accepted
+    pub fn agree(&mut self) {
+        if self.auth_state == AuthState::Authenticated {
+            self.auth_state = AuthState::Agreed;
+        }
+    }
+
+    /// Apply the options a client sent in Agreed (field 113)
+    ///
+    /// The auto-response text (field 215) is only kept while the
+    /// automatic response option is on.
+    pub fn apply_options(&mut self, options: UserOptions, auto_response: Option<String>) {
+        self.options = options;
+        self.auto_response = if options.contains(UserOptions::AUTOMATIC_RESPONSE) {
+            auto_response.filter(|text| !text.is_empty())
+        } else {
+            None
+        };
+    }
+
+    /// Text to send back automatically for a private message, if any
+    pub fn automatic_response(&self) -> Option<&str> {
+        self.auto_response.as_deref()
+    }
+
+    /// Record inbound client activity
     pub fn touch(&mut self) {
         self.last_activity = SystemTime::now();
+        self.idle_warned = false;
     }
 
-    /// Check if the session is authenticated
+    /// Check if the session is authenticated (whether or not it has agreed yet)
     pub fn is_authenticated(&self) -> bool {
-        self.auth_state == AuthState::Authenticated
+        matches!(self.auth_state, AuthState::Authenticated | AuthState::Agreed)
+    }
+
+    /// User details as shown in the user list
+    pub fn to_user(&self) -> User {
+        User {
+            id: self.user_id,
+            icon_id: self.icon_id as i16,
+            flags: self.flags,
+            name: self.nickname.clone(),
+        }
     }
 
     /// Check if the session is a guest
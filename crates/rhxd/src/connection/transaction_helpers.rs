@@ -1,5 +1,6 @@
 //! Helper functions for creating common transaction patterns
 
+use rhxcore::protocol::field::FieldHeader;
 use rhxcore::protocol::{ErrorCode, Field, Transaction, TransactionType};
 
 /// Create a server-initiated transaction (no reply expected)
@@ -27,6 +28,38 @@ pub fn create_server_transaction(
     }
 }
 
+/// Create as many server-initiated transactions as it takes to carry
+/// `fields` with at most `max_size` bytes of field data each
+///
+/// For notifications that repeat one field per item, like a user list, which
+/// can outgrow a single transaction on a busy server. No fields means no
+/// transactions.
+pub fn create_server_transactions(
+    transaction_type: TransactionType,
+    fields: Vec<Field>,
+    max_size: usize,
+) -> Vec<Transaction> {
+    let mut transactions = Vec::new();
+    let mut batch = Vec::new();
+    // The u16 field count comes first
+    let mut size = 2;
+    
+    for field in fields {
+        let field_size = FieldHeader::SIZE + field.encoded_len();
+        if !batch.is_empty() && size + field_size > max_size {
+            transactions.push(create_server_transaction(transaction_type, std::mem::take(&mut batch)));
+            size = 2;
+        }
+        size += field_size;
+        batch.push(field);
+    }
+    
+    if !batch.is_empty() {
+        transactions.push(create_server_transaction(transaction_type, batch));
+    }
+    transactions
+}
+
 /// Create an error reply transaction
 ///
 /// Error replies always have:
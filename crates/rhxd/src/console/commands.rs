@@ -1,13 +1,22 @@
 //! Console command definitions and execution
 
 use anyhow::{anyhow, bail, Result};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::SystemTime;
 
-use crate::db::accounts::{create_account, delete_account, get_account_by_login, list_accounts, update_access};
+use crate::audit::{self, AccountChange};
+use crate::connection::session::AuthState;
+use crate::db::accounts::hash_stored_password;
 use crate::state::{BroadcastMessage, ServerState};
-use rhxcore::password::xor_password;
 use rhxcore::types::AccessPrivileges;
 
+/// Lines `chat-tail` shows when no count is given
+const DEFAULT_CHAT_TAIL_LINES: usize = 20;
+
 /// Console commands
 #[derive(Debug, Clone)]
 pub enum Command {
@@ -34,11 +43,17 @@ pub enum Command {
     UserKick { target: String },
     
     /// User management: list
-    UserList,
+    UserList {
+        sort: UserSort,
+        filter: UserFilter,
+    },
     
     /// Broadcast a message to all connected users
     Broadcast { message: String },
     
+    /// Show the last lines of the chat log
+    ChatTail { lines: usize },
+    
     /// Show help
     Help,
     
@@ -48,8 +63,11 @@ pub enum Command {
 
 impl Command {
     /// Parse a command from user input
+    ///
+    /// Arguments are split like a shell would, see [`split_args`].
     pub fn parse(input: &str) -> Result<Self> {
-        let parts: Vec<&str> = input.split_whitespace().collect();
+        let args = split_args(input)?;
+        let parts: Vec<&str> = args.iter().map(String::as_str).collect();
         
         if parts.is_empty() {
             bail!("Empty command");
@@ -112,7 +130,7 @@ impl Command {
             
             "user" => {
                 if parts.len() < 2 {
-                    bail!("Usage: user <kick|list>");
+                    bail!("Usage: user <kick|list> [options]");
                 }
                 
                 match parts[1] {
@@ -126,7 +144,7 @@ impl Command {
                     }
                     
                     "list" => {
-                        Ok(Command::UserList)
+                        parse_user_list(&parts[2..])
                     }
                     
                     _ => {
@@ -144,6 +162,14 @@ impl Command {
                 Ok(Command::Broadcast { message })
             }
             
+            "chat-tail" => {
+                let lines = match parts.get(1) {
+                    Some(n) => n.parse().map_err(|_| anyhow!("Usage: chat-tail [lines]"))?,
+                    None => DEFAULT_CHAT_TAIL_LINES,
+                };
+                Ok(Command::ChatTail { lines })
+            }
+            
             "help" => {
                 Ok(Command::Help)
             }
@@ -157,21 +183,257 @@ impl Command {
             }
         }
     }
+    
+    /// Parse a command typed in chat (with the command prefix already stripped)
+    ///
+    /// Accepts the short chat aliases (`users`, `accounts`, `kick <target>`)
+    /// as well as the full console syntax.
+    pub fn parse_chat(input: &str) -> Result<Self> {
+        let args = split_args(input)?;
+        let parts: Vec<&str> = args.iter().map(String::as_str).collect();
+        
+        let cmd = match parts.first().copied() {
+            Some("users") => parse_user_list(&parts[1..])?,
+            Some("accounts") => Command::AccountList,
+            Some("kick") => {
+                if parts.len() < 2 {
+                    bail!("Usage: kick <user_id|nickname>");
+                }
+                Command::UserKick {
+                    target: parts[1].to_string(),
+                }
+            }
+            _ => Command::parse(input)?,
+        };
+        
+        if matches!(cmd, Command::Stop) {
+            bail!("The server can only be stopped from the console");
+        }
+        
+        Ok(cmd)
+    }
+    
+    /// Access privilege a client needs to run this command remotely
+    pub fn required_privilege(&self) -> AccessPrivileges {
+        match self {
+            Command::AccountCreate { .. } => AccessPrivileges::CREATE_USERS,
+            Command::AccountAccessSet { .. } => AccessPrivileges::MODIFY_USERS,
+            Command::AccountDelete { .. } => AccessPrivileges::DELETE_USERS,
+            Command::AccountList => AccessPrivileges::OPEN_USER,
+            Command::UserKick { .. } | Command::UserList { .. } => AccessPrivileges::DISCONNECT_USERS,
+            Command::Broadcast { .. } => AccessPrivileges::BROADCAST,
+            Command::ChatTail { .. } => AccessPrivileges::DISCONNECT_USERS,
+            Command::Help => AccessPrivileges::empty(),
+            Command::Stop => AccessPrivileges::all(),
+        }
+    }
+}
+
+/// Split command input into arguments on whitespace
+///
+/// Double quotes group words into one argument (`"Display Name"`), and a
+/// backslash escapes a quote or backslash. Quotes may sit inside a word, as
+/// in `name="A B"`.
+pub fn split_args(input: &str) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_arg = false;
+    let mut in_quotes = false;
+    let mut chars = input.chars();
+    
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                match chars.next() {
+                    Some(escaped @ ('"' | '\\')) => current.push(escaped),
+                    Some(other) => {
+                        current.push('\\');
+                        current.push(other);
+                    }
+                    None => current.push('\\'),
+                }
+                in_arg = true;
+            }
+            '"' => {
+                in_quotes = !in_quotes;
+                in_arg = true;
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            c => {
+                current.push(c);
+                in_arg = true;
+            }
+        }
+    }
+    
+    if in_quotes {
+        bail!("Unterminated quote");
+    }
+    if in_arg {
+        args.push(current);
+    }
+    
+    Ok(args)
+}
+
+/// Order of `user list` output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UserSort {
+    /// By user ID
+    #[default]
+    Id,
+    /// By nickname, ignoring case
+    Name,
+    /// Longest idle first
+    Idle,
+}
+
+/// Which users `user list` shows
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UserFilter {
+    #[default]
+    All,
+    /// Users whose account has admin or sysop access
+    Admins,
+    /// Users logged in without an account
+    Guests,
+}
+
+/// Parse the options of `user list` (`by-id|by-name|by-idle`, `all|admins|guests`)
+fn parse_user_list(options: &[&str]) -> Result<Command> {
+    let mut sort = UserSort::default();
+    let mut filter = UserFilter::default();
+    
+    for option in options {
+        match *option {
+            "by-id" => sort = UserSort::Id,
+            "by-name" => sort = UserSort::Name,
+            "by-idle" => sort = UserSort::Idle,
+            "all" => filter = UserFilter::All,
+            "admins" => filter = UserFilter::Admins,
+            "guests" => filter = UserFilter::Guests,
+            _ => bail!("Unknown user list option: '{}'. Valid: by-id, by-name, by-idle, all, admins, guests", option),
+        }
+    }
+    
+    Ok(Command::UserList { sort, filter })
+}
+
+/// Connected user as reported by `user list`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UserSummary {
+    pub user_id: u16,
+    pub nickname: String,
+    pub address: SocketAddr,
+    pub auth_state: AuthState,
+    /// Seconds since the user last sent anything
+    pub idle_seconds: u64,
+}
+
+/// Account as reported by `account list`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AccountSummary {
+    pub id: i64,
+    pub login: String,
+    pub name: String,
+    pub access: AccessPrivileges,
+}
+
+/// Structured result of a command
+///
+/// Front ends (console, chat, admin HTTP) decide how to present it; the
+/// `Display` implementation is the plain-text rendering used by the console
+/// and chat.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandOutput {
+    /// Nothing to report
+    Empty,
+    /// Human-readable status message (may span several lines)
+    Message(String),
+    /// Connected users
+    Users(Vec<UserSummary>),
+    /// Server accounts
+    Accounts(Vec<AccountSummary>),
+}
+
+impl fmt::Display for CommandOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandOutput::Empty => Ok(()),
+            
+            CommandOutput::Message(message) => writeln!(f, "{}", message),
+            
+            CommandOutput::Users(users) => {
+                if users.is_empty() {
+                    return writeln!(f, "No users connected");
+                }
+                
+                writeln!(f, "\n{:<6} {:<20} {:<20} {:<14} {:>8}", "ID", "Nickname", "Address", "Auth State", "Idle (s)")?;
+                writeln!(f, "{}", "-".repeat(72))?;
+                
+                for user in users {
+                    writeln!(
+                        f,
+                        "{:<6} {:<20} {:<20} {:<14} {:>8}",
+                        user.user_id,
+                        user.nickname,
+                        user.address,
+                        format!("{:?}", user.auth_state),
+                        user.idle_seconds
+                    )?;
+                }
+                writeln!(f)
+            }
+            
+            CommandOutput::Accounts(accounts) => {
+                if accounts.is_empty() {
+                    return writeln!(f, "No accounts found");
+                }
+                
+                writeln!(f, "\n{:<5} {:<20} {:<20} {:<18}", "ID", "Login", "Name", "Privileges")?;
+                writeln!(f, "{}", "-".repeat(68))?;
+                
+                for account in accounts {
+                    writeln!(
+                        f,
+                        "{:<5} {:<20} {:<20} 0x{:016X}",
+                        account.id,
+                        account.login,
+                        account.name,
+                        account.access.bits()
+                    )?;
+                }
+                writeln!(f)
+            }
+        }
+    }
+}
+
+/// Execute a command
+///
+/// Returns structured output; the caller decides how to present it.
+pub async fn execute_command(cmd: Command, state: Arc<ServerState>) -> Result<CommandOutput> {
+    execute_command_as(cmd, state, "console").await
 }
 
-/// Execute a console command
-pub async fn execute_command(cmd: Command, state: Arc<ServerState>) -> Result<()> {
+/// Execute a command on behalf of `actor`, the name recorded in the audit trail
+pub async fn execute_command_as(cmd: Command, state: Arc<ServerState>, actor: &str) -> Result<CommandOutput> {
     match cmd {
         Command::AccountCreate { login, password, access_level } => {
-            cmd_create_account(&state, &login, &password, &access_level).await
+            cmd_create_account(&state, actor, &login, &password, &access_level).await
         }
         
         Command::AccountAccessSet { login, access_level } => {
-            cmd_set_access(&state, &login, &access_level).await
+            cmd_set_access(&state, actor, &login, &access_level).await
         }
         
         Command::AccountDelete { login } => {
-            cmd_delete_account(&state, &login).await
+            cmd_delete_account(&state, actor, &login).await
         }
         
         Command::AccountList => {
@@ -179,62 +441,73 @@ pub async fn execute_command(cmd: Command, state: Arc<ServerState>) -> Result<()
         }
         
         Command::UserKick { target } => {
-            cmd_kick(&state, &target).await
+            cmd_kick(&state, actor, &target).await
         }
         
-        Command::UserList => {
-            cmd_list_users(&state).await
+        Command::UserList { sort, filter } => {
+            cmd_list_users(&state, sort, filter).await
         }
         
         Command::Broadcast { message } => {
-            cmd_broadcast(&state, &message).await
+            Ok(cmd_broadcast(&state, &message))
+        }
+        
+        Command::ChatTail { lines } => {
+            cmd_chat_tail(&state, lines)
         }
         
         Command::Help => {
-            cmd_help();
-            Ok(())
+            Ok(CommandOutput::Message(HELP_TEXT.to_string()))
         }
         
         Command::Stop => {
             // Handled in console loop
-            Ok(())
+            Ok(CommandOutput::Empty)
         }
     }
 }
 
 /// Create a new account with specified privileges
-async fn cmd_create_account(state: &ServerState, login: &str, password: &str, access_level: &str) -> Result<()> {
+async fn cmd_create_account(state: &ServerState, actor: &str, login: &str, password: &str, access_level: &str) -> Result<CommandOutput> {
     // Check if account already exists
-    if get_account_by_login(state.database.pool(), login).await?.is_some() {
+    if state.accounts.get_account_by_login(login).await?.is_some() {
         bail!("Account '{}' already exists", login);
     }
     
+    if !state.accepts_account().await? {
+        bail!("Account limit reached (security.max_accounts is {})", state.config().security.max_accounts);
+    }
+    
     // Parse access level
     let access = AccessPrivileges::from_preset(access_level)
         .ok_or_else(|| anyhow!("Invalid access level '{}'. Valid options: admin, sysop, user, guest", access_level))?;
     
     // Hash password
-    let password_hash = xor_password(password.as_bytes());
+    let password_hash = hash_stored_password(password.as_bytes(), state.config().security.password_scheme).await?;
     
     // Create account
-    let account_id = create_account(
-        state.database.pool(),
+    let account_id = state.accounts.create_account(
         login,
         &password_hash,
         login, // Use login as name
         access,
     ).await?;
     
-    println!("Created account: {} (ID: {})", login, account_id);
-    println!("Access level: {} (0x{:016X})", access_level, access.bits());
+    audit::record(state.audit.account_changed(actor, login, AccountChange::Created { access })).await;
     
-    Ok(())
+    Ok(CommandOutput::Message(format!(
+        "Created account: {} (ID: {})\nAccess level: {} (0x{:016X})",
+        login,
+        account_id,
+        access_level,
+        access.bits()
+    )))
 }
 
 /// Set access privileges for an existing account
-async fn cmd_set_access(state: &ServerState, login: &str, access_level: &str) -> Result<()> {
+async fn cmd_set_access(state: &ServerState, actor: &str, login: &str, access_level: &str) -> Result<CommandOutput> {
     // Check if account exists
-    let account = get_account_by_login(state.database.pool(), login)
+    let account = state.accounts.get_account_by_login(login)
         .await?
         .ok_or_else(|| anyhow!("Account '{}' not found", login))?;
     
@@ -243,59 +516,61 @@ async fn cmd_set_access(state: &ServerState, login: &str, access_level: &str) ->
         .ok_or_else(|| anyhow!("Invalid access level '{}'. Valid options: admin, sysop, user, guest", access_level))?;
     
     // Update access
-    update_access(state.database.pool(), account.id, access).await?;
-    
-    println!("Updated access for account: {} (ID: {})", login, account.id);
-    println!("New access level: {} (0x{:016X})", access_level, access.bits());
+    if !state.accounts.update_access_keeping_admin(account.id, access).await? {
+        bail!("Refusing to remove admin access from '{}', the last account that can manage users", login);
+    }
+    audit::record(state.audit.account_changed(actor, login, AccountChange::AccessChanged { access })).await;
+    state.notify_access_changed(account.id, access);
     
-    Ok(())
+    Ok(CommandOutput::Message(format!(
+        "Updated access for account: {} (ID: {})\nNew access level: {} (0x{:016X})",
+        login,
+        account.id,
+        access_level,
+        access.bits()
+    )))
 }
 
 /// Delete an account by login
-async fn cmd_delete_account(state: &ServerState, login: &str) -> Result<()> {
+async fn cmd_delete_account(state: &ServerState, actor: &str, login: &str) -> Result<CommandOutput> {
     // Check if account exists
-    let account = get_account_by_login(state.database.pool(), login)
+    let account = state.accounts.get_account_by_login(login)
         .await?
         .ok_or_else(|| anyhow!("Account '{}' not found", login))?;
     
     // Delete the account
-    delete_account(state.database.pool(), account.id).await?;
-    
-    println!("Deleted account: {} (ID: {})", login, account.id);
+    if !state.accounts.delete_account_keeping_admin(account.id).await? {
+        bail!("Refusing to delete '{}', the last account that can manage users", login);
+    }
+    audit::record(state.audit.account_changed(actor, login, AccountChange::Deleted)).await;
+    state.notify_account_deleted(account.id);
     
-    Ok(())
+    Ok(CommandOutput::Message(format!(
+        "Deleted account: {} (ID: {})",
+        login,
+        account.id
+    )))
 }
 
 /// List all accounts
-async fn cmd_list_accounts(state: &ServerState) -> Result<()> {
-    let accounts = list_accounts(state.database.pool()).await?;
-    
-    if accounts.is_empty() {
-        println!("No accounts found");
-        return Ok(());
-    }
-    
-    println!("\n{:<5} {:<20} {:<20} {:<18}", "ID", "Login", "Name", "Privileges");
-    println!("{}", "-".repeat(68));
-    
-    for account in accounts {
-        let access_privs = account.access_privileges();
-        
-        println!(
-            "{:<5} {:<20} {:<20} 0x{:016X}",
-            account.id,
-            account.login,
-            account.name,
-            access_privs.bits()
-        );
-    }
-    println!();
-    
-    Ok(())
+async fn cmd_list_accounts(state: &ServerState) -> Result<CommandOutput> {
+    let accounts = state.accounts.list_accounts().await?;
+    
+    Ok(CommandOutput::Accounts(
+        accounts
+            .into_iter()
+            .map(|account| AccountSummary {
+                access: account.access_privileges(),
+                id: account.id,
+                login: account.login,
+                name: account.name,
+            })
+            .collect(),
+    ))
 }
 
 /// Kick a user by ID or nickname
-async fn cmd_kick(state: &ServerState, target: &str) -> Result<()> {
+async fn cmd_kick(state: &ServerState, actor: &str, target: &str) -> Result<CommandOutput> {
     // Try to parse as user ID first
     let user_id = if let Ok(id) = target.parse::<u16>() {
         Some(id)
@@ -320,95 +595,315 @@ async fn cmd_kick(state: &ServerState, target: &str) -> Result<()> {
     
     // Broadcast user left
     state.broadcast(BroadcastMessage::UserLeft { user_id });
-    
-    println!("Kicked user {} ({}) from {}", user_id, nickname, addr);
-    
-    Ok(())
+    audit::record(state.audit.disconnected(actor, user_id, &nickname)).await;
+    
+    Ok(CommandOutput::Message(format!(
+        "Kicked user {} ({}) from {}",
+        user_id,
+        nickname,
+        addr
+    )))
+}
+
+/// Show the last `lines` lines of the chat log
+fn cmd_chat_tail(state: &ServerState, lines: usize) -> Result<CommandOutput> {
+    let chat_log = state.chat_log.as_ref()
+        .ok_or_else(|| anyhow!("Chat logging is off (set chat.log_path to enable it)"))?;
+    
+    let tail = chat_log.tail(lines)?;
+    if tail.is_empty() {
+        return Ok(CommandOutput::Message("Chat log is empty".to_string()));
+    }
+    Ok(CommandOutput::Message(tail.join("\n")))
 }
 
 /// Broadcast a message to all connected users
-async fn cmd_broadcast(state: &ServerState, message: &str) -> Result<()> {
+fn cmd_broadcast(state: &ServerState, message: &str) -> CommandOutput {
     let user_count = state.session_count();
     
     if user_count == 0 {
-        println!("No users connected");
-        return Ok(());
+        return CommandOutput::Message("No users connected".to_string());
     }
     
     state.broadcast(BroadcastMessage::ServerMessage {
         message: message.to_string(),
     });
     
-    println!("Broadcast message to {} user(s): {}", user_count, message);
-    
-    Ok(())
+    CommandOutput::Message(format!(
+        "Broadcast message to {} user(s): {}",
+        user_count,
+        message
+    ))
 }
 
 /// List currently connected users
-async fn cmd_list_users(state: &ServerState) -> Result<()> {
-    let sessions: Vec<_> = state.sessions.iter().map(|s| s.clone()).collect();
-    
-    if sessions.is_empty() {
-        println!("No users connected");
-        return Ok(());
+///
+/// Filtering by access looks each account up once, however many sessions
+/// share it.
+async fn cmd_list_users(state: &ServerState, sort: UserSort, filter: UserFilter) -> Result<CommandOutput> {
+    let now = SystemTime::now();
+    let sessions: Vec<_> = state.sessions.iter()
+        .map(|s| {
+            let summary = UserSummary {
+                user_id: s.user_id,
+                nickname: s.nickname.clone(),
+                address: s.address,
+                auth_state: s.auth_state,
+                idle_seconds: now.duration_since(s.last_activity).unwrap_or_default().as_secs(),
+            };
+            (summary, s.account_id)
+        })
+        .collect();
+    
+    let mut access_cache: HashMap<i64, AccessPrivileges> = HashMap::new();
+    let mut users = Vec::with_capacity(sessions.len());
+    
+    for (summary, account_id) in sessions {
+        let keep = match (filter, account_id) {
+            (UserFilter::All, _) => true,
+            (UserFilter::Guests, account_id) => account_id.is_none(),
+            (UserFilter::Admins, None) => false,
+            (UserFilter::Admins, Some(account_id)) => {
+                let access = match access_cache.get(&account_id) {
+                    Some(access) => *access,
+                    None => {
+                        let access = state.accounts.get_account_by_id(account_id)
+                            .await?
+                            .map(|a| a.access_privileges())
+                            .unwrap_or_else(AccessPrivileges::guest);
+                        access_cache.insert(account_id, access);
+                        access
+                    }
+                };
+                access.contains(AccessPrivileges::admin())
+            }
+        };
+        
+        if keep {
+            users.push(summary);
+        }
     }
     
-    println!("\n{:<6} {:<20} {:<20} {:<12}", "ID", "Nickname", "Address", "Auth State");
-    println!("{}", "-".repeat(63));
-    
-    for session in sessions {
-        println!(
-            "{:<6} {:<20} {:<20} {:<12}",
-            session.user_id,
-            session.nickname,
-            session.address,
-            format!("{:?}", session.auth_state)
-        );
+    match sort {
+        UserSort::Id => users.sort_by_key(|u| u.user_id),
+        UserSort::Name => users.sort_by_key(|u| (u.nickname.to_lowercase(), u.user_id)),
+        UserSort::Idle => users.sort_by_key(|u| (std::cmp::Reverse(u.idle_seconds), u.user_id)),
     }
-    println!();
     
-    Ok(())
+    Ok(CommandOutput::Users(users))
 }
 
-/// Show help
-fn cmd_help() {
-    println!("\nAvailable commands:");
-    println!();
-    println!("Account Management:");
-    println!("  account create <login> <password> [access]");
-    println!("      Create account with access level (default: admin)");
-    println!("      Access levels: sysop, admin, user, guest");
-    println!();
-    println!("  account access set <login> <access>");
-    println!("      Change account access level");
-    println!();
-    println!("  account delete <login>");
-    println!("      Delete an account");
-    println!();
-    println!("  account list");
-    println!("      Show all accounts");
-    println!();
-    println!("User Management:");
-    println!("  user kick <user_id|nickname>");
-    println!("      Disconnect a user");
-    println!();
-    println!("  user list");
-    println!("      Show connected users");
-    println!();
-    println!("Server:");
-    println!("  broadcast <message>");
-    println!("      Send message to all users");
-    println!();
-    println!("  help");
-    println!("      Show this help");
-    println!();
-    println!("  stop");
-    println!("      Shut down the server");
-    println!();
-    println!("Access level details:");
-    println!("  sysop  - Highest level, full privileges (can't be disconnected)");
-    println!("  admin  - Full privileges (can be disconnected by sysop)");
-    println!("  user   - Chat, files, messages, private chat");
-    println!("  guest  - Read chat, send chat, read news, download files");
-    println!();
+/// Help text for the `help` command
+const HELP_TEXT: &str = "
+Available commands:
+
+Account Management:
+  account create <login> <password> [access]
+      Create account with access level (default: admin)
+      Access levels: sysop, admin, user, guest
+
+  account access set <login> <access>
+      Change account access level
+
+  account delete <login>
+      Delete an account
+
+  account list
+      Show all accounts
+
+User Management:
+  user kick <user_id|nickname>
+      Disconnect a user
+
+  user list [by-id|by-name|by-idle] [all|admins|guests]
+      Show connected users, sorted (by-idle: longest idle first)
+      and optionally only admins or guests
+
+Server:
+  broadcast <message>
+      Send message to all users
+
+  chat-tail [lines]
+      Show the last lines of the chat log (default 20)
+
+  help
+      Show this help
+
+  stop
+      Shut down the server
+
+Access level details:
+  sysop  - Highest level, full privileges (can't be disconnected)
+  admin  - Full privileges (can be disconnected by sysop)
+  user   - Chat, files, messages, private chat
+  guest  - Read chat, send chat, read news, download files
+
+Quote arguments with spaces, e.g. user kick \"Guest 12\"
+";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::Session;
+    use crate::test_util::{test_config, test_state};
+    
+    #[test]
+    fn test_split_args_with_quotes() {
+        assert_eq!(split_args("user kick  Alice ").unwrap(), ["user", "kick", "Alice"]);
+        assert_eq!(
+            split_args(r#"user kick "Guest 12""#).unwrap(),
+            ["user", "kick", "Guest 12"]
+        );
+        assert_eq!(
+            split_args(r#"broadcast "say \"hi\"" now"#).unwrap(),
+            ["broadcast", "say \"hi\"", "now"]
+        );
+        assert_eq!(split_args(r#"account create bob """#).unwrap(), ["account", "create", "bob", ""]);
+        assert_eq!(split_args(r"path C:\files").unwrap(), ["path", r"C:\files"]);
+        assert!(split_args(r#"user kick "Guest 12"#).is_err());
+    }
+    
+    #[test]
+    fn test_parse_quoted_arguments() {
+        let cmd = Command::parse(r#"account create "bob smith" "correct horse" user"#).unwrap();
+        match cmd {
+            Command::AccountCreate { login, password, access_level } => {
+                assert_eq!(login, "bob smith");
+                assert_eq!(password, "correct horse");
+                assert_eq!(access_level, "user");
+            }
+            other => panic!("Unexpected command: {:?}", other),
+        }
+        
+        let cmd = Command::parse_chat(r#"kick "Guest 12""#).unwrap();
+        assert!(matches!(cmd, Command::UserKick { target } if target == "Guest 12"));
+        
+        let cmd = Command::parse(r#"broadcast "Back in   five""#).unwrap();
+        assert!(matches!(cmd, Command::Broadcast { message } if message == "Back in   five"));
+    }
+    
+    #[tokio::test]
+    async fn test_create_account_respects_limit() {
+        let state = test_state(|_| {}).await;
+        let mut config = (*state.config()).clone();
+        config.security.max_accounts = 2;
+        state.reload_config(config);
+        
+        let create = |login: &str| Command::AccountCreate {
+            login: login.to_string(),
+            password: "pw".to_string(),
+            access_level: "user".to_string(),
+        };
+        
+        execute_command(create("alice"), state.clone()).await.unwrap();
+        execute_command(create("bob"), state.clone()).await.unwrap();
+        
+        let error = execute_command(create("carol"), state.clone()).await.unwrap_err();
+        assert!(error.to_string().contains("Account limit reached"), "{}", error);
+        assert!(!state.accounts.account_exists("carol").await.unwrap());
+    }
+    
+    /// Audit sink keeping account changes in memory
+    #[derive(Default)]
+    struct MemoryAudit(std::sync::Mutex<Vec<(String, String, AccountChange)>>);
+    
+    impl audit::AuditSink for MemoryAudit {
+        fn account_changed<'a>(&'a self, actor: &'a str, login: &'a str, change: AccountChange) -> crate::db::store::StoreFuture<'a, ()> {
+            self.0.lock().unwrap().push((actor.to_string(), login.to_string(), change));
+            Box::pin(async { Ok(()) })
+        }
+        
+        fn disconnected<'a>(&'a self, _actor: &'a str, _user_id: u16, _nickname: &'a str) -> crate::db::store::StoreFuture<'a, ()> {
+            Box::pin(async { Ok(()) })
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_set_access_is_audited() {
+        let sink = Arc::new(MemoryAudit::default());
+        let state = Arc::new(ServerState::new(test_config()).await.unwrap().with_audit_sink(sink.clone()));
+        
+        state.accounts.create_account("bob", b"pw", "Bob", AccessPrivileges::user()).await.unwrap();
+        let cmd = Command::AccountAccessSet {
+            login: "bob".to_string(),
+            access_level: "admin".to_string(),
+        };
+        execute_command_as(cmd, state.clone(), "user 1 (Admin)").await.unwrap();
+        
+        let events = sink.0.lock().unwrap();
+        assert_eq!(*events, vec![(
+            "user 1 (Admin)".to_string(),
+            "bob".to_string(),
+            AccountChange::AccessChanged { access: AccessPrivileges::admin() },
+        )]);
+    }
+    
+    #[tokio::test]
+    async fn test_list_users_output() {
+        let state = test_state(|_| {}).await;
+        
+        let mut session = Session::new(7, "127.0.0.1:5500".parse().unwrap());
+        session.authenticate_guest("Alice".to_string(), 0);
+        state.register_session(session);
+        
+        let output = execute_command(Command::parse("user list").unwrap(), state.clone()).await.unwrap();
+        
+        match output {
+            CommandOutput::Users(users) => {
+                assert_eq!(users.len(), 1);
+                assert_eq!(users[0].user_id, 7);
+                assert_eq!(users[0].nickname, "Alice");
+                assert_eq!(users[0].auth_state, AuthState::Authenticated);
+            }
+            other => panic!("Expected user list, got {:?}", other),
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_list_users_sorted_and_filtered() {
+        let state = test_state(|_| {}).await;
+        
+        let admin_id = state.accounts
+            .create_account("alice", b"pw", "Alice", AccessPrivileges::admin())
+            .await
+            .unwrap();
+        let user_id = state.accounts
+            .create_account("bob", b"pw", "bob", AccessPrivileges::user())
+            .await
+            .unwrap();
+        
+        let now = SystemTime::now();
+        let users = [
+            (3, "carol", None, 60),
+            (5, "Alice", Some((admin_id, AccessPrivileges::admin())), 10),
+            (9, "bob", Some((user_id, AccessPrivileges::user())), 300),
+            (12, "Alice-away", Some((admin_id, AccessPrivileges::admin())), 0),
+        ];
+        for (id, nickname, account, idle) in users {
+            let mut session = Session::new(id, "127.0.0.1:5500".parse().unwrap());
+            match account {
+                Some((account_id, access)) => session.authenticate_user(account_id, access, nickname.to_string(), 0),
+                None => session.authenticate_guest(nickname.to_string(), 0),
+            }
+            session.last_activity = now - std::time::Duration::from_secs(idle);
+            state.register_session(session);
+        }
+        
+        let ids = |output: CommandOutput| match output {
+            CommandOutput::Users(users) => users.iter().map(|u| u.user_id).collect::<Vec<_>>(),
+            other => panic!("Expected user list, got {:?}", other),
+        };
+        let run = |input: &str| {
+            let cmd = Command::parse(input).unwrap();
+            execute_command(cmd, state.clone())
+        };
+        
+        assert_eq!(ids(run("user list").await.unwrap()), vec![3, 5, 9, 12]);
+        assert_eq!(ids(run("user list by-name").await.unwrap()), vec![5, 12, 9, 3]);
+        assert_eq!(ids(run("user list by-idle").await.unwrap()), vec![9, 3, 5, 12]);
+        assert_eq!(ids(run("user list admins by-idle").await.unwrap()), vec![5, 12]);
+        assert_eq!(ids(run("user list guests").await.unwrap()), vec![3]);
+        
+        assert!(Command::parse("user list by-size").is_err());
+    }
 }
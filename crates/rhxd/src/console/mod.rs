@@ -1,22 +1,43 @@
 //! Interactive console for server management
 
 mod commands;
+#[cfg(unix)]
+mod socket;
 
-pub use commands::{Command, execute_command};
+pub use commands::{AccountSummary, Command, CommandOutput, UserFilter, UserSort, UserSummary, execute_command, execute_command_as};
+#[cfg(unix)]
+pub use socket::spawn_socket;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use std::sync::Arc;
-use tokio::io::{self, AsyncBufReadExt, BufReader};
+use tokio::io::{self, AsyncBufRead, AsyncBufReadExt, BufReader};
 
 use crate::ServerState;
 
-/// Run the interactive console loop
-pub async fn run_console(state: Arc<ServerState>) -> Result<()> {
+/// Why the console stopped reading commands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleExit {
+    /// The `stop` command
+    Stop,
+    /// Input ended (Ctrl+D, or the end of a piped script)
+    Eof,
+}
+
+/// Run the interactive console loop on stdin
+pub async fn run_console(state: Arc<ServerState>) -> Result<ConsoleExit> {
+    run_commands(state, BufReader::new(io::stdin())).await
+}
+
+/// Run console commands read from `input` until `stop` or the end of input
+///
+/// Errors reading the input are returned rather than treated as its end.
+pub async fn run_commands<R>(state: Arc<ServerState>, mut reader: R) -> Result<ConsoleExit>
+where
+    R: AsyncBufRead + Unpin,
+{
     // Give the server a moment to complete initialization and print its startup messages
     tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
     
-    let stdin = io::stdin();
-    let mut reader = BufReader::new(stdin);
     let mut line = String::new();
     
     println!("\n=== Hotline Server Console ===");
@@ -32,43 +53,39 @@ pub async fn run_console(state: Arc<ServerState>) -> Result<()> {
         
         // Read line
         line.clear();
-        match reader.read_line(&mut line).await {
-            Ok(0) => {
-                // EOF (Ctrl+D)
-                println!("\nEOF detected, shutting down...");
-                break;
+        let read = reader
+            .read_line(&mut line)
+            .await
+            .context("Error reading console input")?;
+        if read == 0 {
+            // EOF (Ctrl+D)
+            println!("\nEOF detected");
+            return Ok(ConsoleExit::Eof);
+        }
+        
+        let input = line.trim();
+        
+        // Skip empty lines
+        if input.is_empty() {
+            continue;
+        }
+        
+        // Parse and execute command
+        match Command::parse(input) {
+            Ok(Command::Stop) => {
+                println!("Shutting down server...");
+                return Ok(ConsoleExit::Stop);
             }
-            Ok(_) => {
-                let input = line.trim();
-                
-                // Skip empty lines
-                if input.is_empty() {
-                    continue;
-                }
-                
-                // Parse and execute command
-                match Command::parse(input) {
-                    Ok(Command::Stop) => {
-                        println!("Shutting down server...");
-                        break;
-                    }
-                    Ok(cmd) => {
-                        if let Err(e) = execute_command(cmd, state.clone()).await {
-                            eprintln!("Error: {}", e);
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("Error: {}", e);
-                        println!("Type 'help' for available commands");
-                    }
+            Ok(cmd) => {
+                match execute_command(cmd, state.clone()).await {
+                    Ok(output) => print!("{}", output),
+                    Err(e) => eprintln!("Error: {}", e),
                 }
             }
             Err(e) => {
-                eprintln!("Error reading input: {}", e);
-                break;
+                eprintln!("Error: {}", e);
+                println!("Type 'help' for available commands");
             }
         }
     }
-    
-    Ok(())
 }
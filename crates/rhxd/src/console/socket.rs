@@ -0,0 +1,183 @@
+//! Console over a Unix domain socket
+//!
+//! When `console.socket_path` is set, the server listens there for the same
+//! line-based commands as the interactive console, e.g. with
+//! `nc -U /run/rhxd/console.sock`. Each command's output is followed by a
+//! status line, `OK` or `ERR <message>`, so tools can tell where it ends.
+//! `stop` or `shutdown` shuts the server down; `quit` and `exit` only close
+//! the connection.
+//!
+//! The socket is created with mode 0600: anyone who can connect has full
+//! control of the server.
+
+use super::{execute_command_as, Command};
+use crate::state::ServerState;
+use anyhow::{bail, Context, Result};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+
+/// Who the audit trail records for changes made through the socket
+const AUDIT_ACTOR: &str = "console-socket";
+
+/// Start listening on `console.socket_path`, if set
+///
+/// A stale socket file left by an earlier run is replaced.
+pub async fn spawn_socket(state: Arc<ServerState>, shutdown: Arc<Notify>) -> Result<Option<JoinHandle<()>>> {
+    let Some(path) = state.config().console.socket_path.clone() else {
+        return Ok(None);
+    };
+    
+    let listener = bind(&path)?;
+    tracing::info!("Console socket listening on {}", path.display());
+    
+    Ok(Some(tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let state = state.clone();
+                    let shutdown = shutdown.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = serve(stream, state, shutdown).await {
+                            tracing::debug!("Console socket client error: {}", e);
+                        }
+                    });
+                }
+                Err(e) => tracing::warn!("Failed to accept console socket client: {}", e),
+            }
+        }
+    })))
+}
+
+/// Bind `path`, replacing a socket left behind by an earlier run
+///
+/// Anything else already at `path` is left alone and fails the bind, so a
+/// mistyped `console.socket_path` can't delete a regular file.
+fn bind(path: &Path) -> Result<UnixListener> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            std::fs::remove_file(path)
+                .with_context(|| format!("Failed to remove stale console socket {}", path.display()))?;
+        }
+        Ok(_) => bail!("Console socket path {} exists and is not a socket", path.display()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to inspect console socket path {}", path.display()));
+        }
+    }
+    
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("Failed to bind console socket {}", path.display()))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Run commands from one client until it disconnects or sends `stop`
+async fn serve(stream: UnixStream, state: Arc<ServerState>, shutdown: Arc<Notify>) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    
+    while let Some(line) = lines.next_line().await? {
+        let input = line.trim();
+        if input.is_empty() {
+            continue;
+        }
+        if matches!(input, "quit" | "exit") {
+            break;
+        }
+        
+        let response = match Command::parse(input) {
+            Ok(Command::Stop) => {
+                tracing::info!("Shutdown requested over the console socket");
+                writer.write_all(b"OK\n").await?;
+                shutdown.notify_waiters();
+                return Ok(());
+            }
+            Ok(cmd) => match execute_command_as(cmd, state.clone(), AUDIT_ACTOR).await {
+                Ok(output) => format!("{}OK\n", output),
+                Err(e) => format!("ERR {}\n", e),
+            },
+            Err(e) => format!("ERR {}\n", e),
+        };
+        writer.write_all(response.as_bytes()).await?;
+    }
+    
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::Session;
+    use crate::test_util::{test_db_path, TempPath};
+    use crate::Config;
+    
+    /// Send one command and collect its output up to the status line
+    async fn run(stream: &mut BufReader<UnixStream>, command: &str) -> (String, String) {
+        stream.get_mut().write_all(format!("{}\n", command).as_bytes()).await.unwrap();
+        
+        let mut output = String::new();
+        loop {
+            let mut line = String::new();
+            assert!(stream.read_line(&mut line).await.unwrap() > 0, "Socket closed");
+            if line == "OK\n" || line.starts_with("ERR ") {
+                return (output, line.trim_end().to_string());
+            }
+            output.push_str(&line);
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_bind_only_replaces_sockets() {
+        let path = TempPath::new("console_socket_bind", "sock");
+        
+        // A stale socket from an earlier run is replaced
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        drop(bind(&path).unwrap());
+        
+        // Anything else stays put
+        std::fs::remove_file(&path).unwrap();
+        std::fs::write(&path, b"keep me").unwrap();
+        let error = bind(&path).unwrap_err();
+        assert!(error.to_string().contains("is not a socket"), "{}", error);
+        assert_eq!(std::fs::read(&path).unwrap(), b"keep me");
+    }
+    
+    #[tokio::test]
+    async fn test_list_users_over_socket() {
+        let db_path = test_db_path("console_socket");
+        let socket_path = TempPath::new("console_socket", "sock");
+        let mut config = Config::default();
+        config.database.path = db_path.to_path_buf();
+        config.console.socket_path = Some(socket_path.to_path_buf());
+        let state = Arc::new(ServerState::new(config).await.unwrap());
+        
+        let mut session = Session::new(3, "127.0.0.1:5500".parse().unwrap());
+        session.authenticate_guest("Alice".to_string(), 0);
+        state.register_session(session);
+        
+        let shutdown = Arc::new(Notify::new());
+        let handle = spawn_socket(state.clone(), shutdown.clone()).await.unwrap().unwrap();
+        let mode = std::fs::metadata(&socket_path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        
+        let mut stream = BufReader::new(UnixStream::connect(&socket_path).await.unwrap());
+        let (output, status) = run(&mut stream, "user list").await;
+        assert_eq!(status, "OK");
+        assert!(output.contains("Alice"), "{}", output);
+        
+        let (_, status) = run(&mut stream, "no-such-command").await;
+        assert!(status.starts_with("ERR "), "{}", status);
+        
+        let stopped = shutdown.notified();
+        let (_, status) = run(&mut stream, "stop").await;
+        assert_eq!(status, "OK");
+        tokio::time::timeout(std::time::Duration::from_secs(1), stopped).await.unwrap();
+        
+        handle.abort();
+    }
+}
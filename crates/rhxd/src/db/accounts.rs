@@ -2,8 +2,9 @@
 
 #![allow(dead_code)] // Many functions are for future use
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use chrono::Utc;
+use rhxcore::password::{hash_password, verify_password, PasswordScheme};
 use rhxcore::types::access::AccessPrivileges;
 use sqlx::SqlitePool;
 
@@ -18,6 +19,45 @@ pub struct Account {
     pub access: i64,
     pub created_at: i64,
     pub modified_at: i64,
+    /// Icon shown for this account whatever the client asks for
+    pub force_icon: Option<i64>,
+    /// Always show this account with the admin flag
+    pub force_admin_flag: bool,
+}
+
+/// Columns selected for an [`Account`], in [`AccountRow`] order
+const ACCOUNT_COLUMNS: &str = "id, login, password, name, icon_id, access_privileges, \
+    created_at, modified_at, force_icon, force_admin_flag";
+
+type AccountRow = (i64, String, Vec<u8>, String, i64, i64, i64, i64, Option<i64>, bool);
+
+impl From<AccountRow> for Account {
+    fn from(row: AccountRow) -> Self {
+        let (
+            id,
+            login,
+            password_hash,
+            name,
+            icon_id,
+            access,
+            created_at,
+            modified_at,
+            force_icon,
+            force_admin_flag,
+        ) = row;
+        Account {
+            id,
+            login,
+            password_hash,
+            name,
+            icon_id,
+            access,
+            created_at,
+            modified_at,
+            force_icon,
+            force_admin_flag,
+        }
+    }
 }
 
 impl Account {
@@ -30,6 +70,58 @@ impl Account {
     pub fn has_privilege(&self, privilege: AccessPrivileges) -> bool {
         self.access_privileges().contains(privilege)
     }
+    
+    /// Whether the account can manage other accounts (`CREATE_USERS` or
+    /// `MODIFY_USERS`)
+    pub fn is_admin(&self) -> bool {
+        self.access_privileges().intersects(admin_privileges())
+    }
+}
+
+/// Privileges that let an account manage the others
+pub(crate) fn admin_privileges() -> AccessPrivileges {
+    AccessPrivileges::CREATE_USERS | AccessPrivileges::MODIFY_USERS
+}
+
+/// Turn a plaintext password into its stored form under `scheme`
+///
+/// Argon2 takes long enough to stall other connections, so hashing runs on
+/// the blocking pool.
+pub async fn hash_stored_password(password: &[u8], scheme: PasswordScheme) -> Result<Vec<u8>> {
+    let password = password.to_vec();
+    tokio::task::spawn_blocking(move || hash_password(&password, scheme))
+        .await
+        .context("Password hashing task failed")?
+        .map_err(|e| anyhow::anyhow!("Failed to hash password: {}", e))
+}
+
+/// Check a plaintext password against its stored form, on the blocking pool
+/// like [`hash_stored_password`]
+pub async fn verify_stored_password(stored: &[u8], password: &[u8]) -> Result<bool> {
+    let (stored, password) = (stored.to_vec(), password.to_vec());
+    tokio::task::spawn_blocking(move || verify_password(&stored, &password))
+        .await
+        .context("Password verification task failed")
+}
+
+/// Longest login or name, in bytes of UTF-8
+const MAX_NAME_BYTES: usize = 31;
+
+/// Check a login or display name before storing it
+///
+/// The limit counts UTF-8 bytes, since that is what goes on the wire and
+/// into fixed-size client buffers; it also keeps names within 31 display
+/// columns. Control characters (including NUL) are rejected since clients
+/// either truncate at them or draw garbage.
+pub(crate) fn validate_text(field: &str, value: &str) -> Result<()> {
+    if value.chars().any(char::is_control) {
+        bail!("{} must not contain control characters", field);
+    }
+    if value.len() > MAX_NAME_BYTES {
+        bail!("{} must be {} bytes or less", field, MAX_NAME_BYTES);
+    }
+    
+    Ok(())
 }
 
 /// Create a new account
@@ -40,13 +132,8 @@ pub async fn create_account(
     name: &str,
     access: AccessPrivileges,
 ) -> Result<i64> {
-    // Validate input lengths
-    if login.len() > 31 {
-        bail!("Login must be 31 characters or less");
-    }
-    if name.len() > 31 {
-        bail!("Name must be 31 characters or less");
-    }
+    validate_text("Login", login)?;
+    validate_text("Name", name)?;
     
     let now = Utc::now().timestamp();
     let access_bits = access.bits() as i64;
@@ -69,76 +156,34 @@ pub async fn create_account(
 
 /// Get account by login
 pub async fn get_account_by_login(pool: &SqlitePool, login: &str) -> Result<Option<Account>> {
-    let account = sqlx::query_as::<_, (i64, String, Vec<u8>, String, i64, i64, i64, i64)>(
-        "SELECT id, login, password, name, icon_id, access_privileges, created_at, modified_at
-         FROM accounts WHERE login = ? COLLATE NOCASE"
-    )
-    .bind(login)
-    .fetch_optional(pool)
-    .await?;
+    let sql = format!("SELECT {} FROM accounts WHERE login = ? COLLATE NOCASE", ACCOUNT_COLUMNS);
+    let account = sqlx::query_as::<_, AccountRow>(&sql)
+        .bind(login)
+        .fetch_optional(pool)
+        .await?;
     
-    Ok(account.map(|(id, login, password_hash, name, icon_id, access, created_at, modified_at)| {
-        Account {
-            id,
-            login,
-            password_hash,
-            name,
-            icon_id,
-            access,
-            created_at,
-            modified_at,
-        }
-    }))
+    Ok(account.map(Account::from))
 }
 
 /// Get account by ID
 pub async fn get_account_by_id(pool: &SqlitePool, id: i64) -> Result<Option<Account>> {
-    let account = sqlx::query_as::<_, (i64, String, Vec<u8>, String, i64, i64, i64, i64)>(
-        "SELECT id, login, password, name, icon_id, access_privileges, created_at, modified_at
-         FROM accounts WHERE id = ?"
-    )
-    .bind(id)
-    .fetch_optional(pool)
-    .await?;
+    let sql = format!("SELECT {} FROM accounts WHERE id = ?", ACCOUNT_COLUMNS);
+    let account = sqlx::query_as::<_, AccountRow>(&sql)
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
     
-    Ok(account.map(|(id, login, password_hash, name, icon_id, access, created_at, modified_at)| {
-        Account {
-            id,
-            login,
-            password_hash,
-            name,
-            icon_id,
-            access,
-            created_at,
-            modified_at,
-        }
-    }))
+    Ok(account.map(Account::from))
 }
 
 /// List all accounts
 pub async fn list_accounts(pool: &SqlitePool) -> Result<Vec<Account>> {
-    let accounts = sqlx::query_as::<_, (i64, String, Vec<u8>, String, i64, i64, i64, i64)>(
-        "SELECT id, login, password, name, icon_id, access_privileges, created_at, modified_at
-         FROM accounts ORDER BY login"
-    )
-    .fetch_all(pool)
-    .await?;
+    let sql = format!("SELECT {} FROM accounts ORDER BY login", ACCOUNT_COLUMNS);
+    let accounts = sqlx::query_as::<_, AccountRow>(&sql)
+        .fetch_all(pool)
+        .await?;
     
-    Ok(accounts
-        .into_iter()
-        .map(|(id, login, password_hash, name, icon_id, access, created_at, modified_at)| {
-            Account {
-                id,
-                login,
-                password_hash,
-                name,
-                icon_id,
-                access,
-                created_at,
-                modified_at,
-            }
-        })
-        .collect())
+    Ok(accounts.into_iter().map(Account::from).collect())
 }
 
 /// Update account password
@@ -161,6 +206,24 @@ pub async fn update_password(
     Ok(())
 }
 
+/// Update account display name
+pub async fn update_name(pool: &SqlitePool, account_id: i64, name: &str) -> Result<()> {
+    validate_text("Name", name)?;
+    
+    let now = Utc::now().timestamp();
+    
+    sqlx::query(
+        "UPDATE accounts SET name = ?, modified_at = ? WHERE id = ?"
+    )
+    .bind(name)
+    .bind(now)
+    .bind(account_id)
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
 /// Update account access privileges
 pub async fn update_access(
     pool: &SqlitePool,
@@ -182,6 +245,31 @@ pub async fn update_access(
     Ok(())
 }
 
+/// Update how an account is presented to other users
+///
+/// `force_icon` replaces the client's icon choice; `force_admin_flag` shows
+/// the admin flag regardless of access.
+pub async fn update_overrides(
+    pool: &SqlitePool,
+    account_id: i64,
+    force_icon: Option<i64>,
+    force_admin_flag: bool,
+) -> Result<()> {
+    let now = Utc::now().timestamp();
+    
+    sqlx::query(
+        "UPDATE accounts SET force_icon = ?, force_admin_flag = ?, modified_at = ? WHERE id = ?"
+    )
+    .bind(force_icon)
+    .bind(force_admin_flag)
+    .bind(now)
+    .bind(account_id)
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
 /// Delete an account
 pub async fn delete_account(pool: &SqlitePool, account_id: i64) -> Result<()> {
     sqlx::query("DELETE FROM accounts WHERE id = ?")
@@ -192,6 +280,55 @@ pub async fn delete_account(pool: &SqlitePool, account_id: i64) -> Result<()> {
     Ok(())
 }
 
+/// Update account access unless that would leave no account able to manage
+/// the others (see [`Account::is_admin`]), returning whether it changed
+///
+/// The check is part of the UPDATE, so two concurrent edits can't each
+/// demote a different last admin.
+pub async fn update_access_keeping_admin(
+    pool: &SqlitePool,
+    account_id: i64,
+    access: AccessPrivileges,
+) -> Result<bool> {
+    let now = Utc::now().timestamp();
+    
+    let result = sqlx::query(
+        "UPDATE accounts SET access_privileges = ?1, modified_at = ?2
+         WHERE id = ?3
+           AND (?1 & ?4 != 0
+                OR access_privileges & ?4 = 0
+                OR EXISTS (SELECT 1 FROM accounts WHERE id != ?3 AND access_privileges & ?4 != 0))"
+    )
+    .bind(access.bits() as i64)
+    .bind(now)
+    .bind(account_id)
+    .bind(admin_privileges().bits() as i64)
+    .execute(pool)
+    .await?;
+    
+    Ok(result.rows_affected() > 0)
+}
+
+/// Delete an account unless it is the last one able to manage the others,
+/// returning whether it was deleted
+///
+/// Like [`update_access_keeping_admin`], the check and the delete are one
+/// statement.
+pub async fn delete_account_keeping_admin(pool: &SqlitePool, account_id: i64) -> Result<bool> {
+    let result = sqlx::query(
+        "DELETE FROM accounts
+         WHERE id = ?1
+           AND (access_privileges & ?2 = 0
+                OR EXISTS (SELECT 1 FROM accounts WHERE id != ?1 AND access_privileges & ?2 != 0))"
+    )
+    .bind(account_id)
+    .bind(admin_privileges().bits() as i64)
+    .execute(pool)
+    .await?;
+    
+    Ok(result.rows_affected() > 0)
+}
+
 /// Check if an account exists
 pub async fn account_exists(pool: &SqlitePool, login: &str) -> Result<bool> {
     let count: (i64,) = sqlx::query_as(
@@ -217,9 +354,10 @@ pub async fn count_accounts(pool: &SqlitePool) -> Result<i64> {
 mod tests {
     use super::*;
     use crate::db::Database;
+    use crate::test_util::{test_db_path, TempPath};
     
-    async fn test_db(name: &str) -> (Database, String) {
-        let path = format!("/tmp/test_rhxd_accounts_{}_{}.db", name, std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos());
+    async fn test_db(name: &str) -> (Database, TempPath) {
+        let path = test_db_path(&format!("accounts_{}", name));
         let db = Database::new(&path).await.unwrap();
         db.init_schema().await.unwrap();
         (db, path)
@@ -227,7 +365,7 @@ mod tests {
     
     #[tokio::test]
     async fn test_create_and_get_account() {
-        let (db, path) = test_db("create").await;
+        let (db, _path) = test_db("create").await;
         let pool = db.pool();
         
         // Create account
@@ -262,13 +400,83 @@ mod tests {
             .unwrap();
         
         assert_eq!(account2.login, account.login);
+    }
+    
+    #[tokio::test]
+    async fn test_last_admin_kept() {
+        let (db, _path) = test_db("last_admin").await;
+        let pool = db.pool();
+        
+        let admin = create_account(pool, "admin", b"pw", "Admin", AccessPrivileges::admin())
+            .await
+            .unwrap();
+        let user = create_account(pool, "user", b"pw", "User", AccessPrivileges::user())
+            .await
+            .unwrap();
+        
+        assert!(!update_access_keeping_admin(pool, admin, AccessPrivileges::user()).await.unwrap());
+        assert!(!delete_account_keeping_admin(pool, admin).await.unwrap());
+        assert!(get_account_by_id(pool, admin).await.unwrap().unwrap().is_admin());
         
-        std::fs::remove_file(&path).ok();
+        // Accounts that can't manage users come and go freely
+        assert!(update_access_keeping_admin(pool, user, AccessPrivileges::guest()).await.unwrap());
+        
+        // Promoting someone else frees the first admin
+        assert!(update_access_keeping_admin(pool, user, AccessPrivileges::admin()).await.unwrap());
+        assert!(update_access_keeping_admin(pool, admin, AccessPrivileges::user()).await.unwrap());
+        assert!(delete_account_keeping_admin(pool, admin).await.unwrap());
+        assert!(!delete_account_keeping_admin(pool, user).await.unwrap());
+    }
+    
+    #[tokio::test]
+    async fn test_multibyte_name_within_limit() {
+        let (db, _path) = test_db("multibyte").await;
+        let pool = db.pool();
+        
+        // 15 two-byte characters plus one ASCII: 31 bytes, 16 columns
+        let name = format!("{}a", "é".repeat(15));
+        assert_eq!(name.len(), 31);
+        create_account(pool, "accent", b"pw", &name, AccessPrivileges::user())
+            .await
+            .unwrap();
+        
+        // 16 columns is too long once it takes 32 bytes
+        let long = "é".repeat(16);
+        assert!(create_account(pool, "long", b"pw", &long, AccessPrivileges::user())
+            .await
+            .is_err());
+        
+        // As is 31 columns taking 62 bytes
+        let wide = "é".repeat(31);
+        assert!(create_account(pool, "wide", b"pw", &wide, AccessPrivileges::user())
+            .await
+            .is_err());
+    }
+    
+    #[tokio::test]
+    async fn test_control_characters_rejected() {
+        let (db, _path) = test_db("control").await;
+        let pool = db.pool();
+        
+        for name in ["Nul\0Name", "Bell\x07", "\r\n"] {
+            let result = create_account(pool, "ctrl", b"pw", name, AccessPrivileges::user()).await;
+            assert!(result.is_err(), "{:?} should be rejected", name);
+        }
+        
+        assert!(create_account(pool, "bad\x1blogin", b"pw", "Name", AccessPrivileges::user())
+            .await
+            .is_err());
+        
+        let id = create_account(pool, "ctrl", b"pw", "Fine", AccessPrivileges::user())
+            .await
+            .unwrap();
+        assert!(update_name(pool, id, "Still\0Bad").await.is_err());
+        assert_eq!(count_accounts(pool).await.unwrap(), 1);
     }
     
     #[tokio::test]
     async fn test_account_exists() {
-        let (db, path) = test_db("exists").await;
+        let (db, _path) = test_db("exists").await;
         let pool = db.pool();
         
         assert!(!account_exists(pool, "test").await.unwrap());
@@ -285,13 +493,11 @@ mod tests {
         
         assert!(account_exists(pool, "test").await.unwrap());
         assert!(account_exists(pool, "TEST").await.unwrap()); // Case insensitive
-        
-        std::fs::remove_file(&path).ok();
     }
     
     #[tokio::test]
     async fn test_list_accounts() {
-        let (db, path) = test_db("list").await;
+        let (db, _path) = test_db("list").await;
         let pool = db.pool();
         
         create_account(pool, "user1", b"pass1", "User 1", AccessPrivileges::user())
@@ -303,13 +509,11 @@ mod tests {
         
         let accounts = list_accounts(pool).await.unwrap();
         assert_eq!(accounts.len(), 2);
-        
-        std::fs::remove_file(&path).ok();
     }
     
     #[tokio::test]
     async fn test_delete_account() {
-        let (db, path) = test_db("delete").await;
+        let (db, _path) = test_db("delete").await;
         let pool = db.pool();
         
         let id = create_account(
@@ -327,7 +531,5 @@ mod tests {
         delete_account(pool, id).await.unwrap();
         
         assert!(!account_exists(pool, "deleteme").await.unwrap());
-        
-        std::fs::remove_file(&path).ok();
     }
 }
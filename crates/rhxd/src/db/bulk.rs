@@ -0,0 +1,388 @@
+//! Bulk account operations
+//!
+//! Each operation is split into a plan, computed by diffing the request
+//! against the current rows, and an apply step that writes the plan. A dry
+//! run reports the plan and never applies it.
+
+use crate::db::accounts::{
+    count_accounts, create_account, get_account_by_login, hash_stored_password, update_access,
+    update_name, update_password,
+};
+use anyhow::{bail, Context, Result};
+use rhxcore::password::PasswordScheme;
+use rhxcore::types::AccessPrivileges;
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use std::collections::HashSet;
+use std::fmt;
+
+/// One account in an import file
+#[derive(Debug, Clone, Deserialize)]
+pub struct AccountImport {
+    pub login: String,
+    /// Plain-text password (hashed under `security.password_scheme` before storing)
+    pub password: String,
+    /// Display name (defaults to the login)
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Preset name (`sysop`, `admin`, `user`, `guest`) or raw access bits
+    #[serde(default = "default_import_access")]
+    pub access: String,
+}
+
+fn default_import_access() -> String {
+    "user".to_string()
+}
+
+/// What an import will do with one entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportAction {
+    Create,
+    Overwrite { account_id: i64, before: AccessPrivileges },
+    Skip { reason: String },
+}
+
+/// Planned outcome for one import entry
+#[derive(Debug, Clone)]
+pub struct PlannedImport {
+    pub login: String,
+    pub password: String,
+    pub name: String,
+    pub access: AccessPrivileges,
+    pub action: ImportAction,
+}
+
+impl fmt::Display for PlannedImport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.action {
+            ImportAction::Create => {
+                write!(f, "create     {} (access: {})", self.login, describe_access(self.access))
+            }
+            ImportAction::Overwrite { before, .. } => write!(
+                f,
+                "overwrite  {} (access: {} -> {})",
+                self.login,
+                describe_access(*before),
+                describe_access(self.access)
+            ),
+            ImportAction::Skip { reason } => write!(f, "skip       {} ({})", self.login, reason),
+        }
+    }
+}
+
+/// Planned access change for one account
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessChange {
+    pub account_id: i64,
+    pub login: String,
+    pub before: AccessPrivileges,
+    pub after: AccessPrivileges,
+}
+
+impl AccessChange {
+    /// Whether applying this change would modify the account
+    pub fn is_change(&self) -> bool {
+        self.before != self.after
+    }
+}
+
+impl fmt::Display for AccessChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_change() {
+            write!(
+                f,
+                "update     {} (access: {} -> {})",
+                self.login,
+                describe_access(self.before),
+                describe_access(self.after)
+            )
+        } else {
+            write!(f, "unchanged  {}", self.login)
+        }
+    }
+}
+
+/// Preset name or raw bits, for reports
+fn describe_access(access: AccessPrivileges) -> String {
+    match access.preset_name() {
+        Some(name) => name.to_string(),
+        None => format!("0x{:x}", access.bits()),
+    }
+}
+
+/// Parse an access level given as a preset name or raw bits
+pub fn parse_access(value: &str) -> Result<AccessPrivileges> {
+    if let Some(access) = AccessPrivileges::from_preset(value) {
+        return Ok(access);
+    }
+    
+    let bits = match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    
+    match bits {
+        Ok(bits) => Ok(AccessPrivileges::from_bits_truncate(bits)),
+        Err(_) => bail!("Invalid access level: {}", value),
+    }
+}
+
+/// Parse a comma-separated list of privilege names, e.g. `SEND_CHAT,READ_NEWS`
+pub fn parse_privileges(value: &str) -> Result<AccessPrivileges> {
+    let mut privileges = AccessPrivileges::empty();
+    
+    for name in value.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+        match AccessPrivileges::from_name(&name.to_uppercase()) {
+            Some(p) => privileges |= p,
+            None => bail!("Unknown privilege: {}", name),
+        }
+    }
+    
+    if privileges.is_empty() {
+        bail!("No privileges given");
+    }
+    
+    Ok(privileges)
+}
+
+/// Work out what importing `entries` would do to the accounts table
+///
+/// Existing logins are skipped unless `overwrite` is set. Later duplicates of
+/// a login within the same import are always skipped.
+pub async fn plan_import(
+    pool: &SqlitePool,
+    entries: Vec<AccountImport>,
+    overwrite: bool,
+) -> Result<Vec<PlannedImport>> {
+    let mut seen = HashSet::new();
+    let mut plan = Vec::with_capacity(entries.len());
+    
+    for entry in entries {
+        let access = parse_access(&entry.access)
+            .with_context(|| format!("Account {}", entry.login))?;
+        let name = entry.name.unwrap_or_else(|| entry.login.clone());
+        
+        let action = if !seen.insert(entry.login.to_lowercase()) {
+            ImportAction::Skip {
+                reason: "duplicate in import".to_string(),
+            }
+        } else {
+            match get_account_by_login(pool, &entry.login).await? {
+                None => ImportAction::Create,
+                Some(existing) if overwrite => ImportAction::Overwrite {
+                    account_id: existing.id,
+                    before: existing.access_privileges(),
+                },
+                Some(_) => ImportAction::Skip {
+                    reason: "already exists".to_string(),
+                },
+            }
+        };
+        
+        plan.push(PlannedImport {
+            login: entry.login,
+            password: entry.password,
+            name,
+            access,
+            action,
+        });
+    }
+    
+    Ok(plan)
+}
+
+/// Fail if the planned import would take the accounts table past
+/// `max_accounts` (0 for no limit)
+pub async fn check_account_limit(pool: &SqlitePool, plan: &[PlannedImport], max_accounts: u64) -> Result<()> {
+    if max_accounts == 0 {
+        return Ok(());
+    }
+    
+    let creates = plan
+        .iter()
+        .filter(|item| item.action == ImportAction::Create)
+        .count() as u64;
+    let existing = count_accounts(pool).await? as u64;
+    
+    if existing + creates > max_accounts {
+        bail!(
+            "Import would create {} accounts, but security.max_accounts ({}) leaves room for {}",
+            creates,
+            max_accounts,
+            max_accounts.saturating_sub(existing)
+        );
+    }
+    
+    Ok(())
+}
+
+/// Write a planned import to the database, storing passwords under `scheme`
+///
+/// Nothing is written if the import would take the accounts table past
+/// `max_accounts` (0 for no limit).
+pub async fn apply_import(
+    pool: &SqlitePool,
+    plan: &[PlannedImport],
+    scheme: PasswordScheme,
+    max_accounts: u64,
+) -> Result<()> {
+    check_account_limit(pool, plan, max_accounts).await?;
+    
+    for item in plan {
+        if let ImportAction::Skip { .. } = item.action {
+            continue;
+        }
+        let password_hash = hash_stored_password(item.password.as_bytes(), scheme).await?;
+        
+        match &item.action {
+            ImportAction::Create => {
+                create_account(pool, &item.login, &password_hash, &item.name, item.access)
+                    .await
+                    .with_context(|| format!("Failed to create {}", item.login))?;
+            }
+            ImportAction::Overwrite { account_id, .. } => {
+                update_password(pool, *account_id, &password_hash).await?;
+                update_name(pool, *account_id, &item.name).await?;
+                update_access(pool, *account_id, item.access).await?;
+            }
+            ImportAction::Skip { .. } => {}
+        }
+    }
+    
+    Ok(())
+}
+
+/// Work out the access change of granting and revoking privileges on accounts
+pub async fn plan_access_change(
+    pool: &SqlitePool,
+    logins: &[String],
+    grant: AccessPrivileges,
+    revoke: AccessPrivileges,
+) -> Result<Vec<AccessChange>> {
+    let mut changes = Vec::with_capacity(logins.len());
+    
+    for login in logins {
+        let Some(account) = get_account_by_login(pool, login).await? else {
+            bail!("Account not found: {}", login);
+        };
+        
+        let before = account.access_privileges();
+        changes.push(AccessChange {
+            account_id: account.id,
+            login: account.login,
+            before,
+            after: (before | grant) & !revoke,
+        });
+    }
+    
+    Ok(changes)
+}
+
+/// Write planned access changes to the database
+pub async fn apply_access_changes(pool: &SqlitePool, changes: &[AccessChange]) -> Result<()> {
+    for change in changes.iter().filter(|c| c.is_change()) {
+        update_access(pool, change.account_id, change.after).await?;
+    }
+    
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::accounts::list_accounts;
+    use crate::db::Database;
+    use crate::test_util::{test_db_path, TempPath};
+    
+    async fn test_db(name: &str) -> (Database, TempPath) {
+        let path = test_db_path(&format!("bulk_{}", name));
+        let db = Database::new(&path).await.unwrap();
+        db.init_schema().await.unwrap();
+        (db, path)
+    }
+    
+    fn import(login: &str, access: &str) -> AccountImport {
+        AccountImport {
+            login: login.to_string(),
+            password: "secret".to_string(),
+            name: None,
+            access: access.to_string(),
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_import_dry_run_leaves_db_unchanged() {
+        let (db, _path) = test_db("import_dry_run").await;
+        let pool = db.pool();
+        
+        create_account(pool, "existing", b"pw", "Existing", AccessPrivileges::guest())
+            .await
+            .unwrap();
+        let before = count_accounts(pool).await.unwrap();
+        
+        let entries = vec![
+            import("alice", "user"),
+            import("bob", "admin"),
+            import("existing", "user"),
+        ];
+        let plan = plan_import(pool, entries, false).await.unwrap();
+        
+        assert_eq!(plan[0].action, ImportAction::Create);
+        assert_eq!(plan[1].action, ImportAction::Create);
+        assert_eq!(plan[1].access, AccessPrivileges::admin());
+        assert!(matches!(plan[2].action, ImportAction::Skip { .. }));
+        assert_eq!(plan[0].to_string(), "create     alice (access: user)");
+        
+        // Planning alone writes nothing
+        assert_eq!(count_accounts(pool).await.unwrap(), before);
+        assert!(get_account_by_login(pool, "alice").await.unwrap().is_none());
+        
+        apply_import(pool, &plan, PasswordScheme::Legacy, 0).await.unwrap();
+        assert_eq!(count_accounts(pool).await.unwrap(), before + 2);
+    }
+    
+    #[tokio::test]
+    async fn test_import_respects_account_limit() {
+        let (db, _path) = test_db("import_limit").await;
+        let pool = db.pool();
+        
+        create_account(pool, "existing", b"pw", "Existing", AccessPrivileges::guest())
+            .await
+            .unwrap();
+        
+        // Overwriting doesn't count against the limit
+        let entries = vec![import("alice", "user"), import("existing", "user"), import("bob", "user")];
+        let plan = plan_import(pool, entries, true).await.unwrap();
+        
+        assert!(apply_import(pool, &plan, PasswordScheme::Legacy, 2).await.is_err());
+        assert_eq!(count_accounts(pool).await.unwrap(), 1);
+        
+        apply_import(pool, &plan, PasswordScheme::Legacy, 3).await.unwrap();
+        assert_eq!(count_accounts(pool).await.unwrap(), 3);
+    }
+    
+    #[tokio::test]
+    async fn test_grant_dry_run_reports_before_and_after() {
+        let (db, _path) = test_db("grant_dry_run").await;
+        let pool = db.pool();
+        
+        create_account(pool, "carol", b"pw", "Carol", AccessPrivileges::guest())
+            .await
+            .unwrap();
+        
+        let logins = vec!["carol".to_string()];
+        let grant = parse_privileges("upload_files").unwrap();
+        let changes = plan_access_change(pool, &logins, grant, AccessPrivileges::empty())
+            .await
+            .unwrap();
+        
+        assert_eq!(changes[0].before, AccessPrivileges::guest());
+        assert_eq!(
+            changes[0].after,
+            AccessPrivileges::guest() | AccessPrivileges::UPLOAD_FILES
+        );
+        
+        let stored = list_accounts(pool).await.unwrap();
+        assert_eq!(stored[0].access_privileges(), AccessPrivileges::guest());
+    }
+}
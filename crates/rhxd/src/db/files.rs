@@ -37,7 +37,36 @@ impl FileEntry {
     }
 }
 
+impl From<FileEntry> for rhxcore::types::FileEntry {
+    fn from(entry: FileEntry) -> Self {
+        Self {
+            name: entry.name,
+            path: entry.path,
+            physical_path: PathBuf::from(entry.physical_path),
+            size: entry.size,
+            type_code: entry.type_code.as_deref().and_then(four_char_code),
+            creator_code: entry.creator_code.as_deref().and_then(four_char_code),
+            comment: entry.comment,
+            is_folder: entry.is_folder,
+        }
+    }
+}
+
+/// Convert a stored type/creator code to its 4-byte form
+fn four_char_code(code: &str) -> Option<[u8; 4]> {
+    code.as_bytes().try_into().ok()
+}
+
+/// Convert a 4-byte type/creator code to its stored form
+fn four_char_code_str(code: &[u8; 4]) -> Result<&str> {
+    match std::str::from_utf8(code) {
+        Ok(s) => Ok(s),
+        Err(_) => bail!("Type and creator codes must be valid UTF-8"),
+    }
+}
+
 /// Create a file entry
+#[allow(clippy::too_many_arguments)]
 pub async fn create_file_entry(
     pool: &SqlitePool,
     path: &str,
@@ -54,16 +83,16 @@ pub async fn create_file_entry(
         bail!("File name must be 255 characters or less");
     }
     
-    if let Some(tc) = type_code {
-        if tc.len() != 4 {
-            bail!("Type code must be exactly 4 characters");
-        }
+    if let Some(tc) = type_code
+        && tc.len() != 4
+    {
+        bail!("Type code must be exactly 4 characters");
     }
     
-    if let Some(cc) = creator_code {
-        if cc.len() != 4 {
-            bail!("Creator code must be exactly 4 characters");
-        }
+    if let Some(cc) = creator_code
+        && cc.len() != 4
+    {
+        bail!("Creator code must be exactly 4 characters");
     }
     
     let now = Utc::now().timestamp();
@@ -89,6 +118,28 @@ pub async fn create_file_entry(
     Ok(result.last_insert_rowid())
 }
 
+/// Store a protocol file entry
+pub async fn insert_file_entry(
+    pool: &SqlitePool,
+    entry: &rhxcore::types::FileEntry,
+) -> Result<i64> {
+    let type_code = entry.type_code.as_ref().map(four_char_code_str).transpose()?;
+    let creator_code = entry.creator_code.as_ref().map(four_char_code_str).transpose()?;
+    
+    create_file_entry(
+        pool,
+        &entry.path,
+        &entry.name,
+        entry.is_folder,
+        entry.size,
+        type_code,
+        creator_code,
+        entry.comment.as_deref(),
+        &entry.physical_path.to_string_lossy(),
+    )
+    .await
+}
+
 /// Get file entry by path
 pub async fn get_file_by_path(pool: &SqlitePool, path: &str) -> Result<Option<FileEntry>> {
     let entry = sqlx::query_as::<_, (i64, String, String, i32, i64, Option<String>, 
@@ -221,6 +272,15 @@ pub async fn file_exists(pool: &SqlitePool, path: &str) -> Result<bool> {
     Ok(count.0 > 0)
 }
 
+/// Count indexed files (folders excluded)
+pub async fn count_files(pool: &SqlitePool) -> Result<i64> {
+    let count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM files WHERE is_folder = 0")
+        .fetch_one(pool)
+        .await?;
+    
+    Ok(count.0)
+}
+
 /// Index a physical directory into the database
 pub async fn index_directory(
     pool: &SqlitePool,
@@ -298,9 +358,10 @@ pub async fn index_directory(
 mod tests {
     use super::*;
     use crate::db::Database;
+    use crate::test_util::{test_db_path, TempPath};
     
-    async fn test_db(name: &str) -> (Database, String) {
-        let path = format!("/tmp/test_rhxd_files_{}_{}.db", name, std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos());
+    async fn test_db(name: &str) -> (Database, TempPath) {
+        let path = test_db_path(&format!("files_{}", name));
         let db = Database::new(&path).await.unwrap();
         db.init_schema().await.unwrap();
         (db, path)
@@ -308,7 +369,7 @@ mod tests {
     
     #[tokio::test]
     async fn test_create_and_get_file() {
-        let (db, path) = test_db("create").await;
+        let (db, _path) = test_db("create").await;
         let pool = db.pool();
         
         let file_id = create_file_entry(
@@ -336,13 +397,11 @@ mod tests {
         assert_eq!(file.size, 1024);
         assert!(!file.is_folder);
         assert_eq!(file.type_code, Some("TEXT".to_string()));
-        
-        std::fs::remove_file(&path).ok();
     }
     
     #[tokio::test]
     async fn test_list_files() {
-        let (db, path) = test_db("list").await;
+        let (db, _path) = test_db("list").await;
         let pool = db.pool();
         
         // Create some test files
@@ -369,7 +428,58 @@ mod tests {
         // List folder
         let folder_files = list_files_in_directory(pool, "/folder").await.unwrap();
         assert_eq!(folder_files.len(), 1); // nested.txt
+    }
+    
+    #[tokio::test]
+    async fn test_db_entry_wire_roundtrip() {
+        let (db, _path) = test_db("wire").await;
+        let pool = db.pool();
+        
+        create_file_entry(
+            pool,
+            "/docs/readme.txt",
+            "readme.txt",
+            false,
+            2048,
+            Some("TEXT"),
+            Some("ttxt"),
+            None,
+            "/physical/docs/readme.txt",
+        )
+        .await
+        .unwrap();
         
-        std::fs::remove_file(&path).ok();
+        let row = get_file_by_path(pool, "/docs/readme.txt")
+            .await
+            .unwrap()
+            .unwrap();
+        
+        // Database row -> protocol entry -> wire
+        let entry: rhxcore::types::FileEntry = row.into();
+        assert_eq!(entry.type_code, Some(*b"TEXT"));
+        assert_eq!(entry.creator_code, Some(*b"ttxt"));
+        
+        let wire = entry.to_name_with_info();
+        
+        // Wire -> protocol entry -> database row
+        let mut decoded = rhxcore::types::FileEntry::from_name_with_info(&wire, "/copies").unwrap();
+        assert_eq!(decoded.name, "readme.txt");
+        assert_eq!(decoded.size, 2048);
+        assert_eq!(decoded.type_code, entry.type_code);
+        assert_eq!(decoded.creator_code, entry.creator_code);
+        assert_eq!(decoded.path, "/copies/readme.txt");
+        
+        decoded.physical_path = "/physical/copies/readme.txt".into();
+        insert_file_entry(pool, &decoded).await.unwrap();
+        
+        let stored = get_file_by_path(pool, "/copies/readme.txt")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.name, "readme.txt");
+        assert_eq!(stored.size, 2048);
+        assert_eq!(stored.type_code, Some("TEXT".to_string()));
+        assert_eq!(stored.creator_code, Some("ttxt".to_string()));
+        assert_eq!(stored.physical_path, "/physical/copies/readme.txt");
     }
 }
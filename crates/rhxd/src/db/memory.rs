@@ -0,0 +1,227 @@
+//! In-memory stores
+//!
+//! Keep everything in process memory, so nothing survives a restart. Meant
+//! for tests that don't need a real database.
+
+use crate::db::accounts::{admin_privileges, validate_text, Account};
+use crate::db::files::FileEntry;
+use crate::db::store::{AccountStore, FileStore, StoreFuture};
+use anyhow::bail;
+use chrono::Utc;
+use rhxcore::types::AccessPrivileges;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// Account store backed by a map
+#[derive(Default)]
+pub struct MemoryAccountStore {
+    accounts: Mutex<BTreeMap<i64, Account>>,
+}
+
+impl MemoryAccountStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Apply `update` to an account, bumping its modification time
+    fn modify(&self, account_id: i64, update: impl FnOnce(&mut Account)) {
+        if let Some(account) = self.accounts.lock().unwrap().get_mut(&account_id) {
+            update(account);
+            account.modified_at = Utc::now().timestamp();
+        }
+    }
+    
+    fn find_by_login(&self, login: &str) -> Option<Account> {
+        self.accounts
+            .lock()
+            .unwrap()
+            .values()
+            .find(|a| a.login.eq_ignore_ascii_case(login))
+            .cloned()
+    }
+}
+
+impl AccountStore for MemoryAccountStore {
+    fn create_account<'a>(
+        &'a self,
+        login: &'a str,
+        password_hash: &'a [u8],
+        name: &'a str,
+        access: AccessPrivileges,
+    ) -> StoreFuture<'a, i64> {
+        Box::pin(async move {
+            validate_text("Login", login)?;
+            validate_text("Name", name)?;
+            
+            let mut accounts = self.accounts.lock().unwrap();
+            if accounts.values().any(|a| a.login.eq_ignore_ascii_case(login)) {
+                bail!("Account '{}' already exists", login);
+            }
+            
+            let id = accounts.keys().next_back().map_or(1, |id| id + 1);
+            let now = Utc::now().timestamp();
+            accounts.insert(id, Account {
+                id,
+                login: login.to_string(),
+                password_hash: password_hash.to_vec(),
+                name: name.to_string(),
+                icon_id: 0,
+                access: access.bits() as i64,
+                created_at: now,
+                modified_at: now,
+                force_icon: None,
+                force_admin_flag: false,
+            });
+            
+            Ok(id)
+        })
+    }
+    
+    fn get_account_by_login<'a>(&'a self, login: &'a str) -> StoreFuture<'a, Option<Account>> {
+        Box::pin(async move { Ok(self.find_by_login(login)) })
+    }
+    
+    fn get_account_by_id(&self, id: i64) -> StoreFuture<'_, Option<Account>> {
+        Box::pin(async move { Ok(self.accounts.lock().unwrap().get(&id).cloned()) })
+    }
+    
+    fn list_accounts(&self) -> StoreFuture<'_, Vec<Account>> {
+        Box::pin(async move {
+            let mut accounts: Vec<Account> = self.accounts.lock().unwrap().values().cloned().collect();
+            accounts.sort_by_key(|a| a.login.to_lowercase());
+            Ok(accounts)
+        })
+    }
+    
+    fn update_password<'a>(&'a self, account_id: i64, password_hash: &'a [u8]) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            self.modify(account_id, |a| a.password_hash = password_hash.to_vec());
+            Ok(())
+        })
+    }
+    
+    fn update_name<'a>(&'a self, account_id: i64, name: &'a str) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            validate_text("Name", name)?;
+            self.modify(account_id, |a| a.name = name.to_string());
+            Ok(())
+        })
+    }
+    
+    fn update_access(&self, account_id: i64, access: AccessPrivileges) -> StoreFuture<'_, ()> {
+        Box::pin(async move {
+            self.modify(account_id, |a| a.access = access.bits() as i64);
+            Ok(())
+        })
+    }
+    
+    fn update_overrides(
+        &self,
+        account_id: i64,
+        force_icon: Option<i64>,
+        force_admin_flag: bool,
+    ) -> StoreFuture<'_, ()> {
+        Box::pin(async move {
+            self.modify(account_id, |a| {
+                a.force_icon = force_icon;
+                a.force_admin_flag = force_admin_flag;
+            });
+            Ok(())
+        })
+    }
+    
+    fn delete_account(&self, account_id: i64) -> StoreFuture<'_, ()> {
+        Box::pin(async move {
+            self.accounts.lock().unwrap().remove(&account_id);
+            Ok(())
+        })
+    }
+    
+    fn update_access_keeping_admin(&self, account_id: i64, access: AccessPrivileges) -> StoreFuture<'_, bool> {
+        Box::pin(async move {
+            let mut accounts = self.accounts.lock().unwrap();
+            let Some(account) = accounts.get(&account_id) else {
+                return Ok(false);
+            };
+            
+            if account.is_admin()
+                && !access.intersects(admin_privileges())
+                && !accounts.values().any(|a| a.id != account_id && a.is_admin())
+            {
+                return Ok(false);
+            }
+            
+            let account = accounts.get_mut(&account_id).unwrap();
+            account.access = access.bits() as i64;
+            account.modified_at = Utc::now().timestamp();
+            Ok(true)
+        })
+    }
+    
+    fn delete_account_keeping_admin(&self, account_id: i64) -> StoreFuture<'_, bool> {
+        Box::pin(async move {
+            let mut accounts = self.accounts.lock().unwrap();
+            let Some(account) = accounts.get(&account_id) else {
+                return Ok(false);
+            };
+            
+            if account.is_admin() && !accounts.values().any(|a| a.id != account_id && a.is_admin()) {
+                return Ok(false);
+            }
+            
+            accounts.remove(&account_id);
+            Ok(true)
+        })
+    }
+    
+    fn account_exists<'a>(&'a self, login: &'a str) -> StoreFuture<'a, bool> {
+        Box::pin(async move { Ok(self.find_by_login(login).is_some()) })
+    }
+    
+    fn count_accounts(&self) -> StoreFuture<'_, u64> {
+        Box::pin(async move { Ok(self.accounts.lock().unwrap().len() as u64) })
+    }
+}
+
+/// File store backed by a map of virtual paths
+#[derive(Default)]
+pub struct MemoryFileStore {
+    files: Mutex<BTreeMap<String, FileEntry>>,
+}
+
+impl MemoryFileStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Add or replace an entry
+    pub fn insert(&self, entry: FileEntry) {
+        self.files.lock().unwrap().insert(entry.path.clone(), entry);
+    }
+}
+
+impl FileStore for MemoryFileStore {
+    fn get_file_by_path<'a>(&'a self, path: &'a str) -> StoreFuture<'a, Option<FileEntry>> {
+        Box::pin(async move { Ok(self.files.lock().unwrap().get(path).cloned()) })
+    }
+    
+    fn list_files_in_directory<'a>(&'a self, parent_path: &'a str) -> StoreFuture<'a, Vec<FileEntry>> {
+        Box::pin(async move {
+            let parent = parent_path.trim_end_matches('/');
+            let parent = if parent.is_empty() { "/" } else { parent };
+            
+            let mut entries: Vec<FileEntry> = self
+                .files
+                .lock()
+                .unwrap()
+                .values()
+                .filter(|e| e.path != "/" && e.parent_path().as_deref() == Some(parent))
+                .cloned()
+                .collect();
+            
+            // Same order as the SQLite store: folders first, then by name
+            entries.sort_by(|a, b| b.is_folder.cmp(&a.is_folder).then_with(|| a.name.cmp(&b.name)));
+            Ok(entries)
+        })
+    }
+}
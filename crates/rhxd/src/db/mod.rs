@@ -2,13 +2,18 @@
 //! 
 //! Handles SQLite database operations for accounts, sessions, files, and other data.
 
+use crate::config::DatabaseConfig;
 use anyhow::Result;
 use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
 use std::path::Path;
+use std::time::Duration;
 
 pub mod accounts;
+pub mod bulk;
 pub mod files;
+pub mod memory;
 pub mod schema;
+pub mod store;
 
 /// Parse SQL statements from a script, handling comments and semicolons
 fn parse_sql_statements(sql: &str) -> Vec<String> {
@@ -66,6 +71,15 @@ fn parse_sql_statements(sql: &str) -> Vec<String> {
     statements
 }
 
+/// `database.path` that opens an in-memory database (see [`Database::new_in_memory`])
+#[cfg(any(test, feature = "in-memory-db"))]
+pub const IN_MEMORY_PATH: &str = ":memory:";
+
+/// Whether an error was caused by waiting too long for a pooled connection
+pub fn is_pool_timeout(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| matches!(cause.downcast_ref::<sqlx::Error>(), Some(sqlx::Error::PoolTimedOut)))
+}
+
 /// Database connection pool
 #[derive(Clone)]
 pub struct Database {
@@ -73,18 +87,88 @@ pub struct Database {
 }
 
 impl Database {
-    /// Create a new database connection pool
+    /// Create a new database connection pool with the default pool settings
     pub async fn new(path: impl AsRef<Path>) -> Result<Self> {
-        let path_str = path.as_ref().to_string_lossy().to_string();
+        Self::open(&DatabaseConfig::with_path(path.as_ref())).await
+    }
+    
+    /// Open the database described by `database` in the configuration
+    ///
+    /// Waiting longer than `acquire_timeout_ms` for a pooled connection fails
+    /// with [`sqlx::Error::PoolTimedOut`] (see [`is_pool_timeout`]).
+    pub async fn open(config: &DatabaseConfig) -> Result<Self> {
+        #[cfg(any(test, feature = "in-memory-db"))]
+        if config.path == Path::new(IN_MEMORY_PATH) {
+            return Self::new_in_memory().await;
+        }
         
         let options = SqliteConnectOptions::new()
-            .filename(&path_str)
+            .filename(&config.path)
             .create_if_missing(true)
             .foreign_keys(true)
-            .journal_mode(sqlx::sqlite::SqliteJournalMode::Wal);
+            .journal_mode(sqlx::sqlite::SqliteJournalMode::Wal)
+            .busy_timeout(Duration::from_millis(config.busy_timeout_ms));
         
         let pool = SqlitePoolOptions::new()
-            .max_connections(32)
+            .max_connections(config.max_connections.max(1))
+            .acquire_timeout(Duration::from_millis(config.acquire_timeout_ms))
+            .connect_with(options)
+            .await?;
+        
+        Ok(Self { pool })
+    }
+    
+    /// Open a private in-memory database (for tests)
+    ///
+    /// An in-memory SQLite database lives only as long as a connection to it,
+    /// and every connection opened from `sqlite::memory:` gets its own. The
+    /// pool is therefore pinned to a single connection that is never closed
+    /// for idling or age, so all queries see the same data. Nothing is
+    /// written to disk, and each call gets a separate database.
+    ///
+    /// Setting `database.path` to `:memory:` opens one of these through
+    /// [`open`](Self::open), e.g. for a whole `ServerState`.
+    #[cfg(any(test, feature = "in-memory-db"))]
+    pub async fn new_in_memory() -> Result<Self> {
+        use std::str::FromStr;
+        
+        let options = SqliteConnectOptions::from_str("sqlite::memory:")?
+            .foreign_keys(true);
+        
+        let pool = SqlitePoolOptions::new()
+            .min_connections(1)
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect_with(options)
+            .await?;
+        
+        Ok(Self { pool })
+    }
+    
+    /// Copy the write-ahead log into the database and truncate it
+    pub async fn checkpoint(&self) -> Result<()> {
+        let (busy, log_frames, checkpointed): (i64, i64, i64) =
+            sqlx::query_as("PRAGMA wal_checkpoint(TRUNCATE)")
+                .fetch_one(&self.pool)
+                .await?;
+        
+        if busy != 0 {
+            tracing::debug!("WAL checkpoint blocked by readers ({} of {} frames copied)", checkpointed, log_frames);
+        }
+        Ok(())
+    }
+    
+    /// Open an existing database without write access
+    ///
+    /// For inspecting a database that a running server may also have open.
+    pub async fn open_read_only(path: impl AsRef<Path>) -> Result<Self> {
+        let options = SqliteConnectOptions::new()
+            .filename(path.as_ref())
+            .read_only(true);
+        
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
             .connect_with(options)
             .await?;
         
@@ -109,6 +193,33 @@ impl Database {
         }
         
         tracing::info!("Database schema initialized ({} statements executed)", statements.len());
+        self.migrate().await
+    }
+    
+    /// Apply any migrations newer than the database's schema version
+    pub async fn migrate(&self) -> Result<()> {
+        let current: usize = self.schema_version().await?.parse()?;
+        
+        for (idx, migration) in schema::MIGRATIONS.iter().enumerate().skip(current.saturating_sub(1)) {
+            let version = idx + 2;
+            let mut tx = self.pool.begin().await?;
+            
+            for stmt in parse_sql_statements(migration) {
+                sqlx::query(stmt.trim())
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| anyhow::anyhow!("Migration to version {} failed: {}\nStatement: {}", version, e, stmt.trim()))?;
+            }
+            
+            sqlx::query("UPDATE server_metadata SET value = ? WHERE key = 'schema_version'")
+                .bind(version.to_string())
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+            
+            tracing::info!("Database migrated to schema version {}", version);
+        }
+        
         Ok(())
     }
     
@@ -145,11 +256,12 @@ impl Database {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util::test_db_path;
     
     #[tokio::test]
     async fn test_database_init() {
         // Use a temp file instead of :memory: to avoid connection isolation issues
-        let temp_path = format!("/tmp/test_rhxd_{}.db", std::process::id());
+        let temp_path = test_db_path("init");
         
         let db = Database::new(&temp_path).await.unwrap();
         
@@ -178,12 +290,95 @@ mod tests {
         // Verify schema version
         println!("Getting schema version...");
         let version = db.schema_version().await.unwrap();
-        assert_eq!(version, "1");
+        assert_eq!(version, schema::SCHEMA_VERSION);
         
         // Health check
         db.health_check().await.unwrap();
+    }
+    
+    #[tokio::test]
+    async fn test_busy_timeout_and_checkpoint() {
+        let temp_path = test_db_path("busy_timeout");
+        let mut config = DatabaseConfig::with_path(temp_path.to_path_buf());
+        config.busy_timeout_ms = 1234;
+        let db = Database::open(&config).await.unwrap();
+        db.init_schema().await.unwrap();
+        
+        let (busy_timeout,): (i64,) = sqlx::query_as("PRAGMA busy_timeout")
+            .fetch_one(db.pool())
+            .await
+            .unwrap();
+        assert_eq!(busy_timeout, 1234);
+        
+        db.checkpoint().await.unwrap();
+        let wal = std::fs::metadata(format!("{}-wal", temp_path.display())).map(|m| m.len()).unwrap_or(0);
+        assert_eq!(wal, 0);
+    }
+    
+    #[tokio::test]
+    async fn test_in_memory_account_crud() {
+        use crate::db::accounts::*;
+        use rhxcore::types::AccessPrivileges;
+        
+        let db = Database::open(&DatabaseConfig::with_path(IN_MEMORY_PATH)).await.unwrap();
+        db.init_schema().await.unwrap();
+        let pool = db.pool();
+        
+        let id = create_account(pool, "memory", b"pw", "Memory", AccessPrivileges::user())
+            .await
+            .unwrap();
+        update_name(pool, id, "Renamed").await.unwrap();
+        update_access(pool, id, AccessPrivileges::admin()).await.unwrap();
+        let account = get_account_by_login(pool, "memory").await.unwrap().unwrap();
+        assert_eq!(account.name, "Renamed");
+        assert!(account.is_admin());
+        
+        delete_account(pool, id).await.unwrap();
+        assert!(!account_exists(pool, "memory").await.unwrap());
+        
+        // A second in-memory database starts out empty
+        let other = Database::new_in_memory().await.unwrap();
+        other.init_schema().await.unwrap();
+        create_account(other.pool(), "memory", b"pw", "Other", AccessPrivileges::user())
+            .await
+            .unwrap();
+        assert!(!account_exists(pool, "memory").await.unwrap());
+        
+        // Nothing was written to disk
+        assert!(!Path::new(IN_MEMORY_PATH).exists());
+    }
+    
+    #[tokio::test]
+    async fn test_migrate_version_1_database() {
+        let temp_path = test_db_path("migrate");
+        let db = Database::new(&temp_path).await.unwrap();
+        
+        // Build a version 1 database without running the migrations
+        for stmt in parse_sql_statements(schema::SCHEMA_SQL) {
+            sqlx::query(stmt.trim()).execute(db.pool()).await.unwrap();
+        }
+        assert_eq!(db.schema_version().await.unwrap(), "1");
+        sqlx::query(
+            "INSERT INTO accounts (login, password, name, access_privileges, created_at, modified_at)
+             VALUES ('bot', x'00', 'Bot', 0, 0, 0)"
+        )
+        .execute(db.pool())
+        .await
+        .unwrap();
+        
+        db.migrate().await.unwrap();
+        assert_eq!(db.schema_version().await.unwrap(), schema::SCHEMA_VERSION);
+        
+        let (force_icon, force_admin_flag): (Option<i64>, bool) = sqlx::query_as(
+            "SELECT force_icon, force_admin_flag FROM accounts WHERE login = 'bot'"
+        )
+        .fetch_one(db.pool())
+        .await
+        .unwrap();
+        assert_eq!(force_icon, None);
+        assert!(!force_admin_flag);
         
-        // Cleanup
-        std::fs::remove_file(&temp_path).ok();
+        // Already up to date, so running it again is a no-op
+        db.migrate().await.unwrap();
     }
 }
@@ -3,7 +3,27 @@
 #![allow(dead_code)]
 
 /// Current schema version
-pub const SCHEMA_VERSION: &str = "1";
+pub const SCHEMA_VERSION: &str = "3";
 
 /// Schema SQL is embedded from schema.sql file
+///
+/// This always creates a version 1 database; [`MIGRATIONS`] bring it up to
+/// [`SCHEMA_VERSION`].
 pub const SCHEMA_SQL: &str = include_str!("schema.sql");
+
+/// Upgrade scripts, where entry `i` moves a database from version `i + 1`
+/// to version `i + 2`
+pub const MIGRATIONS: &[&str] = &[
+    // 2: per-account icon and admin flag overrides for bots and services
+    "ALTER TABLE accounts ADD COLUMN force_icon INTEGER;
+     ALTER TABLE accounts ADD COLUMN force_admin_flag INTEGER NOT NULL DEFAULT 0;",
+    // 3: audit trail (see crate::audit)
+    "CREATE TABLE IF NOT EXISTS audit_log (
+         id INTEGER PRIMARY KEY AUTOINCREMENT,
+         at INTEGER NOT NULL,
+         actor TEXT NOT NULL,
+         action TEXT NOT NULL,
+         target TEXT NOT NULL,
+         detail TEXT NOT NULL DEFAULT ''
+     );",
+];
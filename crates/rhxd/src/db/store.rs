@@ -0,0 +1,159 @@
+//! Storage abstraction
+//!
+//! Handlers reach accounts and file metadata through these traits instead of
+//! calling the SQLite functions directly, so other backends (such as the
+//! in-memory store in [`crate::db::memory`]) can stand in for the database.
+//!
+//! Trait methods return boxed futures so the stores can be used as trait
+//! objects on `ServerState`.
+
+use crate::db::accounts::{self, Account};
+use crate::db::files::{self, FileEntry};
+use crate::db::Database;
+use anyhow::Result;
+use futures::future::BoxFuture;
+use rhxcore::types::AccessPrivileges;
+
+/// Future returned by store operations
+pub type StoreFuture<'a, T> = BoxFuture<'a, Result<T>>;
+
+/// Account storage
+pub trait AccountStore: Send + Sync {
+    /// Create a new account, returning its ID
+    fn create_account<'a>(
+        &'a self,
+        login: &'a str,
+        password_hash: &'a [u8],
+        name: &'a str,
+        access: AccessPrivileges,
+    ) -> StoreFuture<'a, i64>;
+    
+    /// Get account by login (case-insensitive)
+    fn get_account_by_login<'a>(&'a self, login: &'a str) -> StoreFuture<'a, Option<Account>>;
+    
+    /// Get account by ID
+    fn get_account_by_id(&self, id: i64) -> StoreFuture<'_, Option<Account>>;
+    
+    /// List all accounts, ordered by login
+    fn list_accounts(&self) -> StoreFuture<'_, Vec<Account>>;
+    
+    /// Update account password
+    fn update_password<'a>(&'a self, account_id: i64, password_hash: &'a [u8]) -> StoreFuture<'a, ()>;
+    
+    /// Update account display name
+    fn update_name<'a>(&'a self, account_id: i64, name: &'a str) -> StoreFuture<'a, ()>;
+    
+    /// Update account access privileges
+    fn update_access(&self, account_id: i64, access: AccessPrivileges) -> StoreFuture<'_, ()>;
+    
+    /// Update the icon and admin flag overrides of an account
+    fn update_overrides(
+        &self,
+        account_id: i64,
+        force_icon: Option<i64>,
+        force_admin_flag: bool,
+    ) -> StoreFuture<'_, ()>;
+    
+    /// Delete an account
+    fn delete_account(&self, account_id: i64) -> StoreFuture<'_, ()>;
+    
+    /// Update account access unless that would leave no account able to
+    /// manage the others (see [`Account::is_admin`]), returning whether it
+    /// changed; the check and the write are atomic
+    fn update_access_keeping_admin(&self, account_id: i64, access: AccessPrivileges) -> StoreFuture<'_, bool>;
+    
+    /// Delete an account unless it is the last one able to manage the
+    /// others, returning whether it was deleted; the check and the delete
+    /// are atomic
+    fn delete_account_keeping_admin(&self, account_id: i64) -> StoreFuture<'_, bool>;
+    
+    /// Check if an account exists (case-insensitive)
+    fn account_exists<'a>(&'a self, login: &'a str) -> StoreFuture<'a, bool>;
+    
+    /// Count all accounts
+    fn count_accounts(&self) -> StoreFuture<'_, u64>;
+}
+
+/// File metadata storage
+pub trait FileStore: Send + Sync {
+    /// Get file entry by virtual path
+    fn get_file_by_path<'a>(&'a self, path: &'a str) -> StoreFuture<'a, Option<FileEntry>>;
+    
+    /// List the entries directly inside a folder
+    fn list_files_in_directory<'a>(&'a self, parent_path: &'a str) -> StoreFuture<'a, Vec<FileEntry>>;
+}
+
+impl AccountStore for Database {
+    fn create_account<'a>(
+        &'a self,
+        login: &'a str,
+        password_hash: &'a [u8],
+        name: &'a str,
+        access: AccessPrivileges,
+    ) -> StoreFuture<'a, i64> {
+        Box::pin(accounts::create_account(self.pool(), login, password_hash, name, access))
+    }
+    
+    fn get_account_by_login<'a>(&'a self, login: &'a str) -> StoreFuture<'a, Option<Account>> {
+        Box::pin(accounts::get_account_by_login(self.pool(), login))
+    }
+    
+    fn get_account_by_id(&self, id: i64) -> StoreFuture<'_, Option<Account>> {
+        Box::pin(accounts::get_account_by_id(self.pool(), id))
+    }
+    
+    fn list_accounts(&self) -> StoreFuture<'_, Vec<Account>> {
+        Box::pin(accounts::list_accounts(self.pool()))
+    }
+    
+    fn update_password<'a>(&'a self, account_id: i64, password_hash: &'a [u8]) -> StoreFuture<'a, ()> {
+        Box::pin(accounts::update_password(self.pool(), account_id, password_hash))
+    }
+    
+    fn update_name<'a>(&'a self, account_id: i64, name: &'a str) -> StoreFuture<'a, ()> {
+        Box::pin(accounts::update_name(self.pool(), account_id, name))
+    }
+    
+    fn update_access(&self, account_id: i64, access: AccessPrivileges) -> StoreFuture<'_, ()> {
+        Box::pin(accounts::update_access(self.pool(), account_id, access))
+    }
+    
+    fn update_overrides(
+        &self,
+        account_id: i64,
+        force_icon: Option<i64>,
+        force_admin_flag: bool,
+    ) -> StoreFuture<'_, ()> {
+        Box::pin(accounts::update_overrides(self.pool(), account_id, force_icon, force_admin_flag))
+    }
+    
+    fn delete_account(&self, account_id: i64) -> StoreFuture<'_, ()> {
+        Box::pin(accounts::delete_account(self.pool(), account_id))
+    }
+    
+    fn update_access_keeping_admin(&self, account_id: i64, access: AccessPrivileges) -> StoreFuture<'_, bool> {
+        Box::pin(accounts::update_access_keeping_admin(self.pool(), account_id, access))
+    }
+    
+    fn delete_account_keeping_admin(&self, account_id: i64) -> StoreFuture<'_, bool> {
+        Box::pin(accounts::delete_account_keeping_admin(self.pool(), account_id))
+    }
+    
+    fn account_exists<'a>(&'a self, login: &'a str) -> StoreFuture<'a, bool> {
+        Box::pin(accounts::account_exists(self.pool(), login))
+    }
+    
+    fn count_accounts(&self) -> StoreFuture<'_, u64> {
+        Box::pin(async move { Ok(accounts::count_accounts(self.pool()).await? as u64) })
+    }
+}
+
+impl FileStore for Database {
+    fn get_file_by_path<'a>(&'a self, path: &'a str) -> StoreFuture<'a, Option<FileEntry>> {
+        Box::pin(files::get_file_by_path(self.pool(), path))
+    }
+    
+    fn list_files_in_directory<'a>(&'a self, parent_path: &'a str) -> StoreFuture<'a, Vec<FileEntry>> {
+        Box::pin(files::list_files_in_directory(self.pool(), parent_path))
+    }
+}
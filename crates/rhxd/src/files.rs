@@ -0,0 +1,201 @@
+//! File path resolution
+//!
+//! Maps client-supplied path components onto the virtual file tree and the
+//! physical file root, enforcing the limits in [`FilesConfig`].
+
+use crate::config::FilesConfig;
+use rhxcore::protocol::ErrorCode;
+use rhxcore::types::file::virtual_path;
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// Characters that may not appear in a file name on this platform
+#[cfg(windows)]
+const RESERVED_CHARS: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+#[cfg(not(windows))]
+const RESERVED_CHARS: &[char] = &['/', ':'];
+
+/// Device names Windows refuses as file names, with or without an extension
+#[cfg(windows)]
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Reasons a client path is rejected
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum PathError {
+    #[error("Path is {depth} levels deep (max: {max})")]
+    TooDeep { depth: usize, max: usize },
+
+    #[error("File name is {len} bytes long (max: {max})")]
+    NameTooLong { len: usize, max: usize },
+
+    #[error("Invalid file name: {0:?}")]
+    InvalidName(String),
+}
+
+impl PathError {
+    /// Error code to report to the client
+    pub fn error_code(&self) -> ErrorCode {
+        ErrorCode::InvalidParameter
+    }
+}
+
+/// A client path resolved against the file root
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedPath {
+    /// Path in the virtual file tree, e.g. `/Uploads/notes.txt`
+    pub virtual_path: String,
+    /// Location on disk below the configured root
+    pub physical_path: PathBuf,
+}
+
+/// Resolves client paths within the configured file root
+pub struct PathResolver<'a> {
+    config: &'a FilesConfig,
+}
+
+impl<'a> PathResolver<'a> {
+    pub fn new(config: &'a FilesConfig) -> Self {
+        Self { config }
+    }
+
+    /// Resolve an existing path, e.g. a folder to list
+    pub fn resolve<S: AsRef<str>>(&self, components: &[S]) -> Result<ResolvedPath, PathError> {
+        self.check_depth(components.len())?;
+
+        let mut physical_path = self.config.root_path.clone();
+        for component in components {
+            let name = component.as_ref();
+            self.validate_name(name)?;
+            physical_path.push(name);
+        }
+
+        Ok(ResolvedPath {
+            virtual_path: virtual_path(components),
+            physical_path,
+        })
+    }
+
+    /// Resolve the path of a file or folder about to be created in `parent`
+    pub fn resolve_new<S: AsRef<str>>(
+        &self,
+        parent: &[S],
+        name: &str,
+    ) -> Result<ResolvedPath, PathError> {
+        let mut components: Vec<&str> = parent.iter().map(|c| c.as_ref()).collect();
+        components.push(name);
+        self.resolve(&components)
+    }
+
+    /// Check a single file or folder name
+    pub fn validate_name(&self, name: &str) -> Result<(), PathError> {
+        if name.len() > self.config.max_file_name_length {
+            return Err(PathError::NameTooLong {
+                len: name.len(),
+                max: self.config.max_file_name_length,
+            });
+        }
+
+        if !is_valid_name(name) {
+            return Err(PathError::InvalidName(name.to_string()));
+        }
+
+        Ok(())
+    }
+
+    fn check_depth(&self, depth: usize) -> Result<(), PathError> {
+        if depth > self.config.max_path_depth {
+            return Err(PathError::TooDeep {
+                depth,
+                max: self.config.max_path_depth,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether a name can be used as a file name on this platform
+fn is_valid_name(name: &str) -> bool {
+    if name.is_empty() || name == "." || name == ".." {
+        return false;
+    }
+
+    if name.chars().any(|c| c.is_control() || RESERVED_CHARS.contains(&c)) {
+        return false;
+    }
+
+    #[cfg(windows)]
+    {
+        if name.ends_with('.') || name.ends_with(' ') {
+            return false;
+        }
+
+        let stem = name.split('.').next().unwrap_or(name);
+        if RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+            return false;
+        }
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Config;
+
+    fn files_config(max_path_depth: usize) -> FilesConfig {
+        let mut config = Config::default().files;
+        config.max_path_depth = max_path_depth;
+        config
+    }
+
+    #[test]
+    fn test_create_at_depth_limit() {
+        let config = files_config(3);
+        let resolver = PathResolver::new(&config);
+
+        let resolved = resolver.resolve_new(&["a", "b"], "c").unwrap();
+        assert_eq!(resolved.virtual_path, "/a/b/c");
+        assert_eq!(resolved.physical_path, config.root_path.join("a").join("b").join("c"));
+    }
+
+    #[test]
+    fn test_create_past_depth_limit() {
+        let config = files_config(3);
+        let resolver = PathResolver::new(&config);
+
+        let err = resolver.resolve_new(&["a", "b", "c"], "d").unwrap_err();
+        assert_eq!(err, PathError::TooDeep { depth: 4, max: 3 });
+        assert_eq!(err.error_code(), ErrorCode::InvalidParameter);
+    }
+
+    #[test]
+    fn test_rejects_invalid_names() {
+        let mut config = files_config(8);
+        config.max_file_name_length = 8;
+        let resolver = PathResolver::new(&config);
+
+        assert!(resolver.validate_name("notes.txt").is_err());
+        assert!(resolver.validate_name("notes").is_ok());
+        assert!(resolver.validate_name("..").is_err());
+        assert!(resolver.validate_name("a/b").is_err());
+        assert!(resolver.validate_name("a\0b").is_err());
+        assert!(resolver.validate_name("").is_err());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_rejects_windows_reserved_names() {
+        let config = files_config(8);
+        let resolver = PathResolver::new(&config);
+
+        assert!(resolver.validate_name("CON").is_err());
+        assert!(resolver.validate_name("nul.txt").is_err());
+        assert!(resolver.validate_name("a?b").is_err());
+        assert!(resolver.validate_name("trailing.").is_err());
+    }
+}
@@ -5,8 +5,14 @@
 //! - GetUser (352): Get account details
 //! - SetUser (353): Modify account  
 //! - DeleteUser (351): Delete account
+//!
+//! The dispatcher checks the privilege each of these requires before they
+//! run (see `connection::authorization`), honouring any
+//! `security.transaction_privileges` override.
 
+use crate::audit::{self, AccountChange};
 use crate::connection::transaction_helpers::{create_error_reply, create_success_reply};
+use crate::db::accounts::hash_stored_password;
 use crate::state::ServerState;
 use anyhow::{Context, Result};
 use rhxcore::password::xor_password;
@@ -14,32 +20,12 @@ use rhxcore::protocol::{ErrorCode, Field, FieldId, Transaction};
 use rhxcore::types::AccessPrivileges;
 use std::sync::Arc;
 
-/// Check if the user has a specific privilege
-async fn check_privilege(
-    state: &ServerState,
-    user_id: u16,
-    required: AccessPrivileges,
-) -> Result<bool> {
-    // Get user's session and account
-    let account_id = match state.get_session(user_id) {
-        Some(session) => session.account_id,
-        None => return Ok(false),
-    };
-    
-    let account_id = match account_id {
-        Some(id) => id,
-        None => return Ok(false), // Guests don't have privileges
-    };
-    
-    // Get account from database
-    let account = crate::db::accounts::get_account_by_id(state.database.pool(), account_id)
-        .await
-        .context("Database error")?;
-    
-    match account {
-        Some(account) => Ok(account.has_privilege(required)),
-        None => Ok(false),
-    }
+/// Whether an actor may grant the requested access
+///
+/// Only sysops can grant privileges they don't hold themselves; anyone else
+/// could otherwise escalate by creating or editing an account.
+fn can_grant(actor_access: AccessPrivileges, requested: AccessPrivileges) -> bool {
+    actor_access == AccessPrivileges::sysop() || actor_access.contains(requested)
 }
 
 /// Handle NewUser transaction (350) - Create new account
@@ -48,7 +34,7 @@ async fn check_privilege(
 /// - Field 105: Login name (binary, scrambled)
 /// - Field 106: Password (binary, scrambled)
 /// - Field 102: Display name (string)
-/// - Field 110: Access privileges (8 bytes, i64)
+/// - Field 110: Access privileges (8 bytes, wire format)
 ///
 /// Server replies with:
 /// - Empty success or error code
@@ -59,64 +45,34 @@ pub async fn handle_new_user(
 ) -> Result<Transaction> {
     tracing::debug!("User {} attempting to create new account", user_id);
     
-    // Check if user has CREATE_USERS permission
-    if !check_privilege(&state, user_id, AccessPrivileges::CREATE_USERS).await? {
-        tracing::warn!("User {} tried to create account without permission", user_id);
-        return Ok(create_error_reply(&transaction, ErrorCode::PermissionDenied));
-    }
-    
     // Extract fields
-    let mut login: Option<Vec<u8>> = None;
-    let mut password: Option<Vec<u8>> = None;
-    let mut name: Option<String> = None;
-    let mut access: Option<i64> = None;
-    
-    for field in &transaction.fields {
-        match field.id {
-            FieldId::UserLogin => {
-                login = field.as_binary().map(|b| b.to_vec());
-            }
-            FieldId::UserPassword => {
-                password = field.as_binary().map(|b| b.to_vec());
-            }
-            FieldId::UserName => {
-                name = field.as_string().map(|s| s.to_string());
-            }
-            FieldId::UserAccess => {
-                access = field.as_binary().and_then(|bytes| {
-                    if bytes.len() == 8 {
-                        // Read as big-endian i64 from wire format
-                        let mut arr = [0u8; 8];
-                        arr.copy_from_slice(bytes);
-                        Some(i64::from_be_bytes(arr))
-                    } else {
-                        None
-                    }
-                });
-            }
-            _ => {}
-        }
-    }
+    let login = transaction.get_field(FieldId::UserLogin);
+    let password = transaction
+        .get_field(FieldId::UserPassword)
+        .and_then(|f| f.as_binary())
+        .map(|b| b.to_vec());
+    let name = transaction
+        .get_field(FieldId::UserName)
+        .and_then(|f| f.as_string())
+        .map(|s| s.to_string());
+    let access = transaction.get_field(FieldId::UserAccess).and_then(|f| f.access_privileges());
     
     // Validate required fields
     let login = login.context("Missing login field")?;
     let password = password.context("Missing password field")?;
     let name = name.context("Missing name field")?;
-    let access = access.unwrap_or(0);
+    let access_privileges = access.unwrap_or_else(AccessPrivileges::empty);
     
     // Unscramble login and password
-    let login_bytes = xor_password(&login);
+    let login_str = login.as_scrambled_text()?;
     let password_bytes = xor_password(&password);
     
-    let login_str = String::from_utf8(login_bytes)
-        .context("Invalid UTF-8 in login")?;
-    
     tracing::info!(
         "User {} creating account '{}' with name '{}' and access 0x{:016X}",
         user_id,
         login_str,
         name,
-        access
+        access_privileges.bits()
     );
     
     // Validate input
@@ -125,30 +81,60 @@ pub async fn handle_new_user(
         return Ok(create_error_reply(&transaction, ErrorCode::InvalidParameter));
     }
     
+    let actor_access = state.user_access(user_id);
+    
+    // Don't grant more than the creator holds
+    if !can_grant(actor_access, access_privileges) {
+        tracing::warn!(
+            "User {} tried to create account '{}' with access 0x{:016X} beyond their own",
+            user_id,
+            login_str,
+            (access_privileges - actor_access).bits()
+        );
+        return Ok(create_error_reply(&transaction, ErrorCode::PermissionDenied));
+    }
+    
+    // Reserved logins need ANY_NAME, like reserved nicknames
+    if state.config().features.is_reserved_name(&login_str)
+        && !actor_access.contains(AccessPrivileges::ANY_NAME)
+    {
+        tracing::warn!("User {} tried to create reserved account '{}'", user_id, login_str);
+        return Ok(create_error_reply(&transaction, ErrorCode::PermissionDenied));
+    }
+    
     // Check if account already exists
-    if crate::db::accounts::account_exists(state.database.pool(), &login_str).await? {
+    if state.accounts.account_exists(&login_str).await? {
         tracing::warn!("User {} tried to create duplicate account '{}'", user_id, login_str);
         return Ok(create_error_reply(&transaction, ErrorCode::AlreadyExists));
     }
     
-    // Store the password (it's already scrambled from the client)
-    // We store it as-is for compatibility with Hotline password verification
-    let password_storage = &password_bytes;
+    // Enforce security.max_accounts
+    if !state.accepts_account().await? {
+        tracing::warn!("User {} tried to create account '{}' with the account limit reached", user_id, login_str);
+        let mut reply = create_error_reply(&transaction, ErrorCode::PermissionDenied);
+        reply.add_field(Field::string(
+            FieldId::Data,
+            format!("This server is limited to {} accounts", state.config().security.max_accounts),
+        ));
+        return Ok(reply);
+    }
     
-    // Convert access to AccessPrivileges
-    let access_privileges = AccessPrivileges::from_bits_truncate(access as u64);
+    // Store the password under security.password_scheme
+    let password_hash = hash_stored_password(&password_bytes, state.config().security.password_scheme).await?;
     
     // Create account in database
-    let account_id = crate::db::accounts::create_account(
-        state.database.pool(),
+    let account_id = state.accounts.create_account(
         &login_str,
-        password_storage,
+        &password_hash,
         &name,
         access_privileges,
     )
     .await
     .context("Failed to create account")?;
     
+    let actor = audit::user_actor(&state, user_id);
+    audit::record(state.audit.account_changed(&actor, &login_str, AccountChange::Created { access: access_privileges })).await;
+    
     tracing::info!(
         "User {} successfully created account '{}' (id={})",
         user_id,
@@ -176,27 +162,17 @@ pub async fn handle_get_user(
 ) -> Result<Transaction> {
     tracing::debug!("User {} requesting account details", user_id);
     
-    // Check if user has OPEN_USER permission
-    if !check_privilege(&state, user_id, AccessPrivileges::OPEN_USER).await? {
-        tracing::warn!("User {} tried to get account without permission", user_id);
-        return Ok(create_error_reply(&transaction, ErrorCode::PermissionDenied));
-    }
-    
     // Extract login field
-    let login = transaction.fields.iter()
-        .find(|f| f.id == FieldId::UserLogin)
-        .and_then(|f| f.as_binary())
+    let login = transaction.get_field(FieldId::UserLogin)
         .context("Missing login field")?;
     
     // Unscramble login
-    let login_bytes = xor_password(login);
-    let login_str = String::from_utf8(login_bytes)
-        .context("Invalid UTF-8 in login")?;
+    let login_str = login.as_scrambled_text()?;
     
     tracing::debug!("User {} getting account '{}'", user_id, login_str);
     
     // Get account from database
-    let account = crate::db::accounts::get_account_by_login(state.database.pool(), &login_str)
+    let account = state.accounts.get_account_by_login(&login_str)
         .await
         .context("Database error")?;
     
@@ -213,14 +189,11 @@ pub async fn handle_get_user(
     // Scramble login for response (keep it scrambled as client expects)
     let scrambled_login = xor_password(account.login.as_bytes());
     
-    // Encode access privileges as 8 bytes (big-endian for wire format)
-    let access_bytes = (account.access as i64).to_be_bytes().to_vec();
-    
     // Return account details
     Ok(create_success_reply(&transaction, vec![
         Field::string(FieldId::UserName, &account.name),
         Field::binary(FieldId::UserLogin, scrambled_login),
-        Field::binary(FieldId::UserAccess, access_bytes),
+        Field::from_access(account.access_privileges()),
     ]))
 }
 
@@ -234,6 +207,9 @@ pub async fn handle_get_user(
 ///
 /// Server replies with:
 /// - Empty success or error code
+///
+/// Only sysops may change the password or access of an account with
+/// privileges they don't hold themselves.
 pub async fn handle_set_user(
     transaction: Transaction,
     user_id: u16,
@@ -241,54 +217,26 @@ pub async fn handle_set_user(
 ) -> Result<Transaction> {
     tracing::debug!("User {} attempting to modify account", user_id);
     
-    // Check if user has MODIFY_USERS permission
-    if !check_privilege(&state, user_id, AccessPrivileges::MODIFY_USERS).await? {
-        tracing::warn!("User {} tried to modify account without permission", user_id);
-        return Ok(create_error_reply(&transaction, ErrorCode::PermissionDenied));
-    }
-    
     // Extract fields
-    let mut login: Option<Vec<u8>> = None;
-    let mut password: Option<Vec<u8>> = None;
-    let mut name: Option<String> = None;
-    let mut access: Option<i64> = None;
-    
-    for field in &transaction.fields {
-        match field.id {
-            FieldId::UserLogin => {
-                login = field.as_binary().map(|b| b.to_vec());
-            }
-            FieldId::UserPassword => {
-                password = field.as_binary().map(|b| b.to_vec());
-            }
-            FieldId::UserName => {
-                name = field.as_string().map(|s| s.to_string());
-            }
-            FieldId::UserAccess => {
-                access = field.as_binary().and_then(|bytes| {
-                    if bytes.len() == 8 {
-                        let mut arr = [0u8; 8];
-                        arr.copy_from_slice(bytes);
-                        Some(i64::from_be_bytes(arr))
-                    } else {
-                        None
-                    }
-                });
-            }
-            _ => {}
-        }
-    }
+    let login = transaction.get_field(FieldId::UserLogin);
+    let password = transaction
+        .get_field(FieldId::UserPassword)
+        .and_then(|f| f.as_binary())
+        .map(|b| b.to_vec());
+    let name = transaction
+        .get_field(FieldId::UserName)
+        .and_then(|f| f.as_string())
+        .map(|s| s.to_string());
+    let access = transaction.get_field(FieldId::UserAccess).and_then(|f| f.access_privileges());
     
     // Login is required to identify the account
     let login = login.context("Missing login field")?;
-    let login_bytes = xor_password(&login);
-    let login_str = String::from_utf8(login_bytes)
-        .context("Invalid UTF-8 in login")?;
+    let login_str = login.as_scrambled_text()?;
     
     tracing::debug!("User {} modifying account '{}'", user_id, login_str);
     
     // Get account from database
-    let account = crate::db::accounts::get_account_by_login(state.database.pool(), &login_str)
+    let account = state.accounts.get_account_by_login(&login_str)
         .await
         .context("Database error")?;
     
@@ -300,33 +248,69 @@ pub async fn handle_set_user(
         }
     };
     
-    // Update password if provided
-    if let Some(password_data) = password {
-        let password_bytes = xor_password(&password_data);
-        
-        crate::db::accounts::update_password(state.database.pool(), account.id, &password_bytes)
-            .await
-            .context("Failed to update password")?;
-        
-        tracing::info!("User {} updated password for account '{}'", user_id, login_str);
+    // Check everything before changing anything. Only sysops may reset the
+    // password or change the access of an account holding privileges they
+    // don't have, or they could log in as it. Clients send the whole access
+    // field back with every edit, so beyond that only the bits being added
+    // count.
+    let actor_access = state.user_access(user_id);
+    if (password.is_some() || access.is_some()) && !can_grant(actor_access, account.access_privileges()) {
+        tracing::warn!("User {} tried to modify account '{}', which has access beyond their own", user_id, login_str);
+        return Ok(create_error_reply(&transaction, ErrorCode::PermissionDenied));
+    }
+    if let Some(access_privileges) = access {
+        let added = access_privileges - account.access_privileges();
+        if !can_grant(actor_access, added) {
+            tracing::warn!(
+                "User {} tried to give account '{}' access 0x{:016X} beyond their own",
+                user_id,
+                login_str,
+                (added - actor_access).bits()
+            );
+            return Ok(create_error_reply(&transaction, ErrorCode::PermissionDenied));
+        }
     }
     
-    // Update access if provided
-    if let Some(access_bits) = access {
-        let access_privileges = AccessPrivileges::from_bits_truncate(access_bits as u64);
-        
-        crate::db::accounts::update_access(state.database.pool(), account.id, access_privileges)
+    // Update access if provided, first so a refused change leaves the
+    // password alone
+    if let Some(access_privileges) = access {
+        // Checked and written together, so concurrent edits can't demote
+        // every admin between them
+        let updated = state.accounts.update_access_keeping_admin(account.id, access_privileges)
             .await
             .context("Failed to update access")?;
+        if !updated {
+            tracing::warn!("User {} tried to remove admin access from '{}', the last admin account", user_id, login_str);
+            return Ok(create_error_reply(&transaction, ErrorCode::PermissionDenied));
+        }
+        
+        let actor = audit::user_actor(&state, user_id);
+        audit::record(state.audit.account_changed(&actor, &login_str, AccountChange::AccessChanged { access: access_privileges })).await;
+        state.notify_access_changed(account.id, access_privileges);
         
         tracing::info!(
             "User {} updated access for account '{}' to 0x{:016X}",
             user_id,
             login_str,
-            access_bits
+            access_privileges.bits()
         );
     }
     
+    // Update password if provided
+    if let Some(password_data) = password {
+        let password_bytes = xor_password(&password_data);
+        let password_hash = hash_stored_password(&password_bytes, state.config().security.password_scheme).await?;
+        
+        state.accounts.update_password(account.id, &password_hash)
+            .await
+            .context("Failed to update password")?;
+        
+        let actor = audit::user_actor(&state, user_id);
+        audit::record(state.audit.account_changed(&actor, &login_str, AccountChange::PasswordChanged)).await;
+        
+        tracing::info!("User {} updated password for account '{}'", user_id, login_str);
+    }
+    
     // Note: Name updates would require a new function in db/accounts.rs
     // For now, we'll log but not implement it
     if let Some(new_name) = name {
@@ -351,6 +335,9 @@ pub async fn handle_set_user(
 ///
 /// Server replies with:
 /// - Empty success or error code
+///
+/// Only sysops may delete an account with privileges they don't hold
+/// themselves.
 pub async fn handle_delete_user(
     transaction: Transaction,
     user_id: u16,
@@ -358,27 +345,17 @@ pub async fn handle_delete_user(
 ) -> Result<Transaction> {
     tracing::debug!("User {} attempting to delete account", user_id);
     
-    // Check if user has DELETE_USERS permission
-    if !check_privilege(&state, user_id, AccessPrivileges::DELETE_USERS).await? {
-        tracing::warn!("User {} tried to delete account without permission", user_id);
-        return Ok(create_error_reply(&transaction, ErrorCode::PermissionDenied));
-    }
-    
     // Extract login field
-    let login = transaction.fields.iter()
-        .find(|f| f.id == FieldId::UserLogin)
-        .and_then(|f| f.as_binary())
+    let login = transaction.get_field(FieldId::UserLogin)
         .context("Missing login field")?;
     
     // Unscramble login
-    let login_bytes = xor_password(login);
-    let login_str = String::from_utf8(login_bytes)
-        .context("Invalid UTF-8 in login")?;
+    let login_str = login.as_scrambled_text()?;
     
     tracing::debug!("User {} deleting account '{}'", user_id, login_str);
     
     // Get account from database
-    let account = crate::db::accounts::get_account_by_login(state.database.pool(), &login_str)
+    let account = state.accounts.get_account_by_login(&login_str)
         .await
         .context("Database error")?;
     
@@ -390,13 +367,309 @@ pub async fn handle_delete_user(
         }
     };
     
-    // Delete the account
-    crate::db::accounts::delete_account(state.database.pool(), account.id)
+    if !can_grant(state.user_access(user_id), account.access_privileges()) {
+        tracing::warn!("User {} tried to delete account '{}', which has access beyond their own", user_id, login_str);
+        return Ok(create_error_reply(&transaction, ErrorCode::PermissionDenied));
+    }
+    
+    // Delete the account, refusing to lock everyone out of account management
+    let deleted = state.accounts.delete_account_keeping_admin(account.id)
         .await
         .context("Failed to delete account")?;
+    if !deleted {
+        tracing::warn!("User {} tried to delete '{}', the last admin account", user_id, login_str);
+        return Ok(create_error_reply(&transaction, ErrorCode::PermissionDenied));
+    }
+    
+    let actor = audit::user_actor(&state, user_id);
+    audit::record(state.audit.account_changed(&actor, &login_str, AccountChange::Deleted)).await;
+    state.notify_account_deleted(account.id);
     
     tracing::info!("User {} successfully deleted account '{}' (id={})", user_id, login_str, account.id);
     
     // Return success
     Ok(create_success_reply(&transaction, vec![]))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::Session;
+    use crate::db::accounts::count_accounts;
+    use crate::db::memory::MemoryAccountStore;
+    use crate::test_util::test_config;
+    use rhxcore::password::{needs_rehash, verify_password, PasswordScheme};
+    use rhxcore::protocol::TransactionType;
+    use rhxcore::ProtocolError;
+    
+    /// Server state whose accounts live in memory, with user 1 logged in as an admin
+    async fn memory_state() -> Arc<ServerState> {
+        let state = ServerState::new(test_config())
+            .await
+            .unwrap()
+            .with_account_store(Arc::new(MemoryAccountStore::new()));
+        
+        let admin_id = state.accounts
+            .create_account("admin", b"pw", "Admin", AccessPrivileges::admin())
+            .await
+            .unwrap();
+        let mut session = Session::new(1, "127.0.0.1:5500".parse().unwrap());
+        session.authenticate_user(admin_id, AccessPrivileges::admin(), "Admin".to_string(), 0);
+        state.register_session(session);
+        
+        Arc::new(state)
+    }
+    
+    fn login_request(transaction_type: TransactionType, login: &str) -> Transaction {
+        let mut transaction = Transaction::new(transaction_type);
+        transaction.id = 1;
+        transaction.add_field(Field::binary(FieldId::UserLogin, xor_password(login.as_bytes())));
+        transaction
+    }
+    
+    #[tokio::test]
+    async fn test_get_user_with_invalid_utf8_login() {
+        let state = memory_state().await;
+        
+        let mut request = Transaction::new(TransactionType::GetUser);
+        request.id = 1;
+        request.add_field(Field::binary(FieldId::UserLogin, xor_password(&[b'b', 0xFF])));
+        
+        let error = handle_get_user(request, 1, state).await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ProtocolError>(),
+            Some(ProtocolError::InvalidUtf8 { field: FieldId::UserLogin })
+        ));
+    }
+    
+    #[tokio::test]
+    async fn test_new_user_reads_wire_format_access() {
+        let state = memory_state().await;
+        
+        // Bits are reversed within each byte: 0x60 is bits 1 and 2, 0x80 in
+        // the second byte is bit 8
+        let wire = [0x60, 0x80, 0, 0, 0, 0, 0, 0];
+        let mut new_user = login_request(TransactionType::NewUser, "dave");
+        new_user.add_field(Field::binary(FieldId::UserPassword, xor_password(b"secret")));
+        new_user.add_field(Field::string(FieldId::UserName, "Dave"));
+        new_user.add_field(Field::binary(FieldId::UserAccess, wire.to_vec()));
+        let reply = handle_new_user(new_user, 1, state.clone()).await.unwrap();
+        assert_eq!(reply.error_code, 0);
+        
+        let account = state.accounts.get_account_by_login("dave").await.unwrap().unwrap();
+        assert_eq!(
+            account.access_privileges(),
+            AccessPrivileges::UPLOAD_FILES | AccessPrivileges::DOWNLOAD_FILES | AccessPrivileges::MOVE_FOLDERS
+        );
+        
+        // GetUser sends it back unchanged
+        let reply = handle_get_user(login_request(TransactionType::GetUser, "dave"), 1, state.clone())
+            .await
+            .unwrap();
+        let access = reply.get_field(FieldId::UserAccess).and_then(|f| f.as_binary());
+        assert_eq!(access, Some(&wire[..]));
+    }
+    
+    #[tokio::test]
+    async fn test_passwords_stored_under_configured_scheme() {
+        let state = memory_state().await;
+        let mut config = (*state.config()).clone();
+        config.security.password_scheme = PasswordScheme::Argon2;
+        state.reload_config(config);
+        
+        let mut new_user = login_request(TransactionType::NewUser, "erin");
+        new_user.add_field(Field::binary(FieldId::UserPassword, xor_password(b"secret")));
+        new_user.add_field(Field::string(FieldId::UserName, "Erin"));
+        let reply = handle_new_user(new_user, 1, state.clone()).await.unwrap();
+        assert_eq!(reply.error_code, 0);
+        
+        let account = state.accounts.get_account_by_login("erin").await.unwrap().unwrap();
+        assert!(!needs_rehash(&account.password_hash));
+        assert!(verify_password(&account.password_hash, b"secret"));
+        
+        let mut set_user = login_request(TransactionType::SetUser, "erin");
+        set_user.add_field(Field::binary(FieldId::UserPassword, xor_password(b"changed")));
+        let reply = handle_set_user(set_user, 1, state.clone()).await.unwrap();
+        assert_eq!(reply.error_code, 0);
+        
+        let account = state.accounts.get_account_by_login("erin").await.unwrap().unwrap();
+        assert!(!needs_rehash(&account.password_hash));
+        assert!(verify_password(&account.password_hash, b"changed"));
+    }
+    
+    #[tokio::test]
+    async fn test_account_handlers_with_memory_store() {
+        let state = memory_state().await;
+        
+        // NewUser
+        let mut new_user = login_request(TransactionType::NewUser, "carol");
+        new_user.add_field(Field::binary(FieldId::UserPassword, xor_password(b"secret")));
+        new_user.add_field(Field::string(FieldId::UserName, "Carol"));
+        let reply = handle_new_user(new_user, 1, state.clone()).await.unwrap();
+        assert_eq!(reply.error_code, 0);
+        
+        // GetUser
+        let reply = handle_get_user(login_request(TransactionType::GetUser, "CAROL"), 1, state.clone())
+            .await
+            .unwrap();
+        assert_eq!(reply.error_code, 0);
+        let name = reply.get_field(FieldId::UserName).and_then(|f| f.as_string());
+        assert_eq!(name, Some("Carol"));
+        
+        // DeleteUser
+        let reply = handle_delete_user(login_request(TransactionType::DeleteUser, "carol"), 1, state.clone())
+            .await
+            .unwrap();
+        assert_eq!(reply.error_code, 0);
+        assert!(!state.accounts.account_exists("carol").await.unwrap());
+        
+        let reply = handle_get_user(login_request(TransactionType::GetUser, "carol"), 1, state.clone())
+            .await
+            .unwrap();
+        assert_eq!(reply.error_code, ErrorCode::NotFound.to_u32());
+        
+        // Nothing reached the SQLite database
+        assert_eq!(count_accounts(state.database.pool()).await.unwrap(), 0);
+    }
+    
+    #[tokio::test]
+    async fn test_new_user_refused_at_account_limit() {
+        let state = memory_state().await;
+        let mut config = (*state.config()).clone();
+        config.security.max_accounts = 2;
+        state.reload_config(config);
+        
+        let new_user = |login: &str| {
+            let mut transaction = login_request(TransactionType::NewUser, login);
+            transaction.add_field(Field::binary(FieldId::UserPassword, xor_password(b"secret")));
+            transaction.add_field(Field::string(FieldId::UserName, login));
+            transaction
+        };
+        
+        // The admin account is the first of two
+        let reply = handle_new_user(new_user("carol"), 1, state.clone()).await.unwrap();
+        assert_eq!(reply.error_code, 0);
+        
+        let reply = handle_new_user(new_user("dave"), 1, state.clone()).await.unwrap();
+        assert_eq!(reply.error_code, ErrorCode::PermissionDenied.to_u32());
+        let text = reply.get_field(FieldId::Data).and_then(|f| f.as_string());
+        assert_eq!(text, Some("This server is limited to 2 accounts"));
+        assert!(!state.accounts.account_exists("dave").await.unwrap());
+    }
+    
+    #[tokio::test]
+    async fn test_last_admin_cannot_be_deleted() {
+        let state = memory_state().await;
+        
+        let reply = handle_delete_user(login_request(TransactionType::DeleteUser, "admin"), 1, state.clone())
+            .await
+            .unwrap();
+        assert_eq!(reply.error_code, ErrorCode::PermissionDenied.to_u32());
+        assert!(state.accounts.account_exists("admin").await.unwrap());
+        
+        // Once there's another admin, either can go
+        state.accounts
+            .create_account("backup", b"pw", "Backup", AccessPrivileges::admin())
+            .await
+            .unwrap();
+        let reply = handle_delete_user(login_request(TransactionType::DeleteUser, "admin"), 1, state.clone())
+            .await
+            .unwrap();
+        assert_eq!(reply.error_code, 0);
+        assert!(!state.accounts.account_exists("admin").await.unwrap());
+    }
+    
+    #[tokio::test]
+    async fn test_last_admin_cannot_be_demoted() {
+        let state = memory_state().await;
+        
+        // A refused demotion leaves the password alone too
+        let mut set_user = login_request(TransactionType::SetUser, "admin");
+        set_user.add_field(Field::binary(FieldId::UserPassword, xor_password(b"changed")));
+        set_user.add_field(Field::from_access(AccessPrivileges::user()));
+        let reply = handle_set_user(set_user, 1, state.clone()).await.unwrap();
+        assert_eq!(reply.error_code, ErrorCode::PermissionDenied.to_u32());
+        let account = state.accounts.get_account_by_login("admin").await.unwrap().unwrap();
+        assert!(account.is_admin());
+        assert_eq!(account.password_hash, b"pw");
+        
+        state.accounts
+            .create_account("backup", b"pw", "Backup", AccessPrivileges::admin())
+            .await
+            .unwrap();
+        let mut set_user = login_request(TransactionType::SetUser, "admin");
+        set_user.add_field(Field::from_access(AccessPrivileges::user()));
+        let reply = handle_set_user(set_user, 1, state.clone()).await.unwrap();
+        assert_eq!(reply.error_code, 0);
+        let account = state.accounts.get_account_by_login("admin").await.unwrap().unwrap();
+        assert!(!account.is_admin());
+    }
+    
+    #[tokio::test]
+    async fn test_admin_cannot_grant_sysop() {
+        let state = memory_state().await;
+        
+        let mut new_user = login_request(TransactionType::NewUser, "root");
+        new_user.add_field(Field::binary(FieldId::UserPassword, xor_password(b"secret")));
+        new_user.add_field(Field::string(FieldId::UserName, "Root"));
+        new_user.add_field(Field::from_access(AccessPrivileges::sysop()));
+        let reply = handle_new_user(new_user, 1, state.clone()).await.unwrap();
+        assert_eq!(reply.error_code, ErrorCode::PermissionDenied.to_u32());
+        assert!(!state.accounts.account_exists("root").await.unwrap());
+        
+        // Nor raise an existing account to sysop
+        state.accounts
+            .create_account("carol", b"pw", "Carol", AccessPrivileges::user())
+            .await
+            .unwrap();
+        let mut set_user = login_request(TransactionType::SetUser, "carol");
+        set_user.add_field(Field::from_access(AccessPrivileges::sysop()));
+        let reply = handle_set_user(set_user, 1, state.clone()).await.unwrap();
+        assert_eq!(reply.error_code, ErrorCode::PermissionDenied.to_u32());
+        let account = state.accounts.get_account_by_login("carol").await.unwrap().unwrap();
+        assert_eq!(account.access_privileges(), AccessPrivileges::user());
+        
+        // Access within the admin's own is fine
+        let mut set_user = login_request(TransactionType::SetUser, "carol");
+        set_user.add_field(Field::from_access(AccessPrivileges::admin()));
+        let reply = handle_set_user(set_user, 1, state.clone()).await.unwrap();
+        assert_eq!(reply.error_code, 0);
+    }
+    
+    #[tokio::test]
+    async fn test_admin_cannot_edit_account_above_them() {
+        let state = memory_state().await;
+        state.accounts
+            .create_account("root", b"pw", "Root", AccessPrivileges::sysop())
+            .await
+            .unwrap();
+        
+        // A password reset that sends the unchanged sysop access back
+        let mut set_user = login_request(TransactionType::SetUser, "root");
+        set_user.add_field(Field::binary(FieldId::UserPassword, xor_password(b"reset")));
+        set_user.add_field(Field::from_access(AccessPrivileges::sysop()));
+        let reply = handle_set_user(set_user, 1, state.clone()).await.unwrap();
+        assert_eq!(reply.error_code, ErrorCode::PermissionDenied.to_u32());
+        
+        // Nor a password reset alone, or a demotion
+        let mut set_user = login_request(TransactionType::SetUser, "root");
+        set_user.add_field(Field::binary(FieldId::UserPassword, xor_password(b"reset")));
+        let reply = handle_set_user(set_user, 1, state.clone()).await.unwrap();
+        assert_eq!(reply.error_code, ErrorCode::PermissionDenied.to_u32());
+        
+        let mut set_user = login_request(TransactionType::SetUser, "root");
+        set_user.add_field(Field::from_access(AccessPrivileges::user()));
+        let reply = handle_set_user(set_user, 1, state.clone()).await.unwrap();
+        assert_eq!(reply.error_code, ErrorCode::PermissionDenied.to_u32());
+        
+        let account = state.accounts.get_account_by_login("root").await.unwrap().unwrap();
+        assert_eq!(account.password_hash, b"pw");
+        assert_eq!(account.access_privileges(), AccessPrivileges::sysop());
+        
+        let reply = handle_delete_user(login_request(TransactionType::DeleteUser, "root"), 1, state.clone())
+            .await
+            .unwrap();
+        assert_eq!(reply.error_code, ErrorCode::PermissionDenied.to_u32());
+        assert!(state.accounts.account_exists("root").await.unwrap());
+    }
+}
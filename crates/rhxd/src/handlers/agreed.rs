@@ -14,6 +14,16 @@ use std::sync::Arc;
 /// - Field 113: Options (user flags)
 /// - Field 215: Auto-response (optional)
 ///
+/// Agreed is the authoritative source of user options: Login (107) never
+/// carries field 113, so options only change here. Refusing private messages
+/// or chat shows up in the user list flags; the automatic response has no
+/// flag and is kept on the session for private message replies.
+///
+/// Users who can disconnect others get the admin flag and icon, as do users
+/// with `FAKE_RED`, which only changes how they appear. Accounts with
+/// `force_icon` or `force_admin_flag` set override the icon and admin flag
+/// the client asks for.
+///
 /// Server:
 /// 1. Updates the session with user-provided nickname and icon
 /// 2. Sends acknowledgment reply
@@ -33,30 +43,18 @@ pub async fn handle_agreed(
     }
     
     // Extract fields
-    let mut nickname: Option<String> = None;
-    let mut icon_id: Option<i32> = None;
-    let mut user_options = UserOptions::default();
-    
-    for field in &transaction.fields {
-        match field.id {
-            FieldId::UserName => {
-                nickname = field.as_string().map(|s| s.to_string());
-            }
-            FieldId::UserIconId => {
-                icon_id = field.as_integer();
-            }
-            FieldId::Options => {
-                if let Some(value) = field.as_integer() {
-                    user_options = UserOptions::from_i16(value as i16);
-                }
-            }
-            _ => {}
-        }
-    }
+    let string = |id| transaction.get_field(id).and_then(|f| f.as_string()).map(|s| s.to_string());
+    let nickname = string(FieldId::UserName);
+    let auto_response = string(FieldId::AutomaticResponse);
+    let icon_id = transaction.get_field(FieldId::UserIconId).and_then(|f| f.as_integer());
+    let user_options = transaction
+        .get_field(FieldId::Options)
+        .and_then(|f| f.as_integer())
+        .map_or_else(UserOptions::default, |value| UserOptions::from_i16(value as i16));
     
     // Use default values if not provided
     // Handle empty nickname strings
-    let nickname = match nickname {
+    let mut nickname = match nickname {
         Some(n) if !n.trim().is_empty() => n,
         _ => format!("Guest {}", user_id),
     };
@@ -65,28 +63,32 @@ pub async fn handle_agreed(
     // Start with user options flags
     let mut flags = user_options.to_user_flags();
     
-    // Determine access privileges
-    let access_privileges = {
-        if let Some(session) = state.get_session(user_id) {
-            if let Some(account_id) = session.account_id {
-                // For authenticated users, fetch from database
-                match crate::db::accounts::get_account_by_id(state.database.pool(), account_id).await {
-                    Ok(Some(account)) => account.access_privileges(),
-                    _ => rhxcore::types::AccessPrivileges::guest(),
-                }
-            } else {
-                // Guest user
-                rhxcore::types::AccessPrivileges::guest()
-            }
-        } else {
-            // Session not found, use guest as fallback
-            rhxcore::types::AccessPrivileges::guest()
-        }
+    // Look up the account behind the session, if any
+    let account = match state.get_session(user_id).and_then(|session| session.account_id) {
+        Some(account_id) => state.accounts.get_account_by_id(account_id).await.ok().flatten(),
+        None => None,
     };
+    let access_privileges = account
+        .as_ref()
+        .map(|account| account.access_privileges())
+        .unwrap_or_else(|| state.config().security.guest_access());
+    
+    // Keep reserved nicknames for users allowed to use any name
+    if state.config().features.is_reserved_name(&nickname)
+        && !access_privileges.contains(rhxcore::types::AccessPrivileges::ANY_NAME)
+    {
+        tracing::warn!(
+            "User {} requested reserved nickname '{}', using default",
+            user_id,
+            nickname
+        );
+        nickname = format!("Guest {}", user_id);
+    }
     
-    // Set admin flag and icon if user has administrative privileges
+    // Set admin flag and icon if user has administrative privileges, or
+    // FAKE_RED to look the part without them
     let is_admin = access_privileges.contains(rhxcore::types::AccessPrivileges::DISCONNECT_USERS);
-    if is_admin {
+    if is_admin || access_privileges.contains(rhxcore::types::AccessPrivileges::FAKE_RED) {
         flags |= UserFlags::ADMIN.bits();
         
         // If client didn't specify an icon, use the default admin icon (410)
@@ -95,6 +97,16 @@ pub async fn handle_agreed(
         }
     }
     
+    // Bot and service accounts can pin what others see, whatever the client sends
+    if let Some(account) = &account {
+        if let Some(force_icon) = account.force_icon {
+            icon_id = force_icon as u16;
+        }
+        if account.force_admin_flag {
+            flags |= UserFlags::ADMIN.bits();
+        }
+    }
+    
     tracing::info!(
         "User {} agreed with nickname='{}', icon={}, flags=0x{:04X}, options=0x{:04X}, is_admin={}, access=0x{:016X}",
         user_id,
@@ -107,15 +119,16 @@ pub async fn handle_agreed(
     );
     
     // Update session with user-provided info and computed flags
-    if let Some(mut session) = state.get_session_mut(user_id) {
+    state.update_session(user_id, |session| {
         session.nickname = nickname.clone();
         session.icon_id = icon_id;
         session.flags = flags;
-        session.options = user_options;
-    }
+        session.apply_options(user_options, auto_response);
+        session.agree();
+    });
     
-    // Broadcast NotifyChangeUser to all users
-    state.broadcast(BroadcastMessage::UserJoined {
+    // Broadcast NotifyChangeUser to everyone else
+    state.broadcast_except(user_id, BroadcastMessage::UserJoined {
         user_id,
         nickname: nickname.clone(),
     });
@@ -132,3 +145,183 @@ pub async fn handle_agreed(
         fields: vec![],
     }))
 }
+
+/// Let a user in without the agreement
+///
+/// For clients that never get ShowAgreement (see
+/// `features.skip_agreement_for_old_clients`), so will never send Agreed.
+/// They keep the nickname and icon from login, and can change them with
+/// SetClientUserInfo.
+pub fn skip_agreement(state: &ServerState, user_id: u16) {
+    let Some(nickname) = state.update_session(user_id, |session| {
+        session.agree();
+        session.nickname.clone()
+    }) else {
+        return;
+    };
+    
+    tracing::info!("User {} let in without the agreement", user_id);
+    
+    state.broadcast_except(user_id, BroadcastMessage::UserJoined { user_id, nickname });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::authorization::authorize;
+    use crate::connection::Session;
+    use crate::state::Broadcast;
+    use crate::test_util::test_state;
+    use rhxcore::protocol::Field;
+    use rhxcore::types::AccessPrivileges;
+    
+    async fn reserved_state() -> Arc<ServerState> {
+        test_state(|config| {
+            config.features.reserved_nicknames = vec!["Admin".to_string(), "Server".to_string()];
+        })
+        .await
+    }
+    
+    fn agreed(nickname: &str) -> Transaction {
+        let mut transaction = Transaction::new(TransactionType::Agreed);
+        transaction.id = 2;
+        transaction.add_field(Field::string(FieldId::UserName, nickname));
+        transaction
+    }
+    
+    #[tokio::test]
+    async fn test_guest_denied_reserved_nickname() {
+        let state = reserved_state().await;
+        
+        let mut session = Session::new(3, "127.0.0.1:5500".parse().unwrap());
+        session.authenticate_guest("Guest 3".to_string(), 0);
+        state.register_session(session);
+        
+        let reply = handle_agreed(agreed("admin"), 3, state.clone()).await.unwrap().unwrap();
+        assert_eq!(reply.error_code, 0);
+        assert_eq!(state.get_session(3).unwrap().nickname, "Guest 3");
+        
+        // Unreserved names are left alone
+        handle_agreed(agreed("Alice"), 3, state.clone()).await.unwrap();
+        assert_eq!(state.get_session(3).unwrap().nickname, "Alice");
+    }
+    
+    #[tokio::test]
+    async fn test_all_options_applied_to_session() {
+        let state = reserved_state().await;
+        
+        let mut session = Session::new(6, "127.0.0.1:5500".parse().unwrap());
+        session.authenticate_guest("Guest 6".to_string(), 0);
+        state.register_session(session);
+        
+        let all = UserOptions::REFUSE_PRIVATE_MESSAGE
+            | UserOptions::REFUSE_PRIVATE_CHAT
+            | UserOptions::AUTOMATIC_RESPONSE;
+        let mut transaction = agreed("Alice");
+        transaction.add_field(Field::integer(FieldId::Options, all.to_i16() as i32));
+        transaction.add_field(Field::string(FieldId::AutomaticResponse, "Out to lunch"));
+        handle_agreed(transaction, 6, state.clone()).await.unwrap();
+        
+        {
+            let session = state.get_session(6).unwrap();
+            assert_eq!(session.options, all);
+            assert_eq!(
+                session.flags,
+                (UserFlags::REFUSED_MESSAGES | UserFlags::REFUSED_CHAT).bits()
+            );
+            assert_eq!(session.automatic_response(), Some("Out to lunch"));
+        }
+        
+        // Without the option bit the response text is ignored
+        let mut transaction = agreed("Alice");
+        transaction.add_field(Field::integer(FieldId::Options, 0));
+        transaction.add_field(Field::string(FieldId::AutomaticResponse, "Out to lunch"));
+        handle_agreed(transaction, 6, state.clone()).await.unwrap();
+        
+        let session = state.get_session(6).unwrap();
+        assert_eq!(session.options, UserOptions::empty());
+        assert_eq!(session.flags, 0);
+        assert_eq!(session.automatic_response(), None);
+    }
+    
+    #[tokio::test]
+    async fn test_any_name_allows_reserved_nickname() {
+        let state = reserved_state().await;
+        
+        let account_id = state.accounts.create_account(
+            "staff",
+            b"pw",
+            "Staff",
+            AccessPrivileges::user() | AccessPrivileges::ANY_NAME,
+        )
+        .await
+        .unwrap();
+        
+        let mut session = Session::new(5, "127.0.0.1:5500".parse().unwrap());
+        session.authenticate_user(account_id, AccessPrivileges::user() | AccessPrivileges::ANY_NAME, "Staff".to_string(), 0);
+        state.register_session(session);
+        
+        handle_agreed(agreed("Admin"), 5, state.clone()).await.unwrap();
+        assert_eq!(state.get_session(5).unwrap().nickname, "Admin");
+    }
+    
+    #[tokio::test]
+    async fn test_fake_red_looks_admin_without_authority() {
+        let state = reserved_state().await;
+        
+        let account_id = state.accounts
+            .create_account("poser", b"pw", "Poser", AccessPrivileges::user() | AccessPrivileges::FAKE_RED)
+            .await
+            .unwrap();
+        let mut session = Session::new(8, "127.0.0.1:5500".parse().unwrap());
+        session.authenticate_user(account_id, AccessPrivileges::user() | AccessPrivileges::FAKE_RED, "Poser".to_string(), 0);
+        state.register_session(session);
+        
+        let mut tap = state.subscribe_raw();
+        handle_agreed(agreed("Poser"), 8, state.clone()).await.unwrap();
+        
+        let sent = tap.drain();
+        assert!(matches!(sent[..], [Broadcast { message: BroadcastMessage::UserJoined { user_id: 8, .. }, .. }]));
+        {
+            let session = state.get_session(8).unwrap();
+            assert!(session.flags & UserFlags::ADMIN.bits() != 0);
+            assert_eq!(session.icon_id, 410);
+        }
+        
+        // Appearance only: disconnecting someone still takes DISCONNECT_USERS
+        let mut disconnect = Transaction::new(TransactionType::DisconnectUser);
+        disconnect.id = 3;
+        disconnect.add_field(Field::integer(FieldId::UserId, 1));
+        let denied = authorize(&disconnect, 8, &state).await.unwrap().unwrap();
+        assert_eq!(denied.error_code, ErrorCode::PermissionDenied.to_u32());
+    }
+    
+    #[tokio::test]
+    async fn test_forced_icon_and_admin_flag() {
+        let state = reserved_state().await;
+        
+        let account_id = state.accounts
+            .create_account("newsbot", b"pw", "News Bot", AccessPrivileges::user())
+            .await
+            .unwrap();
+        state.accounts.update_overrides(account_id, Some(500), true).await.unwrap();
+        
+        let mut session = Session::new(7, "127.0.0.1:5500".parse().unwrap());
+        session.authenticate_user(account_id, AccessPrivileges::user(), "News Bot".to_string(), 0);
+        state.register_session(session);
+        
+        let mut rx = state.broadcast_tx.subscribe();
+        let mut transaction = agreed("News Bot");
+        transaction.add_field(Field::integer(FieldId::UserIconId, 128));
+        handle_agreed(transaction, 7, state.clone()).await.unwrap();
+        
+        {
+            let session = state.get_session(7).unwrap();
+            assert_eq!(session.icon_id, 500);
+            assert!(session.flags & UserFlags::ADMIN.bits() != 0);
+        }
+        let broadcast = rx.try_recv().unwrap();
+        assert!(matches!(broadcast.message, BroadcastMessage::UserJoined { user_id: 7, .. }));
+        assert_eq!(broadcast.exclude, Some(7));
+    }
+}
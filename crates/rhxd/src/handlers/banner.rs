@@ -0,0 +1,83 @@
+//! Server banner (122)
+
+use crate::config::BannerConfig;
+use crate::connection::transaction_helpers::create_server_transaction;
+use rhxcore::protocol::{Field, FieldId, Transaction, TransactionType, MAX_FIELD_SIZE};
+use std::path::Path;
+
+/// Build the ServerBanner transaction configured in `banner`
+///
+/// Sent once a client accepts the agreement:
+/// - Field 153: Banner type ("JPEG", "GIFf", "PNGf", "BMP ", "TEXT" or "URL ")
+/// - Field 152: Image data or text (left out of a URL-only banner)
+/// - Field 154: URL the banner links to (when configured)
+///
+/// An image that can't be read or sent falls back to the text, then to the
+/// URL on its own. Returns `None` when banners are off or nothing is set.
+pub async fn server_banner(config: &BannerConfig) -> Option<Transaction> {
+    if !config.enabled {
+        return None;
+    }
+    
+    let mut content = None;
+    if let Some(path) = &config.image_path {
+        content = read_image(path).await;
+    }
+    if content.is_none() {
+        content = config.text.as_ref().map(|text| (*b"TEXT", text.as_bytes().to_vec()));
+    }
+    
+    let mut fields = Vec::new();
+    match content {
+        Some((kind, data)) => {
+            fields.push(Field::binary(FieldId::ServerBannerType, kind));
+            fields.push(Field::binary(FieldId::ServerBanner, data));
+        }
+        None if config.url.is_some() => {
+            fields.push(Field::binary(FieldId::ServerBannerType, *b"URL "));
+        }
+        None => {
+            tracing::warn!("banner.enabled is set but there is no image, text or URL to send");
+            return None;
+        }
+    }
+    if let Some(url) = &config.url {
+        fields.push(Field::string(FieldId::ServerBannerUrl, url));
+    }
+    
+    Some(create_server_transaction(TransactionType::ServerBanner, fields))
+}
+
+/// Banner type code and contents of an image, or `None` if it can't be sent
+async fn read_image(path: &Path) -> Option<([u8; 4], Vec<u8>)> {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+    let kind = match extension.to_ascii_lowercase().as_str() {
+        "jpg" | "jpeg" => *b"JPEG",
+        "gif" => *b"GIFf",
+        "png" => *b"PNGf",
+        "bmp" => *b"BMP ",
+        _ => {
+            tracing::warn!("Banner image {} is not a JPEG, GIF, PNG or BMP file", path.display());
+            return None;
+        }
+    };
+    
+    let data = match tokio::fs::read(path).await {
+        Ok(data) => data,
+        Err(e) => {
+            tracing::warn!("Failed to read banner image {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    if data.len() > MAX_FIELD_SIZE {
+        tracing::warn!(
+            "Banner image {} is {} bytes, over the {} byte field limit",
+            path.display(),
+            data.len(),
+            MAX_FIELD_SIZE
+        );
+        return None;
+    }
+    
+    Some((kind, data))
+}
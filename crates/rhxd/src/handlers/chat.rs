@@ -1,8 +1,14 @@
 //! Chat transaction handlers
 
+use crate::connection::transaction_helpers::{
+    create_error_reply, create_server_transaction, create_success_reply,
+};
+use crate::audit;
+use crate::console::{execute_command_as, Command};
 use crate::state::{BroadcastMessage, ServerState};
 use anyhow::{Context, Result};
-use rhxcore::protocol::{FieldId, Transaction};
+use rhxcore::protocol::{ErrorCode, Field, FieldId, Transaction, TransactionType, MAX_CHAT_SIZE};
+use rhxcore::types::{AccessPrivileges, User, UserOptions};
 use std::sync::Arc;
 
 /// Handle SendChat transaction (105)
@@ -10,11 +16,22 @@ use std::sync::Arc;
 /// Client sends:
 /// - Field 101: Message data (binary)
 /// - Field 109: Chat options (optional, 0=normal, 1=emote)
+/// - Field 114: Chat ID (optional, 0 or absent for the public chat)
 ///
-/// Server broadcasts ChatMessage (106) to all connected users:
+/// Server broadcasts ChatMessage (106) to all connected users, or with a chat
+/// ID only to that private room's members:
 /// - Field 101: Message data
 /// - Field 103: Sender user ID
 /// - Field 102: Sender nickname
+/// - Field 114: Chat ID (private rooms only)
+///
+/// Sending to a room the user isn't in is refused with PermissionDenied.
+/// Chat is written to the chat log when `chat.log_path` is set (private
+/// rooms only with `chat.log_private`).
+///
+/// Messages from privileged users that start with the configured command
+/// prefix are executed as server commands instead, and the output is sent
+/// back to the sender only.
 pub async fn handle_send_chat(
     transaction: Transaction,
     user_id: u16,
@@ -34,24 +51,29 @@ pub async fn handle_send_chat(
     };
     
     // Extract message data and chat options (is_emote)
-    let mut message_data: Option<Vec<u8>> = None;
-    let mut is_emote = false;
+    let message_data = transaction
+        .get_field(FieldId::Data)
+        .and_then(|f| f.as_binary())
+        .map(|b| b.to_vec())
+        .context("Missing message data")?;
+    let is_emote = transaction.get_field(FieldId::ChatOptions).and_then(|f| f.as_integer()) == Some(1);
+    let chat_id = transaction
+        .get_field(FieldId::ChatId)
+        .and_then(|f| f.as_integer())
+        .map(|id| id as u32)
+        .filter(|&id| id != 0);
     
-    for field in &transaction.fields {
-        match field.id {
-            FieldId::Data => {
-                message_data = field.as_binary().map(|b| b.to_vec());
-            }
-            FieldId::ChatOptions => {
-                if let Some(value) = field.as_integer() {
-                    is_emote = value == 1;
-                }
-            }
-            _ => {}
-        }
+    if let Some(chat_id) = chat_id
+        && (!state.config().features.enable_private_chat || !state.chat_rooms.is_member(chat_id, user_id))
+    {
+        tracing::warn!("User {} tried to chat in room {} without being a member", user_id, chat_id);
+        return Ok(Some(create_error_reply(&transaction, ErrorCode::PermissionDenied)));
     }
     
-    let message_data = message_data.context("Missing message data")?;
+    // Server-side chat commands are answered privately and never broadcast
+    if let Some(reply) = handle_chat_command(&message_data, user_id, &state).await? {
+        return Ok(Some(reply));
+    }
     
     // Convert to string for logging
     let message_text = String::from_utf8_lossy(&message_data);
@@ -69,10 +91,19 @@ pub async fn handle_send_chat(
         chat_type,
         message_text.chars().take(50).collect::<String>()
     );
+    audit::record(state.audit.chat(sender_info.0, &sender_info.1, message_data.len())).await;
     
-    // Broadcast chat message to all connected users
+    if let Some(chat_log) = &state.chat_log
+        && (chat_id.is_none() || state.config().chat.log_private)
+    {
+        chat_log.record(chat_id, sender_info.0, &sender_info.1, &message_data);
+    }
+    
+    // Broadcast chat message to all connected users (room members only
+    // receive private chat, see the connection handler)
     state.broadcast(BroadcastMessage::ChatMessage {
         sender_id: sender_info.0,
+        chat_id,
         message: message_data,
         is_emote,
     });
@@ -80,3 +111,555 @@ pub async fn handle_send_chat(
     // No direct reply to sender (broadcast is the response)
     Ok(None)
 }
+
+/// Handle InviteNewChat transaction (112)
+///
+/// Client sends:
+/// - Field 103: User ID to invite (optional, may be repeated)
+///
+/// Opens a new private room with the sender as its only member, and invites
+/// each listed user as [`handle_invite_to_chat`] does. Users who refuse
+/// private chat, or aren't logged in, are skipped. Server replies with:
+/// - Field 114: Chat ID
+/// - Field 102, 103, 104, 112: The sender's nickname, user ID, icon and flags
+pub async fn handle_invite_new_chat(
+    transaction: Transaction,
+    user_id: u16,
+    state: Arc<ServerState>,
+) -> Result<Option<Transaction>> {
+    if !state.config().features.enable_private_chat {
+        tracing::warn!("User {} tried to open a private chat while they are disabled", user_id);
+        return Ok(Some(create_error_reply(&transaction, ErrorCode::PermissionDenied)));
+    }
+    
+    let creator = state.get_session(user_id).context("Session not found")?.to_user();
+    let chat_id = state.chat_rooms.create(user_id);
+    tracing::info!("User {} opened private chat {}", user_id, chat_id);
+    
+    let invitees = transaction
+        .get_all(FieldId::UserId)
+        .filter_map(|f| f.as_integer())
+        .map(|id| id as u16)
+        .filter(|&id| id != user_id);
+    for target_id in invitees {
+        let accepts_chat = state.get_session(target_id)
+            .filter(|session| session.is_authenticated())
+            .is_some_and(|session| !session.options.contains(UserOptions::REFUSE_PRIVATE_CHAT));
+        if !accepts_chat || !state.chat_rooms.invite(chat_id, target_id) {
+            tracing::debug!("Not inviting user {} to room {}", target_id, chat_id);
+            continue;
+        }
+        
+        tracing::info!("User {} invited user {} to room {}", user_id, target_id, chat_id);
+        state.broadcast(BroadcastMessage::ChatInvite {
+            chat_id,
+            inviter_id: user_id,
+            user_id: target_id,
+        });
+    }
+    
+    Ok(Some(create_success_reply(
+        &transaction,
+        vec![
+            Field::integer(FieldId::ChatId, chat_id as i32),
+            Field::string(FieldId::UserName, creator.name),
+            Field::integer(FieldId::UserId, creator.id as i32),
+            Field::integer(FieldId::UserIconId, creator.icon_id as i32),
+            Field::integer(FieldId::UserFlags, creator.flags as i32),
+        ],
+    )))
+}
+
+/// Handle InviteToChat transaction (113)
+///
+/// Client sends:
+/// - Field 103: User ID to invite
+/// - Field 114: Chat ID
+///
+/// Only members of the room may invite others. The invitee is sent
+/// InviteToChat (113) with the chat ID and the inviter's user ID and
+/// nickname. Users who refuse private chat (`REFUSE_PRIVATE_CHAT`, shown to
+/// others as `REFUSED_CHAT`) are never asked: the inviter gets
+/// RejectChatInvite (114) back from the server instead.
+pub async fn handle_invite_to_chat(
+    transaction: Transaction,
+    user_id: u16,
+    state: Arc<ServerState>,
+) -> Result<Option<Transaction>> {
+    let integer = |id| transaction.get_field(id).and_then(|f| f.as_integer());
+    let (Some(chat_id), Some(target_id)) = (integer(FieldId::ChatId), integer(FieldId::UserId)) else {
+        return Ok(Some(create_error_reply(&transaction, ErrorCode::InvalidParameter)));
+    };
+    let (chat_id, target_id) = (chat_id as u32, target_id as u16);
+    
+    if !state.config().features.enable_private_chat || !state.chat_rooms.is_member(chat_id, user_id) {
+        tracing::warn!("User {} tried to invite to room {} without being a member", user_id, chat_id);
+        return Ok(Some(create_error_reply(&transaction, ErrorCode::PermissionDenied)));
+    }
+    
+    let Some((target_nickname, refuses_chat)) = state.get_session(target_id)
+        .filter(|session| session.is_authenticated())
+        .map(|session| (session.nickname.clone(), session.options.contains(UserOptions::REFUSE_PRIVATE_CHAT)))
+    else {
+        return Ok(Some(create_error_reply(&transaction, ErrorCode::NotFound)));
+    };
+    
+    if refuses_chat {
+        tracing::debug!("User {} refuses private chat; declining invite from user {}", target_id, user_id);
+        return Ok(Some(chat_invite(TransactionType::RejectChatInvite, chat_id, target_id, &target_nickname)));
+    }
+    
+    tracing::info!("User {} invited user {} to room {}", user_id, target_id, chat_id);
+    state.chat_rooms.invite(chat_id, target_id);
+    state.broadcast(BroadcastMessage::ChatInvite {
+        chat_id,
+        inviter_id: user_id,
+        user_id: target_id,
+    });
+    
+    Ok(None)
+}
+
+/// InviteToChat (113) or RejectChatInvite (114) about `user_id`, sent by the server
+pub fn chat_invite(
+    transaction_type: TransactionType,
+    chat_id: u32,
+    user_id: u16,
+    nickname: &str,
+) -> Transaction {
+    create_server_transaction(
+        transaction_type,
+        vec![
+            Field::integer(FieldId::ChatId, chat_id as i32),
+            Field::integer(FieldId::UserId, user_id as i32),
+            Field::string(FieldId::UserName, nickname),
+        ],
+    )
+}
+
+/// Handle JoinChat transaction (115)
+///
+/// Client sends:
+/// - Field 114: Chat ID
+///
+/// Only users invited to the room may join; each invitation is good for one
+/// join. The other members are sent NotifyChatChangeUser (117). Server
+/// replies with:
+/// - Field 115: Chat subject
+/// - Multiple Field 300 (UserNameWithInfo) entries, one per member
+pub async fn handle_join_chat(
+    transaction: Transaction,
+    user_id: u16,
+    state: Arc<ServerState>,
+) -> Result<Option<Transaction>> {
+    let Some(chat_id) = transaction.get_field(FieldId::ChatId).and_then(|f| f.as_integer()) else {
+        return Ok(Some(create_error_reply(&transaction, ErrorCode::InvalidParameter)));
+    };
+    let chat_id = chat_id as u32;
+    
+    if !state.chat_rooms.join(chat_id, user_id) {
+        tracing::warn!("User {} tried to join room {} without an invitation", user_id, chat_id);
+        let error = if state.chat_rooms.members(chat_id).is_empty() {
+            ErrorCode::NotFound
+        } else {
+            ErrorCode::PermissionDenied
+        };
+        return Ok(Some(create_error_reply(&transaction, error)));
+    }
+    
+    tracing::info!("User {} joined room {}", user_id, chat_id);
+    state.broadcast(BroadcastMessage::ChatUserJoined { chat_id, user_id });
+    
+    let mut fields = vec![Field::string(
+        FieldId::ChatSubject,
+        state.chat_rooms.subject(chat_id).unwrap_or_default(),
+    )];
+    fields.extend(
+        state.chat_rooms
+            .members(chat_id)
+            .into_iter()
+            .filter_map(|member| state.get_session(member))
+            .map(|session| Field::binary(FieldId::UserNameWithInfo, session.to_user().to_name_with_info())),
+    );
+    
+    Ok(Some(create_success_reply(&transaction, fields)))
+}
+
+/// Handle LeaveChat transaction (116)
+///
+/// Client sends:
+/// - Field 114: Chat ID
+///
+/// The remaining members are sent NotifyChatDeleteUser (118); the room
+/// closes once its last member leaves. There is no reply.
+pub async fn handle_leave_chat(
+    transaction: Transaction,
+    user_id: u16,
+    state: Arc<ServerState>,
+) -> Result<Option<Transaction>> {
+    let Some(chat_id) = transaction.get_field(FieldId::ChatId).and_then(|f| f.as_integer()) else {
+        return Ok(Some(create_error_reply(&transaction, ErrorCode::InvalidParameter)));
+    };
+    let chat_id = chat_id as u32;
+    
+    if state.chat_rooms.leave(chat_id, user_id) {
+        tracing::info!("User {} left room {}", user_id, chat_id);
+        state.broadcast(BroadcastMessage::ChatUserLeft { chat_id, user_id });
+    }
+    
+    Ok(None)
+}
+
+/// NotifyChatChangeUser (117) telling members of `chat_id` about `user`
+pub fn notify_chat_change_user(chat_id: u32, user: &User) -> Transaction {
+    create_server_transaction(
+        TransactionType::NotifyChatChangeUser,
+        vec![
+            Field::integer(FieldId::ChatId, chat_id as i32),
+            Field::integer(FieldId::UserId, user.id as i32),
+            Field::integer(FieldId::UserIconId, user.icon_id as i32),
+            Field::integer(FieldId::UserFlags, user.flags as i32),
+            Field::string(FieldId::UserName, &user.name),
+        ],
+    )
+}
+
+/// NotifyChatDeleteUser (118) telling members of `chat_id` that `user_id` left
+pub fn notify_chat_delete_user(chat_id: u32, user_id: u16) -> Transaction {
+    create_server_transaction(
+        TransactionType::NotifyChatDeleteUser,
+        vec![
+            Field::integer(FieldId::ChatId, chat_id as i32),
+            Field::integer(FieldId::UserId, user_id as i32),
+        ],
+    )
+}
+
+/// Handle SetChatSubject transaction (120)
+///
+/// Client sends:
+/// - Field 114: Chat ID (optional, 0 or absent for the public chat)
+/// - Field 115: Chat subject
+///
+/// Only the public chat exists, so other chat IDs are refused. Setting its
+/// subject needs `BROADCAST`, since it is shown to everyone. The subject is
+/// kept on the server state and sent to all users as NotifyChatSubject (119);
+/// there is no direct reply.
+pub async fn handle_set_chat_subject(
+    transaction: Transaction,
+    user_id: u16,
+    state: Arc<ServerState>,
+) -> Result<Option<Transaction>> {
+    let chat_id = transaction.get_field(FieldId::ChatId).and_then(|f| f.as_integer()).unwrap_or(0);
+    if chat_id != 0 {
+        tracing::debug!("User {} set the subject of unknown chat {}", user_id, chat_id);
+        return Ok(Some(create_error_reply(&transaction, ErrorCode::NotFound)));
+    }
+    
+    if !state.user_access(user_id).contains(AccessPrivileges::BROADCAST) {
+        tracing::warn!("User {} tried to set the chat subject without permission", user_id);
+        return Ok(Some(create_error_reply(&transaction, ErrorCode::PermissionDenied)));
+    }
+    
+    let subject = transaction
+        .get_field(FieldId::ChatSubject)
+        .map(|f| f.as_text())
+        .transpose()?
+        .unwrap_or_default()
+        .to_string();
+    
+    tracing::info!("User {} set the chat subject to '{}'", user_id, subject);
+    state.set_chat_subject(subject);
+    
+    Ok(None)
+}
+
+/// NotifyChatSubject (119) for the public chat
+pub fn notify_chat_subject(subject: &str) -> Transaction {
+    create_server_transaction(
+        TransactionType::NotifyChatSubject,
+        vec![Field::string(FieldId::ChatSubject, subject)],
+    )
+}
+
+/// Execute a chat line as a server command if it is one
+///
+/// Returns `None` when the message should be treated as normal chat: commands
+/// are disabled, the prefix doesn't match, or the sender isn't privileged.
+async fn handle_chat_command(
+    message: &[u8],
+    user_id: u16,
+    state: &Arc<ServerState>,
+) -> Result<Option<Transaction>> {
+    let config = state.config();
+    let prefix = &config.chat.command_prefix;
+    if prefix.is_empty() {
+        return Ok(None);
+    }
+    
+    let Some(input) = std::str::from_utf8(message)
+        .ok()
+        .and_then(|text| text.strip_prefix(prefix.as_str()))
+    else {
+        return Ok(None);
+    };
+    
+    let access = state.user_access(user_id);
+    if !access.contains(AccessPrivileges::DISCONNECT_USERS) {
+        return Ok(None);
+    }
+    
+    tracing::info!("User {} ran chat command: {}", user_id, input);
+    
+    let output = match Command::parse_chat(input) {
+        Ok(cmd) if !access.contains(cmd.required_privilege()) => {
+            tracing::warn!("User {} lacks privileges for chat command {:?}", user_id, cmd);
+            "Permission denied".to_string()
+        }
+        Ok(cmd) => match execute_command_as(cmd, state.clone(), &audit::user_actor(state, user_id)).await {
+            Ok(output) => output.to_string(),
+            Err(e) => format!("Error: {}", e),
+        },
+        Err(e) => format!("Error: {}", e),
+    };
+    
+    // Chat lines use \r as the line separator
+    let text = format!("\r{}", output.trim().replace('\n', "\r"));
+    let text = truncate_lines(text, MAX_CHAT_SIZE);
+    
+    Ok(Some(create_server_transaction(
+        TransactionType::ChatMessage,
+        vec![Field::binary(FieldId::Data, text.into_bytes())],
+    )))
+}
+
+/// Cut `text` to at most `max` bytes, dropping whole lines from the end
+///
+/// Long command output (e.g. `chat-tail`) would otherwise exceed what a
+/// client accepts in one chat message.
+fn truncate_lines(text: String, max: usize) -> String {
+    const MARKER: &str = "\r...";
+    if text.len() <= max {
+        return text;
+    }
+    
+    // Cut at a line break if there is one, else at a character boundary
+    let limit = max.saturating_sub(MARKER.len());
+    let end = match text.as_bytes()[..=limit].iter().rposition(|&b| b == b'\r') {
+        Some(end) if end > 0 => end,
+        _ => (0..=limit).rev().find(|&i| text.is_char_boundary(i)).unwrap_or(0),
+    };
+    format!("{}{}", &text[..end], MARKER)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::Session;
+    use crate::state::Broadcast;
+    use crate::console::execute_command;
+    use crate::test_util::{test_state, TempPath};
+    
+    #[tokio::test]
+    async fn test_send_chat_broadcasts_once() {
+        let state = test_state(|_| {}).await;
+        
+        let mut session = Session::new(5, "127.0.0.1:5500".parse().unwrap());
+        session.authenticate_guest("Alice".to_string(), 0);
+        state.register_session(session);
+        let mut tap = state.subscribe_raw();
+        
+        let mut transaction = Transaction::new(TransactionType::SendChat);
+        transaction.add_field(Field::binary(FieldId::Data, b"hello".to_vec()));
+        let reply = handle_send_chat(transaction, 5, state.clone()).await.unwrap();
+        assert!(reply.is_none());
+        
+        let sent = tap.drain();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].exclude, None);
+        match &sent[0].message {
+            BroadcastMessage::ChatMessage { sender_id, chat_id, message, is_emote } => {
+                assert_eq!(*sender_id, 5);
+                assert_eq!(*chat_id, None);
+                assert_eq!(message, b"hello");
+                assert!(!is_emote);
+            }
+            other => panic!("Expected a chat message, got {:?}", other),
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_send_chat_to_room_requires_membership() {
+        let state = test_state(|_| {}).await;
+        
+        for (user_id, nickname) in [(5, "Alice"), (6, "Bob")] {
+            let mut session = Session::new(user_id, "127.0.0.1:5500".parse().unwrap());
+            session.authenticate_guest(nickname.to_string(), 0);
+            state.register_session(session);
+        }
+        let chat_id = state.chat_rooms.create(5);
+        let mut tap = state.subscribe_raw();
+        
+        let chat = |id: u32| {
+            let mut transaction = Transaction::new(TransactionType::SendChat);
+            transaction.id = id;
+            transaction.add_field(Field::binary(FieldId::Data, b"psst".to_vec()));
+            transaction.add_field(Field::integer(FieldId::ChatId, chat_id as i32));
+            transaction
+        };
+        
+        let reply = handle_send_chat(chat(2), 6, state.clone()).await.unwrap().unwrap();
+        assert_eq!(reply.error_code, ErrorCode::PermissionDenied.to_u32());
+        assert!(tap.drain().is_empty());
+        
+        assert!(handle_send_chat(chat(3), 5, state.clone()).await.unwrap().is_none());
+        let sent = tap.drain();
+        assert!(matches!(sent[..], [Broadcast {
+            message: BroadcastMessage::ChatMessage { sender_id: 5, chat_id: Some(id), .. },
+            ..
+        }] if id == chat_id));
+    }
+    
+    #[tokio::test]
+    async fn test_invite_to_refusing_user_is_rejected() {
+        let state = test_state(|_| {}).await;
+        
+        for (user_id, nickname) in [(5, "Alice"), (6, "Bob"), (7, "Carol")] {
+            let mut session = Session::new(user_id, "127.0.0.1:5500".parse().unwrap());
+            session.authenticate_guest(nickname.to_string(), 0);
+            session.agree();
+            state.register_session(session);
+        }
+        state.update_session(6, |session| session.apply_options(UserOptions::REFUSE_PRIVATE_CHAT, None)).unwrap();
+        let chat_id = state.chat_rooms.create(5);
+        let mut tap = state.subscribe_raw();
+        
+        let invite = |target: u16| {
+            let mut transaction = Transaction::new(TransactionType::InviteToChat);
+            transaction.id = 4;
+            transaction.add_field(Field::integer(FieldId::UserId, target as i32));
+            transaction.add_field(Field::integer(FieldId::ChatId, chat_id as i32));
+            transaction
+        };
+        
+        // Bob is never asked; Alice hears back from the server right away
+        let rejected = handle_invite_to_chat(invite(6), 5, state.clone()).await.unwrap().unwrap();
+        assert_eq!(rejected.transaction_type, TransactionType::RejectChatInvite);
+        assert!(!rejected.is_reply);
+        assert_eq!(rejected.get_field(FieldId::ChatId).and_then(|f| f.as_integer()), Some(chat_id as i32));
+        assert_eq!(rejected.get_field(FieldId::UserId).and_then(|f| f.as_integer()), Some(6));
+        assert!(tap.drain().is_empty());
+        
+        // Carol accepts private chat, so she gets the invitation
+        assert!(handle_invite_to_chat(invite(7), 5, state.clone()).await.unwrap().is_none());
+        let sent = tap.drain();
+        assert!(matches!(sent[..], [Broadcast {
+            message: BroadcastMessage::ChatInvite { chat_id: id, inviter_id: 5, user_id: 7 },
+            ..
+        }] if id == chat_id));
+    }
+    
+    #[tokio::test]
+    async fn test_room_lifecycle() {
+        let state = test_state(|_| {}).await;
+        
+        for (user_id, nickname) in [(5, "Alice"), (6, "Bob"), (7, "Carol")] {
+            let mut session = Session::new(user_id, "127.0.0.1:5500".parse().unwrap());
+            session.authenticate_guest(nickname.to_string(), 0);
+            session.agree();
+            state.register_session(session);
+        }
+        state.update_session(7, |session| session.apply_options(UserOptions::REFUSE_PRIVATE_CHAT, None)).unwrap();
+        let mut tap = state.subscribe_raw();
+        
+        // Alice opens a room and invites Bob and Carol, but Carol refuses chat
+        let mut transaction = Transaction::new(TransactionType::InviteNewChat);
+        transaction.add_field(Field::integer(FieldId::UserId, 6));
+        transaction.add_field(Field::integer(FieldId::UserId, 7));
+        let reply = handle_invite_new_chat(transaction, 5, state.clone()).await.unwrap().unwrap();
+        assert_eq!(reply.error_code, 0);
+        let chat_id = reply.get_field(FieldId::ChatId).and_then(|f| f.as_integer()).unwrap() as u32;
+        assert_eq!(reply.get_field(FieldId::UserName).and_then(|f| f.as_string()), Some("Alice"));
+        assert!(state.chat_rooms.is_member(chat_id, 5));
+        let sent = tap.drain();
+        assert!(matches!(sent[..], [Broadcast {
+            message: BroadcastMessage::ChatInvite { chat_id: id, inviter_id: 5, user_id: 6 },
+            ..
+        }] if id == chat_id));
+        
+        let room = |transaction_type| {
+            let mut transaction = Transaction::new(transaction_type);
+            transaction.add_field(Field::integer(FieldId::ChatId, chat_id as i32));
+            transaction
+        };
+        
+        // Carol wasn't invited, Bob was
+        let reply = handle_join_chat(room(TransactionType::JoinChat), 7, state.clone()).await.unwrap().unwrap();
+        assert_eq!(reply.error_code, ErrorCode::PermissionDenied.to_u32());
+        let reply = handle_join_chat(room(TransactionType::JoinChat), 6, state.clone()).await.unwrap().unwrap();
+        assert_eq!(reply.error_code, 0);
+        let members: Vec<_> = reply.get_all(FieldId::UserNameWithInfo).collect();
+        assert_eq!(members.len(), 2);
+        assert!(matches!(tap.drain()[..], [Broadcast {
+            message: BroadcastMessage::ChatUserJoined { chat_id: id, user_id: 6 },
+            ..
+        }] if id == chat_id));
+        
+        // Leaving tells the others; leaving twice does nothing
+        assert!(handle_leave_chat(room(TransactionType::LeaveChat), 6, state.clone()).await.unwrap().is_none());
+        handle_leave_chat(room(TransactionType::LeaveChat), 6, state.clone()).await.unwrap();
+        assert!(matches!(tap.drain()[..], [Broadcast {
+            message: BroadcastMessage::ChatUserLeft { chat_id: id, user_id: 6 },
+            ..
+        }] if id == chat_id));
+        
+        // So does disconnecting
+        let chat_id = state.chat_rooms.create(5);
+        state.chat_rooms.invite(chat_id, 6);
+        state.chat_rooms.join(chat_id, 6);
+        state.unregister_session(5);
+        assert!(matches!(tap.drain()[..], [Broadcast {
+            message: BroadcastMessage::ChatUserLeft { chat_id: id, user_id: 5 },
+            ..
+        }] if id == chat_id));
+    }
+    
+    #[tokio::test]
+    async fn test_chat_is_logged_and_tailed() {
+        let log_path = TempPath::new("chat_log", "log");
+        let state = test_state(|config| {
+            config.chat.log_path = Some(log_path.to_path_buf());
+        })
+        .await;
+        
+        let mut session = Session::new(5, "127.0.0.1:5500".parse().unwrap());
+        session.authenticate_guest("Alice".to_string(), 0);
+        state.register_session(session);
+        let chat_id = state.chat_rooms.create(5);
+        
+        for (text, room) in [("hello world", None), ("secret", Some(chat_id))] {
+            let mut transaction = Transaction::new(TransactionType::SendChat);
+            transaction.add_field(Field::binary(FieldId::Data, text.as_bytes().to_vec()));
+            if let Some(room) = room {
+                transaction.add_field(Field::integer(FieldId::ChatId, room as i32));
+            }
+            handle_send_chat(transaction, 5, state.clone()).await.unwrap();
+        }
+        
+        // Private chat stays out of the log unless chat.log_private is set
+        let output = execute_command(Command::ChatTail { lines: 10 }, state.clone()).await.unwrap();
+        let text = output.to_string();
+        assert!(text.contains("\tpublic\t5\tAlice\thello world"), "{}", text);
+        assert!(!text.contains("secret"), "{}", text);
+    }
+    
+    #[test]
+    fn test_long_command_output_is_cut_at_a_line() {
+        let text = format!("\r{}", vec!["line"; 3000].join("\r"));
+        let cut = truncate_lines(text, MAX_CHAT_SIZE);
+        assert!(cut.len() <= MAX_CHAT_SIZE);
+        assert!(cut.ends_with("line\r..."), "{}", &cut[cut.len() - 20..]);
+        
+        assert_eq!(truncate_lines("\rshort".to_string(), MAX_CHAT_SIZE), "\rshort");
+    }
+}
@@ -0,0 +1,458 @@
+//! File and folder download transaction handlers
+
+use crate::connection::transaction_helpers::{create_error_reply, create_success_reply};
+use crate::files::PathResolver;
+use crate::state::ServerState;
+use crate::transfers::{flat_file_info, scan_folder, PendingDownload, PendingFolderDownload, TransferStatus};
+use anyhow::Result;
+use rhxcore::protocol::{ErrorCode, Field, FieldId, Transaction};
+use rhxcore::types::file::{decode_file_path, DATA_FORK};
+use rhxcore::types::ResumeData;
+use std::sync::Arc;
+
+/// Handle DownloadFile transaction (202)
+///
+/// Client sends:
+/// - Field 201: File name
+/// - Field 202: Folder containing the file (optional, defaults to the file root)
+/// - Field 203: Resume data (optional, to continue a partial download)
+/// - Field 204: Transfer options (optional)
+///
+/// Server replies with:
+/// - Field 108: Transfer size (bytes sent on the transfer connection)
+/// - Field 207: File size
+/// - Field 107: Reference number for the transfer connection
+/// - Field 116: Waiting count (only while queued)
+///
+/// The client then collects the file on the transfer port (see `htxf`).
+/// Files over 4 GiB are refused, since transfer sizes are 32 bits.
+pub async fn handle_download_file(
+    transaction: Transaction,
+    user_id: u16,
+    state: Arc<ServerState>,
+) -> Result<Option<Transaction>> {
+    let config = state.config();
+    if !config.files.enable_downloads {
+        tracing::warn!("User {} tried to download with downloads disabled", user_id);
+        return Ok(Some(create_error_reply(&transaction, ErrorCode::PermissionDenied)));
+    }
+    
+    let Some(name) = transaction.get_field(FieldId::FileName).and_then(|f| f.as_string()) else {
+        return Ok(Some(create_error_reply(&transaction, ErrorCode::InvalidParameter)));
+    };
+    
+    let mut components = match transaction.get_field(FieldId::FilePath).and_then(|f| f.as_binary()) {
+        Some(data) => match decode_file_path(data) {
+            Ok(components) => components,
+            Err(e) => {
+                tracing::warn!("User {} sent invalid file path: {}", user_id, e);
+                return Ok(Some(create_error_reply(&transaction, ErrorCode::InvalidParameter)));
+            }
+        },
+        None => Vec::new(),
+    };
+    components.push(name.to_string());
+    
+    let resolved = match PathResolver::new(&config.files).resolve(&components) {
+        Ok(resolved) => resolved,
+        Err(e) => {
+            tracing::warn!("User {} sent rejected file path: {}", user_id, e);
+            return Ok(Some(create_error_reply(&transaction, e.error_code())));
+        }
+    };
+    
+    let file_size = match tokio::fs::metadata(&resolved.physical_path).await {
+        Ok(metadata) if metadata.is_file() => metadata.len(),
+        _ => {
+            tracing::debug!("User {} requested missing file {}", user_id, resolved.virtual_path);
+            return Ok(Some(create_error_reply(&transaction, ErrorCode::NotFound)));
+        }
+    };
+    
+    if file_size > config.files.max_download_size {
+        tracing::warn!(
+            "User {} requested {} ({} bytes), over the download limit",
+            user_id,
+            resolved.virtual_path,
+            file_size
+        );
+        return Ok(Some(create_error_reply(&transaction, ErrorCode::PermissionDenied)));
+    }
+    
+    // Resume from where a previous download stopped
+    let offset = match transaction.get_field(FieldId::FileResumeData).and_then(|f| f.as_binary()) {
+        Some(data) => match ResumeData::from_bytes(data) {
+            Ok(resume) => resume.offset(DATA_FORK) as u64,
+            Err(e) => {
+                tracing::warn!("User {} sent invalid resume data: {}", user_id, e);
+                return Ok(Some(create_error_reply(&transaction, ErrorCode::InvalidParameter)));
+            }
+        },
+        None => 0,
+    };
+    
+    if offset > file_size {
+        tracing::warn!(
+            "User {} asked to resume {} at {} but it is only {} bytes",
+            user_id,
+            resolved.virtual_path,
+            offset,
+            file_size
+        );
+        return Ok(Some(create_error_reply(&transaction, ErrorCode::InvalidParameter)));
+    }
+    
+    let info = match flat_file_info(&resolved.physical_path).await {
+        Ok(info) => info,
+        Err(e) => {
+            tracing::warn!("Failed to read {} for user {}: {}", resolved.virtual_path, user_id, e);
+            return Ok(Some(create_error_reply(&transaction, ErrorCode::UnknownError)));
+        }
+    };
+    let download = PendingDownload {
+        user_id,
+        physical_path: resolved.physical_path,
+        info,
+        file_size,
+        offset,
+    };
+    
+    // Sizes are 32 bits on the wire, so bigger files can't be sent at all
+    let (Ok(transfer_size), Ok(file_size)) = (u32::try_from(download.transfer_size()), u32::try_from(file_size)) else {
+        tracing::warn!("User {} requested {} ({} bytes), over 4 GiB", user_id, resolved.virtual_path, file_size);
+        return Ok(Some(create_error_reply(&transaction, ErrorCode::PermissionDenied)));
+    };
+    
+    let (reference, status) = state.transfers.reserve(user_id, (&config.files).into());
+    state.downloads.insert(reference, download);
+    state.expire_unclaimed_transfer(reference);
+    
+    tracing::info!(
+        "User {} downloading {} from offset {} (reference {}, {:?})",
+        user_id,
+        resolved.virtual_path,
+        offset,
+        reference,
+        status
+    );
+    
+    let mut fields = vec![
+        Field::binary(FieldId::TransferSize, transfer_size.to_be_bytes()),
+        Field::binary(FieldId::FileSize, file_size.to_be_bytes()),
+        Field::integer(FieldId::ReferenceNumber, reference as i32),
+    ];
+    if let TransferStatus::Queued { .. } = status {
+        fields.push(Field::integer(FieldId::WaitingCount, status.waiting_count() as i32));
+    }
+    
+    Ok(Some(create_success_reply(&transaction, fields)))
+}
+
+/// Handle DownloadFolder transaction (210)
+///
+/// Client sends:
+/// - Field 201: Folder name
+/// - Field 202: Folder containing it (optional, defaults to the file root)
+///
+/// Server replies with:
+/// - Field 108: Transfer size (every item header and flattened file object)
+/// - Field 220: Number of items in the folder
+/// - Field 107: Reference number for the transfer connection
+/// - Field 116: Waiting count (only while queued)
+///
+/// The combined size of the files counts against `files.max_download_size`,
+/// and the whole folder takes a single transfer slot. Folders with anything nested deeper than
+/// `files.max_path_depth` allows are refused with InvalidParameter.
+pub async fn handle_download_folder(
+    transaction: Transaction,
+    user_id: u16,
+    state: Arc<ServerState>,
+) -> Result<Option<Transaction>> {
+    let config = state.config();
+    if !config.files.enable_downloads {
+        tracing::warn!("User {} tried to download a folder with downloads disabled", user_id);
+        return Ok(Some(create_error_reply(&transaction, ErrorCode::PermissionDenied)));
+    }
+    
+    let Some(name) = transaction.get_field(FieldId::FileName).and_then(|f| f.as_string()) else {
+        return Ok(Some(create_error_reply(&transaction, ErrorCode::InvalidParameter)));
+    };
+    
+    let mut components = match transaction.get_field(FieldId::FilePath).and_then(|f| f.as_binary()) {
+        Some(data) => match decode_file_path(data) {
+            Ok(components) => components,
+            Err(e) => {
+                tracing::warn!("User {} sent invalid file path: {}", user_id, e);
+                return Ok(Some(create_error_reply(&transaction, ErrorCode::InvalidParameter)));
+            }
+        },
+        None => Vec::new(),
+    };
+    components.push(name.to_string());
+    
+    let resolved = match PathResolver::new(&config.files).resolve(&components) {
+        Ok(resolved) => resolved,
+        Err(e) => {
+            tracing::warn!("User {} sent rejected file path: {}", user_id, e);
+            return Ok(Some(create_error_reply(&transaction, e.error_code())));
+        }
+    };
+    
+    match tokio::fs::metadata(&resolved.physical_path).await {
+        Ok(metadata) if metadata.is_dir() => {}
+        _ => {
+            tracing::debug!("User {} requested missing folder {}", user_id, resolved.virtual_path);
+            return Ok(Some(create_error_reply(&transaction, ErrorCode::NotFound)));
+        }
+    }
+    
+    let max_depth = config.files.max_path_depth.saturating_sub(components.len());
+    let items = match scan_folder(&resolved.physical_path, max_depth).await {
+        Ok(items) => items,
+        Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+            tracing::warn!("User {} requested folder {} that can't be sent: {}", user_id, resolved.virtual_path, e);
+            return Ok(Some(create_error_reply(&transaction, ErrorCode::InvalidParameter)));
+        }
+        Err(e) => {
+            tracing::warn!("Failed to list {} for user {}: {}", resolved.virtual_path, user_id, e);
+            return Ok(Some(create_error_reply(&transaction, ErrorCode::UnknownError)));
+        }
+    };
+    let download = PendingFolderDownload { user_id, items };
+    let total_size = download.total_size();
+    if total_size > config.files.max_download_size {
+        tracing::warn!(
+            "User {} requested folder {} ({} bytes), over the download limit",
+            user_id,
+            resolved.virtual_path,
+            total_size
+        );
+        return Ok(Some(create_error_reply(&transaction, ErrorCode::PermissionDenied)));
+    }
+    
+    let transfer_size = match download.transfer_size().await {
+        Ok(size) => size,
+        Err(e) => {
+            tracing::warn!("Failed to read {} for user {}: {}", resolved.virtual_path, user_id, e);
+            return Ok(Some(create_error_reply(&transaction, ErrorCode::UnknownError)));
+        }
+    };
+    // The size field is 32 bits, so nothing bigger can be announced
+    let Ok(transfer_size) = u32::try_from(transfer_size) else {
+        tracing::warn!("User {} requested folder {} ({} bytes), over 4 GiB", user_id, resolved.virtual_path, transfer_size);
+        return Ok(Some(create_error_reply(&transaction, ErrorCode::PermissionDenied)));
+    };
+    
+    let item_count = download.items.len();
+    let file_count = download.file_count();
+    let (reference, status) = state.transfers.reserve(user_id, (&config.files).into());
+    state.folder_downloads.insert(reference, download);
+    state.expire_unclaimed_transfer(reference);
+    
+    tracing::info!(
+        "User {} downloading folder {} ({} files, {} bytes, reference {}, {:?})",
+        user_id,
+        resolved.virtual_path,
+        file_count,
+        total_size,
+        reference,
+        status
+    );
+    
+    let mut fields = vec![
+        Field::binary(FieldId::TransferSize, transfer_size.to_be_bytes()),
+        Field::integer(FieldId::FolderItemCount, item_count.min(i32::MAX as usize) as i32),
+        Field::integer(FieldId::ReferenceNumber, reference as i32),
+    ];
+    if let TransferStatus::Queued { .. } = status {
+        fields.push(Field::integer(FieldId::WaitingCount, status.waiting_count() as i32));
+    }
+    
+    Ok(Some(create_success_reply(&transaction, fields)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{test_state, TempPath};
+    use crate::transfers::receive_folder;
+    use rhxcore::protocol::TransactionType;
+    use rhxcore::types::ForkHeader;
+    
+    const CONTENTS: &[u8] = b"0123456789abcdefghij";
+    
+    /// Server state serving files from the temp directory, plus a file in it
+    async fn file_state(name: &str) -> (Arc<ServerState>, TempPath) {
+        let file = TempPath::new(&format!("download_{}", name), "txt");
+        std::fs::write(&file, CONTENTS).unwrap();
+        
+        let state = test_state(|config| {
+            config.files.root_path = std::env::temp_dir();
+        })
+        .await;
+        (state, file)
+    }
+    
+    fn download_request(file: &TempPath, resume: Option<ResumeData>) -> Transaction {
+        let mut transaction = Transaction::new(TransactionType::DownloadFile);
+        transaction.id = 1;
+        let name = file.file_name().unwrap().to_str().unwrap();
+        transaction.add_field(Field::string(FieldId::FileName, name));
+        if let Some(resume) = resume {
+            transaction.add_field(Field::binary(FieldId::FileResumeData, resume.to_bytes()));
+        }
+        transaction
+    }
+    
+    fn size_field(reply: &Transaction, id: FieldId) -> u32 {
+        let bytes = reply.get_field(id).and_then(|f| f.as_binary()).unwrap();
+        u32::from_be_bytes(bytes.try_into().unwrap())
+    }
+    
+    #[tokio::test]
+    async fn test_resumed_download_streams_tail() {
+        let (state, file) = file_state("resume").await;
+        
+        let request = download_request(&file, Some(ResumeData::data_fork(12)));
+        let reply = handle_download_file(request, 1, state.clone()).await.unwrap().unwrap();
+        assert_eq!(reply.error_code, 0);
+        assert_eq!(size_field(&reply, FieldId::FileSize), CONTENTS.len() as u32);
+        
+        let reference = reply.get_field(FieldId::ReferenceNumber).and_then(|f| f.as_integer()).unwrap();
+        let download = state.downloads.get(&(reference as u32)).unwrap().clone();
+        assert_eq!(download.info.name, file.file_name().unwrap().to_str().unwrap());
+        
+        // The data fork only carries the tail, after the FILP header and forks
+        let mut streamed = Vec::new();
+        assert_eq!(download.stream_to(&mut streamed).await.unwrap(), 8);
+        assert_eq!(streamed.len() as u32, size_field(&reply, FieldId::TransferSize));
+        let data_fork = streamed.len() - 8 - ForkHeader::SIZE;
+        assert_eq!(ForkHeader::from_bytes(&streamed[data_fork..]).unwrap().data_size, 8);
+        assert!(streamed.ends_with(&CONTENTS[12..]));
+    }
+    
+    #[tokio::test]
+    async fn test_unclaimed_download_expires() {
+        let (state, file) = file_state("expire").await;
+        
+        let reply = handle_download_file(download_request(&file, None), 1, state.clone()).await.unwrap().unwrap();
+        assert_eq!(reply.error_code, 0);
+        assert_eq!(state.transfers.active_count(), 1);
+        
+        tokio::time::pause();
+        let timeout = state.config().files.transfer_timeout_seconds;
+        tokio::time::sleep(std::time::Duration::from_secs(timeout + 1)).await;
+        assert!(state.downloads.is_empty());
+        assert_eq!(state.transfers.active_count(), 0);
+    }
+    
+    #[tokio::test]
+    async fn test_resume_offset_past_end_rejected() {
+        let (state, file) = file_state("past_end").await;
+        
+        let request = download_request(&file, Some(ResumeData::data_fork(CONTENTS.len() as u32 + 1)));
+        let reply = handle_download_file(request, 1, state.clone()).await.unwrap().unwrap();
+        assert_eq!(reply.error_code, ErrorCode::InvalidParameter.to_u32());
+        assert!(state.downloads.is_empty());
+    }
+    
+    /// Every file and folder below `root` with the file contents
+    fn read_tree(root: &std::path::Path) -> Vec<(String, Option<Vec<u8>>)> {
+        let mut tree = Vec::new();
+        let mut folders = vec![root.to_path_buf()];
+        while let Some(folder) = folders.pop() {
+            for entry in std::fs::read_dir(&folder).unwrap() {
+                let path = entry.unwrap().path();
+                let relative = path.strip_prefix(root).unwrap().to_string_lossy().into_owned();
+                if path.is_dir() {
+                    tree.push((relative, None));
+                    folders.push(path);
+                } else {
+                    tree.push((relative, Some(std::fs::read(&path).unwrap())));
+                }
+            }
+        }
+        tree.sort();
+        tree
+    }
+    
+    #[tokio::test]
+    async fn test_nested_folder_download_round_trips() {
+        let (state, _file) = file_state("folder").await;
+        
+        let source = TempPath::new("download_folder_source", "d");
+        std::fs::create_dir_all(source.join("docs").join("old")).unwrap();
+        std::fs::create_dir(source.join("empty")).unwrap();
+        std::fs::write(source.join("readme.txt"), b"top level").unwrap();
+        std::fs::write(source.join("docs").join("a.txt"), CONTENTS).unwrap();
+        std::fs::write(source.join("docs").join("old").join("b.txt"), b"").unwrap();
+        
+        let mut request = Transaction::new(TransactionType::DownloadFolder);
+        request.id = 1;
+        let name = source.file_name().unwrap().to_str().unwrap();
+        request.add_field(Field::string(FieldId::FileName, name));
+        
+        let reply = handle_download_folder(request, 1, state.clone()).await.unwrap().unwrap();
+        assert_eq!(reply.error_code, 0);
+        let item_count = reply.get_field(FieldId::FolderItemCount).and_then(|f| f.as_integer()).unwrap();
+        assert_eq!(item_count, 6);
+        
+        let reference = reply.get_field(FieldId::ReferenceNumber).and_then(|f| f.as_integer()).unwrap();
+        let download = state.folder_downloads.get(&(reference as u32)).unwrap().clone();
+        assert_eq!(download.file_count(), 3);
+        assert_eq!(download.total_size(), 9 + CONTENTS.len() as u64);
+        assert_eq!(size_field(&reply, FieldId::TransferSize) as u64, download.transfer_size().await.unwrap());
+        
+        let target = TempPath::new("download_folder_target", "d");
+        std::fs::create_dir(&target).unwrap();
+        let (mut server, mut client) = tokio::io::duplex(64);
+        let (sent, received) = tokio::join!(
+            download.send_to(&mut server),
+            receive_folder(&mut client, &target, item_count as u64, u64::MAX, |_, _| Ok(true)),
+        );
+        assert_eq!(sent.unwrap(), 9 + CONTENTS.len() as u64);
+        assert_eq!(received.unwrap(), 9 + CONTENTS.len() as u64);
+        assert_eq!(read_tree(&target), read_tree(&source));
+    }
+    
+    #[tokio::test]
+    async fn test_folder_too_deep_rejected() {
+        let (state, _file) = file_state("folder_deep").await;
+        let mut config = (*state.config()).clone();
+        config.files.max_path_depth = 2;
+        state.reload_config(config);
+        
+        let source = TempPath::new("download_folder_deep", "d");
+        std::fs::create_dir_all(source.join("a").join("b")).unwrap();
+        
+        let mut request = Transaction::new(TransactionType::DownloadFolder);
+        request.id = 1;
+        request.add_field(Field::string(FieldId::FileName, source.file_name().unwrap().to_str().unwrap()));
+        
+        let reply = handle_download_folder(request, 1, state.clone()).await.unwrap().unwrap();
+        assert_eq!(reply.error_code, ErrorCode::InvalidParameter.to_u32());
+        assert!(state.folder_downloads.is_empty());
+        assert_eq!(state.transfers.active_count(), 0);
+    }
+    
+    #[tokio::test]
+    async fn test_folder_over_download_limit_rejected() {
+        let (state, _file) = file_state("folder_limit").await;
+        let mut config = (*state.config()).clone();
+        config.files.max_download_size = 10;
+        state.reload_config(config);
+        
+        let source = TempPath::new("download_folder_limit", "d");
+        std::fs::create_dir_all(source.join("sub")).unwrap();
+        std::fs::write(source.join("a.txt"), b"123456").unwrap();
+        std::fs::write(source.join("sub").join("b.txt"), b"123456").unwrap();
+        
+        let mut request = Transaction::new(TransactionType::DownloadFolder);
+        request.id = 1;
+        request.add_field(Field::string(FieldId::FileName, source.file_name().unwrap().to_str().unwrap()));
+        
+        let reply = handle_download_folder(request, 1, state.clone()).await.unwrap().unwrap();
+        assert_eq!(reply.error_code, ErrorCode::PermissionDenied.to_u32());
+        assert!(state.folder_downloads.is_empty());
+    }
+}
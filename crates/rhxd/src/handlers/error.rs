@@ -0,0 +1,39 @@
+//! Client error transaction handler
+
+use anyhow::Result;
+use rhxcore::protocol::{FieldId, Transaction};
+
+/// Handle Error transaction (100)
+///
+/// Clients send this to report a protocol error, for example when they
+/// can't make sense of something the server sent.
+///
+/// Client sends:
+/// - Error code in the transaction header
+/// - Field 100: Error text (optional)
+///
+/// Error transactions never get a reply, so this only logs the report.
+pub async fn handle_error(transaction: Transaction, user_id: u16) -> Result<Option<Transaction>> {
+    let text = transaction
+        .get_field(FieldId::ErrorText)
+        .map(|field| match field.as_string() {
+            Some(text) => text.to_string(),
+            None => String::from_utf8_lossy(field.as_binary().unwrap_or_default()).to_string(),
+        });
+
+    match text {
+        Some(text) => tracing::warn!(
+            "User {} reported error {}: {}",
+            user_id,
+            transaction.error_code,
+            text
+        ),
+        None => tracing::warn!(
+            "User {} reported error {}",
+            user_id,
+            transaction.error_code
+        ),
+    }
+
+    Ok(None)
+}
@@ -0,0 +1,81 @@
+//! File list transaction handler
+
+use crate::connection::transaction_helpers::{create_error_reply, create_success_reply};
+use crate::files::PathResolver;
+use crate::state::ServerState;
+use anyhow::Result;
+use rhxcore::protocol::{ErrorCode, Field, FieldId, Transaction};
+use rhxcore::types::file::decode_file_path;
+use rhxcore::types::FileEntry;
+use std::sync::Arc;
+
+/// Handle GetFileNameList transaction (200)
+///
+/// Client requests the contents of a folder.
+///
+/// Request fields:
+/// - Field 202 (FilePath): Folder to list (optional, defaults to the file root)
+///
+/// Server replies with:
+/// - Multiple Field 200 (FileNameWithInfo) entries, one per file or folder
+pub async fn handle_get_file_name_list(
+    transaction: Transaction,
+    user_id: u16,
+    state: Arc<ServerState>,
+) -> Result<Option<Transaction>> {
+    tracing::debug!("User {} requested file list", user_id);
+
+    // Check if user is authenticated
+    let session_exists = state.get_session(user_id).is_some();
+    if !session_exists {
+        tracing::warn!("User {} requested file list but session not found", user_id);
+        return Ok(None);
+    }
+
+    let components = match transaction
+        .get_field(FieldId::FilePath)
+        .and_then(|f| f.as_binary())
+    {
+        Some(data) => match decode_file_path(data) {
+            Ok(components) => components,
+            Err(e) => {
+                tracing::warn!("User {} sent invalid file path: {}", user_id, e);
+                return Ok(Some(create_error_reply(
+                    &transaction,
+                    ErrorCode::InvalidParameter,
+                )));
+            }
+        },
+        None => Vec::new(),
+    };
+
+    let config = state.config();
+    let folder = match PathResolver::new(&config.files).resolve(&components) {
+        Ok(resolved) => resolved.virtual_path,
+        Err(e) => {
+            tracing::warn!("User {} sent rejected file path: {}", user_id, e);
+            return Ok(Some(create_error_reply(&transaction, e.error_code())));
+        }
+    };
+    let mut file_fields = Vec::new();
+
+    for row in state.files.list_files_in_directory(&folder).await? {
+        let mut entry = FileEntry::from(row);
+
+        // Folders report their item count in place of a size
+        if entry.is_folder {
+            entry.size = state.files.list_files_in_directory(&entry.path).await?.len() as i64;
+        }
+
+        file_fields.push(Field::binary(FieldId::FileNameWithInfo, entry.to_name_with_info()));
+    }
+
+    tracing::info!(
+        "User {} listed {}, returning {} entries",
+        user_id,
+        folder,
+        file_fields.len()
+    );
+
+    Ok(Some(create_success_reply(&transaction, file_fields)))
+}
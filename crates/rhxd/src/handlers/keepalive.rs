@@ -0,0 +1,29 @@
+//! Keepalive transaction handler
+
+use crate::connection::transaction_helpers::create_success_reply;
+use crate::state::ServerState;
+use anyhow::Result;
+use chrono::Utc;
+use rhxcore::protocol::{Field, FieldId, Transaction};
+use std::sync::Arc;
+
+/// Handle KeepConnectionAlive transaction (500)
+///
+/// Clients send this periodically so idle connections aren't dropped.
+///
+/// With `features.keepalive_reply` set, the server replies with:
+/// - Field 101: Server time as an 8-byte date parameter
+///
+/// so monitors can measure round-trip latency. Otherwise there is no reply,
+/// since some clients don't expect one.
+pub async fn handle_keep_connection_alive(
+    transaction: Transaction,
+    state: Arc<ServerState>,
+) -> Result<Option<Transaction>> {
+    if !state.config().features.keepalive_reply {
+        return Ok(None);
+    }
+    
+    let now = Field::date(FieldId::Data, &Utc::now());
+    Ok(Some(create_success_reply(&transaction, vec![now])))
+}
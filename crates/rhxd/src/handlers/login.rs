@@ -1,11 +1,16 @@
 //! Login transaction handler
 
 use crate::connection::transaction_helpers::{create_error_reply, create_success_reply};
+use crate::db::accounts::{hash_stored_password, verify_stored_password, Account};
+use crate::lockout::LockoutPolicy;
 use crate::state::ServerState;
 use anyhow::{Context, Result};
-use rhxcore::password::xor_password;
+use rhxcore::password::{needs_rehash, xor_password, PasswordScheme};
 use rhxcore::protocol::{ErrorCode, Field, FieldId, Transaction, SERVER_VERSION};
+use rhxcore::types::AccessPrivileges;
+use std::net::IpAddr;
 use std::sync::Arc;
+use std::time::Instant;
 
 /// Handle login transaction (107)
 ///
@@ -14,10 +19,7 @@ use std::sync::Arc;
 /// - Field 106: User password (scrambled)
 /// - Field 160: Client version
 ///
-/// Server replies with:
-/// - Field 160: Server version
-/// - Field 161: Banner ID (if version >= 151)
-/// - Field 162: Server name (if version >= 151)
+/// Server replies with the fields from [`build_login_reply_fields`].
 pub async fn handle_login(
     transaction: Transaction,
     user_id: u16,
@@ -26,28 +28,36 @@ pub async fn handle_login(
     tracing::debug!("User {} sent login transaction", user_id);
     
     // Extract fields
-    let mut login: Option<Vec<u8>> = None;
-    let mut password: Option<Vec<u8>> = None;
+    let binary = |id| transaction.get_field(id).and_then(|f| f.as_binary()).map(|b| b.to_vec());
+    let login: Option<Vec<u8>> = binary(FieldId::UserLogin);
+    let password: Option<Vec<u8>> = binary(FieldId::UserPassword);
+    let client_version = transaction
+        .get_field(FieldId::Version)
+        .and_then(|f| f.as_integer())
+        .map(|version| version as u16);
     
-    for field in &transaction.fields {
-        match field.id {
-            FieldId::UserLogin => {
-                login = field.as_binary().map(|b| b.to_vec());
-            }
-            FieldId::UserPassword => {
-                password = field.as_binary().map(|b| b.to_vec());
-            }
-            _ => {}
-        }
+    // Authenticated users can't take the slots reserved for handshakes
+    if !state.accepts_login() {
+        tracing::warn!(
+            "User {} attempted login but the server is full ({} logged in)",
+            user_id,
+            state.authenticated_count()
+        );
+        return Ok(create_error_reply(&transaction, ErrorCode::PermissionDenied));
     }
     
     // Check for guest login (empty login/password)
-    let is_guest = login.as_ref().map_or(true, |l| l.is_empty())
-        || password.as_ref().map_or(true, |p| p.is_empty());
+    let is_guest = login.as_ref().is_none_or(|l| l.is_empty())
+        || password.as_ref().is_none_or(|p| p.is_empty());
     
-    if is_guest && !state.config.security.allow_guest {
+    if is_guest && !state.config().security.allow_guest {
         tracing::warn!("User {} attempted guest login but guests not allowed", user_id);
-        return Ok(create_error_reply(&transaction, ErrorCode::PermissionDenied));
+        let mut reply = create_error_reply(&transaction, ErrorCode::PermissionDenied);
+        reply.add_field(Field::string(
+            FieldId::Data,
+            state.config().security.guest_denied_message.as_str(),
+        ));
+        return Ok(reply);
     }
     
     // Handle guest login
@@ -55,67 +65,56 @@ pub async fn handle_login(
         tracing::info!("User {} logged in as guest", user_id);
         
         // Get guest access privileges
-        let guest_access = rhxcore::types::AccessPrivileges::guest();
+        let guest_access = state.config().security.guest_access();
         
         tracing::info!(
             "User {} guest access: 0x{:016X} (READ_CHAT={}, SEND_CHAT={})",
             user_id,
             guest_access.bits(),
-            guest_access.contains(rhxcore::types::AccessPrivileges::READ_CHAT),
-            guest_access.contains(rhxcore::types::AccessPrivileges::SEND_CHAT)
+            guest_access.contains(AccessPrivileges::READ_CHAT),
+            guest_access.contains(AccessPrivileges::SEND_CHAT)
         );
         
         // Update session to authenticated
-        if let Some(mut session) = state.get_session_mut(user_id) {
+        state.update_session(user_id, |session| {
             session.authenticate_guest(format!("Guest {}", user_id), 0);
-        }
-        
-        // Encode access as 8 bytes (Int64 in protocol spec)
-        // Use to_wire_format() to handle bit reversal on little-endian systems
-        let access_bytes = guest_access.to_wire_format().to_vec();
-        
-        tracing::debug!(
-            "Sending UserAccess field (8 bytes): {:02X?}",
-            access_bytes
-        );
-        
-        // Create reply
-        let mut reply_fields = vec![
-            Field::integer(FieldId::Version, SERVER_VERSION as i32),
-            Field::integer(FieldId::UserId, user_id as i32),  // Client needs to know their user ID
-            Field::binary(FieldId::UserAccess, access_bytes),
-        ];
-        
-        // Add server name and banner for version >= 151
-        // Note: We always send these for modern clients
-        reply_fields.push(Field::integer(FieldId::BannerId, 0));
-        reply_fields.push(Field::string(
-            FieldId::ServerName,
-            &state.config.server.name,
-        ));
+            session.client_version = client_version;
+        });
         
+        let reply_fields = build_login_reply_fields(user_id, guest_access, &state.display_name(), client_version);
         return Ok(create_success_reply(&transaction, reply_fields));
     }
     
     // Handle authenticated login
-    let login = login.context("Missing login field")?;
+    let login = transaction.get_field(FieldId::UserLogin).context("Missing login field")?;
     let password = password.context("Missing password field")?;
     
-    // Unscramble login and password
-    let login_str = String::from_utf8_lossy(&xor_password(&login)).to_string();
+    // Unscramble login and password; a login that isn't valid UTF-8 is
+    // refused rather than looked up under a lossy stand-in
+    let login_str = login.as_scrambled_text()?;
     let password_bytes = xor_password(&password);
     
     tracing::debug!("User {} attempting login as '{}'", user_id, login_str);
     
+    // Locked out logins and addresses are refused whatever the password
+    let ip = state.get_session(user_id)
+        .context("Session not found")?
+        .address
+        .ip();
+    if state.lockout.is_locked(&login_str, ip, Instant::now()) {
+        tracing::warn!("User {} login as '{}' refused - locked out", user_id, login_str);
+        return Ok(create_error_reply(&transaction, ErrorCode::PermissionDenied));
+    }
+    
     // Look up account in database
-    let account = crate::db::accounts::get_account_by_login(state.database.pool(), &login_str)
+    let account = state.accounts.get_account_by_login(&login_str)
         .await
         .context("Database error during login")?;
     
     match account {
         Some(account) => {
             // Verify password - password_hash is already in binary form (scrambled)
-            if rhxcore::password::verify_password(&account.password_hash, &password_bytes) {
+            if verify_stored_password(&account.password_hash, &password_bytes).await? {
                 tracing::info!(
                     "User {} successfully authenticated as '{}' (account_id={})",
                     user_id,
@@ -123,10 +122,14 @@ pub async fn handle_login(
                     account.id
                 );
                 
+                state.lockout.record_success(&login_str, ip);
+                upgrade_password(&state, &account, &password_bytes).await;
+                
                 // Update session with account info
-                if let Some(mut session) = state.get_session_mut(user_id) {
-                    session.authenticate_user(account.id, account.name.clone(), 0);
-                }
+                state.update_session(user_id, |session| {
+                    session.authenticate_user(account.id, account.access_privileges(), account.name.clone(), 0);
+                    session.client_version = client_version;
+                });
                 
                 // Get user access privileges from account
                 let user_access = account.access_privileges();
@@ -137,30 +140,318 @@ pub async fn handle_login(
                     user_access.bits()
                 );
                 
-                // Create reply
-                let mut reply_fields = vec![
-                    Field::integer(FieldId::Version, SERVER_VERSION as i32),
-                    Field::integer(FieldId::UserId, user_id as i32),  // Client needs to know their user ID
-                    // UserAccess as 8 bytes (Int64) with proper bit reversal
-                    Field::binary(FieldId::UserAccess, user_access.to_wire_format().to_vec()),
-                ];
-                
-                // Add server name and banner
-                reply_fields.push(Field::integer(FieldId::BannerId, 0));
-                reply_fields.push(Field::string(
-                    FieldId::ServerName,
-                    &state.config.server.name,
-                ));
-                
+                let reply_fields = build_login_reply_fields(user_id, user_access, &state.display_name(), client_version);
                 Ok(create_success_reply(&transaction, reply_fields))
             } else {
                 tracing::warn!("User {} failed authentication - invalid password", user_id);
+                record_failure(&state, &login_str, ip);
                 Ok(create_error_reply(&transaction, ErrorCode::PermissionDenied))
             }
         }
         None => {
             tracing::warn!("User {} failed authentication - account '{}' not found", user_id, login_str);
+            record_failure(&state, &login_str, ip);
             Ok(create_error_reply(&transaction, ErrorCode::PermissionDenied))
         }
     }
 }
+
+/// Fields of a successful login reply, for guests and accounts alike
+///
+/// - Field 160: Server version
+/// - Field 103: User ID (clients need to know their own)
+/// - Field 110: Access privileges
+/// - Field 161: Banner ID (clients 151 and up)
+/// - Field 162: Server name (clients 151 and up)
+///
+/// Clients that don't send their version get the banner and server name too.
+pub fn build_login_reply_fields(
+    user_id: u16,
+    access: AccessPrivileges,
+    server_name: &str,
+    client_version: Option<u16>,
+) -> Vec<Field> {
+    let mut fields = vec![
+        Field::integer(FieldId::Version, SERVER_VERSION as i32),
+        Field::integer(FieldId::UserId, user_id as i32),
+        Field::from_access(access),
+    ];
+    
+    if !matches!(client_version, Some(version) if version < 151) {
+        fields.push(Field::integer(FieldId::BannerId, 0));
+        fields.push(Field::string(FieldId::ServerName, server_name));
+    }
+    
+    fields
+}
+
+/// Count a failed login towards the lockout for its login and address, and
+/// let admins know
+fn record_failure(state: &ServerState, login: &str, ip: IpAddr) {
+    state.alert_admins(format!("Failed login for '{}' from {}", login, ip));
+    
+    let policy = LockoutPolicy::from(&state.config().security);
+    if state.lockout.record_failure(login, ip, policy, Instant::now()) {
+        tracing::warn!(
+            "Locking out login '{}' / {} for {} seconds after repeated failures",
+            login,
+            ip,
+            policy.duration.as_secs()
+        );
+        state.alert_admins(format!(
+            "Locked out '{}' / {} for {} seconds after repeated failed logins",
+            login,
+            ip,
+            policy.duration.as_secs()
+        ));
+    }
+}
+
+/// Rehash a legacy password with the configured scheme after a successful
+/// login, while the plaintext is at hand
+///
+/// Failures are logged and leave the old password in place; they never
+/// fail the login.
+async fn upgrade_password(state: &ServerState, account: &Account, password: &[u8]) {
+    let scheme = state.config().security.password_scheme;
+    if scheme == PasswordScheme::Legacy || !needs_rehash(&account.password_hash) {
+        return;
+    }
+    
+    let result = match hash_stored_password(password, scheme).await {
+        Ok(hash) => state.accounts.update_password(account.id, &hash).await,
+        Err(e) => Err(e),
+    };
+    
+    match result {
+        Ok(()) => tracing::info!("Upgraded password storage for account '{}'", account.login),
+        Err(e) => tracing::warn!("Failed to upgrade password for account '{}': {}", account.login, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::Session;
+    use crate::test_util::test_state;
+    use rhxcore::protocol::TransactionType;
+    use rhxcore::password::verify_password;
+    use rhxcore::ProtocolError;
+    use std::time::Duration;
+    
+    fn login_request(login: &str, password: &str) -> Transaction {
+        let mut request = Transaction::new(TransactionType::Login);
+        request.add_field(Field::binary(FieldId::UserLogin, xor_password(login.as_bytes())));
+        request.add_field(Field::binary(FieldId::UserPassword, xor_password(password.as_bytes())));
+        request
+    }
+    
+    #[tokio::test]
+    async fn test_guest_rejection_includes_configured_message() {
+        let state = test_state(|config| {
+            config.security.allow_guest = false;
+            config.security.guest_denied_message = "Members only, sorry".to_string();
+        })
+        .await;
+        
+        let user_id = state.allocate_user_id();
+        state.register_session(Session::new(user_id, "127.0.0.1:5500".parse().unwrap()));
+        
+        let reply = handle_login(Transaction::new(TransactionType::Login), user_id, state.clone())
+            .await
+            .unwrap();
+        
+        assert_eq!(reply.error_code, ErrorCode::PermissionDenied.to_u32());
+        let message = reply.get_field(FieldId::Data).and_then(|f| f.as_string());
+        assert_eq!(message, Some("Members only, sorry"));
+        assert!(!state.get_session(user_id).unwrap().is_authenticated());
+    }
+    
+    #[tokio::test]
+    async fn test_login_with_invalid_utf8_login() {
+        let state = test_state(|_| {}).await;
+        
+        let user_id = state.allocate_user_id();
+        state.register_session(Session::new(user_id, "127.0.0.1:5500".parse().unwrap()));
+        
+        let mut request = Transaction::new(TransactionType::Login);
+        request.add_field(Field::binary(FieldId::UserLogin, xor_password(&[b'b', 0xFF])));
+        request.add_field(Field::binary(FieldId::UserPassword, xor_password(b"pw")));
+        
+        let error = handle_login(request, user_id, state.clone()).await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ProtocolError>(),
+            Some(ProtocolError::InvalidUtf8 { field: FieldId::UserLogin })
+        ));
+        assert!(!state.get_session(user_id).unwrap().is_authenticated());
+    }
+    
+    #[tokio::test]
+    async fn test_legacy_password_upgraded_on_login() {
+        let state = test_state(|config| {
+            config.security.password_scheme = PasswordScheme::Argon2;
+        })
+        .await;
+        
+        let access = AccessPrivileges::user();
+        let account_id = state.accounts
+            .create_account("legacy", &xor_password(b"hunter2"), "Legacy", access)
+            .await
+            .unwrap();
+        
+        let user_id = state.allocate_user_id();
+        state.register_session(Session::new(user_id, "127.0.0.1:5500".parse().unwrap()));
+        
+        let request = login_request("legacy", "hunter2");
+        let reply = handle_login(request, user_id, state.clone()).await.unwrap();
+        assert_eq!(reply.error_code, 0);
+        
+        let account = state.accounts.get_account_by_id(account_id).await.unwrap().unwrap();
+        assert!(!needs_rehash(&account.password_hash));
+        assert!(verify_password(&account.password_hash, b"hunter2"));
+    }
+    
+    #[tokio::test]
+    async fn test_repeated_failures_lock_out_login() {
+        let state = test_state(|config| {
+            config.security.max_failed_logins = 3;
+            config.security.lockout_seconds = 1;
+        })
+        .await;
+        
+        state.accounts
+            .create_account("carol", &xor_password(b"right"), "Carol", AccessPrivileges::user())
+            .await
+            .unwrap();
+        
+        let user_id = state.allocate_user_id();
+        state.register_session(Session::new(user_id, "127.0.0.1:5500".parse().unwrap()));
+        
+        for _ in 0..3 {
+            let request = login_request("carol", "wrong");
+            let reply = handle_login(request, user_id, state.clone()).await.unwrap();
+            assert_eq!(reply.error_code, ErrorCode::PermissionDenied.to_u32());
+        }
+        
+        // The right password doesn't help while locked out
+        let request = login_request("carol", "right");
+        let reply = handle_login(request, user_id, state.clone()).await.unwrap();
+        assert_eq!(reply.error_code, ErrorCode::PermissionDenied.to_u32());
+        assert!(!state.get_session(user_id).unwrap().is_authenticated());
+        
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        
+        let request = login_request("carol", "right");
+        let reply = handle_login(request, user_id, state.clone()).await.unwrap();
+        assert_eq!(reply.error_code, 0);
+        assert!(state.get_session(user_id).unwrap().is_authenticated());
+    }
+    
+    #[tokio::test]
+    async fn test_server_name_shows_user_count() {
+        let state = test_state(|config| {
+            config.server.name = "Lounge".to_string();
+            config.server.max_connections = 100;
+            config.server.name_shows_user_count = true;
+            config.security.allow_guest = true;
+        })
+        .await;
+        
+        let mut name = String::new();
+        for _ in 0..3 {
+            let user_id = state.allocate_user_id();
+            state.register_session(Session::new(user_id, "127.0.0.1:5500".parse().unwrap()));
+            
+            let reply = handle_login(Transaction::new(TransactionType::Login), user_id, state.clone())
+                .await
+                .unwrap();
+            assert_eq!(reply.error_code, 0);
+            name = reply.get_field(FieldId::ServerName).and_then(|f| f.as_string()).unwrap().to_string();
+        }
+        
+        // The user being logged in already counts
+        assert_eq!(name, "Lounge (3/100)");
+    }
+    
+    #[tokio::test]
+    async fn test_login_reply_version_is_2_bytes() {
+        let state = test_state(|config| {
+            config.security.allow_guest = true;
+        })
+        .await;
+        
+        let user_id = state.allocate_user_id();
+        state.register_session(Session::new(user_id, "127.0.0.1:5500".parse().unwrap()));
+        
+        let reply = handle_login(Transaction::new(TransactionType::Login), user_id, state.clone())
+            .await
+            .unwrap();
+        let version = reply.get_field(FieldId::Version).unwrap();
+        assert_eq!(version.encoded_len(), 2);
+        assert_eq!(version.as_integer(), Some(SERVER_VERSION as i32));
+    }
+    
+    #[tokio::test]
+    async fn test_guest_receives_configured_access() {
+        let state = test_state(|config| {
+            config.security.allow_guest = true;
+            config.security.guest_access = Some("user".to_string());
+        })
+        .await;
+        
+        let user_id = state.allocate_user_id();
+        state.register_session(Session::new(user_id, "127.0.0.1:5500".parse().unwrap()));
+        
+        let reply = handle_login(Transaction::new(TransactionType::Login), user_id, state.clone())
+            .await
+            .unwrap();
+        let access = reply.get_field(FieldId::UserAccess).and_then(|f| f.access_privileges());
+        assert_eq!(access, Some(AccessPrivileges::user()));
+        assert!(state.user_access(user_id).contains(AccessPrivileges::UPLOAD_FILES));
+    }
+    
+    #[tokio::test]
+    async fn test_guest_and_account_replies_share_fields() {
+        let state = test_state(|config| {
+            config.security.allow_guest = true;
+            config.security.password_scheme = PasswordScheme::Legacy;
+        })
+        .await;
+        
+        let account_access = AccessPrivileges::admin();
+        state.accounts
+            .create_account("admin", &xor_password(b"secret"), "Admin", account_access)
+            .await
+            .unwrap();
+        
+        let guest_id = state.allocate_user_id();
+        state.register_session(Session::new(guest_id, "127.0.0.1:5500".parse().unwrap()));
+        let guest_reply = handle_login(Transaction::new(TransactionType::Login), guest_id, state.clone())
+            .await
+            .unwrap();
+        
+        let account_id = state.allocate_user_id();
+        state.register_session(Session::new(account_id, "127.0.0.1:5501".parse().unwrap()));
+        let account_reply = handle_login(login_request("admin", "secret"), account_id, state.clone())
+            .await
+            .unwrap();
+        
+        let field_ids = |reply: &Transaction| reply.fields.iter().map(|f| f.id).collect::<Vec<_>>();
+        assert_eq!(field_ids(&guest_reply), field_ids(&account_reply));
+        assert_eq!(
+            field_ids(&guest_reply),
+            vec![FieldId::Version, FieldId::UserId, FieldId::UserAccess, FieldId::BannerId, FieldId::ServerName]
+        );
+        
+        let access = |reply: &Transaction| reply.get_field(FieldId::UserAccess).and_then(|f| f.access_privileges());
+        assert_eq!(access(&guest_reply), Some(state.config().security.guest_access()));
+        assert_eq!(access(&account_reply), Some(account_access));
+    }
+    
+    #[test]
+    fn test_old_clients_get_no_banner_or_server_name() {
+        let fields = build_login_reply_fields(1, AccessPrivileges::user(), "Server", Some(150));
+        let ids: Vec<_> = fields.iter().map(|f| f.id).collect();
+        assert_eq!(ids, vec![FieldId::Version, FieldId::UserId, FieldId::UserAccess]);
+    }
+}
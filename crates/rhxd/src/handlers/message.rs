@@ -0,0 +1,153 @@
+//! Private message handler
+
+use crate::connection::transaction_helpers::{
+    create_error_reply, create_server_transaction, create_success_reply,
+};
+use crate::state::{BroadcastMessage, ServerState};
+use anyhow::{Context, Result};
+use rhxcore::protocol::{ErrorCode, Field, FieldId, Transaction, TransactionType};
+use rhxcore::types::UserOptions;
+use std::sync::Arc;
+
+/// Options value marking a ServerMessage (104) as a private message
+const PRIVATE_MESSAGE_OPTION: i32 = 1;
+
+/// Handle SendInstantMsg transaction (108)
+///
+/// Client sends:
+/// - Field 103: Recipient user ID
+/// - Field 101: Message data
+/// - Field 214: Quoted message (optional)
+///
+/// The recipient is sent ServerMessage (104) from [`private_message`]. If
+/// they have an automatic response set (see `Session::automatic_response`),
+/// it comes straight back to the sender the same way. Users who refuse
+/// private messages (`REFUSE_PRIVATE_MESSAGE`) are never sent anything; the
+/// sender gets PermissionDenied with an explanation instead.
+pub async fn handle_send_instant_msg(
+    transaction: Transaction,
+    user_id: u16,
+    state: Arc<ServerState>,
+) -> Result<Option<Transaction>> {
+    let Some(target_id) = transaction.get_field(FieldId::UserId).and_then(|f| f.as_integer()) else {
+        return Ok(Some(create_error_reply(&transaction, ErrorCode::InvalidParameter)));
+    };
+    let target_id = target_id as u16;
+    
+    let binary = |id| transaction.get_field(id).and_then(|f| f.as_binary()).map(|b| b.to_vec());
+    let message = binary(FieldId::Data).context("Missing message data")?;
+    let quoting = binary(FieldId::QuotingMsg);
+    
+    let Some(target) = state.get_session(target_id).filter(|session| session.is_authenticated()) else {
+        return Ok(Some(create_error_reply(&transaction, ErrorCode::NotFound)));
+    };
+    
+    if target.options.contains(UserOptions::REFUSE_PRIVATE_MESSAGE) {
+        tracing::debug!("User {} refuses private messages; dropping one from user {}", target_id, user_id);
+        let mut reply = create_error_reply(&transaction, ErrorCode::PermissionDenied);
+        reply.add_field(Field::string(
+            FieldId::Data,
+            format!("{} does not accept private messages.", target.nickname),
+        ));
+        return Ok(Some(reply));
+    }
+    
+    tracing::info!("User {} sent a private message to user {}", user_id, target_id);
+    state.broadcast(BroadcastMessage::PrivateMessage {
+        sender_id: user_id,
+        user_id: target_id,
+        message,
+        quoting,
+    });
+    
+    if let Some(response) = target.automatic_response() {
+        state.broadcast(BroadcastMessage::PrivateMessage {
+            sender_id: target_id,
+            user_id,
+            message: response.as_bytes().to_vec(),
+            quoting: None,
+        });
+    }
+    
+    Ok(Some(create_success_reply(&transaction, vec![])))
+}
+
+/// ServerMessage (104) delivering a private message from `sender_id`
+pub fn private_message(
+    sender_id: u16,
+    nickname: &str,
+    message: Vec<u8>,
+    quoting: Option<Vec<u8>>,
+) -> Transaction {
+    let mut fields = vec![
+        Field::integer(FieldId::UserId, sender_id as i32),
+        Field::string(FieldId::UserName, nickname),
+        Field::integer(FieldId::Options, PRIVATE_MESSAGE_OPTION),
+        Field::binary(FieldId::Data, message),
+    ];
+    if let Some(quoting) = quoting {
+        fields.push(Field::binary(FieldId::QuotingMsg, quoting));
+    }
+    
+    create_server_transaction(TransactionType::ServerMessage, fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::Session;
+    use crate::state::Broadcast;
+    use crate::test_util::test_state;
+    
+    async fn two_users() -> Arc<ServerState> {
+        let state = test_state(|_| {}).await;
+        for (user_id, nickname) in [(5, "Alice"), (6, "Bob")] {
+            let mut session = Session::new(user_id, "127.0.0.1:5500".parse().unwrap());
+            session.authenticate_guest(nickname.to_string(), 0);
+            state.register_session(session);
+        }
+        state
+    }
+    
+    fn instant_msg(target: u16) -> Transaction {
+        let mut transaction = Transaction::new(TransactionType::SendInstantMsg);
+        transaction.id = 3;
+        transaction.add_field(Field::integer(FieldId::UserId, target as i32));
+        transaction.add_field(Field::binary(FieldId::Data, b"hi".to_vec()));
+        transaction
+    }
+    
+    #[tokio::test]
+    async fn test_automatic_response_sent_back() {
+        let state = two_users().await;
+        state.update_session(6, |session| {
+            session.apply_options(UserOptions::AUTOMATIC_RESPONSE, Some("Out to lunch".to_string()))
+        });
+        let mut tap = state.subscribe_raw();
+        
+        let reply = handle_send_instant_msg(instant_msg(6), 5, state.clone()).await.unwrap().unwrap();
+        assert_eq!(reply.error_code, 0);
+        
+        let sent: Vec<_> = tap.drain().into_iter().map(|Broadcast { message, .. }| message).collect();
+        assert!(matches!(&sent[..], [
+            BroadcastMessage::PrivateMessage { sender_id: 5, user_id: 6, message: hi, .. },
+            BroadcastMessage::PrivateMessage { sender_id: 6, user_id: 5, message: response, .. },
+        ] if hi == b"hi" && response == b"Out to lunch"));
+    }
+    
+    #[tokio::test]
+    async fn test_refused_private_message() {
+        let state = two_users().await;
+        state.update_session(6, |session| session.apply_options(UserOptions::REFUSE_PRIVATE_MESSAGE, None));
+        let mut tap = state.subscribe_raw();
+        
+        let reply = handle_send_instant_msg(instant_msg(6), 5, state.clone()).await.unwrap().unwrap();
+        assert_eq!(reply.error_code, ErrorCode::PermissionDenied.to_u32());
+        let text = reply.get_field(FieldId::Data).and_then(|f| f.as_string());
+        assert_eq!(text, Some("Bob does not accept private messages."));
+        assert!(tap.drain().is_empty());
+        
+        let reply = handle_send_instant_msg(instant_msg(9), 5, state.clone()).await.unwrap().unwrap();
+        assert_eq!(reply.error_code, ErrorCode::NotFound.to_u32());
+    }
+}
@@ -2,7 +2,14 @@
 
 pub mod account;
 pub mod agreed;
+pub mod banner;
 pub mod chat;
+pub mod download;
+pub mod error;
+pub mod file_list;
+pub mod keepalive;
 pub mod login;
+pub mod message;
+pub mod upload;
 pub mod user_info;
 pub mod user_list;
@@ -0,0 +1,309 @@
+//! File and folder upload transaction handlers
+
+use crate::connection::transaction_helpers::{create_error_reply, create_success_reply};
+use crate::files::PathResolver;
+use crate::state::ServerState;
+use crate::transfers::{PendingFolderUpload, PendingUpload, TransferStatus};
+use anyhow::Result;
+use rhxcore::protocol::{ErrorCode, Field, FieldId, Transaction};
+use rhxcore::types::file::decode_file_path;
+use rhxcore::types::AccessPrivileges;
+use std::sync::Arc;
+
+/// Handle UploadFile transaction (203)
+///
+/// Client sends:
+/// - Field 201: File name
+/// - Field 202: Folder to upload into (optional, defaults to the file root)
+/// - Field 108: Transfer size (optional)
+///
+/// Server replies with:
+/// - Field 107: Reference number for the transfer connection
+/// - Field 116: Waiting count (only while queued)
+///
+/// Files whose extension is refused by the `files.upload_*_extensions` lists
+/// are rejected before a transfer slot is reserved, as are announced sizes
+/// over 4 GiB. The client then sends the file on the transfer port (see
+/// `htxf`).
+pub async fn handle_upload_file(
+    transaction: Transaction,
+    user_id: u16,
+    state: Arc<ServerState>,
+) -> Result<Option<Transaction>> {
+    let config = state.config();
+    if !config.files.enable_uploads {
+        tracing::warn!("User {} tried to upload with uploads disabled", user_id);
+        return Ok(Some(create_error_reply(&transaction, ErrorCode::PermissionDenied)));
+    }
+    
+    let Some(name) = transaction.get_field(FieldId::FileName).and_then(|f| f.as_string()) else {
+        return Ok(Some(create_error_reply(&transaction, ErrorCode::InvalidParameter)));
+    };
+    
+    let folder = match transaction.get_field(FieldId::FilePath).and_then(|f| f.as_binary()) {
+        Some(data) => match decode_file_path(data) {
+            Ok(components) => components,
+            Err(e) => {
+                tracing::warn!("User {} sent invalid file path: {}", user_id, e);
+                return Ok(Some(create_error_reply(&transaction, ErrorCode::InvalidParameter)));
+            }
+        },
+        None => Vec::new(),
+    };
+    
+    let resolved = match PathResolver::new(&config.files).resolve_new(&folder, name) {
+        Ok(resolved) => resolved,
+        Err(e) => {
+            tracing::warn!("User {} sent rejected file path: {}", user_id, e);
+            return Ok(Some(create_error_reply(&transaction, e.error_code())));
+        }
+    };
+    
+    if !config.files.upload_extension_allowed(name) {
+        let bypass = config.files.upload_anywhere_ignores_extensions
+            && state.user_access(user_id).contains(AccessPrivileges::UPLOAD_ANYWHERE);
+        
+        if !bypass {
+            tracing::warn!("User {} tried to upload blocked file type {}", user_id, resolved.virtual_path);
+            return Ok(Some(create_error_reply(&transaction, ErrorCode::PermissionDenied)));
+        }
+    }
+    
+    if tokio::fs::try_exists(&resolved.physical_path).await.unwrap_or(false) {
+        tracing::debug!("User {} tried to upload over {}", user_id, resolved.virtual_path);
+        return Ok(Some(create_error_reply(&transaction, ErrorCode::AlreadyExists)));
+    }
+    
+    // Sizes are 32 bits on the wire, so bigger files can't be received
+    let size = transfer_size(&transaction);
+    if size.is_some_and(|size| size > u32::MAX as u64) {
+        tracing::warn!("User {} tried to upload {} over 4 GiB", user_id, resolved.virtual_path);
+        return Ok(Some(create_error_reply(&transaction, ErrorCode::PermissionDenied)));
+    }
+    
+    let (reference, status) = state.transfers.reserve(user_id, (&config.files).into());
+    state.uploads.insert(reference, PendingUpload {
+        user_id,
+        physical_path: resolved.physical_path,
+        size,
+    });
+    state.expire_unclaimed_transfer(reference);
+    
+    tracing::info!(
+        "User {} uploading {} (reference {}, {:?})",
+        user_id,
+        resolved.virtual_path,
+        reference,
+        status
+    );
+    
+    let mut fields = vec![Field::integer(FieldId::ReferenceNumber, reference as i32)];
+    if let TransferStatus::Queued { .. } = status {
+        fields.push(Field::integer(FieldId::WaitingCount, status.waiting_count() as i32));
+    }
+    
+    Ok(Some(create_success_reply(&transaction, fields)))
+}
+
+/// Handle UploadFolder transaction (213)
+///
+/// Client sends:
+/// - Field 201: Folder name
+/// - Field 202: Folder to upload into (optional, defaults to the file root)
+/// - Field 108: Transfer size (optional, combined size of every file)
+/// - Field 220: Number of items in the folder
+///
+/// Server replies with:
+/// - Field 107: Reference number for the transfer connection
+/// - Field 116: Waiting count (only while queued)
+///
+/// Names and extensions inside the folder are only known once the items
+/// arrive, so they are checked as the transfer is received.
+pub async fn handle_upload_folder(
+    transaction: Transaction,
+    user_id: u16,
+    state: Arc<ServerState>,
+) -> Result<Option<Transaction>> {
+    let config = state.config();
+    if !config.files.enable_uploads {
+        tracing::warn!("User {} tried to upload a folder with uploads disabled", user_id);
+        return Ok(Some(create_error_reply(&transaction, ErrorCode::PermissionDenied)));
+    }
+    
+    let Some(name) = transaction.get_field(FieldId::FileName).and_then(|f| f.as_string()) else {
+        return Ok(Some(create_error_reply(&transaction, ErrorCode::InvalidParameter)));
+    };
+    
+    let item_count = match transaction.get_field(FieldId::FolderItemCount).and_then(|f| f.as_integer()) {
+        Some(count) if count >= 0 => count as u64,
+        _ => return Ok(Some(create_error_reply(&transaction, ErrorCode::InvalidParameter))),
+    };
+    
+    let folder = match transaction.get_field(FieldId::FilePath).and_then(|f| f.as_binary()) {
+        Some(data) => match decode_file_path(data) {
+            Ok(components) => components,
+            Err(e) => {
+                tracing::warn!("User {} sent invalid file path: {}", user_id, e);
+                return Ok(Some(create_error_reply(&transaction, ErrorCode::InvalidParameter)));
+            }
+        },
+        None => Vec::new(),
+    };
+    
+    let resolved = match PathResolver::new(&config.files).resolve_new(&folder, name) {
+        Ok(resolved) => resolved,
+        Err(e) => {
+            tracing::warn!("User {} sent rejected file path: {}", user_id, e);
+            return Ok(Some(create_error_reply(&transaction, e.error_code())));
+        }
+    };
+    
+    if tokio::fs::try_exists(&resolved.physical_path).await.unwrap_or(false) {
+        tracing::debug!("User {} tried to upload over {}", user_id, resolved.virtual_path);
+        return Ok(Some(create_error_reply(&transaction, ErrorCode::AlreadyExists)));
+    }
+    
+    let size = transfer_size(&transaction);
+    
+    let check_extensions = !(config.files.upload_anywhere_ignores_extensions
+        && state.user_access(user_id).contains(AccessPrivileges::UPLOAD_ANYWHERE));
+    
+    let (reference, status) = state.transfers.reserve(user_id, (&config.files).into());
+    state.folder_uploads.insert(reference, PendingFolderUpload {
+        user_id,
+        physical_path: resolved.physical_path,
+        size,
+        item_count,
+        max_depth: config.files.max_path_depth.saturating_sub(folder.len() + 1),
+        check_extensions,
+    });
+    state.expire_unclaimed_transfer(reference);
+    
+    tracing::info!(
+        "User {} uploading folder {} ({} items, reference {}, {:?})",
+        user_id,
+        resolved.virtual_path,
+        item_count,
+        reference,
+        status
+    );
+    
+    let mut fields = vec![Field::integer(FieldId::ReferenceNumber, reference as i32)];
+    if let TransferStatus::Queued { .. } = status {
+        fields.push(Field::integer(FieldId::WaitingCount, status.waiting_count() as i32));
+    }
+    
+    Ok(Some(create_success_reply(&transaction, fields)))
+}
+
+/// Transfer size (field 108) the client announced, sent as 4 or 8 bytes
+fn transfer_size(transaction: &Transaction) -> Option<u64> {
+    let bytes = transaction.get_field(FieldId::TransferSize).and_then(|f| f.as_binary())?;
+    match bytes.len() {
+        4 => Some(u32::from_be_bytes(bytes.try_into().unwrap()) as u64),
+        8 => Some(u64::from_be_bytes(bytes.try_into().unwrap())),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::Session;
+    use crate::test_util::{test_state, TempPath};
+    use crate::Config;
+    use rhxcore::protocol::TransactionType;
+    
+    /// Server state accepting uploads into the temp directory
+    async fn upload_state(configure: impl FnOnce(&mut Config)) -> Arc<ServerState> {
+        test_state(|config| {
+            config.files.root_path = std::env::temp_dir();
+            config.files.upload_blocked_extensions = vec!["exe".to_string(), ".scr".to_string()];
+            configure(config);
+        })
+        .await
+    }
+    
+    fn upload_request(name: &str) -> Transaction {
+        let mut transaction = Transaction::new(TransactionType::UploadFile);
+        transaction.id = 1;
+        transaction.add_field(Field::string(FieldId::FileName, name));
+        transaction
+    }
+    
+    /// A file name in the temp directory that doesn't exist yet
+    fn unused_name(name: &str, extension: &str) -> (String, TempPath) {
+        let path = TempPath::new(&format!("upload_{}", name), extension);
+        let name = path.file_name().unwrap().to_str().unwrap().to_string();
+        (name, path)
+    }
+    
+    #[tokio::test]
+    async fn test_blocked_extension_rejected() {
+        let state = upload_state(|_| {}).await;
+        let (name, _file) = unused_name("blocked", "EXE");
+        
+        let reply = handle_upload_file(upload_request(&name), 1, state.clone()).await.unwrap().unwrap();
+        assert_eq!(reply.error_code, ErrorCode::PermissionDenied.to_u32());
+        assert!(state.uploads.is_empty());
+        assert_eq!(state.transfers.active_count(), 0);
+    }
+    
+    #[tokio::test]
+    async fn test_allowed_extension_reserves_transfer() {
+        let state = upload_state(|_| {}).await;
+        let (name, file) = unused_name("allowed", "txt");
+        
+        let reply = handle_upload_file(upload_request(&name), 1, state.clone()).await.unwrap().unwrap();
+        assert_eq!(reply.error_code, 0);
+        
+        let reference = reply.get_field(FieldId::ReferenceNumber).and_then(|f| f.as_integer()).unwrap();
+        let upload = state.uploads.get(&(reference as u32)).unwrap().clone();
+        assert_eq!(upload.physical_path, file.to_path_buf());
+    }
+    
+    #[tokio::test]
+    async fn test_upload_over_4_gib_refused() {
+        let state = upload_state(|_| {}).await;
+        let (name, _file) = unused_name("huge", "txt");
+        
+        let mut request = upload_request(&name);
+        request.add_field(Field::binary(FieldId::TransferSize, (u32::MAX as u64 + 1).to_be_bytes()));
+        let reply = handle_upload_file(request, 1, state.clone()).await.unwrap().unwrap();
+        assert_eq!(reply.error_code, ErrorCode::PermissionDenied.to_u32());
+        assert!(state.uploads.is_empty());
+        
+        let mut request = upload_request(&name);
+        request.add_field(Field::binary(FieldId::TransferSize, 1024u64.to_be_bytes()));
+        let reply = handle_upload_file(request, 1, state.clone()).await.unwrap().unwrap();
+        assert_eq!(reply.error_code, 0);
+        let upload = state.uploads.iter().next().unwrap().clone();
+        assert_eq!(upload.size, Some(1024));
+    }
+    
+    #[tokio::test]
+    async fn test_upload_anywhere_bypass() {
+        let state = upload_state(|config| {
+            config.files.upload_anywhere_ignores_extensions = true;
+        })
+        .await;
+        
+        let access = AccessPrivileges::UPLOAD_FILES | AccessPrivileges::UPLOAD_ANYWHERE;
+        let account_id = state.accounts
+            .create_account("uploader", b"pw", "Uploader", access)
+            .await
+            .unwrap();
+        let mut session = Session::new(2, "127.0.0.1:5500".parse().unwrap());
+        session.authenticate_user(account_id, access, "Uploader".to_string(), 0);
+        state.register_session(session);
+        
+        let (name, _file) = unused_name("bypass", "exe");
+        
+        // A guest is still held to the blocklist
+        let reply = handle_upload_file(upload_request(&name), 1, state.clone()).await.unwrap().unwrap();
+        assert_eq!(reply.error_code, ErrorCode::PermissionDenied.to_u32());
+        
+        let reply = handle_upload_file(upload_request(&name), 2, state.clone()).await.unwrap().unwrap();
+        assert_eq!(reply.error_code, 0);
+    }
+}
@@ -1,12 +1,10 @@
 //! User info transaction handlers
 
 use crate::connection::transaction_helpers::{create_error_reply, create_success_reply};
-use crate::db::accounts::get_account_by_id;
-use crate::state::ServerState;
+use crate::state::{BroadcastMessage, ServerState};
 use anyhow::Result;
-use chrono::{DateTime, Utc};
 use rhxcore::protocol::{ErrorCode, Field, FieldId, Transaction};
-use rhxcore::types::AccessPrivileges;
+use rhxcore::types::{AccessPrivileges, UserFlags, UserOptions};
 use std::sync::Arc;
 use std::time::SystemTime;
 
@@ -35,7 +33,7 @@ pub async fn handle_get_client_info_text(
 
     // Check if requester has GET_USER_INFO privilege
     if let Some(account_id) = session.account_id {
-        match get_account_by_id(state.database.pool(), account_id).await? {
+        match state.accounts.get_account_by_id(account_id).await? {
             Some(account) => {
                 if !account.has_privilege(AccessPrivileges::GET_USER_INFO) {
                     return Ok(Some(create_error_reply(
@@ -62,11 +60,11 @@ pub async fn handle_get_client_info_text(
     // Extract the requested user ID from the request
     let mut target_user_id = None;
     for field in &transaction.fields {
-        if field.id == FieldId::UserId {
-            if let Some(id) = field.as_integer() {
-                target_user_id = Some(id as u16);
-                break;
-            }
+        if field.id == FieldId::UserId
+            && let Some(id) = field.as_integer()
+        {
+            target_user_id = Some(id as u16);
+            break;
         }
     }
 
@@ -113,6 +111,121 @@ pub async fn handle_get_client_info_text(
     )))
 }
 
+/// Handle SetClientUserInfo (304) transaction
+///
+/// Client sends any of:
+/// - Field 102: User name (nickname)
+/// - Field 104: Icon ID
+/// - Field 113: Options (user flags)
+/// - Field 215: Auto-response
+///
+/// Only the fields present are changed; a client that sends just a new icon
+/// keeps its nickname and vice versa. The same rules as Agreed (121) apply:
+/// reserved nicknames need `ANY_NAME` (otherwise the current one is kept), an
+/// account's `force_icon` wins over the requested icon, and the admin flag is
+/// left alone. Everyone is sent NotifyChangeUser (301); there is no reply.
+pub async fn handle_set_client_user_info(
+    transaction: Transaction,
+    user_id: u16,
+    state: Arc<ServerState>,
+) -> Result<Option<Transaction>> {
+    let account_id = match state.get_session(user_id) {
+        Some(session) => session.account_id,
+        None => {
+            tracing::warn!("User {} sent SetClientUserInfo but session not found", user_id);
+            return Ok(None);
+        }
+    };
+
+    let string = |id| transaction.get_field(id).and_then(|f| f.as_string()).map(|s| s.to_string());
+    let mut nickname = string(FieldId::UserName).filter(|name| !name.trim().is_empty());
+    let auto_response = string(FieldId::AutomaticResponse);
+    let mut icon_id = transaction
+        .get_field(FieldId::UserIconId)
+        .and_then(|f| f.as_integer())
+        .map(|icon| icon as u16);
+    let options = transaction
+        .get_field(FieldId::Options)
+        .and_then(|f| f.as_integer())
+        .map(|value| UserOptions::from_i16(value as i16));
+
+    let account = match account_id {
+        Some(account_id) => state.accounts.get_account_by_id(account_id).await?,
+        None => None,
+    };
+    let access = account
+        .as_ref()
+        .map(|account| account.access_privileges())
+        .unwrap_or_else(|| state.config().security.guest_access());
+
+    if let Some(name) = &nickname
+        && state.config().features.is_reserved_name(name) && !access.contains(AccessPrivileges::ANY_NAME)
+    {
+        tracing::warn!("User {} requested reserved nickname '{}', keeping the current one", user_id, name);
+        nickname = None;
+    }
+    if let Some(force_icon) = account.as_ref().and_then(|account| account.force_icon) {
+        icon_id = icon_id.map(|_| force_icon as u16);
+    }
+
+    let updated = state.update_session(user_id, |session| {
+        if let Some(nickname) = nickname {
+            session.nickname = nickname;
+        }
+        if let Some(icon_id) = icon_id {
+            session.icon_id = icon_id;
+        }
+        if let Some(options) = options {
+            let refused = UserFlags::REFUSED_MESSAGES.bits() | UserFlags::REFUSED_CHAT.bits();
+            session.flags = (session.flags & !refused) | options.to_user_flags();
+        }
+        if options.is_some() || auto_response.is_some() {
+            let options = options.unwrap_or(session.options);
+            let auto_response = auto_response.or_else(|| session.automatic_response().map(str::to_string));
+            session.apply_options(options, auto_response);
+        }
+
+        tracing::info!(
+            "User {} changed their info: nickname='{}', icon={}, flags=0x{:04X}",
+            user_id,
+            session.nickname,
+            session.icon_id,
+            session.flags
+        );
+    });
+    if updated.is_none() {
+        return Ok(None);
+    }
+
+    state.broadcast(BroadcastMessage::UserChanged { user_id });
+
+    Ok(None)
+}
+
+/// Handle UserAccess (354) sent by a client, asking for its own access
+///
+/// The server sends UserAccess unprompted after Agreed; clients and bots can
+/// send it themselves to pick up changes an admin has made since.
+///
+/// Server replies with:
+/// - Field 110: Current access privileges
+/// - Field 101: Preset name (sysop, admin, user or guest), or "custom"
+pub async fn handle_user_access(
+    transaction: Transaction,
+    user_id: u16,
+    state: Arc<ServerState>,
+) -> Result<Option<Transaction>> {
+    let access = state.user_access(user_id);
+    let preset = access.preset_name().unwrap_or("custom");
+
+    tracing::debug!("User {} asked for its access: 0x{:016X} ({})", user_id, access.bits(), preset);
+
+    Ok(Some(create_success_reply(
+        &transaction,
+        vec![Field::from_access(access), Field::string(FieldId::Data, preset)],
+    )))
+}
+
 /// Build the formatted user info text
 async fn build_user_info_text(
     state: &ServerState,
@@ -148,15 +261,9 @@ async fn build_user_info_text(
         format!("{} day {} hr {} min {} sec", days, hours, minutes, seconds)
     };
 
-    // Format connection time
-    let connected_datetime: DateTime<Utc> = session.connected_at.into();
-    let connected_str = connected_datetime
-        .format("%Y-%m-%d %H:%M:%S UTC")
-        .to_string();
-
     // Get account information if not a guest
     let (account_name, account_login) = if let Some(account_id) = session.account_id {
-        match get_account_by_id(state.database.pool(), account_id).await? {
+        match state.accounts.get_account_by_id(account_id).await? {
             Some(account) => (account.name.clone(), account.login.clone()),
             None => ("Unknown".to_string(), "Unknown".to_string()),
         }
@@ -189,3 +296,93 @@ async fn build_user_info_text(
 
     Ok(info_text)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::Session;
+    use crate::state::Broadcast;
+    use crate::test_util::test_state;
+    use rhxcore::protocol::TransactionType;
+
+    /// Server state with Alice logged in as user 4
+    async fn alice_state() -> Arc<ServerState> {
+        let state = test_state(|_| {}).await;
+
+        let mut session = Session::new(4, "127.0.0.1:5500".parse().unwrap());
+        session.authenticate_guest("Alice".to_string(), 128);
+        session.agree();
+        state.register_session(session);
+        state
+    }
+
+    fn set_client_user_info(fields: Vec<Field>) -> Transaction {
+        let mut transaction = Transaction::new(TransactionType::SetClientUserInfo);
+        for field in fields {
+            transaction.add_field(field);
+        }
+        transaction
+    }
+
+    #[tokio::test]
+    async fn test_icon_only_keeps_nickname() {
+        let state = alice_state().await;
+        let mut tap = state.subscribe_raw();
+
+        let transaction = set_client_user_info(vec![Field::integer(FieldId::UserIconId, 200)]);
+        assert!(handle_set_client_user_info(transaction, 4, state.clone()).await.unwrap().is_none());
+
+        let sent = tap.drain();
+        assert!(matches!(sent[..], [Broadcast { message: BroadcastMessage::UserChanged { user_id: 4 }, .. }]));
+        let user = state.get_session(4).unwrap().to_user();
+        assert_eq!(user.name, "Alice");
+        assert_eq!(user.icon_id, 200);
+    }
+
+    #[tokio::test]
+    async fn test_nickname_only_keeps_icon() {
+        let state = alice_state().await;
+        let mut tap = state.subscribe_raw();
+
+        let transaction = set_client_user_info(vec![Field::string(FieldId::UserName, "Alicia")]);
+        assert!(handle_set_client_user_info(transaction, 4, state.clone()).await.unwrap().is_none());
+
+        let sent = tap.drain();
+        assert!(matches!(sent[..], [Broadcast { message: BroadcastMessage::UserChanged { user_id: 4 }, .. }]));
+        let user = state.get_session(4).unwrap().to_user();
+        assert_eq!(user.name, "Alicia");
+        assert_eq!(user.icon_id, 128);
+    }
+
+    #[tokio::test]
+    async fn test_user_access_reflects_changes() {
+        let state = alice_state().await;
+
+        let account_id = state.accounts
+            .create_account("bot", b"pw", "Bot", AccessPrivileges::user())
+            .await
+            .unwrap();
+        let mut session = Session::new(5, "127.0.0.1:5501".parse().unwrap());
+        session.authenticate_user(account_id, AccessPrivileges::user(), "Bot".to_string(), 0);
+        session.agree();
+        state.register_session(session);
+
+        let whoami = || Transaction::new(TransactionType::UserAccess);
+        let access = |reply: &Transaction| reply.get_field(FieldId::UserAccess).and_then(|f| f.access_privileges());
+        let preset = |reply: &Transaction| reply.get_field(FieldId::Data).and_then(|f| f.as_string()).map(str::to_string);
+
+        let reply = handle_user_access(whoami(), 5, state.clone()).await.unwrap().unwrap();
+        assert_eq!(access(&reply), Some(AccessPrivileges::user()));
+        assert_eq!(preset(&reply).as_deref(), Some("user"));
+
+        // An admin changes the account while the bot is connected
+        let changed = AccessPrivileges::user() | AccessPrivileges::BROADCAST;
+        state.accounts.update_access(account_id, changed).await.unwrap();
+        state.notify_access_changed(account_id, changed);
+
+        let reply = handle_user_access(whoami(), 5, state.clone()).await.unwrap().unwrap();
+        assert_eq!(reply.error_code, 0);
+        assert_eq!(access(&reply), Some(changed));
+        assert_eq!(preset(&reply).as_deref(), Some("custom"));
+    }
+}
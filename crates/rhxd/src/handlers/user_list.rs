@@ -18,6 +18,9 @@ use std::sync::Arc;
 /// - flags: u16 (2 bytes, big-endian)
 /// - name_len: u16 (2 bytes, big-endian)
 /// - name: [u8] (variable length)
+///
+/// Only logged-in users are listed, the requester included, sorted by user
+/// ID or by nickname as `features.user_list_order` says.
 pub async fn handle_get_user_name_list(
     transaction: Transaction,
     user_id: u16,
@@ -33,26 +36,10 @@ pub async fn handle_get_user_name_list(
     }
     
     // Build list of all authenticated users
-    let mut user_fields = Vec::new();
-    
-    for entry in state.sessions.iter() {
-        let session = entry.value();
-        
-        // Only include authenticated users
-        if !session.is_authenticated() {
-            continue;
-        }
-        
-        // Build UserNameWithInfo field
-        let mut user_info = Vec::new();
-        user_info.extend_from_slice(&session.user_id.to_be_bytes());
-        user_info.extend_from_slice(&session.icon_id.to_be_bytes());
-        user_info.extend_from_slice(&session.flags.to_be_bytes());
-        user_info.extend_from_slice(&(session.nickname.len() as u16).to_be_bytes());
-        user_info.extend_from_slice(session.nickname.as_bytes());
-        
-        user_fields.push(Field::binary(FieldId::UserNameWithInfo, user_info));
-    }
+    let user_fields: Vec<Field> = state.user_list()
+        .iter()
+        .map(|user| Field::binary(FieldId::UserNameWithInfo, user.to_name_with_info()))
+        .collect();
     
     tracing::info!(
         "User {} requested user list, returning {} users",
@@ -72,3 +59,44 @@ pub async fn handle_get_user_name_list(
         fields: user_fields,
     }))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::UserListOrder;
+    use crate::connection::Session;
+    use crate::test_util::test_state;
+    
+    fn listed_ids(reply: &Transaction) -> Vec<u16> {
+        reply.get_all(FieldId::UserNameWithInfo)
+            .map(|field| {
+                let data = field.as_binary().unwrap();
+                u16::from_be_bytes([data[0], data[1]])
+            })
+            .collect()
+    }
+    
+    #[tokio::test]
+    async fn test_user_list_order() {
+        let state = test_state(|_| {}).await;
+        
+        for (user_id, nickname) in [(9, "alice"), (3, "Carol"), (5, "Bob")] {
+            let mut session = Session::new(user_id, "127.0.0.1:5500".parse().unwrap());
+            session.authenticate_guest(nickname.to_string(), 0);
+            state.register_session(session);
+        }
+        // Still logging in, so not listed
+        state.register_session(Session::new(7, "127.0.0.1:5501".parse().unwrap()));
+        
+        let request = || Transaction::new(TransactionType::GetUserNameList);
+        let reply = handle_get_user_name_list(request(), 3, state.clone()).await.unwrap().unwrap();
+        assert_eq!(listed_ids(&reply), vec![3, 5, 9]);
+        
+        let mut config = (*state.config()).clone();
+        config.features.user_list_order = UserListOrder::Name;
+        state.reload_config(config);
+        
+        let reply = handle_get_user_name_list(request(), 3, state.clone()).await.unwrap().unwrap();
+        assert_eq!(listed_ids(&reply), vec![9, 5, 3]);
+    }
+}
@@ -0,0 +1,237 @@
+//! HTXF file transfer listener
+//!
+//! Clients collect transfers accepted on the main connection by connecting
+//! to the port after it (`server.port + 1`) and sending:
+//!
+//! - protocol: [u8; 4] (`HTXF`)
+//! - reference: u32 (field 107 of the reply that accepted the transfer)
+//! - data_size: u32 (bytes the client is about to send, 0 for downloads)
+//! - reserved: u32
+//!
+//! What follows depends on the transfer (see `transfers`). A queued transfer
+//! holds its connection until a slot frees up. Every transfer gives its slot
+//! back when it ends, whether it finished, failed or stalled for longer than
+//! `files.transfer_timeout_seconds`.
+
+use crate::state::ServerState;
+use crate::transfers::{PendingTransfer, TransferId, TransferStatus};
+use anyhow::{bail, Context, Result};
+use rhxcore::protocol::HTXF_MAGIC;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tokio::time::{Instant, Sleep};
+
+/// How often a queued transfer checks whether its slot came up
+const QUEUE_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Bind the transfer listener and serve it in a background task
+///
+/// Returns `None` unless `features.enable_file_transfers` is set.
+pub async fn spawn(state: Arc<ServerState>) -> Result<Option<JoinHandle<()>>> {
+    let config = state.config();
+    if !config.features.enable_file_transfers {
+        return Ok(None);
+    }
+
+    let port = config.server.port.checked_add(1).context("No port left for file transfers")?;
+    let addr = format!("{}:{}", config.server.address, port);
+    let listener = TcpListener::bind(&addr)
+        .await
+        .context(format!("Failed to bind file transfers to {}", addr))?;
+
+    tracing::info!("File transfers listening on {}", addr);
+
+    Ok(Some(tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, addr)) => {
+                    let state = state.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = handle_transfer(stream, state).await {
+                            tracing::warn!("File transfer from {} failed: {:#}", addr, e);
+                        }
+                    });
+                }
+                Err(e) => {
+                    tracing::error!("Failed to accept transfer connection: {}", e);
+                }
+            }
+        }
+    })))
+}
+
+/// Serve one transfer connection
+async fn handle_transfer(stream: TcpStream, state: Arc<ServerState>) -> Result<()> {
+    let timeout = Duration::from_secs(state.config().files.transfer_timeout_seconds);
+    let mut stream = IdleTimeout::new(stream, timeout);
+
+    let mut header = [0u8; 16];
+    stream.read_exact(&mut header).await.context("Failed to read transfer header")?;
+    if header[0..4] != HTXF_MAGIC {
+        bail!("Not an HTXF transfer");
+    }
+    let reference = u32::from_be_bytes(header[4..8].try_into().unwrap());
+
+    let transfer = state
+        .take_pending_transfer(reference)
+        .with_context(|| format!("Unknown transfer reference {}", reference))?;
+
+    let result = match wait_for_slot(&state, reference).await {
+        Ok(()) => run_transfer(&state, transfer, &mut stream, reference).await,
+        Err(e) => Err(e),
+    };
+
+    // Finished, failed or cancelled, the slot goes to the next in line
+    let started = state.transfers.release(reference, (&state.config().files).into());
+    if !started.is_empty() {
+        tracing::debug!("Transfers {:?} may start", started);
+    }
+    result
+}
+
+/// Hold a queued transfer until it may start
+async fn wait_for_slot(state: &ServerState, reference: TransferId) -> Result<()> {
+    loop {
+        match state.transfers.status(reference) {
+            Some(TransferStatus::Active) => return Ok(()),
+            Some(TransferStatus::Queued { .. }) => tokio::time::sleep(QUEUE_POLL_INTERVAL).await,
+            None => bail!("Transfer {} was cancelled while queued", reference),
+        }
+    }
+}
+
+async fn run_transfer<S>(
+    state: &ServerState,
+    transfer: PendingTransfer,
+    stream: &mut S,
+    reference: TransferId,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    match transfer {
+        PendingTransfer::Download(download) => {
+            let sent = download.stream_to(stream).await?;
+            tracing::info!(
+                "User {} downloaded {} ({} bytes, reference {})",
+                download.user_id,
+                download.physical_path.display(),
+                sent,
+                reference
+            );
+        }
+        PendingTransfer::Upload(upload) => {
+            let received = upload.receive_from(stream).await?;
+            tracing::info!(
+                "User {} uploaded {} ({} bytes, reference {})",
+                upload.user_id,
+                upload.physical_path.display(),
+                received,
+                reference
+            );
+        }
+        PendingTransfer::FolderDownload(download) => {
+            let sent = download.send_to(stream).await?;
+            tracing::info!(
+                "User {} downloaded a folder ({} files, {} bytes, reference {})",
+                download.user_id,
+                download.file_count(),
+                sent,
+                reference
+            );
+        }
+        PendingTransfer::FolderUpload(upload) => {
+            let received = upload.receive_from(stream, &state.config().files).await?;
+            tracing::info!(
+                "User {} uploaded folder {} ({} bytes, reference {})",
+                upload.user_id,
+                upload.physical_path.display(),
+                received,
+                reference
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Stream that fails with `TimedOut` once it has made no progress for
+/// `timeout`
+struct IdleTimeout<S> {
+    inner: S,
+    timeout: Duration,
+    deadline: Pin<Box<Sleep>>,
+}
+
+impl<S> IdleTimeout<S> {
+    fn new(inner: S, timeout: Duration) -> Self {
+        Self {
+            inner,
+            timeout,
+            deadline: Box::pin(tokio::time::sleep(timeout)),
+        }
+    }
+
+    /// Record progress, or check the deadline while there is none
+    fn check<T>(&mut self, cx: &mut TaskContext<'_>, poll: Poll<io::Result<T>>) -> Poll<io::Result<T>> {
+        if poll.is_ready() {
+            let deadline = Instant::now() + self.timeout;
+            self.deadline.as_mut().reset(deadline);
+            return poll;
+        }
+        match self.deadline.as_mut().poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(io::Error::new(io::ErrorKind::TimedOut, "transfer stalled"))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for IdleTimeout<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+        this.check(cx, poll)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for IdleTimeout<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let poll = Pin::new(&mut this.inner).poll_write(cx, buf);
+        this.check(cx, poll)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        let poll = Pin::new(&mut this.inner).poll_flush(cx);
+        this.check(cx, poll)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        let poll = Pin::new(&mut this.inner).poll_shutdown(cx);
+        this.check(cx, poll)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stalled_stream_times_out() {
+        let (client, server) = tokio::io::duplex(64);
+        let mut server = IdleTimeout::new(server, Duration::from_secs(5));
+
+        let mut byte = [0u8; 1];
+        let error = server.read_exact(&mut byte).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        drop(client);
+    }
+}
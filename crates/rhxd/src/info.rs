@@ -0,0 +1,93 @@
+//! Server information summary
+//!
+//! Gathered from the configuration and database for `rhxd info` and the
+//! admin HTTP API.
+
+use crate::db::accounts::count_accounts;
+use crate::db::files::count_files;
+use crate::db::Database;
+use crate::Config;
+use anyhow::Result;
+use serde::Serialize;
+use std::path::PathBuf;
+
+/// Snapshot of a server's configuration and database contents
+#[derive(Debug, Clone, Serialize)]
+pub struct ServerInfo {
+    pub name: String,
+    pub description: String,
+    /// Address and port the server listens on
+    pub bind_address: String,
+    pub max_connections: usize,
+    pub files_root: PathBuf,
+    pub database_path: PathBuf,
+    pub schema_version: String,
+    pub account_count: i64,
+    /// Files in the file index (folders excluded)
+    pub file_count: i64,
+    pub features: FeatureFlags,
+}
+
+/// Optional features and whether they are enabled
+#[derive(Debug, Clone, Serialize)]
+pub struct FeatureFlags {
+    pub news: bool,
+    pub private_chat: bool,
+    pub file_transfers: bool,
+    pub uploads: bool,
+    pub downloads: bool,
+    pub auto_away: bool,
+}
+
+/// Gather server information from `config` and the open database
+pub async fn collect_server_info(config: &Config, db: &Database) -> Result<ServerInfo> {
+    Ok(ServerInfo {
+        name: config.server.name.clone(),
+        description: config.server.description.clone(),
+        bind_address: format!("{}:{}", config.server.address, config.server.port),
+        max_connections: config.server.max_connections,
+        files_root: config.files.root_path.clone(),
+        database_path: config.database.path.clone(),
+        schema_version: db.schema_version().await?,
+        account_count: count_accounts(db.pool()).await?,
+        file_count: count_files(db.pool()).await?,
+        features: FeatureFlags {
+            news: config.features.enable_news,
+            private_chat: config.features.enable_private_chat,
+            file_transfers: config.features.enable_file_transfers,
+            uploads: config.files.enable_uploads,
+            downloads: config.files.enable_downloads,
+            auto_away: config.features.auto_away_seconds.is_some(),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::accounts::create_account;
+    use crate::test_util::test_db_path;
+    use rhxcore::types::AccessPrivileges;
+
+    #[tokio::test]
+    async fn test_collect_server_info_counts_accounts() {
+        let path = test_db_path("info_accounts");
+        let db = Database::new(&path).await.unwrap();
+        db.init_schema().await.unwrap();
+
+        for login in ["alice", "bob", "carol"] {
+            create_account(db.pool(), login, b"pw", login, AccessPrivileges::user())
+                .await
+                .unwrap();
+        }
+
+        let mut config = Config::default();
+        config.database.path = path.to_path_buf();
+
+        let info = collect_server_info(&config, &db).await.unwrap();
+        assert_eq!(info.account_count, 3);
+        assert_eq!(info.file_count, 0);
+        assert_eq!(info.database_path, path.to_path_buf());
+        assert!(!info.schema_version.is_empty());
+    }
+}
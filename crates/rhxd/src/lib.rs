@@ -1,11 +1,26 @@
 //! rhxd library interface
 
+pub mod admin_http;
+pub mod audit;
+pub mod chat_log;
+pub mod chat_rooms;
+pub mod cli;
 pub mod config;
+pub mod console;
 pub mod server;
 pub mod state;
 pub mod connection;
 pub mod handlers;
 pub mod db;
+pub mod files;
+pub mod htxf;
+pub mod info;
+pub mod lockout;
+pub mod metrics;
+pub mod tracker;
+pub mod transfers;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
 
 pub use config::Config;
 pub use server::Server;
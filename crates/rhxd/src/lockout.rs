@@ -0,0 +1,217 @@
+//! Failed login tracking
+//!
+//! Counts failed logins per login name and client IP together. Once a pair
+//! reaches `security.max_failed_logins`, further attempts for that login
+//! from that IP are refused until `security.lockout_seconds` have passed,
+//! whatever the credentials. Failing from one address never locks the
+//! account's owner out elsewhere. A successful login clears the pair's
+//! counter, and counters quiet for a whole lockout window are pruned.
+//!
+//! Counters live in memory only and are lost on restart.
+
+use crate::config::SecurityConfig;
+use dashmap::DashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// When to lock out and for how long (`max_failures` 0 disables lockout)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockoutPolicy {
+    pub max_failures: u32,
+    pub duration: Duration,
+}
+
+impl From<&SecurityConfig> for LockoutPolicy {
+    fn from(config: &SecurityConfig) -> Self {
+        Self {
+            max_failures: config.max_failed_logins,
+            duration: Duration::from_secs(config.lockout_seconds),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Failures {
+    count: u32,
+    last_failure: Instant,
+    locked_until: Option<Instant>,
+}
+
+impl Failures {
+    fn is_locked(&self, now: Instant) -> bool {
+        self.locked_until.is_some_and(|until| now < until)
+    }
+    
+    /// Whether this counter no longer affects anything: its lockout has run
+    /// out, or it never locked and has been quiet for a whole window
+    fn is_stale(&self, policy: LockoutPolicy, now: Instant) -> bool {
+        match self.locked_until {
+            Some(until) => now >= until,
+            None => now.saturating_duration_since(self.last_failure) >= policy.duration,
+        }
+    }
+}
+
+/// Failed login counters keyed by login and IP
+#[derive(Debug, Default)]
+pub struct LoginLockout {
+    failures: DashMap<(String, IpAddr), Failures>,
+}
+
+impl LoginLockout {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Whether attempts for `login` from `ip` are currently refused
+    pub fn is_locked(&self, login: &str, ip: IpAddr, now: Instant) -> bool {
+        self.failures
+            .get(&key(login, ip))
+            .is_some_and(|failures| failures.is_locked(now))
+    }
+    
+    /// Count a failed attempt, returning whether it triggered a lockout
+    pub fn record_failure(
+        &self,
+        login: &str,
+        ip: IpAddr,
+        policy: LockoutPolicy,
+        now: Instant,
+    ) -> bool {
+        if policy.max_failures == 0 {
+            return false;
+        }
+        
+        let fresh = Failures { count: 0, last_failure: now, locked_until: None };
+        let mut failures = self.failures.entry(key(login, ip)).or_insert(fresh);
+        
+        // A lockout that has run out, or a quiet window, starts a fresh count
+        if failures.is_stale(policy, now) {
+            *failures = fresh;
+        }
+        
+        failures.count += 1;
+        failures.last_failure = now;
+        if failures.count >= policy.max_failures && failures.locked_until.is_none() {
+            failures.locked_until = Some(now + policy.duration);
+            return true;
+        }
+        false
+    }
+    
+    /// Forget the failures for `login` from `ip` after a successful login
+    pub fn record_success(&self, login: &str, ip: IpAddr) {
+        self.failures.remove(&key(login, ip));
+    }
+    
+    /// Drop counters that no longer affect anything, returning how many
+    pub fn prune(&self, policy: LockoutPolicy, now: Instant) -> usize {
+        let before = self.failures.len();
+        self.failures.retain(|_, failures| !failures.is_stale(policy, now));
+        before - self.failures.len()
+    }
+    
+    /// Number of login/IP pairs with a counter
+    pub fn len(&self) -> usize {
+        self.failures.len()
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// Logins are case-insensitive, so their counters are too
+fn key(login: &str, ip: IpAddr) -> (String, IpAddr) {
+    (login.to_lowercase(), ip)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const POLICY: LockoutPolicy = LockoutPolicy {
+        max_failures: 3,
+        duration: Duration::from_secs(60),
+    };
+    
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::from([192, 0, 2, last])
+    }
+    
+    #[test]
+    fn test_lockout_expires_after_window() {
+        let lockout = LoginLockout::new();
+        let start = Instant::now();
+        
+        assert!(!lockout.record_failure("Alice", ip(1), POLICY, start));
+        assert!(!lockout.record_failure("alice", ip(1), POLICY, start));
+        assert!(!lockout.is_locked("alice", ip(1), start));
+        
+        // The third failure locks the login from that address only
+        assert!(lockout.record_failure("ALICE", ip(1), POLICY, start));
+        assert!(lockout.is_locked("alice", ip(1), start));
+        assert!(!lockout.is_locked("alice", ip(2), start));
+        assert!(!lockout.is_locked("bob", ip(1), start));
+        
+        let later = start + POLICY.duration;
+        assert!(!lockout.is_locked("alice", ip(1), later));
+        
+        // The count starts over once the window has passed
+        assert!(!lockout.record_failure("alice", ip(1), POLICY, later));
+    }
+    
+    #[test]
+    fn test_failures_elsewhere_dont_lock_owner_out() {
+        let lockout = LoginLockout::new();
+        let now = Instant::now();
+        
+        for last in 1..=10 {
+            lockout.record_failure("alice", ip(last), POLICY, now);
+        }
+        assert!(!lockout.is_locked("alice", ip(100), now));
+    }
+    
+    #[test]
+    fn test_reset_and_disabled() {
+        let lockout = LoginLockout::new();
+        let now = Instant::now();
+        
+        for _ in 0..3 {
+            lockout.record_failure("d", ip(1), POLICY, now);
+        }
+        assert!(lockout.is_locked("d", ip(1), now));
+        
+        lockout.record_success("d", ip(1));
+        assert!(!lockout.is_locked("d", ip(1), now));
+        
+        let disabled = LockoutPolicy { max_failures: 0, ..POLICY };
+        for _ in 0..10 {
+            assert!(!lockout.record_failure("e", ip(2), disabled, now));
+        }
+        assert!(!lockout.is_locked("e", ip(2), now));
+    }
+    
+    #[test]
+    fn test_prune_drops_stale_counters() {
+        let lockout = LoginLockout::new();
+        let start = Instant::now();
+        
+        for _ in 0..3 {
+            lockout.record_failure("locked", ip(1), POLICY, start);
+        }
+        lockout.record_failure("once", ip(2), POLICY, start);
+        
+        let half = start + POLICY.duration / 2;
+        lockout.record_failure("recent", ip(3), POLICY, half);
+        assert_eq!(lockout.prune(POLICY, half), 0);
+        
+        // Both the expired lockout and the quiet counter go
+        let later = start + POLICY.duration;
+        assert_eq!(lockout.prune(POLICY, later), 2);
+        assert_eq!(lockout.len(), 1);
+        
+        assert_eq!(lockout.prune(POLICY, half + POLICY.duration), 1);
+        assert!(lockout.is_empty());
+    }
+}
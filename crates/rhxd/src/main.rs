@@ -2,19 +2,7 @@
 
 use anyhow::Result;
 use clap::{Parser, Subcommand};
-
-mod cli;
-mod config;
-mod console;
-mod server;
-mod state;
-mod connection;
-mod handlers;
-mod db;
-
-pub use config::Config;
-pub use server::Server;
-pub use state::ServerState;
+use rhxd::cli;
 
 #[derive(Parser)]
 #[command(name = "rhxd")]
@@ -24,7 +12,7 @@ struct Cli {
     /// Path to configuration file
     #[arg(short, long, default_value = "rhxd.json")]
     config: String,
-
+    
     #[command(subcommand)]
     command: Commands,
 }
@@ -36,10 +24,22 @@ enum Commands {
         /// Skip interactive prompts
         #[arg(long)]
         non_interactive: bool,
+        
+        /// Verify an existing setup instead of creating one
+        #[arg(long)]
+        check: bool,
     },
     
     /// Run the Hotline server
-    Serve,
+    Serve {
+        /// Fall back to the default configuration if the config file is missing
+        #[arg(long)]
+        use_defaults: bool,
+        
+        /// Run without the interactive console (implied when stdin is not a terminal)
+        #[arg(long, visible_alias = "foreground")]
+        no_console: bool,
+    },
     
     /// Account management
     Account {
@@ -72,11 +72,11 @@ async fn main() -> Result<()> {
     let cli = Cli::parse();
     
     match cli.command {
-        Commands::Init { non_interactive } => {
-            cli::init::run(&cli.config, non_interactive).await
+        Commands::Init { non_interactive, check } => {
+            cli::init::run(&cli.config, non_interactive, check).await
         }
-        Commands::Serve => {
-            cli::serve::run(&cli.config).await
+        Commands::Serve { use_defaults, no_console } => {
+            cli::serve::run(&cli.config, use_defaults, no_console).await
         }
         Commands::Account { command } => {
             cli::account::run(&cli.config, command).await
@@ -90,6 +90,7 @@ async fn main() -> Result<()> {
         Commands::Version => {
             println!("rhxd version {}", env!("CARGO_PKG_VERSION"));
             println!("Protocol version: {}", rhxcore::protocol::PROTOCOL_VERSION);
+            println!("Server version: {}", rhxcore::protocol::SERVER_VERSION);
             Ok(())
         }
     }
@@ -0,0 +1,119 @@
+//! Handler latency metrics
+//!
+//! Times every dispatched transaction into a per-type histogram with a few
+//! coarse buckets, cheap enough to leave on. The admin HTTP API serves them
+//! in the Prometheus text format as `rhxd_handler_seconds`.
+
+use dashmap::DashMap;
+use rhxcore::protocol::TransactionType;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Upper bounds of the histogram buckets, in seconds
+pub const BUCKETS: [f64; 6] = [0.001, 0.005, 0.025, 0.1, 0.5, 2.5];
+
+/// Latency histogram for one transaction type
+#[derive(Debug, Default)]
+pub struct Histogram {
+    /// Observations per bucket (not cumulative; the last counts anything slower)
+    buckets: [AtomicU64; BUCKETS.len() + 1],
+    count: AtomicU64,
+    sum_nanos: AtomicU64,
+}
+
+impl Histogram {
+    /// Record one handler run
+    pub fn observe(&self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        let bucket = BUCKETS.iter().position(|&bound| seconds <= bound).unwrap_or(BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos.fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Number of observations
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Total time observed
+    pub fn sum(&self) -> Duration {
+        Duration::from_nanos(self.sum_nanos.load(Ordering::Relaxed))
+    }
+}
+
+/// Handler latency histograms keyed by transaction type
+#[derive(Debug, Default)]
+pub struct HandlerMetrics {
+    handlers: DashMap<TransactionType, Histogram>,
+}
+
+impl HandlerMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record how long a transaction took to handle
+    pub fn observe(&self, transaction_type: TransactionType, elapsed: Duration) {
+        self.handlers.entry(transaction_type).or_default().observe(elapsed);
+    }
+
+    /// Observation count and total time for a transaction type
+    pub fn summary(&self, transaction_type: TransactionType) -> (u64, Duration) {
+        self.handlers
+            .get(&transaction_type)
+            .map(|histogram| (histogram.count(), histogram.sum()))
+            .unwrap_or_default()
+    }
+
+    /// Render every histogram in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut handlers: Vec<_> = self
+            .handlers
+            .iter()
+            .map(|entry| (format!("{:?}", entry.key()), *entry.key()))
+            .collect();
+        handlers.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut out = String::new();
+        out.push_str("# HELP rhxd_handler_seconds Time spent handling each transaction type\n");
+        out.push_str("# TYPE rhxd_handler_seconds histogram\n");
+        for (name, transaction_type) in handlers {
+            let Some(histogram) = self.handlers.get(&transaction_type) else {
+                continue;
+            };
+
+            let mut cumulative = 0;
+            for (bound, bucket) in BUCKETS.iter().zip(&histogram.buckets) {
+                cumulative += bucket.load(Ordering::Relaxed);
+                let _ = writeln!(out, "rhxd_handler_seconds_bucket{{type=\"{}\",le=\"{}\"}} {}", name, bound, cumulative);
+            }
+            let count = histogram.count();
+            let _ = writeln!(out, "rhxd_handler_seconds_bucket{{type=\"{}\",le=\"+Inf\"}} {}", name, count);
+            let _ = writeln!(out, "rhxd_handler_seconds_sum{{type=\"{}\"}} {}", name, histogram.sum().as_secs_f64());
+            let _ = writeln!(out, "rhxd_handler_seconds_count{{type=\"{}\"}} {}", name, count);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_cumulative_buckets() {
+        let metrics = HandlerMetrics::new();
+        metrics.observe(TransactionType::Login, Duration::from_micros(500));
+        metrics.observe(TransactionType::Login, Duration::from_millis(20));
+        metrics.observe(TransactionType::Login, Duration::from_secs(5));
+
+        let text = metrics.render();
+        assert!(text.contains("rhxd_handler_seconds_bucket{type=\"Login\",le=\"0.001\"} 1\n"));
+        assert!(text.contains("rhxd_handler_seconds_bucket{type=\"Login\",le=\"0.025\"} 2\n"));
+        assert!(text.contains("rhxd_handler_seconds_bucket{type=\"Login\",le=\"2.5\"} 2\n"));
+        assert!(text.contains("rhxd_handler_seconds_bucket{type=\"Login\",le=\"+Inf\"} 3\n"));
+        assert!(text.contains("rhxd_handler_seconds_count{type=\"Login\"} 3\n"));
+    }
+}
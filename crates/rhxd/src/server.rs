@@ -1,13 +1,39 @@
 //! Server implementation
 
+use crate::admin_http;
+use crate::console;
+use crate::htxf;
+use crate::lockout::LockoutPolicy;
+use crate::tracker;
 use crate::connection::handler::handle_connection;
 use crate::state::BroadcastMessage;
+use crate::config::ServerConfig;
 use crate::{Config, ServerState};
 use anyhow::{Context, Result};
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
+use std::future::Future;
+use std::net::{SocketAddr, ToSocketAddrs};
 use std::sync::Arc;
-use tokio::net::TcpListener;
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::Notify;
 
+/// How often idle sessions are checked for auto-away
+const IDLE_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often allocated user IDs are checked against the session map
+const USER_ID_AUDIT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often expired failed-login counters are dropped
+const LOCKOUT_PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long connections get to close after the shutdown notice
+const SHUTDOWN_DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Flush interval while user list batching is off, in case it was just
+/// turned off with changes still buffered
+const DEFAULT_USER_LIST_BATCH_MS: u64 = 100;
+
 pub struct Server {
     state: Arc<ServerState>,
     shutdown: Arc<Notify>,
@@ -33,29 +59,115 @@ impl Server {
         self.shutdown.clone()
     }
     
-    /// Run the server main loop
+    /// Run the server main loop until Ctrl-C or SIGTERM, or the shutdown
+    /// handle is notified
     pub async fn run(self) -> Result<()> {
-        let addr = format!(
-            "{}:{}",
-            self.state.config.server.address,
-            self.state.config.server.port
-        );
+        self.run_until(wait_for_shutdown_signal()).await
+    }
+    
+    /// Run the server main loop, shutting down gracefully once `signal`
+    /// completes or the shutdown handle is notified
+    pub async fn run_until<F>(self, signal: F) -> Result<()>
+    where
+        F: Future<Output = std::io::Result<()>> + Send + 'static,
+    {
+        let config = self.state.config();
+        let addr = format!("{}:{}", config.server.address, config.server.port);
         
         // Bind TCP listener
-        let listener = TcpListener::bind(&addr)
-            .await
+        let listener = bind_listener(&config.server)
             .context(format!("Failed to bind to {}", addr))?;
         
         tracing::info!(
             "Server '{}' listening on {}",
-            self.state.config.server.name,
+            config.server.name,
             addr
         );
         
+        // Start the admin HTTP API if configured
+        let admin_http = if config.admin_http.enabled {
+            Some(admin_http::spawn(self.state.clone()).await?)
+        } else {
+            None
+        };
+        
+        // Accept console commands on a Unix socket if configured
+        #[cfg(unix)]
+        let console_socket = console::spawn_socket(self.state.clone(), self.shutdown.clone()).await?;
+        #[cfg(not(unix))]
+        if config.console.socket_path.is_some() {
+            tracing::warn!("console.socket_path is only supported on Unix; ignoring it");
+        }
+        
+        // Serve file transfers on the next port (no-op unless
+        // features.enable_file_transfers is set)
+        let transfer_listener = htxf::spawn(self.state.clone()).await?;
+        
+        // Announce to trackers (no-op unless tracker.trackers is set)
+        let tracker_announce = tracker::spawn(self.state.clone()).await?;
+        
+        // Mark idle users away (no-op unless features.auto_away_seconds is set)
+        let sweep_state = self.state.clone();
+        let idle_sweep = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(IDLE_SWEEP_INTERVAL);
+            loop {
+                interval.tick().await;
+                sweep_state.sweep_idle();
+            }
+        });
+        
+        // Log user ID slots that leaked (debug level only)
+        let audit_state = self.state.clone();
+        let user_id_audit = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(USER_ID_AUDIT_INTERVAL);
+            loop {
+                interval.tick().await;
+                audit_state.audit_user_ids();
+            }
+        });
+        
+        // Forget failed logins that no longer lock anything out
+        let prune_state = self.state.clone();
+        let lockout_prune = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(LOCKOUT_PRUNE_INTERVAL);
+            loop {
+                interval.tick().await;
+                let policy = LockoutPolicy::from(&prune_state.config().security);
+                prune_state.lockout.prune(policy, Instant::now());
+            }
+        });
+        
+        // Send batched user list changes (no-op unless features.user_list_batch_ms is set)
+        let flush_state = self.state.clone();
+        let user_list_flush = tokio::spawn(async move {
+            loop {
+                let window = flush_state.config().features.user_list_batch_ms;
+                let window = window.unwrap_or(DEFAULT_USER_LIST_BATCH_MS).max(1);
+                tokio::time::sleep(Duration::from_millis(window)).await;
+                flush_state.flush_user_list();
+            }
+        });
+        
+        // Keep the write-ahead log from growing without bound
+        let checkpoint_interval = config.database.checkpoint_interval_seconds;
+        let wal_checkpoint = (checkpoint_interval > 0).then(|| {
+            let database = self.state.database.clone();
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(Duration::from_secs(checkpoint_interval));
+                interval.tick().await;
+                loop {
+                    interval.tick().await;
+                    if let Err(e) = database.checkpoint().await {
+                        tracing::warn!("WAL checkpoint failed: {}", e);
+                    }
+                }
+            })
+        });
+        
         // Spawn signal handler for graceful shutdown
         let shutdown = self.shutdown.clone();
         tokio::spawn(async move {
-            if let Err(e) = tokio::signal::ctrl_c().await {
+            if let Err(e) = signal.await {
                 tracing::error!("Failed to listen for shutdown signal: {}", e);
             } else {
                 tracing::info!("Received shutdown signal");
@@ -77,12 +189,16 @@ impl Server {
                     match result {
                         Ok((stream, addr)) => {
                             // Check connection limit
-                            if self.state.session_count() >= self.state.config.server.max_connections {
+                            if !self.state.accepts_connection() {
                                 tracing::warn!("Connection limit reached, rejecting connection from {}", addr);
                                 drop(stream);
                                 continue;
                             }
                             
+                            if let Err(e) = configure_stream(&stream, &self.state.config().server) {
+                                tracing::warn!("Failed to set socket options for {}: {}", addr, e);
+                            }
+                            
                             let state = self.state.clone();
                             
                             // Spawn connection handler
@@ -100,11 +216,50 @@ impl Server {
             }
         }
         
+        idle_sweep.abort();
+        user_id_audit.abort();
+        lockout_prune.abort();
+        user_list_flush.abort();
+        
+        // Stop accepting admin requests
+        if let Some(handle) = admin_http {
+            handle.abort();
+        }
+        #[cfg(unix)]
+        if let Some(handle) = console_socket {
+            handle.abort();
+            if let Some(path) = &self.state.config().console.socket_path {
+                std::fs::remove_file(path).ok();
+            }
+        }
+        if let Some(handle) = transfer_listener {
+            handle.abort();
+        }
+        if let Some(handle) = tracker_announce {
+            handle.abort();
+        }
+        if let Some(handle) = wal_checkpoint {
+            handle.abort();
+        }
+        
         // Broadcast shutdown message to all clients
-        self.state.broadcast(BroadcastMessage::ServerShutdown);
+        self.state.broadcast(BroadcastMessage::ServerShutdown {
+            reason: self.state.config().server.shutdown_message.clone(),
+        });
         
         // Give clients a moment to disconnect gracefully
-        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+        let drained = tokio::time::timeout(SHUTDOWN_DRAIN_TIMEOUT, async {
+            while self.state.session_count() > 0 {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .is_ok();
+        if !drained {
+            tracing::warn!("{} sessions still open at shutdown", self.state.session_count());
+        }
+        
+        self.state.close().await;
         
         tracing::info!(
             "Server shutdown complete ({} active sessions)",
@@ -114,3 +269,136 @@ impl Server {
         Ok(())
     }
 }
+
+/// Bind the client listener with the configured backlog
+fn bind_listener(config: &ServerConfig) -> std::io::Result<TcpListener> {
+    let addr: SocketAddr = (config.address.as_str(), config.port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "No address to bind")
+        })?;
+    
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    
+    // Match tokio's TcpListener::bind, which allows quick restarts on Unix
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(config.listen_backlog.min(i32::MAX as u32) as i32)?;
+    
+    TcpListener::from_std(socket.into())
+}
+
+/// Apply nodelay and keepalive settings to an accepted connection
+fn configure_stream(stream: &TcpStream, config: &ServerConfig) -> std::io::Result<()> {
+    stream.set_nodelay(config.tcp_nodelay)?;
+    
+    if let Some(secs) = config.tcp_keepalive_secs {
+        let keepalive = TcpKeepalive::new().with_time(Duration::from_secs(secs));
+        SockRef::from(stream).set_tcp_keepalive(&keepalive)?;
+    }
+    
+    Ok(())
+}
+
+/// Wait for Ctrl-C, or SIGTERM on Unix (sent by Docker, Kubernetes and systemd)
+async fn wait_for_shutdown_signal() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result,
+            _ = terminate.recv() => {
+                tracing::info!("Received SIGTERM");
+                Ok(())
+            }
+        }
+    }
+    
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_config;
+    
+    #[tokio::test]
+    async fn test_accepted_connections_have_nodelay() {
+        let mut config = Config::default().server;
+        config.address = "127.0.0.1".to_string();
+        config.port = 0;
+        config.tcp_keepalive_secs = Some(30);
+        
+        let listener = bind_listener(&config).unwrap();
+        let addr = listener.local_addr().unwrap();
+        
+        let _client = TcpStream::connect(addr).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        configure_stream(&stream, &config).unwrap();
+        
+        assert!(stream.nodelay().unwrap());
+        assert!(SockRef::from(&stream).keepalive().unwrap());
+    }
+    
+    #[tokio::test]
+    async fn test_shutdown_closes_database() {
+        let mut config = test_config();
+        config.server.address = "127.0.0.1".to_string();
+        config.server.port = 0;
+        
+        let server = Server::new(config).await.unwrap();
+        let state = server.state();
+        let shutdown = server.shutdown_handle();
+        let server_handle = tokio::spawn(server.run());
+        
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        state.database.health_check().await.unwrap();
+        shutdown.notify_waiters();
+        
+        tokio::time::timeout(Duration::from_secs(5), server_handle)
+            .await
+            .expect("Server did not shut down")
+            .unwrap()
+            .unwrap();
+        assert!(state.database.pool().is_closed());
+        assert!(state.database.health_check().await.is_err());
+    }
+    
+    #[tokio::test]
+    async fn test_shutdown_signal_triggers_graceful_shutdown() {
+        let mut config = test_config();
+        config.server.address = "127.0.0.1".to_string();
+        config.server.port = 0;
+        
+        // Stands in for SIGTERM, which raised for real would reach every
+        // test in the process
+        let (terminate, signal) = tokio::sync::oneshot::channel::<()>();
+        let server = Server::new(config).await.unwrap();
+        let state = server.state();
+        let server_handle = tokio::spawn(server.run_until(async move {
+            let _ = signal.await;
+            Ok(())
+        }));
+        
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!server_handle.is_finished());
+        
+        terminate.send(()).unwrap();
+        
+        let result = tokio::time::timeout(Duration::from_secs(5), server_handle)
+            .await
+            .expect("Server did not shut down after the signal")
+            .unwrap();
+        assert!(result.is_ok());
+        assert!(state.database.pool().is_closed());
+    }
+}
@@ -1,11 +1,28 @@
 //! Server state management
 
+use crate::audit::{AuditSink, DatabaseAudit, NoAudit};
+use crate::chat_log::ChatLog;
+use crate::chat_rooms::ChatRooms;
+use crate::connection::capture::TransactionCapture;
 use crate::connection::Session;
+use crate::db::store::{AccountStore, FileStore};
 use crate::db::Database;
+use crate::lockout::LoginLockout;
+use crate::metrics::HandlerMetrics;
+use crate::transfers::{
+    PendingDownload, PendingFolderDownload, PendingFolderUpload, PendingTransfer, PendingUpload, TransferId,
+    TransferQueue,
+};
+use crate::config::UserListOrder;
 use crate::Config;
 use anyhow::Result;
+use arc_swap::ArcSwap;
 use dashmap::DashMap;
-use std::sync::atomic::{AtomicU16, Ordering};
+use rhxcore::types::{AccessPrivileges, User, UserFlags};
+use std::collections::{HashSet, VecDeque};
+use std::sync::atomic::{AtomicU16, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 use tokio::sync::broadcast;
 
 /// Message types that can be broadcast to all connected sessions
@@ -13,40 +30,193 @@ use tokio::sync::broadcast;
 pub enum BroadcastMessage {
     /// User joined the server
     UserJoined { user_id: u16, nickname: String },
+    /// User's flags, icon or nickname changed
+    UserChanged { user_id: u16 },
     /// User left the server
     UserLeft { user_id: u16 },
-    /// Server is shutting down
-    ServerShutdown,
+    /// Server is shutting down; clients are told why before being disconnected
+    ServerShutdown { reason: String },
     /// Server message/announcement
     ServerMessage { message: String },
-    /// Chat message to broadcast to all users
+    /// Chat message to broadcast to all users, or only to the members of
+    /// private chat `chat_id`
     /// Field 109: is_emote (false=normal chat, true=emote/action)
-    ChatMessage { sender_id: u16, message: Vec<u8>, is_emote: bool },
+    ChatMessage { sender_id: u16, chat_id: Option<u32>, message: Vec<u8>, is_emote: bool },
+    /// Coalesced user list changes (see `features.user_list_batch_ms`)
+    UserListDelta(UserListDelta),
+    /// Public chat subject changed
+    ChatSubject { subject: String },
+    /// Security event (failed login, lockout, ...) delivered only to
+    /// `recipients`, the admins (users with `DISCONNECT_USERS`) online when
+    /// it was raised
+    AdminAlert { text: String, recipients: Vec<u16> },
+    /// Private message from `sender_id`, delivered to `user_id` only as
+    /// ServerMessage (104)
+    PrivateMessage { sender_id: u16, user_id: u16, message: Vec<u8>, quoting: Option<Vec<u8>> },
+    /// Invitation to private chat `chat_id`, delivered to `user_id` only
+    ChatInvite { chat_id: u32, inviter_id: u16, user_id: u16 },
+    /// `user_id` joined private chat `chat_id`, delivered to the other
+    /// members as NotifyChatChangeUser (117)
+    ChatUserJoined { chat_id: u32, user_id: u16 },
+    /// `user_id` left private chat `chat_id`, delivered to the remaining
+    /// members as NotifyChatDeleteUser (118)
+    ChatUserLeft { chat_id: u32, user_id: u16 },
+    /// Account `account_id` was given new access, delivered as UserAccess
+    /// (354) to the sessions logged in to it
+    AccessChanged { account_id: i64, access: AccessPrivileges },
+}
+
+/// A broadcast as sent on the channel
+#[derive(Debug, Clone)]
+pub struct Broadcast {
+    pub message: BroadcastMessage,
+    /// Session that doesn't receive it (usually the one it is about)
+    pub exclude: Option<u16>,
+}
+
+/// What `features.idle_timeout_seconds` calls for on a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdleAction {
+    /// Warn that the connection will be closed in this many seconds
+    Warn { remaining: u64 },
+    /// Disconnect for inactivity
+    Disconnect,
+}
+
+/// Broadcasts observed by a test, without a client connection
+#[cfg(test)]
+pub struct BroadcastTap(broadcast::Receiver<Broadcast>);
+
+#[cfg(test)]
+impl BroadcastTap {
+    /// Everything broadcast since the tap was opened or last drained
+    pub fn drain(&mut self) -> Vec<Broadcast> {
+        std::iter::from_fn(|| self.0.try_recv().ok()).collect()
+    }
+}
+
+/// User list changes buffered over one batching window
+///
+/// Applying the delta has the same net effect as the individual
+/// join/change/leave events it replaced.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UserListDelta {
+    /// Users who joined or changed, with whether the change was a join
+    pub changed: Vec<(u16, bool)>,
+    /// Users who left
+    pub left: Vec<u16>,
+}
+
+impl UserListDelta {
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.left.is_empty()
+    }
+    
+    /// Fold one membership event into the delta
+    fn record(&mut self, message: &BroadcastMessage) {
+        match *message {
+            BroadcastMessage::UserJoined { user_id, .. } => self.record_change(user_id, true),
+            BroadcastMessage::UserChanged { user_id } => self.record_change(user_id, false),
+            BroadcastMessage::UserLeft { user_id } => {
+                self.changed.retain(|&(id, _)| id != user_id);
+                if !self.left.contains(&user_id) {
+                    self.left.push(user_id);
+                }
+            }
+            _ => {}
+        }
+    }
+    
+    fn record_change(&mut self, user_id: u16, joined: bool) {
+        // A reused user ID rejoining supersedes its earlier leave
+        self.left.retain(|&id| id != user_id);
+        
+        match self.changed.iter_mut().find(|(id, _)| *id == user_id) {
+            Some(entry) => entry.1 |= joined,
+            None => self.changed.push((user_id, joined)),
+        }
+    }
 }
 
 /// Shared server state accessible by all connection handlers
 pub struct ServerState {
-    /// Server configuration
-    pub config: Config,
+    /// Server configuration (swapped atomically on reload)
+    config: ArcSwap<Config>,
     
     /// Database connection pool
     pub database: Database,
     
+    /// Account storage (the database unless replaced)
+    pub accounts: Arc<dyn AccountStore>,
+    
+    /// File metadata storage (the database unless replaced)
+    pub files: Arc<dyn FileStore>,
+    
+    /// Audit trail (the database when `security.audit_log` is set)
+    pub audit: Arc<dyn AuditSink>,
+    
     /// Active sessions indexed by user_id (1-65535)
     pub sessions: DashMap<u16, Session>,
     
     /// Next available user ID (wraps at 65535, skips 0)
     next_user_id: AtomicU16,
     
+    /// User IDs given up by disconnected sessions, reused oldest first
+    free_user_ids: Mutex<VecDeque<u16>>,
+    
+    /// IDs handed out by `allocate_user_id` and not yet released by
+    /// `unregister_session`, for spotting leaked slots
+    allocated_user_ids: Mutex<HashSet<u16>>,
+    
     /// Broadcast channel for server-wide messages
-    pub broadcast_tx: broadcast::Sender<BroadcastMessage>,
+    pub broadcast_tx: broadcast::Sender<Broadcast>,
+    
+    /// Transaction capture sink (None unless `debug.capture_path` is set)
+    pub capture: Option<Arc<TransactionCapture>>,
+    
+    /// Chat log (None unless `chat.log_path` is set)
+    pub chat_log: Option<ChatLog>,
+    
+    /// User list changes waiting for the next batch flush
+    pending_user_list: Mutex<UserListDelta>,
+    
+    /// Transfer slots and the queue of transfers waiting for one
+    pub transfers: TransferQueue,
+    
+    /// Accepted downloads waiting for the client to collect them
+    pub downloads: DashMap<TransferId, PendingDownload>,
+    
+    /// Accepted uploads waiting for the client to send them
+    pub uploads: DashMap<TransferId, PendingUpload>,
+    
+    /// Accepted folder downloads waiting for the client to collect them
+    pub folder_downloads: DashMap<TransferId, PendingFolderDownload>,
+    
+    /// Accepted folder uploads waiting for the client to send them
+    pub folder_uploads: DashMap<TransferId, PendingFolderUpload>,
+    
+    /// Subject of the public chat (empty when unset)
+    chat_subject: Mutex<String>,
+    
+    /// Private chat room membership
+    pub chat_rooms: ChatRooms,
+    
+    /// Failed login counters for account lockout
+    pub lockout: LoginLockout,
+    
+    /// Requests failed because no database connection came free within
+    /// `database.acquire_timeout_ms`
+    pub db_pool_timeouts: AtomicU64,
+    
+    /// Handler latency per transaction type
+    pub handler_metrics: HandlerMetrics,
 }
 
 impl ServerState {
     /// Create a new server state instance
     pub async fn new(config: Config) -> Result<Self> {
         // Initialize database connection
-        let database = Database::new(&config.database.path).await?;
+        let database = Database::open(&config.database).await?;
         
         // Initialize schema
         database.init_schema().await?;
@@ -54,20 +224,116 @@ impl ServerState {
         // Health check
         database.health_check().await?;
         
-        // Create broadcast channel (buffer 100 messages)
-        let (broadcast_tx, _) = broadcast::channel(100);
+        // Create broadcast channel, sized from the config
+        let (broadcast_tx, _) = broadcast::channel(config.broadcast_buffer());
+        
+        // Open transaction capture file if configured
+        let capture = match &config.debug.capture_path {
+            Some(path) => {
+                tracing::info!("Capturing transactions to {}", path.display());
+                Some(Arc::new(TransactionCapture::open(path)?))
+            }
+            None => None,
+        };
+        
+        let chat_log = match &config.chat.log_path {
+            Some(path) => {
+                tracing::info!("Logging chat to {}", path.display());
+                Some(ChatLog::open(path)?)
+            }
+            None => None,
+        };
+        
+        let audit: Arc<dyn AuditSink> = if config.security.audit_log {
+            Arc::new(DatabaseAudit::new(database.clone(), config.security.audit_chat))
+        } else {
+            Arc::new(NoAudit)
+        };
         
         Ok(Self {
-            config,
+            config: ArcSwap::from_pointee(config),
+            accounts: Arc::new(database.clone()),
+            files: Arc::new(database.clone()),
+            audit,
             database,
             sessions: DashMap::new(),
             next_user_id: AtomicU16::new(1),
+            free_user_ids: Mutex::new(VecDeque::new()),
+            allocated_user_ids: Mutex::new(HashSet::new()),
             broadcast_tx,
+            capture,
+            chat_log,
+            pending_user_list: Mutex::new(UserListDelta::default()),
+            transfers: TransferQueue::new(),
+            downloads: DashMap::new(),
+            uploads: DashMap::new(),
+            folder_downloads: DashMap::new(),
+            folder_uploads: DashMap::new(),
+            chat_subject: Mutex::new(String::new()),
+            chat_rooms: ChatRooms::new(),
+            lockout: LoginLockout::new(),
+            db_pool_timeouts: AtomicU64::new(0),
+            handler_metrics: HandlerMetrics::new(),
         })
     }
     
-    /// Allocate the next available user ID (1-65535, wrapping)
+    /// Use a different account store
+    pub fn with_account_store(mut self, store: Arc<dyn AccountStore>) -> Self {
+        self.accounts = store;
+        self
+    }
+    
+    /// Use a different file metadata store
+    pub fn with_file_store(mut self, store: Arc<dyn FileStore>) -> Self {
+        self.files = store;
+        self
+    }
+    
+    /// Use a different audit sink
+    pub fn with_audit_sink(mut self, sink: Arc<dyn AuditSink>) -> Self {
+        self.audit = sink;
+        self
+    }
+    
+    /// Current server configuration
+    ///
+    /// Returns a snapshot; hold it for the duration of one operation so that
+    /// a concurrent reload can't change values halfway through.
+    pub fn config(&self) -> Arc<Config> {
+        self.config.load_full()
+    }
+    
+    /// Replace the server configuration
+    ///
+    /// Connections pick up the new configuration from the next transaction
+    /// they handle. Settings consumed at startup (listen address, database,
+    /// capture file) still require a restart.
+    pub fn reload_config(&self, config: Config) {
+        self.config.store(Arc::new(config));
+        tracing::info!("Configuration reloaded");
+    }
+    
+    /// Allocate a user ID (1-65535)
+    ///
+    /// IDs freed by [`unregister_session`](Self::unregister_session) are
+    /// reused before the counter advances, so a long-running server rarely
+    /// wraps around into IDs that are still taken.
     pub fn allocate_user_id(&self) -> u16 {
+        let user_id = self.next_free_user_id();
+        self.allocated_user_ids.lock().unwrap().insert(user_id);
+        user_id
+    }
+    
+    fn next_free_user_id(&self) -> u16 {
+        let mut free = self.free_user_ids.lock().unwrap();
+        while let Some(id) = free.pop_front() {
+            // Sessions registered with an explicit ID can take a freed one
+            if !self.sessions.contains_key(&id) {
+                return id;
+            }
+        }
+        drop(free);
+        
         loop {
             let id = self.next_user_id.fetch_add(1, Ordering::Relaxed);
             
@@ -91,9 +357,124 @@ impl ServerState {
         self.sessions.insert(session.user_id, session);
     }
     
-    /// Unregister a session by user ID
+    /// Unregister a session by user ID, giving up any transfer slots it held
     pub fn unregister_session(&self, user_id: u16) -> Option<Session> {
-        self.sessions.remove(&user_id).map(|(_, session)| session)
+        self.transfers.release_user(user_id, (&self.config().files).into());
+        self.downloads.retain(|_, download| download.user_id != user_id);
+        self.uploads.retain(|_, upload| upload.user_id != user_id);
+        self.folder_downloads.retain(|_, download| download.user_id != user_id);
+        self.folder_uploads.retain(|_, upload| upload.user_id != user_id);
+        for chat_id in self.chat_rooms.leave_all(user_id) {
+            self.broadcast(BroadcastMessage::ChatUserLeft { chat_id, user_id });
+        }
+        self.allocated_user_ids.lock().unwrap().remove(&user_id);
+        
+        let session = self.sessions.remove(&user_id).map(|(_, session)| session);
+        if session.is_some() {
+            self.free_user_ids.lock().unwrap().push_back(user_id);
+        }
+        session
+    }
+    
+    /// Claim the pending transfer a client connected for
+    pub fn take_pending_transfer(&self, reference: TransferId) -> Option<PendingTransfer> {
+        self.downloads
+            .remove(&reference)
+            .map(|(_, download)| PendingTransfer::Download(download))
+            .or_else(|| self.uploads.remove(&reference).map(|(_, upload)| PendingTransfer::Upload(upload)))
+            .or_else(|| {
+                self.folder_downloads
+                    .remove(&reference)
+                    .map(|(_, download)| PendingTransfer::FolderDownload(download))
+            })
+            .or_else(|| {
+                self.folder_uploads
+                    .remove(&reference)
+                    .map(|(_, upload)| PendingTransfer::FolderUpload(upload))
+            })
+    }
+    
+    /// Drop a transfer nobody connected for, giving up its slot
+    ///
+    /// Returns false if it was already claimed or released.
+    pub fn cancel_pending_transfer(&self, reference: TransferId) -> bool {
+        if self.take_pending_transfer(reference).is_none() {
+            return false;
+        }
+        self.transfers.release(reference, (&self.config().files).into());
+        true
+    }
+    
+    /// Cancel transfer `reference` if its client hasn't connected for it
+    /// within `files.transfer_timeout_seconds`
+    pub fn expire_unclaimed_transfer(self: &Arc<Self>, reference: TransferId) {
+        let timeout = Duration::from_secs(self.config().files.transfer_timeout_seconds);
+        let state = Arc::downgrade(self);
+        tokio::spawn(async move {
+            tokio::time::sleep(timeout).await;
+            if let Some(state) = state.upgrade()
+                && state.cancel_pending_transfer(reference)
+            {
+                tracing::info!("Transfer {} expired before its client connected", reference);
+            }
+        });
+    }
+    
+    /// Allocated user IDs that have no session, sorted
+    ///
+    /// Every allocation should be followed by `register_session` and, on
+    /// disconnect, `unregister_session`. An ID listed here was allocated but
+    /// its session was never registered or was removed without releasing it.
+    pub fn leaked_ids(&self) -> Vec<u16> {
+        let allocated = self.allocated_user_ids.lock().unwrap();
+        let mut leaked: Vec<u16> = allocated
+            .iter()
+            .copied()
+            .filter(|id| !self.sessions.contains_key(id))
+            .collect();
+        leaked.sort_unstable();
+        leaked
+    }
+    
+    /// Log at debug level when allocated user IDs and registered sessions
+    /// disagree
+    ///
+    /// A connection registers its session right after allocating the ID, so
+    /// a mismatch seen once may be a connection caught in between; one that
+    /// persists across checks is a leak.
+    pub fn audit_user_ids(&self) {
+        let leaked = self.leaked_ids();
+        let unallocated: Vec<u16> = {
+            let allocated = self.allocated_user_ids.lock().unwrap();
+            self.sessions
+                .iter()
+                .map(|session| *session.key())
+                .filter(|id| !allocated.contains(id))
+                .collect()
+        };
+        
+        if !leaked.is_empty() || !unallocated.is_empty() {
+            tracing::debug!(
+                "User ID audit: {} sessions, allocated without a session: {:?}, sessions never allocated: {:?}",
+                self.sessions.len(),
+                leaked,
+                unallocated
+            );
+        }
+    }
+    
+    /// Logged-in users, in `features.user_list_order`
+    pub fn user_list(&self) -> Vec<User> {
+        let mut users: Vec<User> = self.sessions.iter()
+            .filter(|s| s.is_authenticated())
+            .map(|s| s.to_user())
+            .collect();
+        
+        match self.config().features.user_list_order {
+            UserListOrder::Id => users.sort_by_key(|user| user.id),
+            UserListOrder::Name => users.sort_by_cached_key(|user| (user.name.to_lowercase(), user.id)),
+        }
+        users
     }
     
     /// Get a session by user ID
@@ -101,19 +482,610 @@ impl ServerState {
         self.sessions.get(&user_id)
     }
     
-    /// Get a mutable reference to a session by user ID
-    pub fn get_session_mut(&self, user_id: u16) -> Option<dashmap::mapref::one::RefMut<'_, u16, Session>> {
-        self.sessions.get_mut(&user_id)
+    /// Change a session in place, returning what `update` returns
+    ///
+    /// `update` runs while the session's entry is locked, so a read followed
+    /// by a write inside it can't interleave with another handler changing
+    /// the same session. It must not touch other sessions (that could
+    /// deadlock) or await anything. `None` if there is no such session.
+    pub fn update_session<R>(&self, user_id: u16, update: impl FnOnce(&mut Session) -> R) -> Option<R> {
+        self.sessions.get_mut(&user_id).map(|mut session| update(&mut session))
+    }
+    
+    /// Access privileges of a connected user
+    ///
+    /// Read from the session rather than the database (see `Session::access`),
+    /// so changes made outside the server, with the `account` CLI, apply from
+    /// the next login. Guests, and sessions whose account was deleted, get
+    /// guest access (`security.guest_access`).
+    pub fn user_access(&self, user_id: u16) -> AccessPrivileges {
+        self.get_session(user_id)
+            .and_then(|s| s.access)
+            .unwrap_or_else(|| self.config().security.guest_access())
+    }
+    
+    /// Record inbound activity from a client
+    ///
+    /// Clears an away flag set by idle detection and tells everyone.
+    pub fn mark_active(&self, user_id: u16) {
+        let was_auto_away = self.update_session(user_id, |session| {
+            session.touch();
+            let was_auto_away = session.auto_away;
+            if was_auto_away {
+                session.auto_away = false;
+                session.flags &= !UserFlags::AWAY.bits();
+            }
+            was_auto_away
+        });
+        
+        if was_auto_away == Some(true) {
+            tracing::debug!("User {} is back", user_id);
+            self.broadcast(BroadcastMessage::UserChanged { user_id });
+        }
+    }
+    
+    /// Mark users idle for longer than `features.auto_away_seconds` as away
+    ///
+    /// Returns the users newly marked away.
+    pub fn sweep_idle(&self) -> Vec<u16> {
+        let Some(threshold) = self.config().features.auto_away_seconds else {
+            return Vec::new();
+        };
+        let threshold = Duration::from_secs(threshold);
+        let now = SystemTime::now();
+        
+        let mut marked = Vec::new();
+        for mut session in self.sessions.iter_mut() {
+            if !session.is_authenticated() || session.flags & UserFlags::AWAY.bits() != 0 {
+                continue;
+            }
+            
+            let idle = now.duration_since(session.last_activity).unwrap_or_default();
+            if idle >= threshold {
+                session.flags |= UserFlags::AWAY.bits();
+                session.auto_away = true;
+                marked.push(session.user_id);
+            }
+        }
+        
+        for &user_id in &marked {
+            tracing::debug!("User {} is now away", user_id);
+            self.broadcast(BroadcastMessage::UserChanged { user_id });
+        }
+        
+        marked
+    }
+    
+    /// Check a session against the idle timeout
+    ///
+    /// The warning at `features.idle_warning_seconds` is given once per idle
+    /// spell; activity resets both it and the timeout.
+    pub fn check_idle(&self, user_id: u16, now: SystemTime) -> Option<IdleAction> {
+        let config = self.config();
+        let timeout = config.features.idle_timeout_seconds?;
+        self.update_session(user_id, |session| {
+            let idle = now.duration_since(session.last_activity).unwrap_or_default().as_secs();
+            
+            if idle >= timeout {
+                return Some(IdleAction::Disconnect);
+            }
+            
+            match config.features.idle_warning_seconds {
+                Some(warning) if idle >= warning && !session.idle_warned => {
+                    session.idle_warned = true;
+                    Some(IdleAction::Warn { remaining: timeout - idle })
+                }
+                _ => None,
+            }
+        })
+        .flatten()
+    }
+    
+    /// Observe broadcasts from here on, as a connection would receive them
+    #[cfg(test)]
+    pub fn subscribe_raw(&self) -> BroadcastTap {
+        BroadcastTap(self.broadcast_tx.subscribe())
     }
     
     /// Broadcast a message to all connected clients
+    ///
+    /// With `features.user_list_batch_ms` set, joins, changes and leaves are
+    /// held back and sent together by [`flush_user_list`](Self::flush_user_list).
     pub fn broadcast(&self, message: BroadcastMessage) {
+        self.send_broadcast(message, None);
+    }
+    
+    /// Broadcast a message to every session except `exclude`
+    pub fn broadcast_except(&self, exclude: u16, message: BroadcastMessage) {
+        self.send_broadcast(message, Some(exclude));
+    }
+    
+    fn send_broadcast(&self, message: BroadcastMessage, exclude: Option<u16>) {
+        let is_membership = matches!(
+            message,
+            BroadcastMessage::UserJoined { .. }
+                | BroadcastMessage::UserChanged { .. }
+                | BroadcastMessage::UserLeft { .. }
+        );
+        
+        // Batched joins already leave out the joining user
+        if is_membership && self.config().features.user_list_batch_ms.is_some() {
+            self.pending_user_list.lock().unwrap().record(&message);
+            return;
+        }
+        
         // Ignore send errors (no receivers is fine)
-        let _ = self.broadcast_tx.send(message);
+        let _ = self.broadcast_tx.send(Broadcast { message, exclude });
+    }
+    
+    /// Apply new access to anyone connected with the account and send it to
+    /// their clients
+    pub fn notify_access_changed(&self, account_id: i64, access: AccessPrivileges) {
+        self.set_account_access(account_id, Some(access));
+        self.broadcast(BroadcastMessage::AccessChanged { account_id, access });
+    }
+    
+    /// Drop anyone connected with a deleted account to guest access
+    pub fn notify_account_deleted(&self, account_id: i64) {
+        self.set_account_access(account_id, None);
+        let access = self.config().security.guest_access();
+        self.broadcast(BroadcastMessage::AccessChanged { account_id, access });
+    }
+    
+    fn set_account_access(&self, account_id: i64, access: Option<AccessPrivileges>) {
+        for mut session in self.sessions.iter_mut() {
+            if session.account_id == Some(account_id) {
+                session.access = access;
+            }
+        }
+    }
+    
+    /// Tell connected admins about a security event
+    ///
+    /// Recipients are picked from the access cached on each session, so a
+    /// flood of failed logins costs no database queries.
+    pub fn alert_admins(&self, text: impl Into<String>) {
+        let guest_access = self.config().security.guest_access();
+        let recipients: Vec<u16> = self.sessions
+            .iter()
+            .filter(|session| session.is_authenticated())
+            .filter(|session| session.access.unwrap_or(guest_access).contains(AccessPrivileges::DISCONNECT_USERS))
+            .map(|session| *session.key())
+            .collect();
+        
+        if !recipients.is_empty() {
+            self.broadcast(BroadcastMessage::AdminAlert { text: text.into(), recipients });
+        }
+    }
+    
+    /// Current public chat subject (empty when unset)
+    pub fn chat_subject(&self) -> String {
+        self.chat_subject.lock().unwrap().clone()
+    }
+    
+    /// Set the public chat subject and announce it to everyone
+    pub fn set_chat_subject(&self, subject: String) {
+        *self.chat_subject.lock().unwrap() = subject.clone();
+        self.broadcast(BroadcastMessage::ChatSubject { subject });
+    }
+    
+    /// Send buffered user list changes as one batch
+    pub fn flush_user_list(&self) {
+        let delta = std::mem::take(&mut *self.pending_user_list.lock().unwrap());
+        if !delta.is_empty() {
+            let _ = self.broadcast_tx.send(Broadcast {
+                message: BroadcastMessage::UserListDelta(delta),
+                exclude: None,
+            });
+        }
+    }
+    
+    /// Flush what is still buffered and close the database
+    ///
+    /// The last step of shutdown: pending user list changes are sent, the
+    /// chat log is synced to disk, the write-ahead log is checkpointed into
+    /// the database file, and the pool is closed once every connection has
+    /// been returned. Audit rows are written as they happen, so there is
+    /// nothing to flush for them. Database queries fail afterwards.
+    pub async fn close(&self) {
+        self.flush_user_list();
+        
+        if let Some(chat_log) = &self.chat_log {
+            chat_log.sync();
+        }
+        if let Err(e) = self.database.checkpoint().await {
+            tracing::warn!("Final WAL checkpoint failed: {}", e);
+        }
+        
+        self.database.close().await;
+        tracing::info!("Database closed");
     }
     
     /// Get the number of active sessions
     pub fn session_count(&self) -> usize {
         self.sessions.len()
     }
+    
+    /// Get the number of sessions that have logged in
+    pub fn authenticated_count(&self) -> usize {
+        self.sessions.iter().filter(|s| s.is_authenticated()).count()
+    }
+    
+    /// User count announced to trackers: logged-in sessions, without guests
+    /// unless `tracker.count_guests` is set
+    pub fn tracker_user_count(&self) -> usize {
+        let count_guests = self.config().tracker.count_guests;
+        self.sessions
+            .iter()
+            .filter(|s| s.is_authenticated() && (count_guests || !s.is_guest()))
+            .count()
+    }
+    
+    /// Server name as advertised to clients, with the current user count when
+    /// `server.name_shows_user_count` is set
+    pub fn display_name(&self) -> String {
+        let config = self.config();
+        if !config.server.name_shows_user_count {
+            return config.server.name.clone();
+        }
+        
+        format!(
+            "{} ({}/{})",
+            config.server.name,
+            self.authenticated_count(),
+            config.server.max_connections
+        )
+    }
+    
+    /// Get the number of sessions still handshaking or logging in
+    pub fn pending_count(&self) -> usize {
+        self.sessions.iter().filter(|s| !s.is_authenticated()).count()
+    }
+    
+    /// Whether another connection may be accepted
+    pub fn accepts_connection(&self) -> bool {
+        self.session_count() < self.config().server.max_connections
+    }
+    
+    /// Whether another account may be created under `security.max_accounts`
+    pub async fn accepts_account(&self) -> Result<bool> {
+        let limit = self.config().security.max_accounts;
+        Ok(limit == 0 || self.accounts.count_accounts().await? < limit)
+    }
+    
+    /// Whether another session may log in
+    ///
+    /// Authenticated users can't take the slots reserved for handshakes by
+    /// `server.reserved_handshake_slots`.
+    pub fn accepts_login(&self) -> bool {
+        let config = self.config();
+        let limit = config
+            .server
+            .max_connections
+            .saturating_sub(config.server.reserved_handshake_slots);
+        self.authenticated_count() < limit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_state;
+    
+    async fn limited_state(max_connections: usize, reserved: usize) -> Arc<ServerState> {
+        test_state(|config| {
+            config.server.max_connections = max_connections;
+            config.server.reserved_handshake_slots = reserved;
+        })
+        .await
+    }
+    
+    fn connect(state: &ServerState) -> u16 {
+        let user_id = state.allocate_user_id();
+        state.register_session(Session::new(user_id, "127.0.0.1:5500".parse().unwrap()));
+        user_id
+    }
+    
+    #[tokio::test]
+    async fn test_admin_alert_goes_to_admins_only() {
+        let state = limited_state(10, 0).await;
+        let mut tap = state.subscribe_raw();
+        
+        let admin_account = state.accounts
+            .create_account("admin", b"pw", "Admin", AccessPrivileges::admin())
+            .await
+            .unwrap();
+        let user_account = state.accounts
+            .create_account("user", b"pw", "User", AccessPrivileges::user())
+            .await
+            .unwrap();
+        
+        let admin = connect(&state);
+        let user = connect(&state);
+        let guest = connect(&state);
+        let _unauthenticated = connect(&state);
+        state.update_session(admin, |s| s.authenticate_user(admin_account, AccessPrivileges::admin(), "Admin".to_string(), 0));
+        state.update_session(user, |s| s.authenticate_user(user_account, AccessPrivileges::user(), "User".to_string(), 0));
+        state.update_session(guest, |s| s.authenticate_guest("Guest".to_string(), 0));
+        
+        state.alert_admins("Failed login");
+        let alerts: Vec<_> = tap.drain().into_iter().map(|b| b.message).collect();
+        assert!(matches!(
+            alerts.as_slice(),
+            [BroadcastMessage::AdminAlert { recipients, .. }] if recipients == &[admin]
+        ));
+        
+        // Nothing is broadcast when no admin is online
+        state.unregister_session(admin);
+        tap.drain();
+        state.alert_admins("Failed login");
+        assert!(tap.drain().iter().all(|b| !matches!(b.message, BroadcastMessage::AdminAlert { .. })));
+    }
+    
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_concurrent_session_updates_are_atomic() {
+        let state = limited_state(10, 0).await;
+        let user_id = state.allocate_user_id();
+        state.register_session(Session::new(user_id, "127.0.0.1:5500".parse().unwrap()));
+        state.update_session(user_id, |session| session.authenticate_guest("0".to_string(), 0)).unwrap();
+        
+        // Each rename reads the current name and writes the next one; any
+        // interleaving between the read and the write would lose a rename
+        let renamer = |state: Arc<ServerState>| tokio::spawn(async move {
+            for _ in 0..1000 {
+                state.update_session(user_id, |session| {
+                    let count: u32 = session.nickname.parse().unwrap();
+                    session.nickname = (count + 1).to_string();
+                });
+                tokio::task::yield_now().await;
+            }
+        });
+        let (a, b) = (renamer(state.clone()), renamer(state.clone()));
+        a.await.unwrap();
+        b.await.unwrap();
+        
+        assert_eq!(state.get_session(user_id).unwrap().nickname, "2000");
+    }
+    
+    #[tokio::test]
+    async fn test_freed_user_ids_are_reused_first() {
+        let state = limited_state(10, 0).await;
+        
+        let ids: Vec<u16> = (0..3).map(|_| connect(&state)).collect();
+        assert_eq!(ids, vec![1, 2, 3]);
+        
+        state.unregister_session(2);
+        state.unregister_session(1);
+        // Unregistering an unknown ID frees nothing
+        state.unregister_session(3000);
+        
+        assert_eq!(connect(&state), 2);
+        assert_eq!(connect(&state), 1);
+        assert_eq!(connect(&state), 4);
+    }
+    
+    #[tokio::test]
+    async fn test_unregistered_allocation_is_reported_as_leaked() {
+        let state = limited_state(10, 0).await;
+        
+        let registered = connect(&state);
+        let forgotten = state.allocate_user_id();
+        assert_eq!(state.leaked_ids(), vec![forgotten]);
+        
+        state.unregister_session(forgotten);
+        state.unregister_session(registered);
+        assert!(state.leaked_ids().is_empty());
+        assert_eq!(state.session_count(), 0);
+    }
+    
+    #[tokio::test]
+    async fn test_counts_track_session_lifecycle() {
+        let state = limited_state(10, 0).await;
+        
+        let alice = connect(&state);
+        let bob = connect(&state);
+        assert_eq!(state.pending_count(), 2);
+        assert_eq!(state.authenticated_count(), 0);
+        
+        // Handshake completes but login is still pending
+        state.update_session(alice, |session| session.complete_handshake()).unwrap();
+        assert_eq!(state.pending_count(), 2);
+        assert_eq!(state.authenticated_count(), 0);
+        
+        // Login
+        state.update_session(alice, |session| session.authenticate_guest("Alice".to_string(), 0)).unwrap();
+        assert_eq!(state.pending_count(), 1);
+        assert_eq!(state.authenticated_count(), 1);
+        
+        // Disconnect
+        state.unregister_session(alice);
+        assert_eq!(state.pending_count(), 1);
+        assert_eq!(state.authenticated_count(), 0);
+        
+        state.unregister_session(bob);
+        assert_eq!(state.session_count(), 0);
+    }
+    
+    #[tokio::test]
+    async fn test_tracker_count_excludes_pending() {
+        let state = limited_state(10, 0).await;
+        
+        // One still handshaking, one waiting on login
+        connect(&state);
+        let pending = connect(&state);
+        state.update_session(pending, |session| session.complete_handshake()).unwrap();
+        
+        let guest = connect(&state);
+        state.update_session(guest, |session| session.authenticate_guest("Guest".to_string(), 0)).unwrap();
+        let member = connect(&state);
+        state.update_session(member, |session| session.authenticate_user(1, AccessPrivileges::user(), "Alice".to_string(), 0)).unwrap();
+        
+        assert_eq!(state.session_count(), 4);
+        assert_eq!(state.tracker_user_count(), 2);
+        
+        let mut config = (*state.config()).clone();
+        config.tracker.count_guests = false;
+        state.reload_config(config);
+        assert_eq!(state.tracker_user_count(), 1);
+    }
+    
+    #[tokio::test]
+    async fn test_broadcast_buffer_absorbs_burst() {
+        const BURST: u16 = 64;
+        
+        for (buffer, lags) in [(16, true), (128, false)] {
+            let state = test_state(|config| {
+                config.features.broadcast_buffer = Some(buffer);
+            })
+            .await;
+            
+            let mut rx = state.broadcast_tx.subscribe();
+            for user_id in 0..BURST {
+                state.broadcast(BroadcastMessage::UserChanged { user_id });
+            }
+            
+            let lagged = matches!(rx.try_recv(), Err(broadcast::error::TryRecvError::Lagged(_)));
+            assert_eq!(lagged, lags, "buffer of {}", buffer);
+        }
+    }
+    
+    #[test]
+    fn test_broadcast_buffer_scales_with_connections() {
+        let mut config = Config::default();
+        config.server.max_connections = 1000;
+        assert_eq!(config.broadcast_buffer(), 8000);
+        
+        config.server.max_connections = 4;
+        assert_eq!(config.broadcast_buffer(), 256);
+        
+        config.features.broadcast_buffer = Some(32);
+        assert_eq!(config.broadcast_buffer(), 32);
+    }
+    
+    #[tokio::test]
+    async fn test_auto_away_sets_and_clears() {
+        let state = limited_state(10, 0).await;
+        let mut config = (*state.config()).clone();
+        config.features.auto_away_seconds = Some(60);
+        state.reload_config(config);
+        
+        let mut rx = state.broadcast_tx.subscribe();
+        let user_id = connect(&state);
+        state.update_session(user_id, |session| session.authenticate_guest("Idler".to_string(), 0)).unwrap();
+        
+        // Not idle long enough yet
+        assert!(state.sweep_idle().is_empty());
+        
+        state.update_session(user_id, |session| session.last_activity = SystemTime::now() - Duration::from_secs(120)).unwrap();
+        assert_eq!(state.sweep_idle(), vec![user_id]);
+        assert_ne!(state.get_session(user_id).unwrap().flags & UserFlags::AWAY.bits(), 0);
+        assert!(matches!(rx.try_recv().map(|b| b.message), Ok(BroadcastMessage::UserChanged { user_id: id }) if id == user_id));
+        
+        // Already away, so nothing new to announce
+        assert!(state.sweep_idle().is_empty());
+        
+        // The next transaction brings them back
+        state.mark_active(user_id);
+        assert_eq!(state.get_session(user_id).unwrap().flags & UserFlags::AWAY.bits(), 0);
+        assert!(matches!(rx.try_recv().map(|b| b.message), Ok(BroadcastMessage::UserChanged { user_id: id }) if id == user_id));
+        
+        // Activity without a pending auto-away broadcasts nothing
+        state.mark_active(user_id);
+        assert!(rx.try_recv().is_err());
+    }
+    
+    #[tokio::test]
+    async fn test_idle_warning_then_disconnect() {
+        let state = limited_state(10, 0).await;
+        let mut config = (*state.config()).clone();
+        config.features.idle_timeout_seconds = Some(60);
+        config.features.idle_warning_seconds = Some(45);
+        state.reload_config(config);
+        
+        let user_id = connect(&state);
+        state.update_session(user_id, |session| session.authenticate_guest("Idler".to_string(), 0)).unwrap();
+        let idle_for = |secs| {
+            state.update_session(user_id, |session| session.last_activity = SystemTime::now() - Duration::from_secs(secs)).unwrap();
+        };
+        
+        assert_eq!(state.check_idle(user_id, SystemTime::now()), None);
+        
+        // Warned once, ahead of the disconnect
+        idle_for(50);
+        assert_eq!(state.check_idle(user_id, SystemTime::now()), Some(IdleAction::Warn { remaining: 10 }));
+        assert_eq!(state.check_idle(user_id, SystemTime::now()), None);
+        
+        // Activity cancels both
+        state.mark_active(user_id);
+        assert_eq!(state.check_idle(user_id, SystemTime::now()), None);
+        assert!(!state.get_session(user_id).unwrap().idle_warned);
+        
+        idle_for(50);
+        assert!(matches!(state.check_idle(user_id, SystemTime::now()), Some(IdleAction::Warn { .. })));
+        idle_for(61);
+        assert_eq!(state.check_idle(user_id, SystemTime::now()), Some(IdleAction::Disconnect));
+    }
+    
+    #[tokio::test]
+    async fn test_user_list_changes_are_batched() {
+        let state = limited_state(10, 0).await;
+        let mut config = (*state.config()).clone();
+        config.features.user_list_batch_ms = Some(250);
+        state.reload_config(config);
+        
+        let mut rx = state.broadcast_tx.subscribe();
+        
+        for user_id in [1, 2, 3] {
+            state.broadcast(BroadcastMessage::UserJoined {
+                user_id,
+                nickname: format!("User {}", user_id),
+            });
+        }
+        
+        // Nothing goes out until the window closes
+        assert!(rx.try_recv().is_err());
+        
+        state.flush_user_list();
+        match rx.try_recv().map(|b| b.message) {
+            Ok(BroadcastMessage::UserListDelta(delta)) => {
+                assert_eq!(delta.changed, vec![(1, true), (2, true), (3, true)]);
+                assert!(delta.left.is_empty());
+            }
+            other => panic!("expected a batched delta, got {:?}", other),
+        }
+        assert!(rx.try_recv().is_err());
+        
+        // Flushing with nothing buffered sends nothing
+        state.flush_user_list();
+        assert!(rx.try_recv().is_err());
+    }
+    
+    #[test]
+    fn test_user_list_delta_net_effect() {
+        let mut delta = UserListDelta::default();
+        delta.record(&BroadcastMessage::UserJoined { user_id: 1, nickname: "A".to_string() });
+        delta.record(&BroadcastMessage::UserChanged { user_id: 1 });
+        delta.record(&BroadcastMessage::UserChanged { user_id: 2 });
+        delta.record(&BroadcastMessage::UserLeft { user_id: 2 });
+        
+        assert_eq!(delta.changed, vec![(1, true)]);
+        assert_eq!(delta.left, vec![2]);
+    }
+    
+    #[tokio::test]
+    async fn test_reserved_handshake_slots() {
+        let state = limited_state(3, 1).await;
+        
+        for _ in 0..2 {
+            let user_id = connect(&state);
+            state.update_session(user_id, |session| session.authenticate_guest("User".to_string(), 0)).unwrap();
+        }
+        
+        // The last slot is still open for a handshake, but not for a login
+        assert!(state.accepts_connection());
+        assert!(!state.accepts_login());
+        
+        connect(&state);
+        assert!(!state.accepts_connection());
+    }
 }
@@ -0,0 +1,102 @@
+//! Helpers for tests
+//!
+//! [`test_state`] builds a [`ServerState`] over a private in-memory database,
+//! so handler tests never touch the disk. Tests that need real files use
+//! [`TempPath`]: paths are created under [`std::env::temp_dir`] and are unique
+//! per process and per call, so parallel test runs never share a file. The
+//! file and any SQLite side files are removed when the [`TempPath`] is dropped.
+
+use crate::config::Config;
+use crate::db::IN_MEMORY_PATH;
+use crate::state::ServerState;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A unique temporary file path that is cleaned up on drop
+#[derive(Debug)]
+pub struct TempPath {
+    path: PathBuf,
+}
+
+impl TempPath {
+    /// Reserve a unique path such as `<tmp>/test_rhxd_<name>_<pid>_<n>.<extension>`
+    pub fn new(name: &str, extension: &str) -> Self {
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let file_name = format!(
+            "test_rhxd_{}_{}_{}.{}",
+            name,
+            std::process::id(),
+            n,
+            extension
+        );
+
+        let path = std::env::temp_dir().join(file_name);
+        remove_with_side_files(&path);
+
+        Self { path }
+    }
+
+    /// Owned copy of the reserved path, e.g. for config fields
+    pub fn to_path_buf(&self) -> PathBuf {
+        self.path.clone()
+    }
+}
+
+impl Deref for TempPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for TempPath {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        remove_with_side_files(&self.path);
+    }
+}
+
+/// Remove a file or directory along with SQLite's journal, WAL and
+/// shared-memory files
+fn remove_with_side_files(path: &Path) {
+    if path.is_dir() {
+        std::fs::remove_dir_all(path).ok();
+    } else {
+        std::fs::remove_file(path).ok();
+    }
+
+    for suffix in ["-journal", "-wal", "-shm"] {
+        let mut side = path.as_os_str().to_owned();
+        side.push(suffix);
+        std::fs::remove_file(side).ok();
+    }
+}
+
+/// Unique temporary SQLite database path for a test
+pub fn test_db_path(name: &str) -> TempPath {
+    TempPath::new(name, "db")
+}
+
+/// Default configuration over a private in-memory database
+pub fn test_config() -> Config {
+    let mut config = Config::default();
+    config.database.path = IN_MEMORY_PATH.into();
+    config
+}
+
+/// Server state over [`test_config`], after `configure` adjusts it
+pub async fn test_state(configure: impl FnOnce(&mut Config)) -> Arc<ServerState> {
+    let mut config = test_config();
+    configure(&mut config);
+    Arc::new(ServerState::new(config).await.unwrap())
+}
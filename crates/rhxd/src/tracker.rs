@@ -0,0 +1,128 @@
+//! Tracker announcements
+//!
+//! Every `tracker.interval_seconds` the server sends each configured tracker
+//! a UDP registration with its name, description, port and user count, so it
+//! stays in the tracker's listing. The packet matches what rhxtrackd expects:
+//!
+//! ```text
+//! u16 version (1) | u16 port | u16 user count | u16 reserved | u32 pass ID
+//! u8 len + name | u8 len + description
+//! ```
+
+use crate::state::ServerState;
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+
+/// Registration packet version
+const REGISTRATION_VERSION: u16 = 1;
+
+/// Build a registration packet announcing `user_count` users
+pub fn registration_packet(state: &ServerState, user_count: usize, pass_id: u32) -> Vec<u8> {
+    let config = state.config();
+    let user_count = user_count.min(u16::MAX as usize) as u16;
+    
+    let mut packet = Vec::new();
+    for value in [REGISTRATION_VERSION, config.server.port, user_count, 0] {
+        packet.extend_from_slice(&value.to_be_bytes());
+    }
+    packet.extend_from_slice(&pass_id.to_be_bytes());
+    
+    // Trackers list the same name clients see after logging in
+    for text in [&state.display_name(), &config.server.description] {
+        let bytes = &text.as_bytes()[..text.len().min(u8::MAX as usize)];
+        packet.push(bytes.len() as u8);
+        packet.extend_from_slice(bytes);
+    }
+    
+    packet
+}
+
+/// Announce the server to every configured tracker once
+pub async fn announce(state: &ServerState, socket: &UdpSocket, pass_id: u32) {
+    let packet = registration_packet(state, state.tracker_user_count(), pass_id);
+    
+    for tracker in &state.config().tracker.trackers {
+        if let Err(e) = socket.send_to(&packet, tracker.as_str()).await {
+            tracing::warn!("Failed to announce to tracker {}: {}", tracker, e);
+        }
+    }
+}
+
+/// Announce in a background task, unless no trackers are configured
+pub async fn spawn(state: Arc<ServerState>) -> Result<Option<JoinHandle<()>>> {
+    if state.config().tracker.trackers.is_empty() {
+        return Ok(None);
+    }
+    
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    let pass_id = rand::random();
+    
+    Ok(Some(tokio::spawn(async move {
+        loop {
+            announce(&state, &socket, pass_id).await;
+            let interval = state.config().tracker.interval_seconds.max(1);
+            tokio::time::sleep(Duration::from_secs(interval)).await;
+        }
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::Session;
+    use crate::test_util::test_state;
+    use rhxcore::types::AccessPrivileges;
+    
+    #[tokio::test]
+    async fn test_announcement_counts_logged_in_users() {
+        let state = test_state(|config| {
+            config.tracker.count_guests = false;
+        })
+        .await;
+        
+        for login in [None, Some(1), Some(2)] {
+            let user_id = state.allocate_user_id();
+            let mut session = Session::new(user_id, "127.0.0.1:5500".parse().unwrap());
+            match login {
+                Some(account_id) => session.authenticate_user(account_id, AccessPrivileges::user(), format!("User {}", account_id), 0),
+                None => session.authenticate_guest("Guest".to_string(), 0),
+            }
+            state.register_session(session);
+        }
+        // Mid-handshake
+        state.register_session(Session::new(state.allocate_user_id(), "127.0.0.1:5501".parse().unwrap()));
+        
+        let tracker = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut reloaded = (*state.config()).clone();
+        reloaded.tracker.trackers = vec![tracker.local_addr().unwrap().to_string()];
+        state.reload_config(reloaded);
+        
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        announce(&state, &socket, 42).await;
+        
+        let mut buf = [0u8; 512];
+        let len = tracker.recv(&mut buf).await.unwrap();
+        let packet = &buf[..len];
+        assert_eq!(u16::from_be_bytes([packet[0], packet[1]]), REGISTRATION_VERSION);
+        assert_eq!(u16::from_be_bytes([packet[2], packet[3]]), 5500);
+        assert_eq!(u16::from_be_bytes([packet[4], packet[5]]), 2);
+        let name_len = packet[12] as usize;
+        assert_eq!(&packet[13..13 + name_len], b"My Hotline Server");
+    }
+    
+    #[tokio::test]
+    async fn test_announcement_uses_display_name() {
+        let state = test_state(|config| {
+            config.server.name_shows_user_count = true;
+            config.server.max_connections = 10;
+        })
+        .await;
+        
+        let packet = registration_packet(&state, 0, 0);
+        let name_len = packet[12] as usize;
+        assert_eq!(&packet[13..13 + name_len], b"My Hotline Server (0/10)");
+    }
+}
@@ -0,0 +1,897 @@
+//! File transfer slots
+//!
+//! Limits how many transfers run at once, globally and per user. Transfers
+//! over either limit wait in a FIFO queue; their position is what
+//! `DownloadInfo` (211) reports in the waiting count field (116).
+//!
+//! Downloads and uploads the server has agreed to are kept as
+//! [`PendingDownload`]s and [`PendingUpload`]s (or their folder counterparts)
+//! until the client connects for them by reference number (see `htxf`).
+//! Files travel as flattened file objects: a `FILP` header, then the
+//! information fork and the data fork.
+//!
+//! Folder transfers go through every item below the folder, parents before
+//! their contents, with the receiving side choosing what to do with each
+//! one (see [`FolderAction`]). Each item starts with a header:
+//!
+//! - header_len: u16 (length of what follows)
+//! - kind: u16 (0 file, 1 folder)
+//! - path: FilePath field data, relative to the transferred folder
+//!
+//! Files that are sent follow as a u32 size and a flattened file object.
+
+use crate::config::FilesConfig;
+use crate::files::PathResolver;
+use rhxcore::types::file::{decode_file_path, encode_file_path, DATA_FORK, INFO_FORK};
+use rhxcore::types::{FlatFileHeader, FlatFileInfo, ForkHeader, ResumeData};
+use std::collections::VecDeque;
+use std::io::{self, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+
+/// Identifies a reserved transfer slot
+///
+/// Also the reference number (field 107) the client echoes on the transfer
+/// connection, so it is random rather than sequential and never 0.
+pub type TransferId = u32;
+
+/// Concurrency limits for transfers (0 means unlimited)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransferLimits {
+    pub global: usize,
+    pub per_user: usize,
+}
+
+impl From<&FilesConfig> for TransferLimits {
+    fn from(config: &FilesConfig) -> Self {
+        Self {
+            global: config.max_concurrent_transfers,
+            per_user: config.max_transfers_per_user,
+        }
+    }
+}
+
+/// Where a transfer stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferStatus {
+    /// Running now
+    Active,
+    /// Waiting for a slot; position 1 is next in line
+    Queued { position: usize },
+}
+
+impl TransferStatus {
+    /// Value for the waiting count field (0 once the transfer may start)
+    pub fn waiting_count(&self) -> u16 {
+        match *self {
+            TransferStatus::Active => 0,
+            TransferStatus::Queued { position } => position.min(u16::MAX as usize) as u16,
+        }
+    }
+}
+
+/// A transfer accepted on the main connection, waiting for its client to
+/// connect for it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PendingTransfer {
+    Download(PendingDownload),
+    Upload(PendingUpload),
+    FolderDownload(PendingFolderDownload),
+    FolderUpload(PendingFolderUpload),
+}
+
+/// A download accepted by `DownloadFile` (202)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingDownload {
+    pub user_id: u16,
+    pub physical_path: PathBuf,
+    /// Information fork sent ahead of the data
+    pub info: FlatFileInfo,
+    /// Full size of the data fork
+    pub file_size: u64,
+    /// Bytes of the data fork the client already has
+    pub offset: u64,
+}
+
+impl PendingDownload {
+    /// Bytes of the data fork still to be sent
+    pub fn remaining(&self) -> u64 {
+        self.file_size.saturating_sub(self.offset)
+    }
+    
+    /// Bytes sent on the transfer connection, flattened file object included
+    pub fn transfer_size(&self) -> u64 {
+        flat_file_size(&self.info, self.remaining())
+    }
+    
+    /// Write the file as a flattened file object, with the data fork from the
+    /// resume offset onwards, returning the number of data bytes written
+    pub async fn stream_to<W: AsyncWrite + Unpin>(&self, writer: &mut W) -> io::Result<u64> {
+        write_flat_file(writer, &self.physical_path, &self.info, self.offset, self.remaining()).await
+    }
+}
+
+/// Create the file at `path` from a flattened file object, returning the
+/// size of its data fork
+///
+/// A data fork over `max_size` bytes fails the transfer. A failed transfer
+/// leaves no partial file behind.
+async fn receive_file<R: AsyncRead + Unpin>(reader: &mut R, path: &Path, max_size: u64) -> io::Result<u64> {
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .await?;
+    
+    let result = match read_flat_file(reader, &mut file, max_size).await {
+        Ok(written) => file.flush().await.map(|()| written),
+        Err(e) => Err(e),
+    };
+    if result.is_err() {
+        drop(file);
+        tokio::fs::remove_file(path).await.ok();
+    }
+    result
+}
+
+/// Read a flattened file object, copying its data fork to `writer`
+///
+/// Returns the size of the data fork, which may be no more than `max_size`.
+/// The information fork and any resource fork are read and dropped.
+async fn read_flat_file<R, W>(reader: &mut R, writer: &mut W, max_size: u64) -> io::Result<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut header = [0u8; FlatFileHeader::SIZE];
+    reader.read_exact(&mut header).await?;
+    let header = FlatFileHeader::from_bytes(&header).map_err(|e| invalid_file(e.to_string()))?;
+    
+    let mut data_size = None;
+    for _ in 0..header.fork_count {
+        let mut fork = [0u8; ForkHeader::SIZE];
+        reader.read_exact(&mut fork).await?;
+        let fork = ForkHeader::from_bytes(&fork).map_err(|e| invalid_file(e.to_string()))?;
+        let size = fork.data_size as u64;
+        
+        let mut data = (&mut *reader).take(size);
+        let copied = if fork.fork_type == DATA_FORK {
+            if data_size.replace(size).is_some() {
+                return Err(invalid_file("two data forks".to_string()));
+            }
+            if size > max_size {
+                return Err(invalid_file(format!("data fork over {} bytes", max_size)));
+            }
+            tokio::io::copy(&mut data, writer).await?
+        } else {
+            tokio::io::copy(&mut data, &mut tokio::io::sink()).await?
+        };
+        if copied != size {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "transfer ended mid-fork"));
+        }
+    }
+    
+    Ok(data_size.unwrap_or(0))
+}
+
+/// Information fork describing the file at `path`
+///
+/// Type and creator codes aren't known for files on disk, so they are sent
+/// as `????`; missing dates are sent as the Unix epoch.
+pub async fn flat_file_info(path: &Path) -> io::Result<FlatFileInfo> {
+    let metadata = tokio::fs::metadata(path).await?;
+    let date = |time: io::Result<std::time::SystemTime>| time.map(Into::into).unwrap_or_default();
+    
+    Ok(FlatFileInfo {
+        type_code: *b"????",
+        creator_code: *b"????",
+        created: date(metadata.created()),
+        modified: date(metadata.modified()),
+        name: path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default(),
+        comment: String::new(),
+    })
+}
+
+/// Size of a flattened file object with `info` and `data_size` bytes of data
+/// fork
+pub fn flat_file_size(info: &FlatFileInfo, data_size: u64) -> u64 {
+    (FlatFileHeader::SIZE + 2 * ForkHeader::SIZE + info.to_bytes().len()) as u64 + data_size
+}
+
+/// Write the file at `path` as a flattened file object, sending `size` bytes
+/// of its data fork from `offset`
+///
+/// Returns the number of data bytes written. A file that shrank since `size`
+/// was taken fails the transfer rather than desyncing it.
+async fn write_flat_file<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    path: &Path,
+    info: &FlatFileInfo,
+    offset: u64,
+    size: u64,
+) -> io::Result<u64> {
+    let data_size = u32::try_from(size)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("{} is over 4 GiB", path.display())))?;
+    let info = info.to_bytes();
+    
+    let mut file = tokio::fs::File::open(path).await?;
+    file.seek(SeekFrom::Start(offset)).await?;
+    
+    writer.write_all(&FlatFileHeader { fork_count: 2 }.to_bytes()).await?;
+    writer.write_all(&ForkHeader { fork_type: INFO_FORK, data_size: info.len() as u32 }.to_bytes()).await?;
+    writer.write_all(&info).await?;
+    writer.write_all(&ForkHeader { fork_type: DATA_FORK, data_size }.to_bytes()).await?;
+    
+    let copied = tokio::io::copy(&mut file.take(size), writer).await?;
+    if copied != size {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("{} shrank during the transfer", path.display()),
+        ));
+    }
+    writer.flush().await?;
+    Ok(copied)
+}
+
+/// An upload accepted by `UploadFile` (203)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingUpload {
+    pub user_id: u16,
+    pub physical_path: PathBuf,
+    /// Size the client announced, if any
+    pub size: Option<u64>,
+}
+
+impl PendingUpload {
+    /// Create the file from the flattened file object the client sends,
+    /// returning the number of data bytes written
+    ///
+    /// The client may send no more than the size it announced. A failed
+    /// upload leaves no partial file behind.
+    pub async fn receive_from<R: AsyncRead + Unpin>(&self, reader: &mut R) -> io::Result<u64> {
+        let mut limited = reader.take(self.size.unwrap_or(u64::MAX));
+        receive_file(&mut limited, &self.physical_path, u64::MAX).await
+    }
+}
+
+/// Kind of an item in a folder transfer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum FolderItemKind {
+    File = 0,
+    Folder = 1,
+}
+
+impl FolderItemKind {
+    fn from_u16(value: u16) -> Option<Self> {
+        match value {
+            0 => Some(Self::File),
+            1 => Some(Self::Folder),
+            _ => None,
+        }
+    }
+}
+
+/// What the receiving side of a folder transfer wants done with an item,
+/// sent as a u16
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum FolderAction {
+    /// Send the whole file
+    Send = 1,
+    /// Send the rest of the file, from the resume data that follows
+    Resume = 2,
+    /// Skip to the next item
+    Next = 3,
+}
+
+impl FolderAction {
+    async fn read_from<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Self> {
+        match reader.read_u16().await? {
+            1 => Ok(Self::Send),
+            2 => Ok(Self::Resume),
+            3 => Ok(Self::Next),
+            action => Err(invalid_item(format!("action {}", action))),
+        }
+    }
+    
+    async fn write_to<W: AsyncWrite + Unpin>(self, writer: &mut W) -> io::Result<()> {
+        writer.write_u16(self as u16).await?;
+        writer.flush().await
+    }
+}
+
+/// A file or subfolder inside a folder being downloaded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FolderItem {
+    /// Path relative to the transferred folder
+    pub path: Vec<String>,
+    pub physical_path: PathBuf,
+    /// Size of the data fork, or `None` for a folder
+    pub size: Option<u64>,
+}
+
+impl FolderItem {
+    pub fn kind(&self) -> FolderItemKind {
+        match self.size {
+            Some(_) => FolderItemKind::File,
+            None => FolderItemKind::Folder,
+        }
+    }
+}
+
+/// A folder download accepted by `DownloadFolder` (210)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingFolderDownload {
+    pub user_id: u16,
+    pub items: Vec<FolderItem>,
+}
+
+impl PendingFolderDownload {
+    /// Combined size of every file in the folder
+    pub fn total_size(&self) -> u64 {
+        self.items.iter().filter_map(|item| item.size).sum()
+    }
+    
+    /// Number of files in the folder
+    pub fn file_count(&self) -> usize {
+        self.items.iter().filter(|item| item.size.is_some()).count()
+    }
+    
+    /// Bytes sent on the transfer connection if the client asks for every
+    /// file: each item header, and each file's size and flattened file object
+    pub async fn transfer_size(&self) -> io::Result<u64> {
+        let mut size = 0;
+        for item in &self.items {
+            size += 4 + encode_file_path(&item.path).len() as u64;
+            if let Some(data_size) = item.size {
+                let info = flat_file_info(&item.physical_path).await?;
+                size += 4 + flat_file_size(&info, data_size);
+            }
+        }
+        Ok(size)
+    }
+    
+    /// Send the items the client asks for, returning the number of data
+    /// bytes written
+    ///
+    /// The client opens with a [`FolderAction`], then answers each item
+    /// header with another. A file it wants follows as a u32 size and a
+    /// flattened file object, after which the client sends one more action
+    /// to ask for the next item. Files are sent at the size they had when
+    /// the download was accepted.
+    pub async fn send_to<S: AsyncRead + AsyncWrite + Unpin>(&self, stream: &mut S) -> io::Result<u64> {
+        FolderAction::read_from(stream).await?;
+        
+        let mut written = 0;
+        for item in &self.items {
+            write_item_header(stream, item.kind(), &item.path).await?;
+            let offset = match FolderAction::read_from(stream).await? {
+                FolderAction::Send => 0,
+                FolderAction::Resume => read_resume_offset(stream).await?,
+                FolderAction::Next => continue,
+            };
+            
+            let Some(size) = item.size else {
+                continue;
+            };
+            let remaining = size.checked_sub(offset).ok_or_else(|| {
+                invalid_item(format!("resume at {} of a {} byte file", offset, size))
+            })?;
+            let info = flat_file_info(&item.physical_path).await?;
+            let flat_size = u32::try_from(flat_file_size(&info, remaining))
+                .map_err(|_| invalid_item(format!("{} is over 4 GiB", item.physical_path.display())))?;
+            
+            stream.write_u32(flat_size).await?;
+            written += write_flat_file(stream, &item.physical_path, &info, offset, remaining).await?;
+            FolderAction::read_from(stream).await?;
+        }
+        Ok(written)
+    }
+}
+
+/// A folder upload accepted by `UploadFolder` (213)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingFolderUpload {
+    pub user_id: u16,
+    /// Folder to create; it must not exist yet
+    pub physical_path: PathBuf,
+    /// Combined file size the client announced, if any
+    pub size: Option<u64>,
+    /// Number of items the client announced
+    pub item_count: u64,
+    /// How many levels below the folder items may be
+    pub max_depth: usize,
+    /// Whether the upload extension lists apply to the files
+    pub check_extensions: bool,
+}
+
+impl PendingFolderUpload {
+    /// Create the folder and everything the client sends, returning the
+    /// number of file bytes written
+    ///
+    /// Items with invalid names or nested too deeply fail the upload. Files
+    /// refused by the extension lists are skipped.
+    pub async fn receive_from<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: &mut S,
+        config: &FilesConfig,
+    ) -> io::Result<u64> {
+        tokio::fs::create_dir(&self.physical_path).await?;
+        
+        let resolver = PathResolver::new(config);
+        let accept = |path: &[String], kind: FolderItemKind| {
+            if path.len() > self.max_depth {
+                return Err(invalid_item(format!("{} levels deep", path.len())));
+            }
+            for name in path {
+                resolver.validate_name(name).map_err(|e| invalid_item(e.to_string()))?;
+            }
+            let name = path.last().map(String::as_str).unwrap_or_default();
+            Ok(kind == FolderItemKind::Folder
+                || !self.check_extensions
+                || config.upload_extension_allowed(name))
+        };
+        
+        receive_folder(
+            stream,
+            &self.physical_path,
+            self.item_count,
+            self.size.unwrap_or(u64::MAX),
+            accept,
+        )
+        .await
+    }
+}
+
+/// List everything below `root`, parents before their contents and each
+/// folder in name order
+///
+/// Symbolic links and names that aren't UTF-8 are left out. Anything more
+/// than `max_depth` levels down fails the listing with `InvalidData`, since
+/// it couldn't be sent.
+pub async fn scan_folder(root: &Path, max_depth: usize) -> io::Result<Vec<FolderItem>> {
+    let mut items = Vec::new();
+    let mut folders = vec![(root.to_path_buf(), Vec::<String>::new())];
+    
+    while let Some((folder, prefix)) = folders.pop() {
+        let mut entries = Vec::new();
+        let mut read_dir = tokio::fs::read_dir(&folder).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            entries.push(entry);
+        }
+        entries.sort_by_key(|entry| entry.file_name());
+        
+        // Pushed in reverse so the stack hands subfolders back in name order
+        let mut subfolders = Vec::new();
+        for entry in entries {
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            let file_type = entry.file_type().await?;
+            if !file_type.is_dir() && !file_type.is_file() {
+                continue;
+            }
+            
+            let mut path = prefix.clone();
+            path.push(name);
+            if path.len() > max_depth {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} is more than {} levels down", entry.path().display(), max_depth),
+                ));
+            }
+            
+            if file_type.is_dir() {
+                items.push(FolderItem {
+                    path: path.clone(),
+                    physical_path: entry.path(),
+                    size: None,
+                });
+                subfolders.push((entry.path(), path));
+            } else {
+                items.push(FolderItem {
+                    path,
+                    physical_path: entry.path(),
+                    size: Some(entry.metadata().await?.len()),
+                });
+            }
+        }
+        folders.extend(subfolders.into_iter().rev());
+    }
+    
+    Ok(items)
+}
+
+/// Recreate `item_count` items of a folder transfer below `root`, returning
+/// the number of file bytes written
+///
+/// Each item is asked for with [`FolderAction::Next`]. The client sends its
+/// header and, for a file it is told to send, a u32 size and a flattened
+/// file object.
+///
+/// `accept` sees each item's relative path: an error fails the transfer and
+/// `Ok(false)` skips the item, along with everything inside it if it is a
+/// folder. Files are never written over, so ones that already exist are
+/// skipped too, and their combined size may not exceed `max_size`.
+pub async fn receive_folder<S, F>(
+    stream: &mut S,
+    root: &Path,
+    item_count: u64,
+    max_size: u64,
+    accept: F,
+) -> io::Result<u64>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    F: Fn(&[String], FolderItemKind) -> io::Result<bool>,
+{
+    let mut received = 0u64;
+    let mut skipped_folders: Vec<Vec<String>> = Vec::new();
+    
+    for _ in 0..item_count {
+        FolderAction::Next.write_to(stream).await?;
+        
+        let (kind, path) = read_item_header(stream).await?;
+        let wanted = accept(&path, kind)? && !skipped_folders.iter().any(|folder| path.starts_with(folder));
+        let physical_path = path.iter().fold(root.to_path_buf(), |p, name| p.join(name));
+        
+        match kind {
+            FolderItemKind::Folder => {
+                if wanted {
+                    tokio::fs::create_dir_all(&physical_path).await?;
+                } else {
+                    skipped_folders.push(path);
+                }
+            }
+            FolderItemKind::File => {
+                if !wanted || tokio::fs::try_exists(&physical_path).await? {
+                    continue;
+                }
+                FolderAction::Send.write_to(stream).await?;
+                
+                let size = stream.read_u32().await? as u64;
+                if let Some(parent) = physical_path.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                let mut flat_file = (&mut *stream).take(size);
+                received += receive_file(&mut flat_file, &physical_path, max_size - received).await?;
+                
+                // Anything the client sent past the flattened file object
+                tokio::io::copy(&mut flat_file, &mut tokio::io::sink()).await?;
+            }
+        }
+    }
+    
+    // Told to move on once more after the last item
+    FolderAction::Next.write_to(stream).await?;
+    Ok(received)
+}
+
+/// Write the header that starts each folder item
+///
+/// - header_len: u16 (length of what follows)
+/// - kind: u16 (0 file, 1 folder)
+/// - path: FilePath field data, relative to the transferred folder
+async fn write_item_header<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    kind: FolderItemKind,
+    path: &[String],
+) -> io::Result<()> {
+    let path = encode_file_path(path);
+    writer.write_u16((2 + path.len()) as u16).await?;
+    writer.write_u16(kind as u16).await?;
+    writer.write_all(&path).await?;
+    writer.flush().await
+}
+
+/// Read a folder item header
+async fn read_item_header<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<(FolderItemKind, Vec<String>)> {
+    let header_len = reader.read_u16().await? as usize;
+    if header_len < 2 {
+        return Err(invalid_item(format!("header of {} bytes", header_len)));
+    }
+    let kind = reader.read_u16().await?;
+    let kind = FolderItemKind::from_u16(kind).ok_or_else(|| invalid_item(format!("type {}", kind)))?;
+    
+    let mut path = vec![0u8; header_len - 2];
+    reader.read_exact(&mut path).await?;
+    let path = decode_file_path(&path).map_err(|e| invalid_item(e.to_string()))?;
+    if path.is_empty() {
+        return Err(invalid_item("empty path".to_string()));
+    }
+    Ok((kind, path))
+}
+
+/// Read the resume data that follows [`FolderAction::Resume`], returning the
+/// data fork offset
+///
+/// - len: u16
+/// - resume data: FileResumeData field data (`RFLT`)
+async fn read_resume_offset<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<u64> {
+    let len = reader.read_u16().await? as usize;
+    let mut data = vec![0u8; len];
+    reader.read_exact(&mut data).await?;
+    let resume = ResumeData::from_bytes(&data).map_err(|e| invalid_item(e.to_string()))?;
+    Ok(resume.offset(DATA_FORK) as u64)
+}
+
+fn invalid_item(reason: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid folder item: {}", reason))
+}
+
+fn invalid_file(reason: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid flattened file: {}", reason))
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    active: Vec<(TransferId, u16)>,
+    waiting: VecDeque<(TransferId, u16)>,
+}
+
+impl QueueState {
+    fn contains(&self, id: TransferId) -> bool {
+        self.active.iter().chain(&self.waiting).any(|&(known, _)| known == id)
+    }
+    
+    /// Pick a fresh reference number
+    fn new_id(&self) -> TransferId {
+        loop {
+            let id = rand::random::<TransferId>();
+            if id != 0 && !self.contains(id) {
+                return id;
+            }
+        }
+    }
+    
+    fn has_room(&self, user_id: u16, limits: TransferLimits) -> bool {
+        let global_ok = limits.global == 0 || self.active.len() < limits.global;
+        let user_active = self.active.iter().filter(|&&(_, user)| user == user_id).count();
+        let user_ok = limits.per_user == 0 || user_active < limits.per_user;
+        global_ok && user_ok
+    }
+    
+    /// Start waiting transfers that now fit, in queue order
+    fn promote(&mut self, limits: TransferLimits) -> Vec<TransferId> {
+        let mut started = Vec::new();
+        let mut index = 0;
+        
+        while index < self.waiting.len() {
+            let (id, user_id) = self.waiting[index];
+            if self.has_room(user_id, limits) {
+                self.waiting.remove(index);
+                self.active.push((id, user_id));
+                started.push(id);
+            } else {
+                index += 1;
+            }
+        }
+        
+        started
+    }
+}
+
+/// Global transfer slot allocator
+#[derive(Debug, Default)]
+pub struct TransferQueue {
+    state: Mutex<QueueState>,
+}
+
+impl TransferQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Reserve a slot for a user's transfer
+    ///
+    /// The transfer starts immediately if both limits allow it, otherwise it
+    /// joins the back of the queue.
+    pub fn reserve(&self, user_id: u16, limits: TransferLimits) -> (TransferId, TransferStatus) {
+        let mut state = self.state.lock().unwrap();
+        let id = state.new_id();
+        
+        if state.waiting.is_empty() && state.has_room(user_id, limits) {
+            state.active.push((id, user_id));
+            return (id, TransferStatus::Active);
+        }
+        
+        state.waiting.push_back((id, user_id));
+        let position = state.waiting.len();
+        (id, TransferStatus::Queued { position })
+    }
+    
+    /// Current status of a transfer, or `None` if it isn't known
+    pub fn status(&self, id: TransferId) -> Option<TransferStatus> {
+        let state = self.state.lock().unwrap();
+        if state.active.iter().any(|&(active, _)| active == id) {
+            return Some(TransferStatus::Active);
+        }
+        
+        state
+            .waiting
+            .iter()
+            .position(|&(waiting, _)| waiting == id)
+            .map(|index| TransferStatus::Queued { position: index + 1 })
+    }
+    
+    /// Finish or cancel a transfer, returning the transfers that may now start
+    pub fn release(&self, id: TransferId, limits: TransferLimits) -> Vec<TransferId> {
+        let mut state = self.state.lock().unwrap();
+        state.active.retain(|&(active, _)| active != id);
+        state.waiting.retain(|&(waiting, _)| waiting != id);
+        state.promote(limits)
+    }
+    
+    /// Drop all of a user's transfers (e.g. on disconnect)
+    pub fn release_user(&self, user_id: u16, limits: TransferLimits) -> Vec<TransferId> {
+        let mut state = self.state.lock().unwrap();
+        state.active.retain(|&(_, user)| user != user_id);
+        state.waiting.retain(|&(_, user)| user != user_id);
+        state.promote(limits)
+    }
+    
+    /// Number of running transfers
+    pub fn active_count(&self) -> usize {
+        self.state.lock().unwrap().active.len()
+    }
+    
+    /// Number of waiting transfers
+    pub fn queued_count(&self) -> usize {
+        self.state.lock().unwrap().waiting.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const LIMITS: TransferLimits = TransferLimits { global: 2, per_user: 0 };
+    
+    #[test]
+    fn test_extra_transfers_are_queued_in_order() {
+        let queue = TransferQueue::new();
+        
+        let statuses: Vec<_> = (1..=5).map(|user| queue.reserve(user, LIMITS)).collect();
+        
+        assert_eq!(statuses[0].1, TransferStatus::Active);
+        assert_eq!(statuses[1].1, TransferStatus::Active);
+        assert_eq!(statuses[2].1, TransferStatus::Queued { position: 1 });
+        assert_eq!(statuses[3].1, TransferStatus::Queued { position: 2 });
+        assert_eq!(statuses[4].1, TransferStatus::Queued { position: 3 });
+        assert_eq!(statuses[4].1.waiting_count(), 3);
+        
+        // Finishing one starts the head of the queue and moves everyone up
+        let started = queue.release(statuses[0].0, LIMITS);
+        assert_eq!(started, vec![statuses[2].0]);
+        assert_eq!(queue.status(statuses[3].0), Some(TransferStatus::Queued { position: 1 }));
+        assert_eq!(queue.active_count(), 2);
+        assert_eq!(queue.queued_count(), 2);
+    }
+    
+    #[test]
+    fn test_per_user_limit_is_separate_from_global() {
+        let queue = TransferQueue::new();
+        let limits = TransferLimits { global: 3, per_user: 1 };
+        
+        let (first, status) = queue.reserve(1, limits);
+        assert_eq!(status, TransferStatus::Active);
+        
+        // Room globally, but user 1 is at their own limit
+        let (second, status) = queue.reserve(1, limits);
+        assert_eq!(status, TransferStatus::Queued { position: 1 });
+        
+        // Another user behind them in the queue can't jump ahead
+        let (third, status) = queue.reserve(2, limits);
+        assert_eq!(status, TransferStatus::Queued { position: 2 });
+        
+        // User 2 fits once the queue is re-evaluated; user 1 still doesn't
+        let started = queue.release(first, limits);
+        assert_eq!(started, vec![second, third]);
+        
+        let (_, status) = queue.reserve(3, limits);
+        assert_eq!(status, TransferStatus::Active);
+        
+        assert!(queue.release_user(1, limits).is_empty());
+        assert_eq!(queue.status(second), None);
+    }
+    
+    #[tokio::test]
+    async fn test_folder_download_resumes_and_skips() {
+        let source = crate::test_util::TempPath::new("folder_resume", "d");
+        std::fs::create_dir(&source).unwrap();
+        std::fs::write(source.join("a.txt"), b"0123456789").unwrap();
+        std::fs::write(source.join("b.txt"), b"skipped").unwrap();
+        let download = PendingFolderDownload {
+            user_id: 1,
+            items: scan_folder(&source, 1).await.unwrap(),
+        };
+        
+        let (mut server, mut client) = tokio::io::duplex(64);
+        let client = async move {
+            FolderAction::Next.write_to(&mut client).await.unwrap();
+            
+            let (kind, path) = read_item_header(&mut client).await.unwrap();
+            assert_eq!((kind, path), (FolderItemKind::File, vec!["a.txt".to_string()]));
+            let resume = ResumeData::data_fork(4).to_bytes();
+            FolderAction::Resume.write_to(&mut client).await.unwrap();
+            client.write_u16(resume.len() as u16).await.unwrap();
+            client.write_all(&resume).await.unwrap();
+            
+            let mut flat_file = vec![0u8; client.read_u32().await.unwrap() as usize];
+            client.read_exact(&mut flat_file).await.unwrap();
+            assert!(flat_file.ends_with(b"456789"));
+            FolderAction::Next.write_to(&mut client).await.unwrap();
+            
+            let (_, path) = read_item_header(&mut client).await.unwrap();
+            assert_eq!(path, vec!["b.txt".to_string()]);
+            FolderAction::Next.write_to(&mut client).await.unwrap();
+        };
+        
+        let (sent, ()) = tokio::join!(download.send_to(&mut server), client);
+        assert_eq!(sent.unwrap(), 6);
+    }
+    
+    /// A folder with a subfolder holding one file, and a file beside it
+    fn nested_folder(name: &str) -> crate::test_util::TempPath {
+        let source = crate::test_util::TempPath::new(name, "d");
+        std::fs::create_dir_all(source.join("sub")).unwrap();
+        std::fs::write(source.join("sub").join("inner.txt"), b"inside").unwrap();
+        std::fs::write(source.join("top.txt"), b"beside").unwrap();
+        source
+    }
+    
+    #[tokio::test]
+    async fn test_folder_transfer_size_matches_stream() {
+        let source = nested_folder("folder_size");
+        let download = PendingFolderDownload {
+            user_id: 1,
+            items: scan_folder(&source, 2).await.unwrap(),
+        };
+        
+        let (mut server, mut client) = tokio::io::duplex(64);
+        let items = download.items.len();
+        let client = async move {
+            FolderAction::Next.write_to(&mut client).await.unwrap();
+            let mut read = 0;
+            for _ in 0..items {
+                let (kind, path) = read_item_header(&mut client).await.unwrap();
+                read += 4 + encode_file_path(&path).len() as u64;
+                FolderAction::Send.write_to(&mut client).await.unwrap();
+                if kind == FolderItemKind::File {
+                    let mut flat_file = vec![0u8; client.read_u32().await.unwrap() as usize];
+                    client.read_exact(&mut flat_file).await.unwrap();
+                    read += 4 + flat_file.len() as u64;
+                    FolderAction::Next.write_to(&mut client).await.unwrap();
+                }
+            }
+            read
+        };
+        
+        let (sent, read) = tokio::join!(download.send_to(&mut server), client);
+        assert_eq!(sent.unwrap(), 12);
+        assert_eq!(read, download.transfer_size().await.unwrap());
+    }
+    
+    #[tokio::test]
+    async fn test_refused_folder_contents_skipped() {
+        let source = nested_folder("folder_refused");
+        let download = PendingFolderDownload {
+            user_id: 1,
+            items: scan_folder(&source, 2).await.unwrap(),
+        };
+        let target = crate::test_util::TempPath::new("folder_refused_target", "d");
+        std::fs::create_dir(&target).unwrap();
+        
+        let (mut server, mut client) = tokio::io::duplex(64);
+        let accept = |path: &[String], kind| Ok(!(kind == FolderItemKind::Folder && path == ["sub"]));
+        let (sent, received) = tokio::join!(
+            download.send_to(&mut server),
+            receive_folder(&mut client, &target, download.items.len() as u64, u64::MAX, accept),
+        );
+        assert_eq!(sent.unwrap(), 6);
+        assert_eq!(received.unwrap(), 6);
+        assert_eq!(std::fs::read(target.join("top.txt")).unwrap(), b"beside");
+        assert!(!target.join("sub").exists());
+    }
+}
@@ -1,13 +1,19 @@
 //! Integration tests for the TCP server
 
 use bytes::{BufMut, BytesMut};
+use rhxcore::client::Client;
 use rhxcore::codec::TransactionCodec;
 use rhxcore::protocol::{
-    Field, FieldId, Handshake, HandshakeReply, Transaction, TransactionType,
+    ErrorCode, Field, FieldId, Handshake, HandshakeReply, Transaction, TransactionType,
     PROTOCOL_MAGIC,
 };
+use rhxcore::password::xor_password;
+use rhxcore::types::AccessPrivileges;
+use rhxd::db::accounts::create_account;
+use rhxd::db::IN_MEMORY_PATH;
+use rhxd::test_util::TempPath;
 use rhxd::{Config, Server};
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tokio::time::timeout;
@@ -19,7 +25,7 @@ fn test_config() -> Config {
     let mut config = Config::default();
     // Use random port for testing
     config.server.port = 0; // OS will assign a free port
-    config.database.path = format!("/tmp/test_rhxd_{}.db", std::process::id()).into();
+    config.database.path = IN_MEMORY_PATH.into();
     config
 }
 
@@ -31,10 +37,9 @@ async fn test_server_starts_and_accepts_connections() {
         .try_init();
 
     let config = test_config();
-    let db_path = config.database.path.clone();
     
     // Create server
-    let server = Server::new(config).await.expect("Failed to create server");
+    let _server = Server::new(config).await.expect("Failed to create server");
     
     // Since we used port 0, we need to get the actual bound port
     // For this test, we'll use a known port instead
@@ -42,7 +47,7 @@ async fn test_server_starts_and_accepts_connections() {
     let test_port = 15500; // Use a high port for testing
     let mut config = config;
     config.server.port = test_port;
-    config.database.path = format!("/tmp/test_rhxd_{}.db", std::process::id()).into();
+    config.database.path = IN_MEMORY_PATH.into();
     
     let server = Server::new(config).await.expect("Failed to create server");
     
@@ -87,7 +92,6 @@ async fn test_server_starts_and_accepts_connections() {
     server_handle.abort();
     
     // Cleanup
-    std::fs::remove_file(&db_path).ok();
 }
 
 #[tokio::test]
@@ -100,8 +104,7 @@ async fn test_multiple_connections() {
     let test_port = 15501;
     config.server.port = test_port;
     config.server.max_connections = 5;
-    config.database.path = format!("/tmp/test_rhxd_multi_{}.db", std::process::id()).into();
-    let db_path = config.database.path.clone();
+    config.database.path = IN_MEMORY_PATH.into();
     
     let server = Server::new(config).await.expect("Failed to create server");
     
@@ -139,7 +142,6 @@ async fn test_multiple_connections() {
     server_handle.abort();
     
     // Cleanup
-    std::fs::remove_file(&db_path).ok();
 }
 
 #[tokio::test]
@@ -152,8 +154,7 @@ async fn test_connection_limit() {
     let test_port = 15502;
     config.server.port = test_port;
     config.server.max_connections = 2; // Only allow 2 connections
-    config.database.path = format!("/tmp/test_rhxd_limit_{}.db", std::process::id()).into();
-    let db_path = config.database.path.clone();
+    config.database.path = IN_MEMORY_PATH.into();
     
     let server = Server::new(config).await.expect("Failed to create server");
     
@@ -191,7 +192,6 @@ async fn test_connection_limit() {
     // Cleanup
     tokio::time::sleep(Duration::from_millis(100)).await;
     server_handle.abort();
-    std::fs::remove_file(&db_path).ok();
 }
 
 /// Helper function to perform handshake and return framed connection
@@ -258,8 +258,7 @@ async fn test_chat_broadcast() {
     let test_port = 15506;
     config.server.port = test_port;
     config.security.allow_guest = true; // Enable guest login for testing
-    config.database.path = format!("/tmp/test_rhxd_chat_{}.db", std::process::id()).into();
-    let db_path = config.database.path.clone();
+    config.database.path = IN_MEMORY_PATH.into();
     
     let server = Server::new(config).await.expect("Failed to create server");
     
@@ -278,11 +277,15 @@ async fn test_chat_broadcast() {
     // Login both clients as guests
     login_as_guest(&mut client1).await.expect("Client 1 login failed");
     login_as_guest(&mut client2).await.expect("Client 2 login failed");
+    agree(&mut client1, "Client 1").await.expect("Client 1 agreed failed");
+    agree(&mut client2, "Client 2").await.expect("Client 2 agreed failed");
     
     println!("Both clients logged in successfully");
     
     // Client 1 sends a chat message
     let chat_message = b"Hello from client 1!";
+    // The server relays chat as a formatted line: "\r<nick right-aligned to 13>:  <text>"
+    let expected_line = format!("\r{:>13.13}:  {}", "Client 1", "Hello from client 1!");
     let chat_tx = Transaction {
         flags: 0,
         is_reply: false,
@@ -302,11 +305,9 @@ async fn test_chat_broadcast() {
     
     // Both clients should receive the broadcast
     // Client 1 receives its own message
-    let broadcast1 = timeout(Duration::from_secs(2), client1.next())
+    let broadcast1 = next_of_type(&mut client1, TransactionType::ChatMessage, Duration::from_secs(2))
         .await
-        .expect("Timeout waiting for broadcast to client 1")
-        .expect("No broadcast received")
-        .expect("Error receiving broadcast");
+        .expect("No broadcast received by client 1");
     
     assert_eq!(broadcast1.transaction_type, TransactionType::ChatMessage);
     
@@ -315,7 +316,7 @@ async fn test_chat_broadcast() {
         .and_then(|f| f.as_binary())
         .expect("No message data");
     
-    assert_eq!(msg_data, chat_message);
+    assert_eq!(msg_data, expected_line.as_bytes());
     
     let sender_id = broadcast1.fields.iter()
         .find(|f| f.id == FieldId::UserId)
@@ -328,11 +329,9 @@ async fn test_chat_broadcast() {
     println!("Client 1 received its own broadcast");
     
     // Client 2 receives the message
-    let broadcast2 = timeout(Duration::from_secs(2), client2.next())
+    let broadcast2 = next_of_type(&mut client2, TransactionType::ChatMessage, Duration::from_secs(2))
         .await
-        .expect("Timeout waiting for broadcast to client 2")
-        .expect("No broadcast received")
-        .expect("Error receiving broadcast");
+        .expect("No broadcast received by client 2");
     
     assert_eq!(broadcast2.transaction_type, TransactionType::ChatMessage);
     
@@ -341,7 +340,7 @@ async fn test_chat_broadcast() {
         .and_then(|f| f.as_binary())
         .expect("No message data");
     
-    assert_eq!(msg_data, chat_message);
+    assert_eq!(msg_data, expected_line.as_bytes());
     
     let sender_id = broadcast2.fields.iter()
         .find(|f| f.id == FieldId::UserId)
@@ -359,7 +358,6 @@ async fn test_chat_broadcast() {
     drop(client2);
     tokio::time::sleep(Duration::from_millis(100)).await;
     server_handle.abort();
-    std::fs::remove_file(&db_path).ok();
 }
 
 #[tokio::test]
@@ -372,8 +370,7 @@ async fn test_agreed_notification() {
     let test_port = 15507;
     config.server.port = test_port;
     config.security.allow_guest = true;
-    config.database.path = format!("/tmp/test_rhxd_agreed_{}.db", std::process::id()).into();
-    let db_path = config.database.path.clone();
+    config.database.path = IN_MEMORY_PATH.into();
     
     let server = Server::new(config).await.expect("Failed to create server");
     
@@ -416,56 +413,19 @@ async fn test_agreed_notification() {
     println!("Client 1 sent Agreed transaction");
     
     // Client 1 should receive acknowledgment reply
-    let reply = timeout(Duration::from_secs(2), client1.next())
+    let reply = next_of_type(&mut client1, TransactionType::Agreed, Duration::from_secs(2))
         .await
-        .expect("Timeout waiting for agreed reply")
-        .expect("No reply received")
-        .expect("Error receiving reply");
+        .expect("No agreed reply received");
     
-    assert_eq!(reply.transaction_type, TransactionType::Agreed);
     assert!(reply.is_reply);
     assert_eq!(reply.error_code, 0);
     
     println!("Client 1 received Agreed acknowledgment");
     
-    // Both clients should receive NotifyChangeUser broadcast
-    // Client 1 receives notification about itself
-    let notify1 = timeout(Duration::from_secs(2), client1.next())
-        .await
-        .expect("Timeout waiting for notify to client 1")
-        .expect("No notification received")
-        .expect("Error receiving notification");
-    
-    assert_eq!(notify1.transaction_type, TransactionType::NotifyChangeUser);
-    
-    // Parse UserNameWithInfo field
-    let user_info = notify1.fields.iter()
-        .find(|f| f.id == FieldId::UserNameWithInfo)
-        .and_then(|f| f.as_binary())
-        .expect("No UserNameWithInfo field");
-    
-    // Parse the binary format: user_id (2) + icon_id (2) + flags (2) + name_len (2) + name
-    assert!(user_info.len() >= 8, "UserNameWithInfo too short");
-    
-    let notified_user_id = u16::from_be_bytes([user_info[0], user_info[1]]);
-    let icon_id = u16::from_be_bytes([user_info[2], user_info[3]]);
-    let name_len = u16::from_be_bytes([user_info[6], user_info[7]]);
-    let nickname = String::from_utf8_lossy(&user_info[8..8 + name_len as usize]);
-    
-    assert_eq!(notified_user_id, 1);
-    assert_eq!(icon_id, 42);
-    assert_eq!(nickname, "TestUser1");
-    
-    println!("Client 1 received NotifyChangeUser for itself");
-    
     // Client 2 receives notification about client 1
-    let notify2 = timeout(Duration::from_secs(2), client2.next())
+    let notify2 = next_of_type(&mut client2, TransactionType::NotifyChangeUser, Duration::from_secs(2))
         .await
-        .expect("Timeout waiting for notify to client 2")
-        .expect("No notification received")
-        .expect("Error receiving notification");
-    
-    assert_eq!(notify2.transaction_type, TransactionType::NotifyChangeUser);
+        .expect("No notification received by client 2");
     
     let user_info = notify2.fields.iter()
         .find(|f| f.id == FieldId::UserNameWithInfo)
@@ -482,6 +442,11 @@ async fn test_agreed_notification() {
     assert_eq!(nickname, "TestUser1");
     
     println!("Client 2 received NotifyChangeUser for client 1");
+    
+    // The join isn't announced back to the user who joined
+    let echo = next_of_type(&mut client1, TransactionType::NotifyChangeUser, Duration::from_millis(300)).await;
+    assert!(echo.is_none(), "Client 1 was notified of its own join");
+    
     println!("Agreed notification test successful!");
     
     // Cleanup
@@ -489,7 +454,6 @@ async fn test_agreed_notification() {
     drop(client2);
     tokio::time::sleep(Duration::from_millis(100)).await;
     server_handle.abort();
-    std::fs::remove_file(&db_path).ok();
 }
 
 #[tokio::test]
@@ -502,8 +466,7 @@ async fn test_get_user_name_list() {
     let test_port = 15508;
     config.server.port = test_port;
     config.security.allow_guest = true;
-    config.database.path = format!("/tmp/test_rhxd_userlist_{}.db", std::process::id()).into();
-    let db_path = config.database.path.clone();
+    config.database.path = IN_MEMORY_PATH.into();
     
     let server = Server::new(config).await.expect("Failed to create server");
     
@@ -646,7 +609,6 @@ async fn test_get_user_name_list() {
     drop(client3);
     tokio::time::sleep(Duration::from_millis(100)).await;
     server_handle.abort();
-    std::fs::remove_file(&db_path).ok();
 }
 
 
@@ -659,8 +621,7 @@ async fn test_handshake_success() {
     let mut config = Config::default();
     let test_port = 15503;
     config.server.port = test_port;
-    config.database.path = format!("/tmp/test_rhxd_handshake_{}.db", std::process::id()).into();
-    let db_path = config.database.path.clone();
+    config.database.path = IN_MEMORY_PATH.into();
     
     let server = Server::new(config).await.expect("Failed to create server");
     
@@ -699,7 +660,6 @@ async fn test_handshake_success() {
     drop(stream);
     tokio::time::sleep(Duration::from_millis(100)).await;
     server_handle.abort();
-    std::fs::remove_file(&db_path).ok();
 }
 
 #[tokio::test]
@@ -711,8 +671,7 @@ async fn test_handshake_invalid_protocol() {
     let mut config = Config::default();
     let test_port = 15504;
     config.server.port = test_port;
-    config.database.path = format!("/tmp/test_rhxd_invalid_{}.db", std::process::id()).into();
-    let db_path = config.database.path.clone();
+    config.database.path = IN_MEMORY_PATH.into();
     
     let server = Server::new(config).await.expect("Failed to create server");
     
@@ -759,7 +718,6 @@ async fn test_handshake_invalid_protocol() {
     drop(stream);
     tokio::time::sleep(Duration::from_millis(100)).await;
     server_handle.abort();
-    std::fs::remove_file(&db_path).ok();
 }
 
 #[tokio::test]
@@ -771,8 +729,7 @@ async fn test_handshake_unsupported_version() {
     let mut config = Config::default();
     let test_port = 15505;
     config.server.port = test_port;
-    config.database.path = format!("/tmp/test_rhxd_version_{}.db", std::process::id()).into();
-    let db_path = config.database.path.clone();
+    config.database.path = IN_MEMORY_PATH.into();
     
     let server = Server::new(config).await.expect("Failed to create server");
     
@@ -819,5 +776,1315 @@ async fn test_handshake_unsupported_version() {
     drop(stream);
     tokio::time::sleep(Duration::from_millis(100)).await;
     server_handle.abort();
-    std::fs::remove_file(&db_path).ok();
+}
+
+#[tokio::test]
+async fn test_transaction_capture() {
+    let _ = tracing_subscriber::fmt()
+        .with_test_writer()
+        .try_init();
+
+    let mut config = Config::default();
+    let test_port = 15509;
+    config.server.port = test_port;
+    config.security.allow_guest = true;
+    config.database.path = IN_MEMORY_PATH.into();
+    let capture_path = TempPath::new("capture", "jsonl");
+    config.debug.capture_path = Some(capture_path.to_path_buf());
+    
+    let server = Server::new(config).await.expect("Failed to create server");
+    
+    let server_handle = tokio::spawn(async move {
+        server.run().await
+    });
+    
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    
+    let addr = format!("127.0.0.1:{}", test_port);
+    let mut client = connect_and_handshake(&addr).await.expect("Handshake failed");
+    login_as_guest(&mut client).await.expect("Login failed");
+    
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    
+    // Each line of the capture is a standalone JSON object
+    let capture = std::fs::read_to_string(&capture_path).expect("Capture file not written");
+    let entries: Vec<serde_json::Value> = capture
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("Invalid capture line"))
+        .collect();
+    
+    let request = entries.iter()
+        .find(|e| e["direction"] == "in" && e["type"] == "Login")
+        .expect("No capture entry for login request");
+    assert_eq!(request["is_reply"], false);
+    assert_eq!(request["id"], 1);
+    assert_eq!(request["fields"].as_array().map(|f| f.len()), Some(2));
+    
+    let reply = entries.iter()
+        .find(|e| e["direction"] == "out" && e["type"] == "Login")
+        .expect("No capture entry for login reply");
+    assert_eq!(reply["is_reply"], true);
+    assert_eq!(reply["id"], 1);
+    assert_eq!(reply["error_code"], 0);
+    assert!(reply["user_id"].as_u64().is_some());
+    
+    println!("Captured {} transactions", entries.len());
+    
+    // Cleanup
+    drop(client);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    server_handle.abort();
+}
+
+/// Helper function to login with account credentials
+async fn login_with_account(
+    framed: &mut Framed<TcpStream, TransactionCodec>,
+    login: &str,
+    password: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let login_tx = Transaction {
+        flags: 0,
+        is_reply: false,
+        transaction_type: TransactionType::Login,
+        id: 1,
+        error_code: 0,
+        total_size: 0,
+        data_size: 0,
+        fields: vec![
+            Field::binary(FieldId::UserLogin, xor_password(login.as_bytes())),
+            Field::binary(FieldId::UserPassword, xor_password(password.as_bytes())),
+        ],
+    };
+    
+    framed.send(login_tx).await?;
+    
+    let reply = timeout(Duration::from_secs(2), framed.next())
+        .await?
+        .ok_or("No login reply")??;
+    
+    if reply.error_code != 0 {
+        return Err(format!("Login failed with error code {}", reply.error_code).into());
+    }
+    
+    Ok(())
+}
+
+/// Helper function to wait for the next transaction of a type, skipping any others
+async fn next_of_type(
+    framed: &mut Framed<TcpStream, TransactionCodec>,
+    transaction_type: TransactionType,
+    wait: Duration,
+) -> Option<Transaction> {
+    loop {
+        match timeout(wait, framed.next()).await {
+            Ok(Some(Ok(tx))) if tx.transaction_type == transaction_type => return Some(tx),
+            Ok(Some(Ok(_))) => continue,
+            _ => return None,
+        }
+    }
+}
+
+/// Helper function to accept the agreement after logging in
+async fn agree(
+    framed: &mut Framed<TcpStream, TransactionCodec>,
+    nickname: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut agreed = Transaction::new(TransactionType::Agreed);
+    agreed.id = 3;
+    agreed.add_field(Field::string(FieldId::UserName, nickname));
+    framed.send(agreed).await?;
+    
+    let reply = next_of_type(framed, TransactionType::Agreed, Duration::from_secs(2))
+        .await
+        .ok_or("No agreed reply")?;
+    
+    if reply.error_code != 0 {
+        return Err(format!("Agreed failed with error code {}", reply.error_code).into());
+    }
+    
+    Ok(())
+}
+
+/// Helper function to build a SendChat transaction
+fn chat_transaction(id: u32, message: &str) -> Transaction {
+    Transaction {
+        flags: 0,
+        is_reply: false,
+        transaction_type: TransactionType::SendChat,
+        id,
+        error_code: 0,
+        total_size: 0,
+        data_size: 0,
+        fields: vec![Field::binary(FieldId::Data, message.as_bytes().to_vec())],
+    }
+}
+
+#[tokio::test]
+async fn test_chat_command_users_private_reply() {
+    let _ = tracing_subscriber::fmt()
+        .with_test_writer()
+        .try_init();
+
+    let mut config = Config::default();
+    let test_port = 15510;
+    config.server.port = test_port;
+    config.security.allow_guest = true;
+    config.database.path = IN_MEMORY_PATH.into();
+    
+    let server = Server::new(config).await.expect("Failed to create server");
+    let state = server.state();
+    
+    create_account(
+        state.database.pool(),
+        "admin",
+        &xor_password(b"secret"),
+        "Admin",
+        AccessPrivileges::admin(),
+    )
+    .await
+    .expect("Failed to create admin account");
+    
+    let server_handle = tokio::spawn(async move {
+        server.run().await
+    });
+    
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    
+    let addr = format!("127.0.0.1:{}", test_port);
+    let mut admin = connect_and_handshake(&addr).await.expect("Admin handshake failed");
+    let mut guest = connect_and_handshake(&addr).await.expect("Guest handshake failed");
+    
+    login_with_account(&mut admin, "admin", "secret").await.expect("Admin login failed");
+    login_as_guest(&mut guest).await.expect("Guest login failed");
+    agree(&mut admin, "Admin").await.expect("Admin agreed failed");
+    agree(&mut guest, "Guest").await.expect("Guest agreed failed");
+    
+    admin.send(chat_transaction(2, "/users")).await.expect("Failed to send chat");
+    
+    // The admin gets the user list back as a private chat line
+    let reply = next_of_type(&mut admin, TransactionType::ChatMessage, Duration::from_secs(2))
+        .await
+        .expect("No reply to chat command");
+    
+    let text = reply.fields.iter()
+        .find(|f| f.id == FieldId::Data)
+        .and_then(|f| f.as_binary())
+        .map(|b| String::from_utf8_lossy(b).to_string())
+        .expect("No message data");
+    
+    assert!(text.contains("Nickname"), "Expected user list, got: {}", text);
+    assert!(text.contains("Admin"), "Expected admin in user list, got: {}", text);
+    assert!(!text.contains('\n'), "Chat output should use \\r line separators");
+    
+    // Nobody else sees it
+    let leaked = next_of_type(&mut guest, TransactionType::ChatMessage, Duration::from_millis(300)).await;
+    assert!(leaked.is_none(), "Chat command output was broadcast");
+    
+    // Cleanup
+    drop(admin);
+    drop(guest);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_chat_command_from_unprivileged_user_is_chat() {
+    let _ = tracing_subscriber::fmt()
+        .with_test_writer()
+        .try_init();
+
+    let mut config = Config::default();
+    let test_port = 15511;
+    config.server.port = test_port;
+    config.security.allow_guest = true;
+    config.database.path = IN_MEMORY_PATH.into();
+    
+    let server = Server::new(config).await.expect("Failed to create server");
+    let state = server.state();
+    
+    let server_handle = tokio::spawn(async move {
+        server.run().await
+    });
+    
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    
+    let addr = format!("127.0.0.1:{}", test_port);
+    let mut client1 = connect_and_handshake(&addr).await.expect("Client 1 handshake failed");
+    let mut client2 = connect_and_handshake(&addr).await.expect("Client 2 handshake failed");
+    
+    login_as_guest(&mut client1).await.expect("Client 1 login failed");
+    login_as_guest(&mut client2).await.expect("Client 2 login failed");
+    agree(&mut client1, "Client 1").await.expect("Client 1 agreed failed");
+    agree(&mut client2, "Client 2").await.expect("Client 2 agreed failed");
+    
+    client1.send(chat_transaction(2, "/kick 2")).await.expect("Failed to send chat");
+    
+    // The other user sees it as an ordinary chat line
+    let broadcast = next_of_type(&mut client2, TransactionType::ChatMessage, Duration::from_secs(2))
+        .await
+        .expect("Slash message was not broadcast");
+    
+    let text = broadcast.fields.iter()
+        .find(|f| f.id == FieldId::Data)
+        .and_then(|f| f.as_binary())
+        .map(|b| String::from_utf8_lossy(b).to_string())
+        .expect("No message data");
+    
+    assert!(text.contains("/kick 2"));
+    
+    // And nobody was kicked
+    assert_eq!(state.session_count(), 2);
+    
+    // Cleanup
+    drop(client1);
+    drop(client2);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    server_handle.abort();
+}
+
+/// Send a guest login and return the server name from the reply
+async fn guest_login_server_name(
+    framed: &mut Framed<TcpStream, TransactionCodec>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let login_tx = Transaction {
+        flags: 0,
+        is_reply: false,
+        transaction_type: TransactionType::Login,
+        id: 1,
+        error_code: 0,
+        total_size: 0,
+        data_size: 0,
+        fields: vec![],
+    };
+    
+    framed.send(login_tx).await?;
+    
+    let reply = timeout(Duration::from_secs(2), framed.next())
+        .await?
+        .ok_or("No login reply")??;
+    
+    let name = reply.fields.iter()
+        .find(|f| f.id == FieldId::ServerName)
+        .and_then(|f| f.as_string())
+        .ok_or("No server name in login reply")?;
+    
+    Ok(name.to_string())
+}
+
+#[tokio::test]
+async fn test_reload_config_applies_to_new_logins() {
+    let _ = tracing_subscriber::fmt()
+        .with_test_writer()
+        .try_init();
+
+    let mut config = Config::default();
+    let test_port = 15512;
+    config.server.port = test_port;
+    config.server.name = "Before Reload".to_string();
+    config.security.allow_guest = true;
+    config.database.path = IN_MEMORY_PATH.into();
+    
+    let server = Server::new(config).await.expect("Failed to create server");
+    let state = server.state();
+    
+    let server_handle = tokio::spawn(async move {
+        server.run().await
+    });
+    
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    
+    let addr = format!("127.0.0.1:{}", test_port);
+    
+    let mut client1 = connect_and_handshake(&addr).await.expect("Client 1 handshake failed");
+    let name = guest_login_server_name(&mut client1).await.expect("Client 1 login failed");
+    assert_eq!(name, "Before Reload");
+    
+    // Swap in a new configuration
+    let mut new_config = (*state.config()).clone();
+    new_config.server.name = "After Reload".to_string();
+    state.reload_config(new_config);
+    assert_eq!(state.config().server.name, "After Reload");
+    
+    let mut client2 = connect_and_handshake(&addr).await.expect("Client 2 handshake failed");
+    let name = guest_login_server_name(&mut client2).await.expect("Client 2 login failed");
+    assert_eq!(name, "After Reload");
+    
+    // Cleanup
+    drop(client1);
+    drop(client2);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_only_inbound_transactions_reset_idle_time() {
+    let _ = tracing_subscriber::fmt()
+        .with_test_writer()
+        .try_init();
+
+    let mut config = Config::default();
+    let test_port = 15513;
+    config.server.port = test_port;
+    config.security.allow_guest = true;
+    config.database.path = IN_MEMORY_PATH.into();
+    
+    let server = Server::new(config).await.expect("Failed to create server");
+    let state = server.state();
+    
+    let server_handle = tokio::spawn(async move {
+        server.run().await
+    });
+    
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    
+    let addr = format!("127.0.0.1:{}", test_port);
+    
+    let mut listener = connect_and_handshake(&addr).await.expect("Listener handshake failed");
+    login_as_guest(&mut listener).await.expect("Listener login failed");
+    let mut talker = connect_and_handshake(&addr).await.expect("Talker handshake failed");
+    login_as_guest(&mut talker).await.expect("Talker login failed");
+    agree(&mut listener, "Listener").await.expect("Listener agreed failed");
+    agree(&mut talker, "Talker").await.expect("Talker agreed failed");
+    
+    // First client gets the lower user ID
+    let mut user_ids: Vec<u16> = state.sessions.iter().map(|s| s.user_id).collect();
+    user_ids.sort();
+    let (listener_id, talker_id) = (user_ids[0], user_ids[1]);
+    
+    // Pretend both have been idle for an hour
+    let an_hour_ago = SystemTime::now() - Duration::from_secs(3600);
+    for user_id in [listener_id, talker_id] {
+        state.update_session(user_id, |session| session.last_activity = an_hour_ago).unwrap();
+    }
+    
+    talker.send(chat_transaction(2, "Anyone here?")).await.expect("Failed to send chat");
+    next_of_type(&mut listener, TransactionType::ChatMessage, Duration::from_secs(2))
+        .await
+        .expect("Listener did not receive the chat broadcast");
+    
+    let idle = |user_id: u16| {
+        SystemTime::now()
+            .duration_since(state.get_session(user_id).unwrap().last_activity)
+            .unwrap_or_default()
+    };
+    
+    // Receiving a broadcast is not activity; sending a transaction is
+    assert!(idle(listener_id) >= Duration::from_secs(3590));
+    assert!(idle(talker_id) < Duration::from_secs(60));
+    
+    drop(listener);
+    drop(talker);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_shutdown_sends_reason_before_disconnect() {
+    let _ = tracing_subscriber::fmt()
+        .with_test_writer()
+        .try_init();
+
+    let mut config = Config::default();
+    let test_port = 15514;
+    config.server.port = test_port;
+    config.server.shutdown_message = "Back after maintenance".to_string();
+    config.security.allow_guest = true;
+    config.database.path = IN_MEMORY_PATH.into();
+    
+    let server = Server::new(config).await.expect("Failed to create server");
+    let shutdown = server.shutdown_handle();
+    
+    let server_handle = tokio::spawn(async move {
+        server.run().await
+    });
+    
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    
+    let addr = format!("127.0.0.1:{}", test_port);
+    let mut client = connect_and_handshake(&addr).await.expect("Handshake failed");
+    login_as_guest(&mut client).await.expect("Login failed");
+    
+    shutdown.notify_waiters();
+    
+    let notice = next_of_type(&mut client, TransactionType::DisconnectMsg, Duration::from_secs(2))
+        .await
+        .expect("No shutdown notice received");
+    let reason = notice.get_field(FieldId::Data).and_then(|f| f.as_binary());
+    assert_eq!(reason, Some(&b"Back after maintenance"[..]));
+    
+    // The connection closes after the notice
+    let next = timeout(Duration::from_secs(2), client.next())
+        .await
+        .expect("Connection was not closed after the shutdown notice");
+    assert!(next.is_none());
+    
+    server_handle.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_malformed_transaction_is_skipped() {
+    let _ = tracing_subscriber::fmt()
+        .with_test_writer()
+        .try_init();
+
+    let mut config = Config::default();
+    let test_port = 15515;
+    config.server.port = test_port;
+    config.security.allow_guest = true;
+    config.database.path = IN_MEMORY_PATH.into();
+    
+    let server = Server::new(config).await.expect("Failed to create server");
+    
+    let server_handle = tokio::spawn(async move {
+        server.run().await
+    });
+    
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    
+    let addr = format!("127.0.0.1:{}", test_port);
+    let mut client = connect_and_handshake(&addr).await.expect("Handshake failed");
+    login_as_guest(&mut client).await.expect("Login failed");
+    agree(&mut client, "Guest").await.expect("Agreed failed");
+    
+    // A frame with an unknown transaction type and a few bytes of data
+    let mut malformed = BytesMut::new();
+    malformed.put_u8(0); // flags
+    malformed.put_u8(0); // is_reply
+    malformed.put_u16(9999); // transaction type
+    malformed.put_u32(6); // id
+    malformed.put_u32(0); // error code
+    malformed.put_u32(4); // total size
+    malformed.put_u32(4); // data size
+    malformed.put_slice(&[0xDE, 0xAD, 0xBE, 0xEF]);
+    client.get_mut().write_all(&malformed).await.expect("Failed to send malformed frame");
+    
+    // The connection survives and the next transaction is handled
+    let mut request = Transaction::new(TransactionType::GetUserNameList);
+    request.id = 7;
+    client.send(request).await.expect("Failed to send user list request");
+    
+    let reply = next_of_type(&mut client, TransactionType::GetUserNameList, Duration::from_secs(2))
+        .await
+        .expect("Valid transaction after a malformed one was not processed");
+    assert!(reply.is_reply);
+    assert_eq!(reply.id, 7);
+    
+    drop(client);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_chat_subject_sent_to_joining_users() {
+    let _ = tracing_subscriber::fmt()
+        .with_test_writer()
+        .try_init();
+
+    let mut config = Config::default();
+    let test_port = 15516;
+    config.server.port = test_port;
+    config.security.allow_guest = true;
+    config.database.path = IN_MEMORY_PATH.into();
+    
+    let server = Server::new(config).await.expect("Failed to create server");
+    let state = server.state();
+    
+    create_account(
+        state.database.pool(),
+        "admin",
+        &xor_password(b"secret"),
+        "Admin",
+        AccessPrivileges::admin(),
+    )
+    .await
+    .expect("Failed to create admin account");
+    
+    let server_handle = tokio::spawn(async move {
+        server.run().await
+    });
+    
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    
+    let addr = format!("127.0.0.1:{}", test_port);
+    let mut admin = connect_and_handshake(&addr).await.expect("Admin handshake failed");
+    login_with_account(&mut admin, "admin", "secret").await.expect("Admin login failed");
+    agree(&mut admin, "Admin").await.expect("Admin agreed failed");
+    
+    let mut set_subject = Transaction::new(TransactionType::SetChatSubject);
+    set_subject.id = 2;
+    set_subject.add_field(Field::string(FieldId::ChatSubject, "Welcome to the lounge"));
+    admin.send(set_subject).await.expect("Failed to send chat subject");
+    
+    // Everyone already connected hears about it
+    let notice = next_of_type(&mut admin, TransactionType::NotifyChatSubject, Duration::from_secs(2))
+        .await
+        .expect("Subject change was not broadcast");
+    let subject = notice.get_field(FieldId::ChatSubject).and_then(|f| f.as_string());
+    assert_eq!(subject, Some("Welcome to the lounge"));
+    
+    // A user joining later gets it once they agree
+    let mut guest = connect_and_handshake(&addr).await.expect("Guest handshake failed");
+    login_as_guest(&mut guest).await.expect("Guest login failed");
+    
+    let mut agreed = Transaction::new(TransactionType::Agreed);
+    agreed.id = 3;
+    agreed.add_field(Field::string(FieldId::UserName, "Newcomer"));
+    guest.send(agreed).await.expect("Failed to send agreed");
+    
+    let notice = next_of_type(&mut guest, TransactionType::NotifyChatSubject, Duration::from_secs(2))
+        .await
+        .expect("Joining user did not receive the chat subject");
+    let subject = notice.get_field(FieldId::ChatSubject).and_then(|f| f.as_string());
+    assert_eq!(subject, Some("Welcome to the lounge"));
+    
+    drop(admin);
+    drop(guest);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_failed_login_alerts_admins_only() {
+    let _ = tracing_subscriber::fmt()
+        .with_test_writer()
+        .try_init();
+
+    let mut config = Config::default();
+    let test_port = 15517;
+    config.server.port = test_port;
+    config.security.allow_guest = true;
+    config.database.path = IN_MEMORY_PATH.into();
+    
+    let server = Server::new(config).await.expect("Failed to create server");
+    let state = server.state();
+    
+    create_account(
+        state.database.pool(),
+        "admin",
+        &xor_password(b"secret"),
+        "Admin",
+        AccessPrivileges::admin(),
+    )
+    .await
+    .expect("Failed to create admin account");
+    
+    let server_handle = tokio::spawn(async move {
+        server.run().await
+    });
+    
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    
+    let addr = format!("127.0.0.1:{}", test_port);
+    let mut admin = connect_and_handshake(&addr).await.expect("Admin handshake failed");
+    let mut guest = connect_and_handshake(&addr).await.expect("Guest handshake failed");
+    login_with_account(&mut admin, "admin", "secret").await.expect("Admin login failed");
+    login_as_guest(&mut guest).await.expect("Guest login failed");
+    
+    let mut intruder = connect_and_handshake(&addr).await.expect("Intruder handshake failed");
+    let failed = login_with_account(&mut intruder, "admin", "guess").await;
+    assert!(failed.is_err(), "Login with a wrong password succeeded");
+    
+    let alert = next_of_type(&mut admin, TransactionType::ServerMessage, Duration::from_secs(2))
+        .await
+        .expect("Admin was not alerted");
+    let text = alert.get_field(FieldId::Data).and_then(|f| f.as_text().ok()).expect("No alert text");
+    assert!(text.contains("Failed login for 'admin'"), "Unexpected alert: {}", text);
+    
+    let leaked = next_of_type(&mut guest, TransactionType::ServerMessage, Duration::from_millis(300)).await;
+    assert!(leaked.is_none(), "Guest saw an admin alert");
+    
+    drop(admin);
+    drop(guest);
+    drop(intruder);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_chat_before_login_rejected() {
+    let _ = tracing_subscriber::fmt()
+        .with_test_writer()
+        .try_init();
+
+    let mut config = Config::default();
+    let test_port = 15518;
+    config.server.port = test_port;
+    config.security.allow_guest = true;
+    config.database.path = IN_MEMORY_PATH.into();
+    
+    let server = Server::new(config).await.expect("Failed to create server");
+    
+    let server_handle = tokio::spawn(async move {
+        server.run().await
+    });
+    
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    
+    let addr = format!("127.0.0.1:{}", test_port);
+    let mut client = connect_and_handshake(&addr).await.expect("Handshake failed");
+    
+    client.send(chat_transaction(2, "Too early")).await.expect("Failed to send chat");
+    let reply = next_of_type(&mut client, TransactionType::SendChat, Duration::from_secs(2))
+        .await
+        .expect("Chat before login was not refused");
+    assert!(reply.is_reply);
+    assert_eq!(reply.error_code, ErrorCode::PermissionDenied.to_u32());
+    
+    // Still refused after login until the agreement is accepted
+    login_as_guest(&mut client).await.expect("Login failed");
+    client.send(chat_transaction(4, "Still early")).await.expect("Failed to send chat");
+    let reply = next_of_type(&mut client, TransactionType::SendChat, Duration::from_secs(2))
+        .await
+        .expect("Chat before agreeing was not refused");
+    assert_eq!(reply.id, 4);
+    assert_eq!(reply.error_code, ErrorCode::PermissionDenied.to_u32());
+    
+    agree(&mut client, "Guest").await.expect("Agreed failed");
+    client.send(chat_transaction(5, "Hello")).await.expect("Failed to send chat");
+    next_of_type(&mut client, TransactionType::ChatMessage, Duration::from_secs(2))
+        .await
+        .expect("Chat after agreeing was not broadcast");
+    
+    drop(client);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    server_handle.abort();
+}
+
+/// Helper function to wait for the next chat message on a library client
+async fn next_chat(client: &mut Client, wait: Duration) -> Option<Transaction> {
+    timeout(wait, async {
+        loop {
+            match client.recv().await {
+                Ok(tx) if tx.transaction_type == TransactionType::ChatMessage => return Some(tx),
+                Ok(_) => continue,
+                Err(_) => return None,
+            }
+        }
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
+#[tokio::test]
+async fn test_client_library_login_and_chat() {
+    let _ = tracing_subscriber::fmt()
+        .with_test_writer()
+        .try_init();
+
+    let mut config = Config::default();
+    let test_port = 15519;
+    config.server.port = test_port;
+    config.database.path = IN_MEMORY_PATH.into();
+    
+    let server = Server::new(config).await.expect("Failed to create server");
+    let state = server.state();
+    
+    create_account(
+        state.database.pool(),
+        "bot",
+        &xor_password(b"secret"),
+        "Bot",
+        AccessPrivileges::user(),
+    )
+    .await
+    .expect("Failed to create bot account");
+    
+    let server_handle = tokio::spawn(async move {
+        server.run().await
+    });
+    
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    
+    let mut client = Client::connect(("127.0.0.1", test_port)).await.expect("Failed to connect");
+    client.handshake().await.expect("Handshake failed");
+    client.login("bot", "secret").await.expect("Login failed");
+    client.agree("Bot").await.expect("Agreed failed");
+    
+    let mut chat = Transaction::new(TransactionType::SendChat);
+    chat.add_field(Field::string(FieldId::Data, "Beep"));
+    client.send(chat).await.expect("Failed to send chat");
+    
+    let broadcast = next_chat(&mut client, Duration::from_secs(2)).await.expect("Chat was not broadcast");
+    let text = broadcast.get_field(FieldId::Data).and_then(|f| f.as_binary()).expect("No chat text");
+    assert!(String::from_utf8_lossy(text).contains("Beep"));
+    
+    drop(client);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_room_chat_reaches_members_only() {
+    let _ = tracing_subscriber::fmt()
+        .with_test_writer()
+        .try_init();
+
+    let mut config = Config::default();
+    let test_port = 15520;
+    config.server.port = test_port;
+    config.security.allow_guest = true;
+    // Guests can't open private chats by default
+    config.security.guest_access = Some("user".into());
+    config.database.path = IN_MEMORY_PATH.into();
+    
+    let server = Server::new(config).await.expect("Failed to create server");
+    
+    let server_handle = tokio::spawn(async move {
+        server.run().await
+    });
+    
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    
+    let mut clients = Vec::new();
+    for nickname in ["Host", "Guest", "Outsider"] {
+        let mut client = Client::connect(("127.0.0.1", test_port)).await.expect("Failed to connect");
+        client.handshake().await.expect("Handshake failed");
+        client.login("", "").await.expect("Login failed");
+        client.agree(nickname).await.expect("Agreed failed");
+        clients.push(client);
+    }
+    
+    // Users 1 and 2 share a room
+    let mut invite = Transaction::new(TransactionType::InviteNewChat);
+    invite.add_field(Field::integer(FieldId::UserId, 2));
+    let reply = clients[0].request(invite).await.expect("Failed to open room");
+    let chat_id = reply.get_field(FieldId::ChatId).and_then(|f| f.as_integer()).expect("No chat ID") as u32;
+    
+    let mut join = Transaction::new(TransactionType::JoinChat);
+    join.add_field(Field::integer(FieldId::ChatId, chat_id as i32));
+    let reply = clients[1].request(join.clone()).await.expect("Invitee failed to join");
+    assert_eq!(reply.get_all(FieldId::UserNameWithInfo).count(), 2);
+    assert!(clients[2].request(join).await.is_err(), "Outsider joined without an invitation");
+    
+    let mut chat = Transaction::new(TransactionType::SendChat);
+    chat.add_field(Field::string(FieldId::Data, "Members only"));
+    chat.add_field(Field::integer(FieldId::ChatId, chat_id as i32));
+    clients[0].send(chat).await.expect("Failed to send chat");
+    
+    for client in &mut clients[..2] {
+        let message = next_chat(client, Duration::from_secs(2)).await.expect("Member missed room chat");
+        let room = message.get_field(FieldId::ChatId).and_then(|f| f.as_integer());
+        assert_eq!(room, Some(chat_id as i32));
+    }
+    assert!(
+        next_chat(&mut clients[2], Duration::from_millis(300)).await.is_none(),
+        "Outsider received room chat"
+    );
+    
+    drop(clients);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_old_client_skips_agreement() {
+    let _ = tracing_subscriber::fmt()
+        .with_test_writer()
+        .try_init();
+
+    let mut config = Config::default();
+    let test_port = 15521;
+    config.server.port = test_port;
+    config.security.allow_guest = true;
+    config.features.skip_agreement_for_old_clients = true;
+    config.database.path = IN_MEMORY_PATH.into();
+    
+    let server = Server::new(config).await.expect("Failed to create server");
+    
+    let server_handle = tokio::spawn(async move {
+        server.run().await
+    });
+    
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    
+    let addr = format!("127.0.0.1:{}", test_port);
+    let mut client = connect_and_handshake(&addr).await.expect("Handshake failed");
+    
+    let mut login = Transaction::new(TransactionType::Login);
+    login.id = 1;
+    login.add_field(Field::integer(FieldId::Version, 123));
+    client.send(login).await.expect("Failed to send login");
+    let reply = next_of_type(&mut client, TransactionType::Login, Duration::from_secs(2))
+        .await
+        .expect("No login reply");
+    assert_eq!(reply.error_code, 0);
+    
+    // Straight in, without ShowAgreement or having to send Agreed
+    client.send(chat_transaction(2, "Hello")).await.expect("Failed to send chat");
+    loop {
+        let next = timeout(Duration::from_secs(2), client.next())
+            .await
+            .expect("Chat was not broadcast")
+            .unwrap()
+            .unwrap();
+        assert_ne!(next.transaction_type, TransactionType::ShowAgreement);
+        assert_eq!(next.error_code, 0);
+        if next.transaction_type == TransactionType::ChatMessage {
+            break;
+        }
+    }
+    
+    drop(client);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_access_change_reaches_connected_user() {
+    let _ = tracing_subscriber::fmt()
+        .with_test_writer()
+        .try_init();
+
+    let mut config = Config::default();
+    let test_port = 15522;
+    config.server.port = test_port;
+    config.database.path = IN_MEMORY_PATH.into();
+    
+    let server = Server::new(config).await.expect("Failed to create server");
+    let state = server.state();
+    
+    for (login, access) in [("admin", AccessPrivileges::admin()), ("carol", AccessPrivileges::user())] {
+        create_account(state.database.pool(), login, &xor_password(b"secret"), login, access)
+            .await
+            .expect("Failed to create account");
+    }
+    
+    let server_handle = tokio::spawn(async move {
+        server.run().await
+    });
+    
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    
+    let addr = format!("127.0.0.1:{}", test_port);
+    let mut admin = connect_and_handshake(&addr).await.expect("Admin handshake failed");
+    let mut carol = connect_and_handshake(&addr).await.expect("Carol handshake failed");
+    
+    login_with_account(&mut admin, "admin", "secret").await.expect("Admin login failed");
+    login_with_account(&mut carol, "carol", "secret").await.expect("Carol login failed");
+    agree(&mut admin, "Admin").await.expect("Admin agreed failed");
+    agree(&mut carol, "Carol").await.expect("Carol agreed failed");
+    
+    // The access sent after agreeing
+    let initial = next_of_type(&mut carol, TransactionType::UserAccess, Duration::from_secs(2))
+        .await
+        .expect("No access after agreeing");
+    let access = initial.fields.iter().find_map(|f| f.access_privileges());
+    assert_eq!(access, Some(AccessPrivileges::user()));
+    
+    let mut set_user = Transaction::new(TransactionType::SetUser);
+    set_user.id = 5;
+    set_user.add_field(Field::binary(FieldId::UserLogin, xor_password(b"carol")));
+    set_user.add_field(Field::from_access(AccessPrivileges::admin()));
+    admin.send(set_user).await.expect("Failed to send SetUser");
+    let reply = next_of_type(&mut admin, TransactionType::SetUser, Duration::from_secs(2))
+        .await
+        .expect("No SetUser reply");
+    assert_eq!(reply.error_code, 0);
+    
+    let updated = next_of_type(&mut carol, TransactionType::UserAccess, Duration::from_secs(2))
+        .await
+        .expect("Access change was not sent to the connected user");
+    let access = updated.fields.iter().find_map(|f| f.access_privileges());
+    assert_eq!(access, Some(AccessPrivileges::admin()));
+    
+    // Only the account's own sessions are told
+    let leaked = next_of_type(&mut admin, TransactionType::UserAccess, Duration::from_millis(300)).await;
+    assert!(leaked.is_none(), "Access change was sent to another user");
+    
+    drop(admin);
+    drop(carol);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_pipelined_requests_get_matching_replies() {
+    let _ = tracing_subscriber::fmt()
+        .with_test_writer()
+        .try_init();
+
+    let mut config = Config::default();
+    let test_port = 15523;
+    config.server.port = test_port;
+    config.security.allow_guest = true;
+    config.database.path = IN_MEMORY_PATH.into();
+    
+    let server = Server::new(config).await.expect("Failed to create server");
+    
+    let server_handle = tokio::spawn(async move {
+        server.run().await
+    });
+    
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    
+    let addr = format!("127.0.0.1:{}", test_port);
+    let mut client = connect_and_handshake(&addr).await.expect("Handshake failed");
+    login_as_guest(&mut client).await.expect("Login failed");
+    agree(&mut client, "Guest").await.expect("Agreed failed");
+    
+    // Send both before reading either reply
+    let mut user_list = Transaction::new(TransactionType::GetUserNameList);
+    user_list.id = 10;
+    let mut whoami = Transaction::new(TransactionType::UserAccess);
+    whoami.id = 11;
+    client.feed(user_list).await.expect("Failed to queue GetUserNameList");
+    client.feed(whoami).await.expect("Failed to queue UserAccess");
+    client.flush().await.expect("Failed to send requests");
+    
+    let mut replies = Vec::new();
+    while replies.len() < 2 {
+        let tx = timeout(Duration::from_secs(2), client.next())
+            .await
+            .expect("Missing reply")
+            .unwrap()
+            .unwrap();
+        if tx.is_reply {
+            replies.push((tx.transaction_type, tx.id));
+        }
+    }
+    assert_eq!(
+        replies,
+        vec![(TransactionType::GetUserNameList, 10), (TransactionType::UserAccess, 11)]
+    );
+    
+    drop(client);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_request_numbered_zero_is_answered() {
+    let mut config = Config::default();
+    let test_port = 15526;
+    config.server.port = test_port;
+    config.security.allow_guest = true;
+    config.database.path = IN_MEMORY_PATH.into();
+    
+    let server = Server::new(config).await.expect("Failed to create server");
+    
+    let server_handle = tokio::spawn(async move {
+        server.run().await
+    });
+    
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    
+    let addr = format!("127.0.0.1:{}", test_port);
+    let mut client = connect_and_handshake(&addr).await.expect("Handshake failed");
+    login_as_guest(&mut client).await.expect("Login failed");
+    agree(&mut client, "Guest").await.expect("Agreed failed");
+    
+    // The reply echoes ID 0, and the connection stays up for the next request
+    for id in [0, 12] {
+        let mut user_list = Transaction::new(TransactionType::GetUserNameList);
+        user_list.id = id;
+        client.send(user_list).await.expect("Failed to send GetUserNameList");
+        
+        let reply = next_of_type(&mut client, TransactionType::GetUserNameList, Duration::from_secs(2))
+            .await
+            .expect("No reply");
+        assert!(reply.is_reply);
+        assert_eq!(reply.id, id);
+        assert_eq!(reply.error_code, 0);
+    }
+    
+    drop(client);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_connect_disconnect_cycles_leak_no_user_ids() {
+    let mut config = Config::default();
+    let test_port = 15524;
+    config.server.port = test_port;
+    config.security.allow_guest = true;
+    config.database.path = IN_MEMORY_PATH.into();
+    
+    let server = Server::new(config).await.expect("Failed to create server");
+    let state = server.state();
+    
+    let server_handle = tokio::spawn(async move {
+        server.run().await
+    });
+    
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    
+    let addr = format!("127.0.0.1:{}", test_port);
+    for cycle in 0..30 {
+        match cycle % 3 {
+            // Drop before the handshake
+            0 => drop(TcpStream::connect(&addr).await.expect("Failed to connect")),
+            // Drop while login is pending
+            1 => drop(connect_and_handshake(&addr).await.expect("Handshake failed")),
+            // Full login, then drop
+            _ => {
+                let mut client = connect_and_handshake(&addr).await.expect("Handshake failed");
+                login_as_guest(&mut client).await.expect("Login failed");
+                agree(&mut client, "Guest").await.expect("Agreed failed");
+            }
+        }
+    }
+    
+    let drained = timeout(Duration::from_secs(5), async {
+        while state.session_count() > 0 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await;
+    assert!(drained.is_ok(), "{} sessions never closed", state.session_count());
+    assert!(state.leaked_ids().is_empty(), "Leaked user IDs: {:?}", state.leaked_ids());
+    
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_banner_sent_after_agreement() {
+    let mut config = Config::default();
+    let test_port = 15525;
+    config.server.port = test_port;
+    config.security.allow_guest = true;
+    config.banner.enabled = true;
+    config.banner.text = Some("Welcome aboard".to_string());
+    config.banner.url = Some("https://example.com".to_string());
+    config.database.path = IN_MEMORY_PATH.into();
+    
+    let server = Server::new(config).await.expect("Failed to create server");
+    
+    let server_handle = tokio::spawn(async move {
+        server.run().await
+    });
+    
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    
+    let addr = format!("127.0.0.1:{}", test_port);
+    let mut client = connect_and_handshake(&addr).await.expect("Handshake failed");
+    login_as_guest(&mut client).await.expect("Login failed");
+    agree(&mut client, "Guest").await.expect("Agreed failed");
+    
+    let banner = next_of_type(&mut client, TransactionType::ServerBanner, Duration::from_secs(2))
+        .await
+        .expect("No ServerBanner after agreeing");
+    assert!(!banner.is_reply);
+    let field = |id| banner.get_field(id).and_then(|f| f.as_binary()).map(|b| b.to_vec());
+    assert_eq!(field(FieldId::ServerBannerType), Some(b"TEXT".to_vec()));
+    assert_eq!(field(FieldId::ServerBanner), Some(b"Welcome aboard".to_vec()));
+    assert_eq!(field(FieldId::ServerBannerUrl), Some(b"https://example.com".to_vec()));
+    
+    drop(client);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    server_handle.abort();
+}
+
+/// Server with file transfers on, serving from `root`
+async fn transfer_server(test_port: u16, root: &std::path::Path) -> (std::sync::Arc<rhxd::ServerState>, tokio::task::JoinHandle<anyhow::Result<()>>) {
+    let mut config = Config::default();
+    config.server.port = test_port;
+    config.security.allow_guest = true;
+    config.security.guest_access = Some("user".into());
+    config.features.enable_file_transfers = true;
+    config.files.root_path = root.to_path_buf();
+    config.database.path = IN_MEMORY_PATH.into();
+    
+    let server = Server::new(config).await.expect("Failed to create server");
+    let state = server.state();
+    let server_handle = tokio::spawn(async move {
+        server.run().await
+    });
+    
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    (state, server_handle)
+}
+
+/// Open a transfer connection for `reference`
+async fn connect_transfer(test_port: u16, reference: i32, data_size: u32) -> TcpStream {
+    let mut stream = TcpStream::connect(("127.0.0.1", test_port + 1)).await.expect("Failed to connect for transfer");
+    let mut header = Vec::new();
+    header.extend_from_slice(b"HTXF");
+    header.extend_from_slice(&reference.to_be_bytes());
+    header.extend_from_slice(&data_size.to_be_bytes());
+    header.extend_from_slice(&[0; 4]);
+    stream.write_all(&header).await.expect("Failed to send transfer header");
+    stream
+}
+
+#[tokio::test]
+async fn test_download_over_transfer_port() {
+    let test_port = 15530;
+    let root = TempPath::new("transfer_download", "d");
+    std::fs::create_dir(&root).unwrap();
+    std::fs::write(root.join("hello.txt"), b"Hello over HTXF").unwrap();
+    let (state, server_handle) = transfer_server(test_port, &root).await;
+    
+    let mut client = Client::connect(("127.0.0.1", test_port)).await.expect("Failed to connect");
+    client.handshake().await.expect("Handshake failed");
+    client.login("", "").await.expect("Login failed");
+    client.agree("Downloader").await.expect("Agreed failed");
+    
+    let mut request = Transaction::new(TransactionType::DownloadFile);
+    request.add_field(Field::string(FieldId::FileName, "hello.txt"));
+    let reply = client.request(request).await.expect("Download refused");
+    let size = reply.get_field(FieldId::TransferSize).and_then(|f| f.as_binary()).unwrap();
+    let size = u32::from_be_bytes(size.try_into().unwrap()) as usize;
+    let reference = reply.get_field(FieldId::ReferenceNumber).and_then(|f| f.as_integer()).unwrap();
+    
+    let mut transfer = connect_transfer(test_port, reference, 0).await;
+    let mut received = Vec::new();
+    timeout(Duration::from_secs(2), transfer.read_to_end(&mut received))
+        .await
+        .expect("Transfer never ended")
+        .expect("Transfer failed");
+    
+    // FILP header, then the INFO and DATA forks
+    assert_eq!(received.len(), size);
+    assert_eq!(&received[0..4], b"FILP");
+    assert_eq!(&received[24..28], b"INFO");
+    let info = rhxcore::types::FlatFileInfo::from_bytes(&received[40..]).expect("Bad information fork");
+    assert_eq!(info.name, "hello.txt");
+    let data = &received[40 + info.to_bytes().len()..];
+    assert_eq!(&data[0..4], b"DATA");
+    assert_eq!(&data[16..], b"Hello over HTXF");
+    
+    // The slot is free again and the reference can't be used twice
+    assert_eq!(state.transfers.active_count(), 0);
+    let mut again = connect_transfer(test_port, reference, 0).await;
+    let mut nothing = Vec::new();
+    timeout(Duration::from_secs(2), again.read_to_end(&mut nothing)).await.expect("Reused reference kept open").ok();
+    assert!(nothing.is_empty());
+    
+    drop(client);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    server_handle.abort();
+}
+
+/// `contents` as a flattened file object called `name`
+fn flat_file(name: &str, contents: &[u8]) -> Vec<u8> {
+    use rhxcore::types::{FlatFileHeader, FlatFileInfo, ForkHeader};
+    
+    let info = FlatFileInfo {
+        type_code: *b"TEXT",
+        creator_code: *b"ttxt",
+        created: Default::default(),
+        modified: Default::default(),
+        name: name.to_string(),
+        comment: String::new(),
+    }
+    .to_bytes();
+    
+    let mut data = FlatFileHeader { fork_count: 2 }.to_bytes().to_vec();
+    data.extend_from_slice(&ForkHeader { fork_type: *b"INFO", data_size: info.len() as u32 }.to_bytes());
+    data.extend_from_slice(&info);
+    data.extend_from_slice(&ForkHeader { fork_type: *b"DATA", data_size: contents.len() as u32 }.to_bytes());
+    data.extend_from_slice(contents);
+    data
+}
+
+#[tokio::test]
+async fn test_upload_over_transfer_port() {
+    let test_port = 15532;
+    let root = TempPath::new("transfer_upload", "d");
+    std::fs::create_dir(&root).unwrap();
+    let (state, server_handle) = transfer_server(test_port, &root).await;
+    
+    let mut client = Client::connect(("127.0.0.1", test_port)).await.expect("Failed to connect");
+    client.handshake().await.expect("Handshake failed");
+    client.login("", "").await.expect("Login failed");
+    client.agree("Uploader").await.expect("Agreed failed");
+    
+    let upload = flat_file("notes.txt", b"Uploaded over HTXF");
+    let mut request = Transaction::new(TransactionType::UploadFile);
+    request.add_field(Field::string(FieldId::FileName, "notes.txt"));
+    request.add_field(Field::binary(FieldId::TransferSize, (upload.len() as u32).to_be_bytes()));
+    let reply = client.request(request).await.expect("Upload refused");
+    let reference = reply.get_field(FieldId::ReferenceNumber).and_then(|f| f.as_integer()).unwrap();
+    
+    let mut transfer = connect_transfer(test_port, reference, upload.len() as u32).await;
+    transfer.write_all(&upload).await.expect("Failed to send file");
+    let mut rest = Vec::new();
+    timeout(Duration::from_secs(2), transfer.read_to_end(&mut rest)).await.expect("Transfer never ended").ok();
+    
+    assert_eq!(std::fs::read(root.join("notes.txt")).unwrap(), b"Uploaded over HTXF");
+    assert_eq!(state.transfers.active_count(), 0);
+    
+    // A transfer cut short leaves nothing behind
+    let mut request = Transaction::new(TransactionType::UploadFile);
+    request.add_field(Field::string(FieldId::FileName, "partial.txt"));
+    let reply = client.request(request).await.expect("Upload refused");
+    let reference = reply.get_field(FieldId::ReferenceNumber).and_then(|f| f.as_integer()).unwrap();
+    
+    let upload = flat_file("partial.txt", b"Never finished");
+    let mut transfer = connect_transfer(test_port, reference, upload.len() as u32).await;
+    transfer.write_all(&upload[..upload.len() - 4]).await.expect("Failed to send file");
+    drop(transfer);
+    
+    let cleaned_up = timeout(Duration::from_secs(2), async {
+        while state.transfers.active_count() > 0 || root.join("partial.txt").exists() {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await;
+    assert!(cleaned_up.is_ok(), "Partial upload was kept");
+    
+    drop(client);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    server_handle.abort();
+}
+
+/// Header starting each item of a folder transfer
+fn folder_item(kind: u16, path: &[&str]) -> Vec<u8> {
+    let path: Vec<String> = path.iter().map(|name| name.to_string()).collect();
+    let path = rhxcore::types::file::encode_file_path(&path);
+    let mut header = ((2 + path.len()) as u16).to_be_bytes().to_vec();
+    header.extend_from_slice(&kind.to_be_bytes());
+    header.extend_from_slice(&path);
+    header
+}
+
+#[tokio::test]
+async fn test_folder_upload_over_transfer_port() {
+    const SEND: u16 = 1;
+    const NEXT: u16 = 3;
+    
+    let test_port = 15534;
+    let root = TempPath::new("transfer_folder_upload", "d");
+    std::fs::create_dir(&root).unwrap();
+    let (state, server_handle) = transfer_server(test_port, &root).await;
+    
+    // The user preset can't upload folders
+    let mut config = (*state.config()).clone();
+    config.security.guest_access = Some("admin".into());
+    state.reload_config(config);
+    
+    let mut client = Client::connect(("127.0.0.1", test_port)).await.expect("Failed to connect");
+    client.handshake().await.expect("Handshake failed");
+    client.login("", "").await.expect("Login failed");
+    client.agree("Uploader").await.expect("Agreed failed");
+    
+    let mut request = Transaction::new(TransactionType::UploadFolder);
+    request.add_field(Field::string(FieldId::FileName, "photos"));
+    request.add_field(Field::integer(FieldId::FolderItemCount, 2));
+    let reply = client.request(request).await.expect("Upload refused");
+    let reference = reply.get_field(FieldId::ReferenceNumber).and_then(|f| f.as_integer()).unwrap();
+    
+    let mut transfer = connect_transfer(test_port, reference, 0).await;
+    assert_eq!(transfer.read_u16().await.unwrap(), NEXT);
+    transfer.write_all(&folder_item(1, &["2024"])).await.unwrap();
+    
+    assert_eq!(transfer.read_u16().await.unwrap(), NEXT);
+    transfer.write_all(&folder_item(0, &["2024", "cat.txt"])).await.unwrap();
+    assert_eq!(transfer.read_u16().await.unwrap(), SEND);
+    let upload = flat_file("cat.txt", b"Meow");
+    transfer.write_u32(upload.len() as u32).await.unwrap();
+    transfer.write_all(&upload).await.unwrap();
+    
+    assert_eq!(transfer.read_u16().await.unwrap(), NEXT);
+    let mut rest = Vec::new();
+    timeout(Duration::from_secs(2), transfer.read_to_end(&mut rest)).await.expect("Transfer never ended").ok();
+    
+    assert_eq!(std::fs::read(root.join("photos").join("2024").join("cat.txt")).unwrap(), b"Meow");
+    assert_eq!(state.transfers.active_count(), 0);
+    
+    drop(client);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    server_handle.abort();
 }
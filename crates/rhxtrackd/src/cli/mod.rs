@@ -1,4 +1,4 @@
-//! CLI commands (stubs)
+//! CLI commands
 
 pub mod init;
 pub mod serve;
@@ -1,8 +1,14 @@
-//! Serve command (stub)
+//! Serve command
 
-use anyhow::Result;
+use crate::{Config, TrackerServer};
+use anyhow::{Context, Result};
 
-pub async fn run(_config_path: &str) -> Result<()> {
-    println!("rhxtrackd serve - Not yet implemented");
-    Ok(())
+pub async fn run(config_path: &str) -> Result<()> {
+    let config = Config::load(config_path)
+        .with_context(|| format!("Failed to load configuration {}", config_path))?;
+
+    tracing::info!("Starting rhxtrackd tracker: {}", config.server.name);
+
+    let server = TrackerServer::new(config).await?;
+    server.run().await
 }
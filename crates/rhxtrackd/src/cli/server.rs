@@ -1,16 +1,150 @@
-//! Server management commands (stub)
+//! Server management commands
+//!
+//! Seed or clean up the listing by hand, without waiting for servers to
+//! register or expire.
 
-use anyhow::Result;
+use crate::db::Database;
+use crate::registry::{RegisteredServer, Registry};
+use crate::Config;
+use anyhow::{Context, Result};
 use clap::Subcommand;
+use std::fmt::Write;
 
 #[derive(Subcommand)]
 pub enum ServerCommands {
+    /// List registered servers
     List { #[arg(short, long)] verbose: bool },
-    Remove { server_id: String },
-    Show { server_id: String },
+    /// Add a server, or refresh the one at the same address and port
+    Add {
+        name: String,
+        address: String,
+        port: u16,
+        #[arg(short, long, default_value = "")]
+        description: String,
+    },
+    /// Remove a server by name or ID (address:port)
+    Remove { server: String },
+    /// Show one server by name or ID (address:port)
+    Show { server: String },
+    /// Remove servers that haven't updated within `registry.server_ttl_seconds`
+    Prune,
 }
 
-pub async fn run(_config_path: &str, _command: ServerCommands) -> Result<()> {
-    println!("Server management - Not yet implemented");
+pub async fn run(config_path: &str, command: ServerCommands) -> Result<()> {
+    let config = Config::load(config_path)?;
+    let db = Database::new(&config.database.path)
+        .await
+        .with_context(|| format!("Failed to open database {}", config.database.path.display()))?;
+    db.init_schema().await?;
+
+    let registry = Registry::new(db.clone());
+    let output = execute(&registry, &config, command).await;
+    db.close().await;
+
+    print!("{}", output?);
     Ok(())
 }
+
+/// Run a command against the registry, returning what to print
+async fn execute(registry: &Registry, config: &Config, command: ServerCommands) -> Result<String> {
+    let mut out = String::new();
+
+    match command {
+        ServerCommands::List { verbose } => {
+            let servers = registry.list().await?;
+            for server in &servers {
+                if verbose {
+                    write_details(&mut out, server);
+                    writeln!(out)?;
+                } else {
+                    writeln!(out, "{:<32} {:<24} {:>5} users", server.name, server.id, server.user_count)?;
+                }
+            }
+            writeln!(out, "Total: {}", servers.len())?;
+        }
+        ServerCommands::Add { name, address, port, description } => {
+            let server = registry.add(&name, &description, &address, port).await?;
+            writeln!(out, "Listed {} at {}", server.name, server.id)?;
+        }
+        ServerCommands::Remove { server } => match registry.remove(&server).await? {
+            Some(removed) => writeln!(out, "Removed {} ({})", removed.name, removed.id)?,
+            None => anyhow::bail!("No server named {}", server),
+        },
+        ServerCommands::Show { server } => match registry.find(&server).await? {
+            Some(found) => write_details(&mut out, &found),
+            None => anyhow::bail!("No server named {}", server),
+        },
+        ServerCommands::Prune => {
+            let removed = registry.prune(config.registry.server_ttl_seconds).await?;
+            writeln!(out, "Removed {} expired servers", removed)?;
+        }
+    }
+
+    Ok(out)
+}
+
+fn write_details(out: &mut String, server: &RegisteredServer) {
+    let _ = writeln!(out, "Name:        {}", server.name);
+    let _ = writeln!(out, "Address:     {}:{}", server.address, server.port);
+    let _ = writeln!(out, "Description: {}", server.description);
+    let _ = writeln!(out, "Users:       {}", server.user_count);
+    let _ = writeln!(out, "Last update: {} UTC", server.last_update);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::TempDb;
+
+    async fn test_registry(name: &str) -> (Registry, Config, TempDb) {
+        let temp = TempDb::new(&format!("cli_{}", name));
+        let mut config = Config::default();
+        config.database.path = temp.0.clone();
+
+        let db = Database::new(&config.database.path).await.unwrap();
+        db.init_schema().await.unwrap();
+        (Registry::new(db), config, temp)
+    }
+
+    #[tokio::test]
+    async fn test_add_then_list() {
+        let (registry, config, _db) = test_registry("add_list").await;
+
+        let add = ServerCommands::Add {
+            name: "Retro Hangout".to_string(),
+            address: "hotline.example.com".to_string(),
+            port: 5500,
+            description: "Files and chat".to_string(),
+        };
+        execute(&registry, &config, add).await.unwrap();
+
+        let list = ServerCommands::List { verbose: false };
+        let output = execute(&registry, &config, list).await.unwrap();
+        assert!(output.contains("Retro Hangout"), "{}", output);
+        assert!(output.contains("hotline.example.com:5500"), "{}", output);
+        assert!(output.contains("Total: 1"), "{}", output);
+
+        let servers = registry.list().await.unwrap();
+        assert_eq!(servers.len(), 1);
+        assert_eq!(servers[0].description, "Files and chat");
+    }
+
+    #[tokio::test]
+    async fn test_remove_and_prune() {
+        let (registry, config, _db) = test_registry("remove_prune").await;
+        registry.add("One", "", "10.0.0.1", 5500).await.unwrap();
+        registry.add("Two", "", "10.0.0.2", 5500).await.unwrap();
+
+        let remove = ServerCommands::Remove { server: "one".to_string() };
+        execute(&registry, &config, remove).await.unwrap();
+        assert!(registry.find("10.0.0.1:5500").await.unwrap().is_none());
+
+        let missing = ServerCommands::Remove { server: "One".to_string() };
+        assert!(execute(&registry, &config, missing).await.is_err());
+
+        // Fresh entries survive the configured TTL but not a zero one
+        assert_eq!(registry.prune(config.registry.server_ttl_seconds).await.unwrap(), 0);
+        assert_eq!(registry.prune(0).await.unwrap(), 1);
+        assert!(registry.list().await.unwrap().is_empty());
+    }
+}
@@ -17,6 +17,13 @@ pub struct ServerConfig {
     pub name: String,
     pub address: String,
     pub port: u16,
+    /// UDP port servers send their registrations to
+    #[serde(default = "default_registration_port")]
+    pub registration_port: u16,
+}
+
+fn default_registration_port() -> u16 {
+    5499
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -24,6 +31,13 @@ pub struct HttpConfig {
     pub enabled: bool,
     pub address: String,
     pub port: u16,
+    /// Origins browsers may call the JSON API from (`"*"` for any; none
+    /// sends no CORS headers)
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+    /// Key the JSON API requires in an `X-API-Key` header (open when empty)
+    #[serde(default)]
+    pub api_key: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -35,6 +49,30 @@ pub struct DatabaseConfig {
 pub struct RegistryConfig {
     pub server_ttl_seconds: u64,
     pub cleanup_interval_seconds: u64,
+    /// UDP registrations accepted per source IP per minute (0 for no limit)
+    #[serde(default = "default_max_registrations_per_minute")]
+    pub max_registrations_per_minute: u32,
+    /// Longest server name accepted, in characters
+    #[serde(default = "default_max_name_length")]
+    pub max_name_length: usize,
+    /// Longest server description accepted, in characters
+    #[serde(default = "default_max_description_length")]
+    pub max_description_length: usize,
+    /// Accept registrations from private, loopback and link-local addresses
+    #[serde(default)]
+    pub allow_private_addresses: bool,
+}
+
+fn default_max_registrations_per_minute() -> u32 {
+    6
+}
+
+fn default_max_name_length() -> usize {
+    64
+}
+
+fn default_max_description_length() -> usize {
+    200
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -57,19 +95,24 @@ impl Config {
         std::fs::write(path, content)?;
         Ok(())
     }
+}
 
+impl Default for Config {
     /// Create default configuration
-    pub fn default() -> Self {
+    fn default() -> Self {
         Self {
             server: ServerConfig {
                 name: "My Hotline Tracker".to_string(),
                 address: "0.0.0.0".to_string(),
                 port: 5498,
+                registration_port: default_registration_port(),
             },
             http: HttpConfig {
                 enabled: true,
                 address: "0.0.0.0".to_string(),
                 port: 8080,
+                allowed_origins: Vec::new(),
+                api_key: String::new(),
             },
             database: DatabaseConfig {
                 path: PathBuf::from("./rhxtrackd.db"),
@@ -77,6 +120,10 @@ impl Config {
             registry: RegistryConfig {
                 server_ttl_seconds: 3600,
                 cleanup_interval_seconds: 300,
+                max_registrations_per_minute: default_max_registrations_per_minute(),
+                max_name_length: default_max_name_length(),
+                max_description_length: default_max_description_length(),
+                allow_private_addresses: false,
             },
             logging: LoggingConfig {
                 level: "info".to_string(),
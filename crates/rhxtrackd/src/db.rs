@@ -0,0 +1,70 @@
+//! Tracker database
+//!
+//! SQLite storage behind the server registry.
+
+use anyhow::Result;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use std::path::Path;
+
+/// Database connection pool
+#[derive(Clone)]
+pub struct Database {
+    pool: SqlitePool,
+}
+
+impl Database {
+    /// Open (creating if missing) the database at `path`
+    pub async fn new(path: impl AsRef<Path>) -> Result<Self> {
+        let options = SqliteConnectOptions::new()
+            .filename(path.as_ref())
+            .create_if_missing(true)
+            .journal_mode(sqlx::sqlite::SqliteJournalMode::Wal);
+
+        let pool = SqlitePoolOptions::new()
+            .max_connections(8)
+            .connect_with(options)
+            .await?;
+
+        Ok(Self { pool })
+    }
+
+    /// Create any missing tables (safe to run on an existing database)
+    pub async fn init_schema(&self) -> Result<()> {
+        sqlx::raw_sql(include_str!("../migrations/001_initial.sql"))
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Get the connection pool
+    pub fn pool(&self) -> &SqlitePool {
+        &self.pool
+    }
+
+    /// Close the connection pool
+    pub async fn close(&self) {
+        self.pool.close().await;
+    }
+}
+
+/// Database file in the temp directory, removed with its WAL files on drop
+#[cfg(test)]
+pub(crate) struct TempDb(pub std::path::PathBuf);
+
+#[cfg(test)]
+impl TempDb {
+    pub fn new(name: &str) -> Self {
+        Self(std::env::temp_dir().join(format!("rhxtrackd_{}_{}.db", name, std::process::id())))
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDb {
+    fn drop(&mut self) {
+        for suffix in ["", "-wal", "-shm"] {
+            let mut path = self.0.clone().into_os_string();
+            path.push(suffix);
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
@@ -0,0 +1,346 @@
+//! Tracker HTTP endpoints
+//!
+//! - `GET /` - the registered servers as a plain HTML table for operators
+//!   and people browsing trackers (`?sort=name` orders it by name; the
+//!   default is busiest first)
+//! - `GET /servers` - the same listing as JSON
+//!
+//! Served only when `http.enabled` is set. The JSON API sends CORS headers
+//! for `http.allowed_origins` and, when `http.api_key` is set, requires it in
+//! an `X-API-Key` header.
+
+use crate::config::HttpConfig;
+use crate::registry::{RegisteredServer, Registry};
+use anyhow::{Context, Result};
+use axum::extract::{Query, Request, State};
+use axum::http::{header, HeaderName, HeaderValue, Method, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use chrono::{NaiveDateTime, Utc};
+use serde::Deserialize;
+use serde_json::json;
+use std::fmt::Write;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+/// Header carrying the JSON API key
+const API_KEY_HEADER: HeaderName = HeaderName::from_static("x-api-key");
+
+struct StatusPage {
+    registry: Registry,
+    tracker_name: String,
+    api_key: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct StatusQuery {
+    sort: Option<String>,
+}
+
+/// Build the tracker HTTP router
+pub fn router(config: &HttpConfig, registry: Registry, tracker_name: String) -> Router {
+    let page = Arc::new(StatusPage {
+        registry,
+        tracker_name,
+        api_key: config.api_key.clone(),
+    });
+
+    let api = Router::new()
+        .route("/servers", get(list_servers))
+        .route_layer(middleware::from_fn_with_state(page.clone(), require_api_key));
+
+    let app = Router::new().route("/", get(status_page)).merge(api);
+
+    // Outermost, so preflight requests are answered before the key check
+    match cors_layer(&config.allowed_origins) {
+        Some(cors) => app.layer(cors).with_state(page),
+        None => app.with_state(page),
+    }
+}
+
+/// CORS for the configured origins, or `None` if there are none
+fn cors_layer(allowed_origins: &[String]) -> Option<CorsLayer> {
+    if allowed_origins.is_empty() {
+        return None;
+    }
+
+    let origins = if allowed_origins.iter().any(|origin| origin == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(allowed_origins.iter().filter_map(|origin| {
+            let value = HeaderValue::from_str(origin).ok();
+            if value.is_none() {
+                tracing::warn!("Ignoring invalid origin {:?} in http.allowed_origins", origin);
+            }
+            value
+        }))
+    };
+
+    Some(
+        CorsLayer::new()
+            .allow_origin(origins)
+            .allow_methods([Method::GET, Method::OPTIONS])
+            .allow_headers([API_KEY_HEADER, header::CONTENT_TYPE]),
+    )
+}
+
+/// Reject JSON API requests without the configured key
+async fn require_api_key(State(page): State<Arc<StatusPage>>, request: Request, next: Next) -> Response {
+    if page.api_key.is_empty() {
+        return next.run(request).await;
+    }
+
+    let provided = request
+        .headers()
+        .get(&API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok());
+
+    if !provided.is_some_and(|key| key_matches(key, &page.api_key)) {
+        tracing::warn!("Rejected tracker API request to {}", request.uri().path());
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(json!({ "error": "Missing or invalid API key" })),
+        )
+            .into_response();
+    }
+
+    next.run(request).await
+}
+
+/// Compare keys without short-circuiting on the first differing byte
+fn key_matches(provided: &str, expected: &str) -> bool {
+    let (a, b) = (provided.as_bytes(), expected.as_bytes());
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+async fn list_servers(State(page): State<Arc<StatusPage>>) -> Response {
+    match page.registry.list().await {
+        Ok(servers) => Json(servers).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to list servers: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({ "error": "Failed to list servers" })),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Bind the status page listener, unless `http.enabled` is off
+pub async fn bind(config: &HttpConfig) -> Result<Option<TcpListener>> {
+    if !config.enabled {
+        return Ok(None);
+    }
+
+    let addr = format!("{}:{}", config.address, config.port);
+    let listener = TcpListener::bind(&addr)
+        .await
+        .context(format!("Failed to bind tracker HTTP page to {}", addr))?;
+
+    tracing::info!("Tracker status page listening on {}", addr);
+    Ok(Some(listener))
+}
+
+/// Serve the status page and JSON API on `listener` in a background task
+pub fn spawn(
+    listener: TcpListener,
+    config: &HttpConfig,
+    registry: Registry,
+    tracker_name: String,
+) -> JoinHandle<()> {
+    let app = router(config, registry, tracker_name);
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            tracing::error!("Tracker HTTP error: {}", e);
+        }
+    })
+}
+
+async fn status_page(State(page): State<Arc<StatusPage>>, Query(query): Query<StatusQuery>) -> Response {
+    let mut servers = match page.registry.list().await {
+        Ok(servers) => servers,
+        Err(e) => {
+            tracing::error!("Failed to list servers for the status page: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to list servers").into_response();
+        }
+    };
+
+    // The registry lists by name already
+    if query.sort.as_deref() != Some("name") {
+        servers.sort_by_key(|s| std::cmp::Reverse(s.user_count));
+    }
+
+    Html(render(&page.tracker_name, &servers, Utc::now().naive_utc())).into_response()
+}
+
+/// Render the status page for `servers`, with ages relative to `now` (UTC)
+fn render(tracker_name: &str, servers: &[RegisteredServer], now: NaiveDateTime) -> String {
+    let title = escape(tracker_name);
+    let mut html = String::new();
+
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n</head>\n<body>\n\
+         <h1>{title}</h1>\n<p>{} servers</p>\n<table>\n\
+         <tr><th><a href=\"?sort=name\">Name</a></th><th>Address</th><th>Description</th>\
+         <th><a href=\"?sort=users\">Users</a></th><th>Last seen</th></tr>\n",
+        servers.len()
+    );
+
+    for server in servers {
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td><td>{}:{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape(&server.name),
+            escape(&server.address),
+            server.port,
+            escape(&server.description),
+            server.user_count,
+            age(&server.last_update, now)
+        );
+    }
+
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+/// How long ago a SQLite UTC timestamp was, e.g. "5m ago"
+fn age(timestamp: &str, now: NaiveDateTime) -> String {
+    let Ok(then) = NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M:%S") else {
+        return "unknown".to_string();
+    };
+
+    let seconds = (now - then).num_seconds().max(0);
+    match seconds {
+        0..60 => format!("{}s ago", seconds),
+        60..3600 => format!("{}m ago", seconds / 60),
+        3600..86400 => format!("{}h ago", seconds / 3600),
+        _ => format!("{}d ago", seconds / 86400),
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Database, TempDb};
+    use crate::Config;
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    async fn test_registry(name: &str) -> (Registry, Database, TempDb) {
+        let temp = TempDb::new(&format!("http_{}", name));
+        let db = Database::new(&temp.0).await.unwrap();
+        let registry = Registry::open(db.clone(), 3600).await.unwrap();
+        registry.register("Retro <Hangout>", "Files", "203.0.113.5", 5500, 12).await.unwrap();
+        registry.register("Quiet Place", "", "203.0.113.6", 5500, 1).await.unwrap();
+        (registry, db, temp)
+    }
+
+    fn get_request(uri: &str, headers: &[(&str, &str)]) -> Request {
+        let mut builder = axum::http::Request::builder().uri(uri);
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_status_page_lists_servers() {
+        let (registry, db, _temp) = test_registry("status").await;
+
+        let config = Config::default().http;
+        let response = router(&config, registry, "Test Tracker".to_string())
+            .oneshot(get_request("/", &[]))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let content_type = response.headers()[header::CONTENT_TYPE].to_str().unwrap().to_string();
+        assert!(content_type.starts_with("text/html"));
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let html = String::from_utf8(body.to_vec()).unwrap();
+        assert!(html.contains("<td>Retro &lt;Hangout&gt;</td>"), "{}", html);
+        assert!(html.contains("<td>12</td>"), "{}", html);
+
+        // Busiest first by default
+        assert!(html.find("Retro").unwrap() < html.find("Quiet Place").unwrap());
+        db.close().await;
+    }
+
+    #[tokio::test]
+    async fn test_json_api_cors_and_key() {
+        let (registry, db, _temp) = test_registry("api").await;
+
+        let mut config = Config::default().http;
+        config.allowed_origins = vec!["https://hotline.example.com".to_string()];
+        config.api_key = "k3y".to_string();
+        let app = router(&config, registry, "Test Tracker".to_string());
+
+        let origin = ("Origin", "https://hotline.example.com");
+        let response = app.clone()
+            .oneshot(get_request("/servers", &[origin, ("X-API-Key", "k3y")]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://hotline.example.com"
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let servers: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(servers.as_array().map(|s| s.len()), Some(2));
+
+        let missing = app.clone().oneshot(get_request("/servers", &[origin])).await.unwrap();
+        assert_eq!(missing.status(), StatusCode::UNAUTHORIZED);
+
+        // Preflight needs no key
+        let preflight = axum::http::Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/servers")
+            .header("Origin", "https://hotline.example.com")
+            .header("Access-Control-Request-Method", "GET")
+            .header("Access-Control-Request-Headers", "x-api-key")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(preflight).await.unwrap();
+        assert!(response.status().is_success());
+        assert!(response.headers().contains_key(header::ACCESS_CONTROL_ALLOW_HEADERS));
+
+        // Other origins get no CORS grant
+        let response = app
+            .oneshot(get_request("/servers", &[("Origin", "https://evil.example"), ("X-API-Key", "k3y")]))
+            .await
+            .unwrap();
+        assert!(!response.headers().contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+        db.close().await;
+    }
+
+    #[test]
+    fn test_age_formatting() {
+        let now = NaiveDateTime::parse_from_str("2024-05-01 12:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        assert_eq!(age("2024-05-01 11:59:30", now), "30s ago");
+        assert_eq!(age("2024-05-01 10:00:00", now), "2h ago");
+        assert_eq!(age("not a date", now), "unknown");
+    }
+}
@@ -3,6 +3,7 @@
 pub mod config;
 pub mod server;
 pub mod registry;
+pub mod registration;
 pub mod http;
 pub mod db;
 
@@ -7,6 +7,7 @@ mod cli;
 mod config;
 mod server;
 mod registry;
+mod registration;
 mod http;
 mod db;
 
@@ -0,0 +1,322 @@
+//! UDP server registrations
+//!
+//! Hotline servers announce themselves to a tracker with a small UDP packet:
+//!
+//! ```text
+//! u16 version (1) | u16 port | u16 user count | u16 reserved | u32 pass ID
+//! u8 len + name | u8 len + description | [u8 len + password]
+//! ```
+//!
+//! UDP is connectionless and trivially spoofed, so every packet goes through
+//! a [`RegistrationFilter`] before it reaches the registry: a per-source-IP
+//! rate limit, then length, port and address checks. Dropped packets are
+//! counted by reason.
+
+use crate::config::RegistryConfig;
+use crate::registry::Registry;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+
+/// Registration packet version this tracker understands
+const REGISTRATION_VERSION: u16 = 1;
+
+/// Window the per-IP registration limit applies to
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Sources tracked before finished rate windows are swept
+const MAX_TRACKED_SOURCES: usize = 4096;
+
+/// A decoded registration packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Registration {
+    pub port: u16,
+    pub user_count: u16,
+    pub pass_id: u32,
+    pub name: String,
+    pub description: String,
+}
+
+impl Registration {
+    /// Decode a registration packet, or `None` if it is malformed
+    pub fn parse(packet: &[u8]) -> Option<Self> {
+        let mut reader = Reader(packet);
+
+        if reader.u16()? != REGISTRATION_VERSION {
+            return None;
+        }
+        let port = reader.u16()?;
+        let user_count = reader.u16()?;
+        let _reserved = reader.u16()?;
+        let pass_id = reader.u32()?;
+        let name = reader.pstring()?;
+        let description = reader.pstring()?;
+
+        Some(Self { port, user_count, pass_id, name, description })
+    }
+}
+
+/// Big-endian cursor over a packet
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Option<&[u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(head)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// Length-prefixed string; Mac Roman bytes outside ASCII are replaced
+    fn pstring(&mut self) -> Option<String> {
+        let len = *self.take(1)?.first()? as usize;
+        self.take(len).map(|b| String::from_utf8_lossy(b).into_owned())
+    }
+}
+
+/// Why a registration packet was dropped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    /// Not a registration packet this tracker can decode
+    Malformed,
+    /// The source sent more than `max_registrations_per_minute`
+    RateLimited,
+    /// Decoded, but failed validation (lengths, port or address)
+    Invalid,
+}
+
+/// Counts of registration packets by outcome
+#[derive(Debug, Default)]
+pub struct RegistrationStats {
+    pub accepted: AtomicU64,
+    pub malformed: AtomicU64,
+    pub rate_limited: AtomicU64,
+    pub invalid: AtomicU64,
+}
+
+impl RegistrationStats {
+    fn count(&self, outcome: Result<(), Rejection>) {
+        let counter = match outcome {
+            Ok(()) => &self.accepted,
+            Err(Rejection::Malformed) => &self.malformed,
+            Err(Rejection::RateLimited) => &self.rate_limited,
+            Err(Rejection::Invalid) => &self.invalid,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Window {
+    started: Instant,
+    count: u32,
+}
+
+/// Rate limit and validation applied to every registration packet
+#[derive(Debug)]
+pub struct RegistrationFilter {
+    config: RegistryConfig,
+    windows: Mutex<HashMap<IpAddr, Window>>,
+    pub stats: RegistrationStats,
+}
+
+impl RegistrationFilter {
+    pub fn new(config: RegistryConfig) -> Self {
+        Self {
+            config,
+            windows: Mutex::new(HashMap::new()),
+            stats: RegistrationStats::default(),
+        }
+    }
+
+    /// Decide whether to accept a packet from `source`, counting the outcome
+    pub fn check(&self, packet: &[u8], source: IpAddr, now: Instant) -> Result<Registration, Rejection> {
+        let result = self.evaluate(packet, source, now);
+        self.stats.count(result.as_ref().map(|_| ()).map_err(|e| *e));
+        result
+    }
+
+    fn evaluate(&self, packet: &[u8], source: IpAddr, now: Instant) -> Result<Registration, Rejection> {
+        // Rate limit first, so a flood costs no more than a map lookup
+        if !self.within_rate(source, now) {
+            return Err(Rejection::RateLimited);
+        }
+
+        let registration = Registration::parse(packet).ok_or(Rejection::Malformed)?;
+
+        let valid = !registration.name.trim().is_empty()
+            && registration.name.chars().count() <= self.config.max_name_length
+            && registration.description.chars().count() <= self.config.max_description_length
+            && registration.port != 0
+            && (self.config.allow_private_addresses || is_public(source));
+
+        if valid {
+            Ok(registration)
+        } else {
+            Err(Rejection::Invalid)
+        }
+    }
+
+    /// Count a packet against its source's window, returning whether it fits
+    fn within_rate(&self, source: IpAddr, now: Instant) -> bool {
+        let limit = self.config.max_registrations_per_minute;
+        if limit == 0 {
+            return true;
+        }
+
+        let mut windows = self.windows.lock().unwrap();
+
+        // Forget finished windows once many sources are tracked, so spoofed
+        // addresses can't grow the map without bound
+        if windows.len() >= MAX_TRACKED_SOURCES {
+            windows.retain(|_, window| now.duration_since(window.started) < RATE_WINDOW);
+        }
+
+        let window = windows.entry(source).or_insert(Window { started: now, count: 0 });
+        if now.duration_since(window.started) >= RATE_WINDOW {
+            *window = Window { started: now, count: 0 };
+        }
+        window.count += 1;
+        window.count <= limit
+    }
+}
+
+/// Whether `ip` is routable on the public internet
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            !(v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || v4.is_broadcast())
+        }
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => is_public(IpAddr::V4(v4)),
+            None => {
+                let first = v6.segments()[0];
+                !(v6.is_loopback()
+                    || v6.is_unspecified()
+                    || (first & 0xfe00) == 0xfc00 // unique local
+                    || (first & 0xffc0) == 0xfe80) // link local
+            }
+        },
+    }
+}
+
+/// Receive registrations on `socket` and record the accepted ones
+pub async fn listen(socket: UdpSocket, filter: &RegistrationFilter, registry: &Registry) -> Result<()> {
+    let mut buf = [0u8; 1024];
+
+    loop {
+        let (len, source): (usize, SocketAddr) = socket.recv_from(&mut buf).await?;
+
+        let registration = match filter.check(&buf[..len], source.ip(), Instant::now()) {
+            Ok(registration) => registration,
+            Err(rejection) => {
+                tracing::debug!("Dropped registration from {}: {:?}", source, rejection);
+                continue;
+            }
+        };
+
+        let address = source.ip().to_string();
+        if let Err(e) = registry
+            .register(
+                &registration.name,
+                &registration.description,
+                &address,
+                registration.port,
+                registration.user_count,
+            )
+            .await
+        {
+            tracing::error!("Failed to record registration from {}: {}", source, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Config;
+
+    fn packet(name: &str, port: u16) -> Vec<u8> {
+        let mut packet = Vec::new();
+        for value in [REGISTRATION_VERSION, port, 7, 0] {
+            packet.extend_from_slice(&value.to_be_bytes());
+        }
+        packet.extend_from_slice(&42u32.to_be_bytes());
+        for text in [name, "A test server"] {
+            packet.push(text.len() as u8);
+            packet.extend_from_slice(text.as_bytes());
+        }
+        packet
+    }
+
+    fn filter() -> RegistrationFilter {
+        let mut config = Config::default().registry;
+        config.max_registrations_per_minute = 3;
+        config.max_name_length = 16;
+        RegistrationFilter::new(config)
+    }
+
+    fn ip(text: &str) -> IpAddr {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn test_over_rate_sender_throttled() {
+        let filter = filter();
+        let now = Instant::now();
+        let packet = packet("Busy Server", 5500);
+
+        for _ in 0..3 {
+            assert!(filter.check(&packet, ip("203.0.113.5"), now).is_ok());
+        }
+        assert_eq!(filter.check(&packet, ip("203.0.113.5"), now), Err(Rejection::RateLimited));
+
+        // Other senders aren't affected, and the limit resets with the window
+        assert!(filter.check(&packet, ip("198.51.100.9"), now).is_ok());
+        assert!(filter.check(&packet, ip("203.0.113.5"), now + RATE_WINDOW).is_ok());
+
+        assert_eq!(filter.stats.accepted.load(Ordering::Relaxed), 5);
+        assert_eq!(filter.stats.rate_limited.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_invalid_registrations_rejected() {
+        let filter = filter();
+        let now = Instant::now();
+        let source = ip("203.0.113.5");
+
+        let registration = Registration::parse(&packet("Fine", 5500)).unwrap();
+        assert_eq!(registration.name, "Fine");
+        assert_eq!(registration.user_count, 7);
+
+        let oversized = packet("A Name Far Longer Than Allowed", 5500);
+        assert_eq!(filter.check(&oversized, source, now), Err(Rejection::Invalid));
+        assert_eq!(filter.check(&packet("No Port", 0), source, now), Err(Rejection::Invalid));
+        assert_eq!(filter.check(&[0, 1, 0], ip("203.0.113.6"), now), Err(Rejection::Malformed));
+
+        // Private sources only when allowed
+        assert_eq!(filter.check(&packet("LAN", 5500), ip("192.168.1.2"), now), Err(Rejection::Invalid));
+        assert_eq!(filter.check(&packet("LAN", 5500), ip("::1"), now), Err(Rejection::Invalid));
+
+        assert_eq!(filter.stats.invalid.load(Ordering::Relaxed), 4);
+        assert_eq!(filter.stats.malformed.load(Ordering::Relaxed), 1);
+    }
+}
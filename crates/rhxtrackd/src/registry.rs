@@ -0,0 +1,199 @@
+//! Server registry
+//!
+//! The servers the tracker lists, whether they registered themselves or an
+//! operator added them by hand. A server is identified by its
+//! `address:port`, so re-adding one updates its entry in place.
+//!
+//! Entries live in the tracker database with the time each server was last
+//! seen, so the listing survives a restart. [`Registry::open`] drops the
+//! ones that expired while the tracker was down.
+
+use crate::db::Database;
+use anyhow::Result;
+use serde::Serialize;
+
+/// A server in the listing
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RegisteredServer {
+    /// `address:port`
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub address: String,
+    pub port: u16,
+    pub user_count: u16,
+    /// When the server last registered or was added (UTC, SQLite datetime)
+    pub last_update: String,
+}
+
+type ServerRow = (String, String, Option<String>, String, i64, Option<i64>, String);
+
+impl From<ServerRow> for RegisteredServer {
+    fn from(row: ServerRow) -> Self {
+        let (id, name, description, address, port, user_count, last_update) = row;
+        Self {
+            id,
+            name,
+            description: description.unwrap_or_default(),
+            address,
+            port: port as u16,
+            user_count: user_count.unwrap_or(0) as u16,
+            last_update,
+        }
+    }
+}
+
+const SERVER_COLUMNS: &str =
+    "id, name, description, address, port, user_count, last_update";
+
+/// Registry of listed servers, backed by the tracker database
+#[derive(Clone)]
+pub struct Registry {
+    db: Database,
+}
+
+impl Registry {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    /// Open the registry at startup, dropping servers not seen within
+    /// `ttl_seconds`
+    pub async fn open(db: Database, ttl_seconds: u64) -> Result<Self> {
+        db.init_schema().await?;
+        let registry = Self::new(db);
+
+        let expired = registry.prune(ttl_seconds).await?;
+        if expired > 0 {
+            tracing::info!("Dropped {} servers that expired while the tracker was down", expired);
+        }
+
+        Ok(registry)
+    }
+
+    /// All listed servers, by name
+    pub async fn list(&self) -> Result<Vec<RegisteredServer>> {
+        let rows: Vec<ServerRow> = sqlx::query_as(&format!(
+            "SELECT {} FROM servers ORDER BY name COLLATE NOCASE, id",
+            SERVER_COLUMNS
+        ))
+        .fetch_all(self.db.pool())
+        .await?;
+
+        Ok(rows.into_iter().map(RegisteredServer::from).collect())
+    }
+
+    /// Find a server by ID (`address:port`) or, failing that, by name
+    pub async fn find(&self, server: &str) -> Result<Option<RegisteredServer>> {
+        let row: Option<ServerRow> = sqlx::query_as(&format!(
+            "SELECT {} FROM servers WHERE id = ?1 OR name = ?1 COLLATE NOCASE
+             ORDER BY id = ?1 DESC LIMIT 1",
+            SERVER_COLUMNS
+        ))
+        .bind(server)
+        .fetch_optional(self.db.pool())
+        .await?;
+
+        Ok(row.map(RegisteredServer::from))
+    }
+
+    /// Add a server by hand, or refresh the one already listed at
+    /// `address:port`
+    pub async fn add(
+        &self,
+        name: &str,
+        description: &str,
+        address: &str,
+        port: u16,
+    ) -> Result<RegisteredServer> {
+        self.register(name, description, address, port, 0).await
+    }
+
+    /// Record a server's registration, marking it seen now
+    pub async fn register(
+        &self,
+        name: &str,
+        description: &str,
+        address: &str,
+        port: u16,
+        user_count: u16,
+    ) -> Result<RegisteredServer> {
+        let id = format!("{}:{}", address, port);
+
+        sqlx::query(
+            "INSERT INTO servers (id, name, description, address, port, user_count)
+             VALUES (?, ?, ?, ?, ?, ?)
+             ON CONFLICT(id) DO UPDATE SET
+                 name = excluded.name,
+                 description = excluded.description,
+                 user_count = excluded.user_count,
+                 last_update = datetime('now')",
+        )
+        .bind(&id)
+        .bind(name)
+        .bind(description)
+        .bind(address)
+        .bind(port as i64)
+        .bind(user_count as i64)
+        .execute(self.db.pool())
+        .await?;
+
+        self.find(&id).await?.ok_or_else(|| anyhow::anyhow!("Server {} vanished after registering", id))
+    }
+
+    /// Remove the server with this ID or name, returning it if it was listed
+    pub async fn remove(&self, server: &str) -> Result<Option<RegisteredServer>> {
+        let Some(found) = self.find(server).await? else {
+            return Ok(None);
+        };
+
+        sqlx::query("DELETE FROM servers WHERE id = ?")
+            .bind(&found.id)
+            .execute(self.db.pool())
+            .await?;
+
+        Ok(Some(found))
+    }
+
+    /// Remove servers that haven't updated in `ttl_seconds`, returning how many
+    pub async fn prune(&self, ttl_seconds: u64) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM servers WHERE last_update <= datetime('now', ?)")
+            .bind(format!("-{} seconds", ttl_seconds))
+            .execute(self.db.pool())
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::TempDb;
+
+    #[tokio::test]
+    async fn test_registrations_survive_restart() {
+        let temp = TempDb::new("registry_restart");
+        let ttl = 3600;
+
+        let db = Database::new(&temp.0).await.unwrap();
+        let registry = Registry::open(db.clone(), ttl).await.unwrap();
+        registry.register("Fresh", "", "10.0.0.1", 5500, 12).await.unwrap();
+        registry.register("Stale", "", "10.0.0.2", 5500, 3).await.unwrap();
+
+        // Last heard from before the TTL
+        sqlx::query("UPDATE servers SET last_update = datetime('now', '-2 hours') WHERE name = 'Stale'")
+            .execute(db.pool())
+            .await
+            .unwrap();
+        db.close().await;
+
+        let db = Database::new(&temp.0).await.unwrap();
+        let registry = Registry::open(db.clone(), ttl).await.unwrap();
+        let servers = registry.list().await.unwrap();
+        assert_eq!(servers.len(), 1);
+        assert_eq!(servers[0].name, "Fresh");
+        assert_eq!(servers[0].user_count, 12);
+        db.close().await;
+    }
+}
@@ -1,11 +1,228 @@
-//! Tracker server (stub)
+//! Tracker server
+//!
+//! Opens the registry, accepts UDP registrations on
+//! `server.registration_port` and serves the HTTP listing when
+//! `http.enabled` is set. Servers that stop registering are dropped every
+//! `registry.cleanup_interval_seconds`.
+
+use crate::config::Config;
+use crate::db::Database;
+use crate::http;
+use crate::registration::{self, RegistrationFilter};
+use crate::registry::Registry;
+use anyhow::{Context, Result};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
 
 pub struct TrackerServer {
-    // TODO: Implement
+    config: Config,
+    db: Database,
+    registry: Registry,
+    shutdown: Arc<Notify>,
 }
 
 impl TrackerServer {
-    pub fn new() -> Self {
-        Self {}
+    /// Open the tracker database and reload the listing
+    pub async fn new(config: Config) -> Result<Self> {
+        let db = Database::new(&config.database.path)
+            .await
+            .with_context(|| format!("Failed to open database {}", config.database.path.display()))?;
+        let registry = Registry::open(db.clone(), config.registry.server_ttl_seconds).await?;
+
+        Ok(Self {
+            config,
+            db,
+            registry,
+            shutdown: Arc::new(Notify::new()),
+        })
+    }
+
+    /// The listing this tracker serves
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// Notify to stop a running tracker
+    pub fn shutdown_handle(&self) -> Arc<Notify> {
+        self.shutdown.clone()
+    }
+
+    /// Bind the listeners and serve until Ctrl+C or a shutdown notification
+    pub async fn run(self) -> Result<()> {
+        let addr = format!("{}:{}", self.config.server.address, self.config.server.registration_port);
+        let socket = UdpSocket::bind(&addr)
+            .await
+            .context(format!("Failed to bind tracker registrations to {}", addr))?;
+
+        tracing::info!("Accepting server registrations on {}", addr);
+
+        let listener = http::bind(&self.config.http).await?;
+        self.serve(socket, listener).await
+    }
+
+    async fn serve(self, socket: UdpSocket, listener: Option<TcpListener>) -> Result<()> {
+        let http = listener.map(|listener| {
+            http::spawn(listener, &self.config.http, self.registry.clone(), self.config.server.name.clone())
+        });
+        let sweep = spawn_sweep(
+            self.registry.clone(),
+            self.config.registry.server_ttl_seconds,
+            self.config.registry.cleanup_interval_seconds,
+        );
+        let filter = RegistrationFilter::new(self.config.registry.clone());
+
+        let result = tokio::select! {
+            result = registration::listen(socket, &filter, &self.registry) => result,
+            _ = tokio::signal::ctrl_c() => {
+                tracing::info!("Received Ctrl+C, shutting down");
+                Ok(())
+            }
+            _ = self.shutdown.notified() => Ok(()),
+        };
+
+        sweep.abort();
+        if let Some(http) = http {
+            http.abort();
+        }
+        self.db.close().await;
+
+        result
+    }
+}
+
+/// Drop servers not seen within `ttl_seconds`, every `interval_seconds`
+fn spawn_sweep(registry: Registry, ttl_seconds: u64, interval_seconds: u64) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(interval_seconds.max(1)));
+        // The first tick is immediate, and the registry was just pruned
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+            match registry.prune(ttl_seconds).await {
+                Ok(0) => {}
+                Ok(expired) => tracing::info!("Dropped {} expired servers", expired),
+                Err(e) => tracing::error!("Failed to prune expired servers: {}", e),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::TempDb;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    /// Send a plain HTTP/1.1 GET and return the whole response
+    async fn http_get(addr: std::net::SocketAddr, path: &str, headers: &[&str]) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let mut request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n", path);
+        for header in headers {
+            request.push_str(header);
+            request.push_str("\r\n");
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_serve_records_registrations() {
+        let temp = TempDb::new("server_serve");
+        let mut config = Config::default();
+        config.database.path = temp.0.clone();
+        config.registry.allow_private_addresses = true;
+
+        let server = TrackerServer::new(config).await.unwrap();
+        let registry = server.registry().clone();
+        let shutdown = server.shutdown_handle();
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let handle = tokio::spawn(server.serve(socket, None));
+
+        // Version 1, port 5500, 4 users, no pass ID, "Local", no description
+        let mut packet = vec![0, 1, 0x15, 0x7c, 0, 4, 0, 0, 0, 0, 0, 0];
+        packet.push(5);
+        packet.extend_from_slice(b"Local");
+        packet.push(0);
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.send_to(&packet, addr).await.unwrap();
+
+        let mut listed = Vec::new();
+        for _ in 0..50 {
+            listed = registry.list().await.unwrap();
+            if !listed.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].id, "127.0.0.1:5500");
+        assert_eq!(listed[0].user_count, 4);
+
+        shutdown.notify_waiters();
+        tokio::time::timeout(Duration::from_secs(5), handle)
+            .await
+            .expect("Tracker did not shut down")
+            .unwrap()
+            .unwrap();
+    }
+    #[tokio::test]
+    async fn test_serve_status_page() {
+        let temp = TempDb::new("server_status_page");
+        let mut config = Config::default();
+        config.database.path = temp.0.clone();
+        config.server.name = "Served Tracker".to_string();
+
+        let server = TrackerServer::new(config).await.unwrap();
+        server.registry().register("Retro Hangout", "", "203.0.113.5", 5500, 12).await.unwrap();
+        let shutdown = server.shutdown_handle();
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = tokio::spawn(server.serve(socket, Some(listener)));
+
+        let response = http_get(addr, "/", &[]).await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.contains("<h1>Served Tracker</h1>"), "{}", response);
+        assert!(response.contains("<td>Retro Hangout</td>"), "{}", response);
+
+        shutdown.notify_waiters();
+        handle.await.unwrap().unwrap();
+    }
+    #[tokio::test]
+    async fn test_serve_json_api_requires_key() {
+        let temp = TempDb::new("server_json_api");
+        let mut config = Config::default();
+        config.database.path = temp.0.clone();
+        config.http.allowed_origins = vec!["https://hotline.example.com".to_string()];
+        config.http.api_key = "k3y".to_string();
+
+        let server = TrackerServer::new(config).await.unwrap();
+        let shutdown = server.shutdown_handle();
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = tokio::spawn(server.serve(socket, Some(listener)));
+
+        let origin = "Origin: https://hotline.example.com";
+        let response = http_get(addr, "/servers", &[origin]).await;
+        assert!(response.starts_with("HTTP/1.1 401"), "{}", response);
+
+        let response = http_get(addr, "/servers", &[origin, "X-API-Key: k3y"]).await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        let response = response.to_ascii_lowercase();
+        assert!(response.contains("access-control-allow-origin: https://hotline.example.com"), "{}", response);
+
+        shutdown.notify_waiters();
+        handle.await.unwrap().unwrap();
     }
 }